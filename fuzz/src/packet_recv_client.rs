@@ -6,6 +6,7 @@ extern crate libfuzzer_sys;
 #[macro_use]
 extern crate lazy_static;
 
+use std::net::SocketAddr;
 use std::sync::Mutex;
 
 lazy_static! {
@@ -30,9 +31,21 @@ static SCID: [u8; quiche::MAX_CONN_ID_LEN] = [0; quiche::MAX_CONN_ID_LEN];
 
 fuzz_target!(|data: &[u8]| {
     let mut buf = data.to_vec();
-    let mut conn =
-        quiche::connect(Some("quic.tech"), &SCID, &mut CONFIG.lock().unwrap())
-            .unwrap();
 
-    conn.recv(&mut buf).ok();
+    let to: SocketAddr = "127.0.0.1:4321".parse().unwrap();
+
+    let mut conn = quiche::connect(
+        Some("quic.tech"),
+        &SCID,
+        to,
+        &mut CONFIG.lock().unwrap(),
+    )
+    .unwrap();
+
+    let info = quiche::RecvInfo {
+        from: to,
+        ecn: quiche::Ecn::NotEct,
+    };
+
+    conn.recv(&mut buf, info).ok();
 });
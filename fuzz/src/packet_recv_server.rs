@@ -6,6 +6,7 @@ extern crate libfuzzer_sys;
 #[macro_use]
 extern crate lazy_static;
 
+use std::net::SocketAddr;
 use std::sync::Mutex;
 
 lazy_static! {
@@ -36,8 +37,16 @@ static SCID: [u8; quiche::MAX_CONN_ID_LEN] = [0; quiche::MAX_CONN_ID_LEN];
 
 fuzz_target!(|data: &[u8]| {
     let mut buf = data.to_vec();
+
+    let from: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+
     let mut conn =
-        quiche::accept(&SCID, None, &mut CONFIG.lock().unwrap()).unwrap();
+        quiche::accept(&SCID, None, from, &mut CONFIG.lock().unwrap()).unwrap();
+
+    let info = quiche::RecvInfo {
+        from,
+        ecn: quiche::Ecn::NotEct,
+    };
 
-    conn.recv(&mut buf).ok();
+    conn.recv(&mut buf, info).ok();
 });
@@ -34,9 +34,16 @@ extern "C" {
 #include <stdint.h>
 #include <stdbool.h>
 #include <stddef.h>
+#include <time.h>
 #ifdef __unix__
 #include <sys/types.h>
 #endif
+#if defined(WIN32) || defined(_WIN32) || defined(__WIN32__) || defined(__NT__)
+#include <winsock2.h>
+#include <ws2tcpip.h>
+#else
+#include <sys/socket.h>
+#endif
 #ifdef _MSC_VER
 #include <BaseTsd.h>
 #define ssize_t SSIZE_T
@@ -46,7 +53,11 @@ extern "C" {
 //
 
 // The current QUIC wire version.
-#define QUICHE_PROTOCOL_VERSION 0xff00001d
+#define QUICHE_PROTOCOL_VERSION 0x00000001
+
+// The QUIC version 1 and version 2 wire versions.
+#define QUICHE_PROTOCOL_VERSION_V1 0x00000001
+#define QUICHE_PROTOCOL_VERSION_V2 0x6b3343cf
 
 // The maximum length of a connection ID.
 #define QUICHE_MAX_CONN_ID_LEN 20
@@ -99,6 +110,18 @@ enum quiche_error {
 
     // Error in congestion control.
     QUICHE_ERR_CONGESTION_CONTROL = -14,
+
+    // Too many connection IDs were provided.
+    QUICHE_ERR_ID_LIMIT = -15,
+
+    // The peer's version information doesn't match the negotiated version.
+    QUICHE_ERR_VERSION_NEGOTIATION = -16,
+
+    // The specified stream was stopped by the peer.
+    QUICHE_ERR_STREAM_STOPPED = -17,
+
+    // The specified stream was reset by the peer.
+    QUICHE_ERR_STREAM_RESET = -18,
 };
 
 // Returns a human readable string with the quiche version number.
@@ -169,12 +192,36 @@ void quiche_config_set_ack_delay_exponent(quiche_config *config, uint64_t v);
 // Sets the `max_ack_delay` transport parameter.
 void quiche_config_set_max_ack_delay(quiche_config *config, uint64_t v);
 
+// Sets the `min_ack_delay` transport parameter, in microseconds.
+void quiche_config_set_min_ack_delay(quiche_config *config, uint64_t v);
+
 // Sets the `disable_active_migration` transport parameter.
 void quiche_config_set_disable_active_migration(quiche_config *config, bool v);
 
+// Sets the `active_connection_id_limit` transport parameter.
+void quiche_config_set_active_connection_id_limit(quiche_config *config,
+                                                  uint64_t v);
+
+// Sets the `stateless_reset_token` transport parameter from the given 16
+// bytes token, or clears it if `reset_token` is NULL.
+void quiche_config_set_stateless_reset_token(quiche_config *config,
+                                             const uint8_t *reset_token);
+
+// Sets the `preferred_address` transport parameter. Either address can be
+// NULL, and `reset_token` points to a 16 bytes token.
+int quiche_config_set_preferred_address(quiche_config *config,
+                                        const struct sockaddr *v4,
+                                        socklen_t v4_len,
+                                        const struct sockaddr *v6,
+                                        socklen_t v6_len,
+                                        const uint8_t *cid, size_t cid_len,
+                                        const uint8_t *reset_token);
+
 enum quiche_cc_algorithm {
     QUICHE_CC_RENO = 0,
     QUICHE_CC_CUBIC = 1,
+    QUICHE_CC_BBR = 2,
+    QUICHE_CC_LEDBAT = 3,
 };
 
 // Sets the congestion control algorithm used.
@@ -183,6 +230,27 @@ void quiche_config_set_cc_algorithm(quiche_config *config, enum quiche_cc_algori
 // Configures whether to use HyStart++.
 void quiche_config_enable_hystart(quiche_config *config, bool v);
 
+// Configures whether to discover the path MTU.
+void quiche_config_discover_pmtu(quiche_config *config, bool v);
+
+// Configures whether to mark outgoing packets with ECN.
+void quiche_config_enable_ecn(quiche_config *config, bool v);
+
+// Configures whether to pace outgoing packets.
+void quiche_config_enable_pacing(quiche_config *config, bool v);
+
+// Sets the maximum number of 1-RTT packets sent with the same keys.
+void quiche_config_set_max_packets_per_key(quiche_config *config, uint64_t v);
+
+// Sets the maximum number of received packets buffered while the keys needed
+// to decrypt them are not available.
+void quiche_config_set_max_undecryptable_packets(quiche_config *config,
+                                                 size_t v);
+
+// Sets the number of bytes an incremental stream can send before the next
+// incremental stream with the same urgency is scheduled.
+void quiche_config_set_incremental_quantum(quiche_config *config, size_t v);
+
 // Enables support for receiving DAT frames.
 void quiche_config_set_dgram_frames_supported(quiche_config *config, bool v);
 
@@ -192,6 +260,12 @@ void quiche_config_set_dgram_recv_max_queue_len(quiche_config *config, size_t v)
 // Sets the maximum length of the DATAGRAM send queue.
 void quiche_config_set_dgram_send_max_queue_len(quiche_config *config, size_t v);
 
+typedef struct TokenKey quiche_token_key;
+
+// Sets the key used to mint and validate NEW_TOKEN tokens.
+void quiche_config_set_token_key(quiche_config *config,
+                                 const quiche_token_key *key);
+
 // Frees the config object.
 void quiche_config_free(quiche_config *config);
 
@@ -209,11 +283,13 @@ typedef struct Connection quiche_conn;
 // Creates a new server-side connection.
 quiche_conn *quiche_accept(const uint8_t *scid, size_t scid_len,
                            const uint8_t *odcid, size_t odcid_len,
+                           const struct sockaddr *from, socklen_t from_len,
                            quiche_config *config);
 
 // Creates a new client-side connection.
 quiche_conn *quiche_connect(const char *server_name, const uint8_t *scid,
-                            size_t scid_len, quiche_config *config);
+                            size_t scid_len, const struct sockaddr *to,
+                            socklen_t to_len, quiche_config *config);
 
 // Writes a version negotiation packet.
 ssize_t quiche_negotiate_version(const uint8_t *scid, size_t scid_len,
@@ -227,11 +303,50 @@ ssize_t quiche_retry(const uint8_t *scid, size_t scid_len,
                      const uint8_t *token, size_t token_len,
                      uint32_t version, uint8_t *out, size_t out_len);
 
+// Returns true if the given token was issued in a NEW_TOKEN frame.
+bool quiche_is_new_token(const uint8_t *token, size_t token_len);
+
+// Creates a token key from the given 32 bytes secret.
+quiche_token_key *quiche_token_key_new(const uint8_t *key, size_t key_len);
+
+// Makes the given 32 bytes secret the current key, while still accepting
+// tokens minted with the previous one.
+int quiche_token_key_rotate(quiche_token_key *token_key, const uint8_t *key,
+                            size_t key_len);
+
+// Writes a Retry token for the given client address and original destination
+// connection ID.
+ssize_t quiche_token_key_mint_retry_token(const quiche_token_key *token_key,
+                                          const struct sockaddr *from,
+                                          socklen_t from_len,
+                                          const uint8_t *odcid,
+                                          size_t odcid_len,
+                                          uint8_t *out, size_t out_len);
+
+// Validates a Retry token and writes the original destination connection ID
+// to |odcid|, returning its length.
+ssize_t quiche_token_key_validate_retry_token(const quiche_token_key *token_key,
+                                              const struct sockaddr *from,
+                                              socklen_t from_len,
+                                              const uint8_t *token,
+                                              size_t token_len,
+                                              uint8_t *odcid, size_t odcid_len);
+
+// Frees the token key object.
+void quiche_token_key_free(quiche_token_key *token_key);
+
+// Writes a stateless reset packet using the given 16 bytes token, in response
+// to a packet of `pkt_len` bytes.
+ssize_t quiche_stateless_reset(const uint8_t *reset_token, size_t pkt_len,
+                               uint8_t *out, size_t out_len);
+
 // Returns true if the given protocol version is supported.
 bool quiche_version_is_supported(uint32_t version);
 
 quiche_conn *quiche_conn_new_with_tls(const uint8_t *scid, size_t scid_len,
                                       const uint8_t *odcid, size_t odcid_len,
+                                      const struct sockaddr *peer,
+                                      socklen_t peer_len,
                                       quiche_config *config, void *ssl,
                                       bool is_server);
 
@@ -249,11 +364,75 @@ bool quiche_conn_set_qlog_path(quiche_conn *conn, const char *path,
 void quiche_conn_set_qlog_fd(quiche_conn *conn, int fd, const char *log_title,
                              const char *log_desc);
 
+// Configures the given session for resumption.
+int quiche_conn_set_session(quiche_conn *conn, const uint8_t *buf,
+                            size_t buf_len);
+
+// Configures the address validation token to send in Initial packets.
+int quiche_conn_set_token(quiche_conn *conn, const uint8_t *buf,
+                          size_t buf_len);
+
 // Processes QUIC packets received from the peer.
-ssize_t quiche_conn_recv(quiche_conn *conn, uint8_t *buf, size_t buf_len);
+typedef struct {
+    // The remote address the packet was received from.
+    struct sockaddr *from;
+    socklen_t from_len;
+
+    // The ECN codepoint the packet was received with, in the two least
+    // significant bits.
+    uint8_t ecn;
+} quiche_recv_info;
+
+ssize_t quiche_conn_recv(quiche_conn *conn, uint8_t *buf, size_t buf_len,
+                         const quiche_recv_info *info);
+
+// Processes a batch of UDP datagrams coalesced by generic receive offload,
+// each segment_size bytes long except for the last one.
+ssize_t quiche_conn_recv_batch(quiche_conn *conn, uint8_t *buf, size_t buf_len,
+                               size_t segment_size,
+                               const quiche_recv_info *info);
+
+typedef struct {
+    // The address the packet should be sent to.
+    struct sockaddr_storage to;
+    socklen_t to_len;
+
+    // The ECN codepoint the packet should be sent with.
+    uint8_t ecn;
+
+    // The earliest time the packet should be sent at, on the CLOCK_MONOTONIC
+    // clock.
+    struct timespec at;
+} quiche_send_info;
 
 // Writes a single QUIC packet to be sent to the peer.
-ssize_t quiche_conn_send(quiche_conn *conn, uint8_t *out, size_t out_len);
+ssize_t quiche_conn_send(quiche_conn *conn, uint8_t *out, size_t out_len,
+                         quiche_send_info *out_info);
+
+typedef struct {
+    // The address the packets should be sent to.
+    struct sockaddr_storage to;
+    socklen_t to_len;
+
+    // The ECN codepoint the packets should be sent with.
+    uint8_t ecn;
+
+    // The earliest time the first packet should be sent at, on the
+    // CLOCK_MONOTONIC clock.
+    struct timespec at;
+
+    // The size of each packet, except for the last one which can be shorter.
+    size_t segment_size;
+
+    // The number of packets in the batch.
+    size_t segments;
+} quiche_send_batch_info;
+
+// Writes a batch of up to max_segments QUIC packets to be sent to the peer
+// with generic segmentation offload.
+ssize_t quiche_conn_send_batch(quiche_conn *conn, uint8_t *out, size_t out_len,
+                               size_t max_segments,
+                               quiche_send_batch_info *out_info);
 
 // Buffer holding data at a specific offset.
 typedef struct RangeBuf quiche_rangebuf;
@@ -305,6 +484,11 @@ int quiche_conn_close(quiche_conn *conn, bool app, uint64_t err,
 void quiche_conn_application_proto(quiche_conn *conn, const uint8_t **out,
                                    size_t *out_len);
 
+// Returns the serialized session for resumption, if any. The returned buffer
+// is only valid until the next call on the connection.
+void quiche_conn_session(quiche_conn *conn, const uint8_t **out,
+                         size_t *out_len);
+
 // Returns true if the connection handshake is complete.
 bool quiche_conn_is_established(quiche_conn *conn);
 
@@ -312,9 +496,48 @@ bool quiche_conn_is_established(quiche_conn *conn);
 // enough to send or receive early data.
 bool quiche_conn_is_in_early_data(quiche_conn *conn);
 
+// Initiates an update of the keys used to protect 1-RTT packets.
+int quiche_conn_initiate_key_update(quiche_conn *conn);
+
+// Schedules a NEW_TOKEN frame to be sent to the client.
+int quiche_conn_send_new_token(quiche_conn *conn);
+
+// Returns the latest token received in a NEW_TOKEN frame, if any. The returned
+// buffer is only valid until the next call on the connection.
+void quiche_conn_new_token(quiche_conn *conn, const uint8_t **out,
+                           size_t *out_len);
+
 // Returns true if the connection is closed.
 bool quiche_conn_is_closed(quiche_conn *conn);
 
+// Returns true if the connection was reset by the peer.
+bool quiche_conn_is_reset(quiche_conn *conn);
+
+// Returns true if the connection was closed because of a local transport
+// error, in which case the error code is stored in `error_code`.
+bool quiche_conn_local_error(quiche_conn *conn, uint64_t *error_code);
+
+// Provides an additional source connection ID, along with its 16 bytes
+// stateless reset token. On success the ID's sequence number is stored in
+// `seq` and 0 is returned.
+int quiche_conn_new_source_cid(quiche_conn *conn, const uint8_t *scid,
+                               size_t scid_len, const uint8_t *reset_token,
+                               bool retire_if_needed, uint64_t *seq);
+
+// Returns the number of additional source connection IDs the peer is willing
+// to store.
+size_t quiche_conn_source_cids_left(quiche_conn *conn);
+
+// Writes the next source connection ID retired by the peer to `out`, which
+// needs to be at least QUICHE_MAX_CONN_ID_LEN bytes long, and returns its
+// length, or QUICHE_ERR_DONE if there are none.
+ssize_t quiche_conn_retired_scid_next(quiche_conn *conn, uint8_t *out,
+                                      size_t out_len);
+
+// Returns the number of destination connection IDs provided by the peer that
+// are not in use.
+size_t quiche_conn_available_dcids(quiche_conn *conn);
+
 // Initializes the stream's application data.
 //
 // Stream data can only be initialized once. Additional calls to this method
@@ -345,6 +568,10 @@ typedef struct {
     // The number of QUIC packets that were lost.
     size_t lost;
 
+    // The number of QUIC packets that were declared lost but were acked
+    // later on.
+    size_t spurious_lost;
+
     // The estimated round-trip time of the connection (in nanoseconds).
     uint64_t rtt;
 
@@ -353,6 +580,20 @@ typedef struct {
 
     // The estimated data delivery rate in bytes/s.
     uint64_t delivery_rate;
+
+    // The number of QUIC packets buffered because the keys needed to decrypt
+    // them were not available yet.
+    size_t undecryptable_buffered;
+
+    // The number of QUIC packets dropped because too many packets were
+    // already buffered waiting for their keys.
+    size_t undecryptable_dropped;
+
+    // The maximum size of UDP payloads currently sent on the connection.
+    size_t pmtu;
+
+    // The rate at which packets are paced in bytes/s.
+    uint64_t pacing_rate;
 } quiche_stats;
 
 // Collects and returns statistics about the connection.
@@ -382,7 +623,7 @@ void quiche_conn_free(quiche_conn *conn);
 //
 
 // List of ALPN tokens of supported HTTP/3 versions.
-#define QUICHE_H3_APPLICATION_PROTOCOL "\x05h3-29\x05h3-28\x05h3-27"
+#define QUICHE_H3_APPLICATION_PROTOCOL "\x02h3\x05h3-29\x05h3-28\x05h3-27"
 
 enum quiche_h3_error {
     /// There is no error or no work to do
@@ -463,6 +704,7 @@ enum quiche_h3_event_type {
     QUICHE_H3_EVENT_HEADERS,
     QUICHE_H3_EVENT_DATA,
     QUICHE_H3_EVENT_FINISHED,
+    QUICHE_H3_EVENT_RESET,
 };
 
 typedef struct Http3Event quiche_h3_event;
@@ -0,0 +1,337 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! ACK frequency
+//!
+//! This implementation is based on the following draft:
+//!
+//! https://tools.ietf.org/html/draft-ietf-quic-ack-frequency
+
+use std::cmp;
+use std::time;
+
+// A new maximum ACK delay is only requested once it differs from the previous
+// one by more than this fraction of the RTT, so that RTT variations don't
+// cause a new frame to be sent with almost every packet.
+const MAX_ACK_DELAY_RTT_DIVISOR: u32 = 8;
+
+/// The ack-eliciting threshold and maximum ACK delay requested by a peer, or
+/// from it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Params {
+    /// The number of ack-eliciting packets that can be received without
+    /// sending an ACK.
+    pub ack_eliciting_threshold: u64,
+
+    /// The maximum time an ACK can be delayed by.
+    pub max_ack_delay: time::Duration,
+
+    /// How far out of order a packet can arrive without triggering an
+    /// immediate ACK, or 0 to ignore reordering.
+    pub reordering_threshold: u64,
+}
+
+/// ACK frequency state of a connection.
+#[derive(Default)]
+pub struct AckFrequency {
+    /// The parameters requested by the peer with the latest ACK_FREQUENCY
+    /// frame, if any.
+    peer_params: Option<Params>,
+
+    /// The sequence number of the latest ACK_FREQUENCY frame received.
+    rx_seq_num: u64,
+
+    /// The number of ack-eliciting packets received since the last ACK was
+    /// sent.
+    ack_eliciting_count: u64,
+
+    /// The time at which a delayed ACK needs to be sent.
+    ack_timer: Option<time::Instant>,
+
+    /// The parameters last requested from the peer, if any.
+    local_params: Option<Params>,
+
+    /// The sequence number of the next ACK_FREQUENCY frame to send.
+    tx_seq_num: u64,
+}
+
+impl AckFrequency {
+    /// Records the parameters requested by the peer in an ACK_FREQUENCY frame.
+    ///
+    /// Frames that arrive out of order are ignored.
+    pub fn on_frame_received(&mut self, seq_num: u64, params: Params) {
+        if self.peer_params.is_some() && seq_num <= self.rx_seq_num {
+            return;
+        }
+
+        self.rx_seq_num = seq_num;
+        self.peer_params = Some(params);
+    }
+
+    /// Returns whether an ACK needs to be sent right away after receiving a
+    /// packet.
+    ///
+    /// The `largest_rx_pkt_num` parameter is the largest packet number
+    /// received before this packet.
+    pub fn on_packet_received(
+        &mut self, ack_eliciting: bool, pkt_num: u64, largest_rx_pkt_num: u64,
+        now: time::Instant,
+    ) -> bool {
+        if !ack_eliciting {
+            return false;
+        }
+
+        // Without an ACK_FREQUENCY frame from the peer, every ack-eliciting
+        // packet is acked right away.
+        let params = match self.peer_params {
+            Some(v) => v,
+
+            None => return true,
+        };
+
+        self.ack_eliciting_count += 1;
+
+        let out_of_order = params.reordering_threshold > 0 &&
+            (pkt_num < largest_rx_pkt_num ||
+                pkt_num - largest_rx_pkt_num > params.reordering_threshold);
+
+        if self.ack_eliciting_count > params.ack_eliciting_threshold ||
+            out_of_order
+        {
+            return true;
+        }
+
+        if self.ack_timer.is_none() {
+            self.ack_timer = Some(now + params.max_ack_delay);
+        }
+
+        false
+    }
+
+    /// Resets the delayed ACK state after an ACK was sent.
+    pub fn on_ack_sent(&mut self) {
+        self.ack_eliciting_count = 0;
+        self.ack_timer = None;
+    }
+
+    /// Returns the time at which a delayed ACK needs to be sent.
+    pub fn ack_timer(&self) -> Option<time::Instant> {
+        self.ack_timer
+    }
+
+    /// Returns whether the delayed ACK timer expired, in which case an ACK
+    /// needs to be sent.
+    pub fn on_timeout(&mut self, now: time::Instant) -> bool {
+        match self.ack_timer {
+            Some(timer) if timer <= now => {
+                self.ack_timer = None;
+
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Returns whether the given parameters need to be requested from the
+    /// peer, as they differ enough from the ones previously requested, given
+    /// the current `rtt`.
+    pub fn should_request(&self, params: Params, rtt: time::Duration) -> bool {
+        let local_params = match self.local_params {
+            Some(v) => v,
+
+            None => return true,
+        };
+
+        if params.ack_eliciting_threshold !=
+            local_params.ack_eliciting_threshold ||
+            params.reordering_threshold != local_params.reordering_threshold
+        {
+            return true;
+        }
+
+        let delay_change =
+            cmp::max(params.max_ack_delay, local_params.max_ack_delay) -
+                cmp::min(params.max_ack_delay, local_params.max_ack_delay);
+
+        delay_change > rtt / MAX_ACK_DELAY_RTT_DIVISOR
+    }
+
+    /// Returns the sequence number to use for the next ACK_FREQUENCY frame.
+    pub fn next_seq_num(&self) -> u64 {
+        self.tx_seq_num
+    }
+
+    /// Records that an ACK_FREQUENCY frame requesting the given parameters
+    /// was sent.
+    pub fn on_frame_sent(&mut self, params: Params) {
+        self.local_params = Some(params);
+        self.tx_seq_num += 1;
+    }
+
+    /// Schedules a new ACK_FREQUENCY frame after one was lost, unless it was
+    /// already superseded by a newer one.
+    pub fn on_frame_lost(&mut self, seq_num: u64) {
+        if seq_num + 1 == self.tx_seq_num {
+            self.local_params = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delayed_ack() {
+        let mut ack_freq = AckFrequency::default();
+
+        let now = time::Instant::now();
+
+        // Every ack-eliciting packet is acked by default.
+        assert!(ack_freq.on_packet_received(true, 0, 0, now));
+        assert!(!ack_freq.on_packet_received(false, 1, 0, now));
+
+        ack_freq.on_frame_received(0, Params {
+            ack_eliciting_threshold: 2,
+            max_ack_delay: time::Duration::from_millis(10),
+            reordering_threshold: 1,
+        });
+
+        assert!(!ack_freq.on_packet_received(true, 2, 1, now));
+        assert_eq!(
+            ack_freq.ack_timer(),
+            Some(now + time::Duration::from_millis(10))
+        );
+
+        assert!(!ack_freq.on_packet_received(true, 3, 2, now));
+        assert!(ack_freq.on_packet_received(true, 4, 3, now));
+
+        ack_freq.on_ack_sent();
+        assert_eq!(ack_freq.ack_timer(), None);
+
+        // Reordering is acked right away.
+        assert!(ack_freq.on_packet_received(true, 6, 4, now));
+        ack_freq.on_ack_sent();
+
+        assert!(ack_freq.on_packet_received(true, 5, 6, now));
+        ack_freq.on_ack_sent();
+
+        // The timer triggers an ACK once the maximum delay elapsed.
+        assert!(!ack_freq.on_packet_received(true, 7, 6, now));
+        assert!(!ack_freq.on_timeout(now));
+        assert!(ack_freq.on_timeout(now + time::Duration::from_millis(10)));
+        assert_eq!(ack_freq.ack_timer(), None);
+    }
+
+    #[test]
+    fn old_frame_ignored() {
+        let mut ack_freq = AckFrequency::default();
+
+        let now = time::Instant::now();
+
+        ack_freq.on_frame_received(1, Params {
+            ack_eliciting_threshold: 0,
+            max_ack_delay: time::Duration::from_millis(10),
+            reordering_threshold: 0,
+        });
+
+        ack_freq.on_frame_received(0, Params {
+            ack_eliciting_threshold: 10,
+            max_ack_delay: time::Duration::from_millis(10),
+            reordering_threshold: 0,
+        });
+
+        assert!(ack_freq.on_packet_received(true, 0, 0, now));
+    }
+
+    #[test]
+    fn request() {
+        let mut ack_freq = AckFrequency::default();
+
+        let params = Params {
+            ack_eliciting_threshold: 3,
+            max_ack_delay: time::Duration::from_millis(10),
+            reordering_threshold: 1,
+        };
+
+        let rtt = time::Duration::from_millis(40);
+
+        assert!(ack_freq.should_request(params, rtt));
+        assert_eq!(ack_freq.next_seq_num(), 0);
+
+        ack_freq.on_frame_sent(params);
+        assert!(!ack_freq.should_request(params, rtt));
+        assert_eq!(ack_freq.next_seq_num(), 1);
+
+        // Only the latest frame is sent again when lost.
+        ack_freq.on_frame_sent(params);
+        ack_freq.on_frame_lost(0);
+        assert!(!ack_freq.should_request(params, rtt));
+
+        ack_freq.on_frame_lost(1);
+        assert!(ack_freq.should_request(params, rtt));
+    }
+
+    #[test]
+    fn request_rtt_change() {
+        let mut ack_freq = AckFrequency::default();
+
+        let params = |threshold, delay_ms| Params {
+            ack_eliciting_threshold: threshold,
+            max_ack_delay: time::Duration::from_millis(delay_ms),
+            reordering_threshold: 1,
+        };
+
+        // The maximum ACK delay is a quarter of the RTT.
+        let rtt = time::Duration::from_millis(100);
+
+        assert!(ack_freq.should_request(params(3, 25), rtt));
+        ack_freq.on_frame_sent(params(3, 25));
+
+        // Small RTT variations don't cause a new request.
+        for &rtt_ms in &[90, 110, 120, 80] {
+            let rtt = time::Duration::from_millis(rtt_ms);
+
+            assert!(!ack_freq.should_request(params(3, rtt_ms / 4), rtt));
+        }
+
+        // The RTT tripled.
+        let rtt = time::Duration::from_millis(300);
+
+        assert!(ack_freq.should_request(params(3, 75), rtt));
+        ack_freq.on_frame_sent(params(3, 75));
+
+        // The RTT went back down.
+        let rtt = time::Duration::from_millis(100);
+
+        assert!(ack_freq.should_request(params(3, 25), rtt));
+        ack_freq.on_frame_sent(params(3, 25));
+
+        // A different threshold is always requested.
+        assert!(ack_freq.should_request(params(4, 25), rtt));
+    }
+}
@@ -0,0 +1,715 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::cmp;
+
+use std::collections::VecDeque;
+
+use crate::Error;
+use crate::Result;
+
+use crate::frame;
+
+/// A connection ID, along with its sequence number and stateless reset token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionIdEntry {
+    /// The connection ID.
+    pub cid: Vec<u8>,
+
+    /// The sequence number of the connection ID.
+    pub seq: u64,
+
+    /// The stateless reset token associated with the connection ID, if any.
+    pub reset_token: Option<u128>,
+}
+
+/// Keeps track of the connection IDs issued by both endpoints.
+pub struct ConnectionIdentifiers {
+    /// Local connection IDs, ordered by sequence number.
+    scids: VecDeque<ConnectionIdEntry>,
+
+    /// Peer's connection IDs, ordered by sequence number.
+    dcids: VecDeque<ConnectionIdEntry>,
+
+    /// Sequence number of the next local connection ID.
+    next_scid_seq: u64,
+
+    /// Local connection IDs with a lower sequence number need to be retired
+    /// by the peer.
+    retire_prior_to: u64,
+
+    /// Largest Retire Prior To value received from the peer.
+    largest_peer_retire_prior_to: u64,
+
+    /// Sequence number of the peer's connection ID currently in use.
+    active_dcid_seq: u64,
+
+    /// Maximum number of peer's connection IDs we are willing to store, as
+    /// advertised in the local `active_connection_id_limit`.
+    dcid_limit: usize,
+
+    /// Maximum number of local connection IDs the peer is willing to store.
+    scid_limit: usize,
+
+    /// Sequence numbers of local connection IDs that need to be advertised
+    /// in NEW_CONNECTION_ID frames.
+    advertise_scids: VecDeque<u64>,
+
+    /// Sequence numbers of peer's connection IDs that need to be retired with
+    /// RETIRE_CONNECTION_ID frames.
+    retire_dcids: VecDeque<u64>,
+
+    /// Local connection IDs retired by the peer, that the application hasn't
+    /// been notified of yet.
+    retired_scids: VecDeque<Vec<u8>>,
+}
+
+impl ConnectionIdentifiers {
+    pub fn new(scid: &[u8], dcid_limit: u64) -> ConnectionIdentifiers {
+        let mut scids = VecDeque::new();
+        scids.push_back(ConnectionIdEntry {
+            cid: scid.to_vec(),
+            seq: 0,
+            reset_token: None,
+        });
+
+        let mut dcids = VecDeque::new();
+        dcids.push_back(ConnectionIdEntry {
+            cid: Vec::new(),
+            seq: 0,
+            reset_token: None,
+        });
+
+        ConnectionIdentifiers {
+            scids,
+            dcids,
+            next_scid_seq: 1,
+            retire_prior_to: 0,
+            largest_peer_retire_prior_to: 0,
+            active_dcid_seq: 0,
+            dcid_limit: limit_to_usize(dcid_limit),
+            scid_limit: 2,
+            advertise_scids: VecDeque::new(),
+            retire_dcids: VecDeque::new(),
+            retired_scids: VecDeque::new(),
+        }
+    }
+
+    /// Sets the peer's connection ID used during the handshake, which has
+    /// sequence number 0, along with its stateless reset token.
+    pub fn set_initial_dcid(&mut self, cid: &[u8], reset_token: Option<u128>) {
+        if let Some(e) = self.dcids.iter_mut().find(|e| e.seq == 0) {
+            e.cid = cid.to_vec();
+            e.reset_token = reset_token;
+        }
+    }
+
+    /// Sets the maximum number of local connection IDs the peer is willing to
+    /// store, from its `active_connection_id_limit` transport parameter.
+    pub fn set_source_conn_id_limit(&mut self, limit: u64) {
+        self.scid_limit = limit_to_usize(limit);
+    }
+
+    /// Returns true if the given connection ID was issued by us and wasn't
+    /// retired yet.
+    pub fn has_scid(&self, cid: &[u8]) -> bool {
+        self.scids.iter().any(|e| e.cid == cid)
+    }
+
+    /// Returns true if the given token matches the stateless reset token
+    /// associated with one of the peer's connection IDs.
+    pub fn is_stateless_reset(&self, token: &[u8]) -> bool {
+        self.dcids.iter().filter_map(|e| e.reset_token).any(|t| {
+            ring::constant_time::verify_slices_are_equal(&t.to_be_bytes(), token)
+                .is_ok()
+        })
+    }
+
+    /// Returns the number of additional local connection IDs the peer is
+    /// willing to store.
+    pub fn scids_left(&self) -> usize {
+        let active = self
+            .scids
+            .iter()
+            .filter(|e| e.seq >= self.retire_prior_to)
+            .count();
+
+        self.scid_limit.saturating_sub(active)
+    }
+
+    /// Adds a new local connection ID, and returns its sequence number.
+    ///
+    /// If the peer can't store any more connection IDs, the oldest one is
+    /// retired when `retire_if_needed` is true, otherwise `IdLimit` is
+    /// returned.
+    pub fn new_scid(
+        &mut self, cid: &[u8], reset_token: u128, retire_if_needed: bool,
+    ) -> Result<u64> {
+        if let Some(e) = self.scids.iter().find(|e| e.cid == cid) {
+            if e.reset_token != Some(reset_token) {
+                return Err(Error::InvalidState);
+            }
+
+            return Ok(e.seq);
+        }
+
+        if self.scids_left() == 0 {
+            if !retire_if_needed {
+                return Err(Error::IdLimit);
+            }
+
+            // Ask the peer to retire the oldest connection ID still in use.
+            let oldest = self
+                .scids
+                .iter()
+                .find(|e| e.seq >= self.retire_prior_to)
+                .map(|e| e.seq)
+                .ok_or(Error::InvalidState)?;
+
+            self.retire_prior_to = oldest + 1;
+        }
+
+        let seq = self.next_scid_seq;
+
+        self.scids.push_back(ConnectionIdEntry {
+            cid: cid.to_vec(),
+            seq,
+            reset_token: Some(reset_token),
+        });
+
+        self.advertise_scids.push_back(seq);
+
+        self.next_scid_seq += 1;
+
+        Ok(seq)
+    }
+
+    /// Adds the local connection ID advertised in the `preferred_address`
+    /// transport parameter, which has sequence number 1.
+    ///
+    /// Unlike the ones added by `new_scid()`, it doesn't need to be advertised
+    /// in a NEW_CONNECTION_ID frame. It must be added before any other local
+    /// connection ID.
+    pub fn set_preferred_address_scid(&mut self, cid: &[u8], reset_token: u128) {
+        if self.next_scid_seq != 1 {
+            return;
+        }
+
+        self.scids.push_back(ConnectionIdEntry {
+            cid: cid.to_vec(),
+            seq: 1,
+            reset_token: Some(reset_token),
+        });
+
+        self.next_scid_seq = 2;
+    }
+
+    /// Processes a RETIRE_CONNECTION_ID frame received in a packet addressed
+    /// to `pkt_dcid`.
+    pub fn on_retire_connection_id(
+        &mut self, seq: u64, pkt_dcid: &[u8],
+    ) -> Result<()> {
+        // The peer can't retire a connection ID that was never issued.
+        if seq >= self.next_scid_seq {
+            return Err(Error::InvalidState);
+        }
+
+        let pos = match self.scids.iter().position(|e| e.seq == seq) {
+            Some(v) => v,
+
+            // Already retired.
+            None => return Ok(()),
+        };
+
+        // The peer can't retire the connection ID the frame was sent to.
+        if self.scids[pos].cid == pkt_dcid {
+            return Err(Error::InvalidState);
+        }
+
+        if let Some(e) = self.scids.remove(pos) {
+            self.advertise_scids.retain(|&s| s != seq);
+            self.retired_scids.push_back(e.cid);
+        }
+
+        Ok(())
+    }
+
+    /// Processes a NEW_CONNECTION_ID frame.
+    ///
+    /// If the peer asked to retire the connection ID currently in use, a new
+    /// one is selected.
+    pub fn on_new_connection_id(
+        &mut self, cid: Vec<u8>, seq: u64, retire_prior_to: u64,
+        reset_token: u128,
+    ) -> Result<()> {
+        if retire_prior_to > seq {
+            return Err(Error::InvalidFrame);
+        }
+
+        if cid.is_empty() || cid.len() > crate::MAX_CONN_ID_LEN {
+            return Err(Error::InvalidFrame);
+        }
+
+        // The peer's connection ID can't be changed if it's zero-length.
+        if self.active_dcid().is_empty() {
+            return Err(Error::InvalidState);
+        }
+
+        if let Some(e) = self.dcids.iter().find(|e| e.seq == seq) {
+            if e.cid != cid || e.reset_token != Some(reset_token) {
+                return Err(Error::InvalidState);
+            }
+
+            // Duplicate frame, nothing to do.
+            return Ok(());
+        }
+
+        if self.dcids.iter().any(|e| e.cid == cid) {
+            return Err(Error::InvalidState);
+        }
+
+        // The connection ID was already retired by an earlier frame, so
+        // retire it right away.
+        if seq < self.largest_peer_retire_prior_to {
+            if !self.retire_dcids.contains(&seq) {
+                self.retire_dcids.push_back(seq);
+            }
+
+            return self.check_retire_dcids_limit();
+        }
+
+        let pos = self
+            .dcids
+            .iter()
+            .position(|e| e.seq > seq)
+            .unwrap_or(self.dcids.len());
+
+        self.dcids.insert(pos, ConnectionIdEntry {
+            cid,
+            seq,
+            reset_token: Some(reset_token),
+        });
+
+        if retire_prior_to > self.largest_peer_retire_prior_to {
+            self.largest_peer_retire_prior_to = retire_prior_to;
+
+            let retire_dcids = &mut self.retire_dcids;
+
+            self.dcids.retain(|e| {
+                if e.seq < retire_prior_to {
+                    retire_dcids.push_back(e.seq);
+                    return false;
+                }
+
+                true
+            });
+
+            // Switch to the connection ID with the lowest sequence number
+            // still available, as the one in use was retired.
+            if self.active_dcid_seq < retire_prior_to {
+                if let Some(e) = self.dcids.front() {
+                    self.active_dcid_seq = e.seq;
+                }
+            }
+        }
+
+        if self.dcids.len() > self.dcid_limit {
+            return Err(Error::IdLimit);
+        }
+
+        self.check_retire_dcids_limit()
+    }
+
+    /// Returns `IdLimit` if too many of the peer's connection IDs are waiting
+    /// to be retired, which happens when the peer keeps raising Retire Prior
+    /// To faster than RETIRE_CONNECTION_ID frames can be sent.
+    fn check_retire_dcids_limit(&self) -> Result<()> {
+        // Allow up to twice as many IDs as the peer can have active at once.
+        if self.retire_dcids.len() > self.dcid_limit.saturating_mul(2) {
+            return Err(Error::IdLimit);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the peer's connection ID currently in use.
+    pub fn active_dcid(&self) -> &[u8] {
+        self.dcids
+            .iter()
+            .find(|e| e.seq == self.active_dcid_seq)
+            .map(|e| e.cid.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the number of peer's connection IDs available other than the
+    /// one in use.
+    pub fn spare_dcids(&self) -> usize {
+        self.dcids.len().saturating_sub(1)
+    }
+
+    /// Switches to an unused peer's connection ID and retires the one in use.
+    ///
+    /// Returns false if no spare connection ID is available.
+    pub fn rotate_dcid(&mut self) -> bool {
+        match self.switch_dcid() {
+            Some(prev) => {
+                self.retire_dcid(prev);
+                true
+            },
+
+            None => false,
+        }
+    }
+
+    /// Switches to an unused peer's connection ID, without retiring the one
+    /// in use, and returns the sequence number of the latter.
+    ///
+    /// Returns `None` if no spare connection ID is available.
+    pub fn switch_dcid(&mut self) -> Option<u64> {
+        let active = self.active_dcid_seq;
+
+        let next = self.dcids.iter().find(|e| e.seq != active)?.seq;
+
+        self.active_dcid_seq = next;
+
+        Some(active)
+    }
+
+    /// Switches back to the peer's connection ID with the given sequence
+    /// number.
+    ///
+    /// Returns false if the connection ID was retired in the meantime.
+    pub fn restore_dcid(&mut self, seq: u64) -> bool {
+        if !self.dcids.iter().any(|e| e.seq == seq) {
+            return false;
+        }
+
+        self.active_dcid_seq = seq;
+
+        true
+    }
+
+    /// Retires the peer's connection ID with the given sequence number, unless
+    /// it's the one in use.
+    pub fn retire_dcid(&mut self, seq: u64) {
+        if seq == self.active_dcid_seq || !self.dcids.iter().any(|e| e.seq == seq)
+        {
+            return;
+        }
+
+        self.dcids.retain(|e| e.seq != seq);
+        self.retire_dcids.push_back(seq);
+    }
+
+    /// Returns true if there are NEW_CONNECTION_ID or RETIRE_CONNECTION_ID
+    /// frames waiting to be sent.
+    pub fn has_pending_frames(&self) -> bool {
+        !self.advertise_scids.is_empty() || !self.retire_dcids.is_empty()
+    }
+
+    /// Returns the next NEW_CONNECTION_ID frame to be sent, if any.
+    pub fn next_new_connection_id(&self) -> Option<frame::Frame> {
+        let seq = *self.advertise_scids.front()?;
+
+        let e = self.scids.iter().find(|e| e.seq == seq)?;
+
+        Some(frame::Frame::NewConnectionId {
+            seq_num: e.seq,
+            retire_prior_to: self.retire_prior_to,
+            conn_id: e.cid.clone(),
+            reset_token: e.reset_token.unwrap_or(0).to_be_bytes().to_vec(),
+        })
+    }
+
+    /// Marks the frame returned by `next_new_connection_id()` as sent.
+    pub fn on_new_connection_id_sent(&mut self) {
+        self.advertise_scids.pop_front();
+    }
+
+    /// Schedules a lost NEW_CONNECTION_ID frame for retransmission.
+    pub fn on_new_connection_id_lost(&mut self, seq: u64) {
+        if self.scids.iter().any(|e| e.seq == seq) &&
+            !self.advertise_scids.contains(&seq)
+        {
+            self.advertise_scids.push_back(seq);
+        }
+    }
+
+    /// Returns the next RETIRE_CONNECTION_ID frame to be sent, if any.
+    pub fn next_retire_connection_id(&self) -> Option<frame::Frame> {
+        let seq_num = *self.retire_dcids.front()?;
+
+        Some(frame::Frame::RetireConnectionId { seq_num })
+    }
+
+    /// Marks the frame returned by `next_retire_connection_id()` as sent.
+    pub fn on_retire_connection_id_sent(&mut self) {
+        self.retire_dcids.pop_front();
+    }
+
+    /// Schedules a lost RETIRE_CONNECTION_ID frame for retransmission.
+    pub fn on_retire_connection_id_lost(&mut self, seq: u64) {
+        if !self.retire_dcids.contains(&seq) {
+            self.retire_dcids.push_back(seq);
+        }
+    }
+
+    /// Returns the next local connection ID retired by the peer, if any.
+    pub fn retired_scid_next(&mut self) -> Option<Vec<u8>> {
+        self.retired_scids.pop_front()
+    }
+}
+
+fn limit_to_usize(limit: u64) -> usize {
+    cmp::min(limit, usize::MAX as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_scid_limit() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 2);
+        ids.set_source_conn_id_limit(2);
+
+        assert_eq!(ids.scids_left(), 1);
+
+        assert_eq!(ids.new_scid(&[0xbb; 16], 1, false), Ok(1));
+        assert_eq!(ids.scids_left(), 0);
+
+        // Adding the same ID twice is a no-op.
+        assert_eq!(ids.new_scid(&[0xbb; 16], 1, false), Ok(1));
+        assert_eq!(
+            ids.new_scid(&[0xbb; 16], 2, false),
+            Err(Error::InvalidState)
+        );
+
+        assert_eq!(ids.new_scid(&[0xbc; 16], 3, false), Err(Error::IdLimit));
+
+        // Retire the oldest ID to make room for the new one.
+        assert_eq!(ids.new_scid(&[0xbc; 16], 3, true), Ok(2));
+
+        assert_eq!(
+            ids.next_new_connection_id(),
+            Some(frame::Frame::NewConnectionId {
+                seq_num: 1,
+                retire_prior_to: 1,
+                conn_id: vec![0xbb; 16],
+                reset_token: 1u128.to_be_bytes().to_vec(),
+            })
+        );
+
+        ids.on_new_connection_id_sent();
+
+        assert_eq!(
+            ids.next_new_connection_id(),
+            Some(frame::Frame::NewConnectionId {
+                seq_num: 2,
+                retire_prior_to: 1,
+                conn_id: vec![0xbc; 16],
+                reset_token: 3u128.to_be_bytes().to_vec(),
+            })
+        );
+
+        ids.on_new_connection_id_sent();
+
+        assert!(!ids.has_pending_frames());
+
+        // The peer retires the oldest ID, using one of the others.
+        assert_eq!(ids.on_retire_connection_id(0, &[0xbb; 16]), Ok(()));
+
+        assert!(!ids.has_scid(&[0xba; 16]));
+        assert_eq!(ids.retired_scid_next(), Some(vec![0xba; 16]));
+        assert_eq!(ids.retired_scid_next(), None);
+    }
+
+    #[test]
+    fn preferred_address_scid() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 2);
+        ids.set_preferred_address_scid(&[0xbb; 16], 0xcafe);
+
+        // The connection ID is already known to the peer.
+        assert!(ids.has_scid(&[0xbb; 16]));
+        assert!(!ids.has_pending_frames());
+
+        ids.set_source_conn_id_limit(3);
+
+        assert_eq!(ids.scids_left(), 1);
+        assert_eq!(ids.new_scid(&[0xbc; 16], 1, false), Ok(2));
+    }
+
+    #[test]
+    fn retire_scid_invalid() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 2);
+
+        assert_eq!(ids.new_scid(&[0xbb; 16], 1, false), Ok(1));
+
+        // Sequence number was never issued.
+        assert_eq!(
+            ids.on_retire_connection_id(2, &[0xba; 16]),
+            Err(Error::InvalidState)
+        );
+
+        // Can't retire the ID the packet was sent to.
+        assert_eq!(
+            ids.on_retire_connection_id(1, &[0xbb; 16]),
+            Err(Error::InvalidState)
+        );
+    }
+
+    #[test]
+    fn new_dcid_retire_prior_to() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 3);
+        ids.set_initial_dcid(&[0xaa; 16], None);
+
+        assert_eq!(ids.active_dcid(), &[0xaa; 16]);
+
+        assert_eq!(ids.on_new_connection_id(vec![0xab; 16], 1, 0, 1), Ok(()));
+
+        // Duplicate frame.
+        assert_eq!(ids.on_new_connection_id(vec![0xab; 16], 1, 0, 1), Ok(()));
+
+        assert_eq!(ids.spare_dcids(), 1);
+        assert!(!ids.has_pending_frames());
+
+        // Peer asks to retire the IDs with sequence number lower than 2.
+        assert_eq!(ids.on_new_connection_id(vec![0xac; 16], 2, 2, 2), Ok(()));
+
+        assert_eq!(ids.active_dcid(), &[0xac; 16]);
+        assert_eq!(ids.spare_dcids(), 0);
+
+        assert_eq!(
+            ids.next_retire_connection_id(),
+            Some(frame::Frame::RetireConnectionId { seq_num: 0 })
+        );
+        ids.on_retire_connection_id_sent();
+
+        assert_eq!(
+            ids.next_retire_connection_id(),
+            Some(frame::Frame::RetireConnectionId { seq_num: 1 })
+        );
+        ids.on_retire_connection_id_sent();
+
+        assert_eq!(ids.next_retire_connection_id(), None);
+
+        // A late ID that was already retired is retired right away.
+        assert_eq!(ids.on_new_connection_id(vec![0xab; 16], 1, 0, 1), Ok(()));
+
+        assert_eq!(
+            ids.next_retire_connection_id(),
+            Some(frame::Frame::RetireConnectionId { seq_num: 1 })
+        );
+    }
+
+    #[test]
+    fn new_dcid_limit() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 2);
+        ids.set_initial_dcid(&[0xaa; 16], None);
+
+        assert_eq!(ids.on_new_connection_id(vec![0xab; 16], 1, 0, 1), Ok(()));
+
+        assert_eq!(
+            ids.on_new_connection_id(vec![0xac; 16], 2, 0, 2),
+            Err(Error::IdLimit)
+        );
+    }
+
+    #[test]
+    fn retire_dcids_limit() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 2);
+        ids.set_initial_dcid(&[0xaa; 16], None);
+
+        assert_eq!(ids.on_new_connection_id(vec![0xab; 16], 1, 0, 1), Ok(()));
+
+        // Every new ID retires all the previous ones, but none of the
+        // RETIRE_CONNECTION_ID frames are sent.
+        for seq in 2..5 {
+            assert_eq!(
+                ids.on_new_connection_id(vec![0xa0 + seq as u8; 16], seq, seq, 0),
+                Ok(())
+            );
+        }
+
+        assert_eq!(ids.active_dcid(), &[0xa4; 16]);
+
+        assert_eq!(
+            ids.on_new_connection_id(vec![0xa5; 16], 5, 5, 0),
+            Err(Error::IdLimit)
+        );
+    }
+
+    #[test]
+    fn rotate_dcid() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 2);
+        ids.set_initial_dcid(&[0xaa; 16], None);
+
+        assert!(!ids.rotate_dcid());
+
+        assert_eq!(ids.on_new_connection_id(vec![0xab; 16], 1, 0, 1), Ok(()));
+
+        assert!(ids.rotate_dcid());
+        assert_eq!(ids.active_dcid(), &[0xab; 16]);
+
+        assert_eq!(
+            ids.next_retire_connection_id(),
+            Some(frame::Frame::RetireConnectionId { seq_num: 0 })
+        );
+    }
+
+    #[test]
+    fn switch_dcid() {
+        let mut ids = ConnectionIdentifiers::new(&[0xba; 16], 2);
+        ids.set_initial_dcid(&[0xaa; 16], None);
+
+        assert_eq!(ids.switch_dcid(), None);
+
+        assert_eq!(ids.on_new_connection_id(vec![0xab; 16], 1, 0, 1), Ok(()));
+
+        // The previous ID is kept until explicitly retired.
+        assert_eq!(ids.switch_dcid(), Some(0));
+        assert_eq!(ids.active_dcid(), &[0xab; 16]);
+        assert!(!ids.has_pending_frames());
+
+        assert!(ids.restore_dcid(0));
+        assert_eq!(ids.active_dcid(), &[0xaa; 16]);
+
+        // The ID in use can't be retired.
+        ids.retire_dcid(0);
+        assert!(!ids.has_pending_frames());
+
+        assert_eq!(ids.switch_dcid(), Some(0));
+
+        ids.retire_dcid(0);
+        assert_eq!(
+            ids.next_retire_connection_id(),
+            Some(frame::Frame::RetireConnectionId { seq_num: 0 })
+        );
+
+        assert!(!ids.restore_dcid(0));
+        assert_eq!(ids.active_dcid(), &[0xab; 16]);
+    }
+}
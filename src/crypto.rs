@@ -112,8 +112,12 @@ impl Algorithm {
 pub struct Open {
     alg: Algorithm,
 
+    secret: Vec<u8>,
+
     hp_key: aead::quic::HeaderProtectionKey,
 
+    raw_hp_key: Vec<u8>,
+
     key: aead::LessSafeKey,
 
     nonce: Vec<u8>,
@@ -124,12 +128,16 @@ impl Open {
         alg: Algorithm, key: &[u8], iv: &[u8], hp_key: &[u8],
     ) -> Result<Open> {
         Ok(Open {
+            secret: Vec::new(),
+
             hp_key: aead::quic::HeaderProtectionKey::new(
                 alg.get_ring_hp(),
                 hp_key,
             )
             .map_err(|_| Error::CryptoFail)?,
 
+            raw_hp_key: Vec::from(hp_key),
+
             key: aead::LessSafeKey::new(
                 aead::UnboundKey::new(alg.get_ring_aead(), key)
                     .map_err(|_| Error::CryptoFail)?,
@@ -141,7 +149,9 @@ impl Open {
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8]) -> Result<Open> {
+    pub fn from_secret(
+        aead: Algorithm, version: u32, secret: &[u8],
+    ) -> Result<Open> {
         let key_len = aead.key_len();
         let nonce_len = aead.nonce_len();
 
@@ -149,11 +159,32 @@ impl Open {
         let mut iv = vec![0; nonce_len];
         let mut pn_key = vec![0; key_len];
 
-        derive_pkt_key(aead, &secret, &mut key)?;
-        derive_pkt_iv(aead, &secret, &mut iv)?;
-        derive_hdr_key(aead, &secret, &mut pn_key)?;
+        derive_pkt_key(aead, version, &secret, &mut key)?;
+        derive_pkt_iv(aead, version, &secret, &mut iv)?;
+        derive_hdr_key(aead, version, &secret, &mut pn_key)?;
+
+        let mut v = Open::new(aead, &key, &iv, &pn_key)?;
+        v.secret = Vec::from(secret);
 
-        Open::new(aead, &key, &iv, &pn_key)
+        Ok(v)
+    }
+
+    /// Derives the packet protection keys for the next key phase.
+    ///
+    /// The header protection key is not updated.
+    pub fn derive_next_packet_key(&self, version: u32) -> Result<Open> {
+        let next_secret = derive_next_secret(self.alg, version, &self.secret)?;
+
+        let mut key = vec![0; self.alg.key_len()];
+        let mut iv = vec![0; self.alg.nonce_len()];
+
+        derive_pkt_key(self.alg, version, &next_secret, &mut key)?;
+        derive_pkt_iv(self.alg, version, &next_secret, &mut iv)?;
+
+        let mut v = Open::new(self.alg, &key, &iv, &self.raw_hp_key)?;
+        v.secret = next_secret;
+
+        Ok(v)
     }
 
     pub fn open_with_u64_counter(
@@ -196,8 +227,12 @@ impl Open {
 pub struct Seal {
     alg: Algorithm,
 
+    secret: Vec<u8>,
+
     hp_key: aead::quic::HeaderProtectionKey,
 
+    raw_hp_key: Vec<u8>,
+
     key: aead::LessSafeKey,
 
     nonce: Vec<u8>,
@@ -208,12 +243,16 @@ impl Seal {
         alg: Algorithm, key: &[u8], iv: &[u8], hp_key: &[u8],
     ) -> Result<Seal> {
         Ok(Seal {
+            secret: Vec::new(),
+
             hp_key: aead::quic::HeaderProtectionKey::new(
                 alg.get_ring_hp(),
                 hp_key,
             )
             .map_err(|_| Error::CryptoFail)?,
 
+            raw_hp_key: Vec::from(hp_key),
+
             key: aead::LessSafeKey::new(
                 aead::UnboundKey::new(alg.get_ring_aead(), key)
                     .map_err(|_| Error::CryptoFail)?,
@@ -225,7 +264,9 @@ impl Seal {
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8]) -> Result<Seal> {
+    pub fn from_secret(
+        aead: Algorithm, version: u32, secret: &[u8],
+    ) -> Result<Seal> {
         let key_len = aead.key_len();
         let nonce_len = aead.nonce_len();
 
@@ -233,11 +274,32 @@ impl Seal {
         let mut iv = vec![0; nonce_len];
         let mut pn_key = vec![0; key_len];
 
-        derive_pkt_key(aead, &secret, &mut key)?;
-        derive_pkt_iv(aead, &secret, &mut iv)?;
-        derive_hdr_key(aead, &secret, &mut pn_key)?;
+        derive_pkt_key(aead, version, &secret, &mut key)?;
+        derive_pkt_iv(aead, version, &secret, &mut iv)?;
+        derive_hdr_key(aead, version, &secret, &mut pn_key)?;
+
+        let mut v = Seal::new(aead, &key, &iv, &pn_key)?;
+        v.secret = Vec::from(secret);
 
-        Seal::new(aead, &key, &iv, &pn_key)
+        Ok(v)
+    }
+
+    /// Derives the packet protection keys for the next key phase.
+    ///
+    /// The header protection key is not updated.
+    pub fn derive_next_packet_key(&self, version: u32) -> Result<Seal> {
+        let next_secret = derive_next_secret(self.alg, version, &self.secret)?;
+
+        let mut key = vec![0; self.alg.key_len()];
+        let mut iv = vec![0; self.alg.nonce_len()];
+
+        derive_pkt_key(self.alg, version, &next_secret, &mut key)?;
+        derive_pkt_iv(self.alg, version, &next_secret, &mut iv)?;
+
+        let mut v = Seal::new(self.alg, &key, &iv, &self.raw_hp_key)?;
+        v.secret = next_secret;
+
+        Ok(v)
     }
 
     pub fn seal_with_u64_counter(
@@ -305,9 +367,9 @@ pub fn derive_initial_key_material(
     let mut client_hp_key = vec![0; key_len];
 
     derive_client_initial_secret(&initial_secret, &mut secret)?;
-    derive_pkt_key(aead, &secret, &mut client_key)?;
-    derive_pkt_iv(aead, &secret, &mut client_iv)?;
-    derive_hdr_key(aead, &secret, &mut client_hp_key)?;
+    derive_pkt_key(aead, version, &secret, &mut client_key)?;
+    derive_pkt_iv(aead, version, &secret, &mut client_iv)?;
+    derive_hdr_key(aead, version, &secret, &mut client_hp_key)?;
 
     // Server.
     let mut server_key = vec![0; key_len];
@@ -315,9 +377,9 @@ pub fn derive_initial_key_material(
     let mut server_hp_key = vec![0; key_len];
 
     derive_server_initial_secret(&initial_secret, &mut secret)?;
-    derive_pkt_key(aead, &secret, &mut server_key)?;
-    derive_pkt_iv(aead, &secret, &mut server_iv)?;
-    derive_hdr_key(aead, &secret, &mut server_hp_key)?;
+    derive_pkt_key(aead, version, &secret, &mut server_key)?;
+    derive_pkt_iv(aead, version, &secret, &mut server_iv)?;
+    derive_hdr_key(aead, version, &secret, &mut server_hp_key)?;
 
     let (open, seal) = if is_server {
         (
@@ -335,6 +397,16 @@ pub fn derive_initial_key_material(
 }
 
 fn derive_initial_secret(secret: &[u8], version: u32) -> Result<hkdf::Prk> {
+    const INITIAL_SALT_V1: [u8; 20] = [
+        0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6,
+        0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb, 0x7f, 0x0a,
+    ];
+
+    const INITIAL_SALT_V2: [u8; 20] = [
+        0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe,
+        0x6e, 0x26, 0x9d, 0xcb, 0xf9, 0xbd, 0x2e, 0xd9,
+    ];
+
     const INITIAL_SALT: [u8; 20] = [
         0xaf, 0xbf, 0xec, 0x28, 0x99, 0x93, 0xd2, 0x4c, 0x9e, 0x97, 0x86, 0xf1,
         0x9c, 0x61, 0x11, 0xe0, 0x43, 0x90, 0xa8, 0x99,
@@ -346,6 +418,10 @@ fn derive_initial_secret(secret: &[u8], version: u32) -> Result<hkdf::Prk> {
     ];
 
     let salt = match version {
+        crate::PROTOCOL_VERSION_V1 => &INITIAL_SALT_V1,
+
+        crate::PROTOCOL_VERSION_V2 => &INITIAL_SALT_V2,
+
         crate::PROTOCOL_VERSION_DRAFT27 | crate::PROTOCOL_VERSION_DRAFT28 =>
             &INITIAL_SALT_OLD,
 
@@ -367,9 +443,16 @@ fn derive_server_initial_secret(prk: &hkdf::Prk, out: &mut [u8]) -> Result<()> {
 }
 
 pub fn derive_hdr_key(
-    aead: Algorithm, secret: &[u8], out: &mut [u8],
+    aead: Algorithm, version: u32, secret: &[u8], out: &mut [u8],
 ) -> Result<()> {
     const LABEL: &[u8] = b"quic hp";
+    const LABEL_V2: &[u8] = b"quicv2 hp";
+
+    let label = if version == crate::PROTOCOL_VERSION_V2 {
+        LABEL_V2
+    } else {
+        LABEL
+    };
 
     let key_len = aead.key_len();
 
@@ -378,13 +461,20 @@ pub fn derive_hdr_key(
     }
 
     let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
+    hkdf_expand_label(&secret, label, &mut out[..key_len])
 }
 
 pub fn derive_pkt_key(
-    aead: Algorithm, secret: &[u8], out: &mut [u8],
+    aead: Algorithm, version: u32, secret: &[u8], out: &mut [u8],
 ) -> Result<()> {
     const LABEL: &[u8] = b"quic key";
+    const LABEL_V2: &[u8] = b"quicv2 key";
+
+    let label = if version == crate::PROTOCOL_VERSION_V2 {
+        LABEL_V2
+    } else {
+        LABEL
+    };
 
     let key_len = aead.key_len();
 
@@ -393,13 +483,46 @@ pub fn derive_pkt_key(
     }
 
     let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
+    hkdf_expand_label(&secret, label, &mut out[..key_len])
+}
+
+pub fn derive_next_secret(
+    aead: Algorithm, version: u32, secret: &[u8],
+) -> Result<Vec<u8>> {
+    const LABEL: &[u8] = b"quic ku";
+    const LABEL_V2: &[u8] = b"quicv2 ku";
+
+    let label = if version == crate::PROTOCOL_VERSION_V2 {
+        LABEL_V2
+    } else {
+        LABEL
+    };
+
+    // Initial keys are not derived from a traffic secret, and can't be
+    // updated.
+    if secret.is_empty() {
+        return Err(Error::CryptoFail);
+    }
+
+    let mut out = vec![0; secret.len()];
+
+    let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
+    hkdf_expand_label(&secret, label, &mut out)?;
+
+    Ok(out)
 }
 
 pub fn derive_pkt_iv(
-    aead: Algorithm, secret: &[u8], out: &mut [u8],
+    aead: Algorithm, version: u32, secret: &[u8], out: &mut [u8],
 ) -> Result<()> {
     const LABEL: &[u8] = b"quic iv";
+    const LABEL_V2: &[u8] = b"quicv2 iv";
+
+    let label = if version == crate::PROTOCOL_VERSION_V2 {
+        LABEL_V2
+    } else {
+        LABEL
+    };
 
     let nonce_len = aead.nonce_len();
 
@@ -408,7 +531,7 @@ pub fn derive_pkt_iv(
     }
 
     let secret = hkdf::Prk::new_less_safe(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..nonce_len])
+    hkdf_expand_label(&secret, label, &mut out[..nonce_len])
 }
 
 fn hkdf_expand_label(
@@ -468,8 +591,9 @@ mod tests {
 
         let aead = Algorithm::AES128_GCM;
 
-        let initial_secret =
-            derive_initial_secret(&dcid, crate::PROTOCOL_VERSION).unwrap();
+        let version = crate::PROTOCOL_VERSION_DRAFT29;
+
+        let initial_secret = derive_initial_secret(&dcid, version).unwrap();
 
         // Client.
         assert!(
@@ -482,21 +606,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key = [
             0x17, 0x52, 0x57, 0xa3, 0x1e, 0xb0, 0x9d, 0xea, 0x93, 0x66, 0xd8,
             0xbb, 0x79, 0xad, 0x80, 0xba,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv = [
             0x6b, 0x26, 0x11, 0x4b, 0x9c, 0xba, 0x2b, 0x63, 0xa9, 0xe8, 0xdd,
             0x4f,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_client_hdr_key = [
             0x9d, 0xdd, 0x12, 0xc9, 0x94, 0xc0, 0x69, 0x8b, 0x89, 0x37, 0x4a,
             0x9c, 0x07, 0x7a, 0x30, 0x77,
@@ -514,21 +638,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key = [
             0x14, 0x9d, 0x0b, 0x16, 0x62, 0xab, 0x87, 0x1f, 0xbe, 0x63, 0xc4,
             0x9b, 0x5e, 0x65, 0x5a, 0x5d,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv = [
             0xba, 0xb2, 0xb1, 0x2a, 0x4c, 0x76, 0x01, 0x6a, 0xce, 0x47, 0x85,
             0x6d,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key = [
             0xc0, 0xc4, 0x99, 0xa6, 0x5a, 0x60, 0x02, 0x4a, 0x18, 0xa2, 0x50,
             0x97, 0x4e, 0xa0, 0x1d, 0xfa,
@@ -536,6 +660,166 @@ mod tests {
         assert_eq!(&hdr_key, &expected_server_hdr_key);
     }
 
+    #[test]
+    fn derive_initial_secrets_v1() {
+        let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut secret = [0; 32];
+        let mut pkt_key = [0; 16];
+        let mut pkt_iv = [0; 12];
+        let mut hdr_key = [0; 16];
+
+        let aead = Algorithm::AES128_GCM;
+
+        let version = crate::PROTOCOL_VERSION_V1;
+
+        let initial_secret = derive_initial_secret(&dcid, version).unwrap();
+
+        // Client.
+        assert!(
+            derive_client_initial_secret(&initial_secret, &mut secret).is_ok()
+        );
+        let expected_client_initial_secret = [
+            0xc0, 0x0c, 0xf1, 0x51, 0xca, 0x5b, 0xe0, 0x75, 0xed, 0x0e, 0xbf,
+            0xb5, 0xc8, 0x03, 0x23, 0xc4, 0x2d, 0x6b, 0x7d, 0xb6, 0x78, 0x81,
+            0x28, 0x9a, 0xf4, 0x00, 0x8f, 0x1f, 0x6c, 0x35, 0x7a, 0xea,
+        ];
+        assert_eq!(&secret, &expected_client_initial_secret);
+
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_client_pkt_key = [
+            0x1f, 0x36, 0x96, 0x13, 0xdd, 0x76, 0xd5, 0x46, 0x77, 0x30, 0xef,
+            0xcb, 0xe3, 0xb1, 0xa2, 0x2d,
+        ];
+        assert_eq!(&pkt_key, &expected_client_pkt_key);
+
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_client_pkt_iv = [
+            0xfa, 0x04, 0x4b, 0x2f, 0x42, 0xa3, 0xfd, 0x3b, 0x46, 0xfb, 0x25,
+            0x5c,
+        ];
+        assert_eq!(&pkt_iv, &expected_client_pkt_iv);
+
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_client_hdr_key = [
+            0x9f, 0x50, 0x44, 0x9e, 0x04, 0xa0, 0xe8, 0x10, 0x28, 0x3a, 0x1e,
+            0x99, 0x33, 0xad, 0xed, 0xd2,
+        ];
+        assert_eq!(&hdr_key, &expected_client_hdr_key);
+
+        // Server.
+        assert!(
+            derive_server_initial_secret(&initial_secret, &mut secret).is_ok()
+        );
+        let expected_server_initial_secret = [
+            0x3c, 0x19, 0x98, 0x28, 0xfd, 0x13, 0x9e, 0xfd, 0x21, 0x6c, 0x15,
+            0x5a, 0xd8, 0x44, 0xcc, 0x81, 0xfb, 0x82, 0xfa, 0x8d, 0x74, 0x46,
+            0xfa, 0x7d, 0x78, 0xbe, 0x80, 0x3a, 0xcd, 0xda, 0x95, 0x1b,
+        ];
+        assert_eq!(&secret, &expected_server_initial_secret);
+
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_server_pkt_key = [
+            0xcf, 0x3a, 0x53, 0x31, 0x65, 0x3c, 0x36, 0x4c, 0x88, 0xf0, 0xf3,
+            0x79, 0xb6, 0x06, 0x7e, 0x37,
+        ];
+        assert_eq!(&pkt_key, &expected_server_pkt_key);
+
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_server_pkt_iv = [
+            0x0a, 0xc1, 0x49, 0x3c, 0xa1, 0x90, 0x58, 0x53, 0xb0, 0xbb, 0xa0,
+            0x3e,
+        ];
+        assert_eq!(&pkt_iv, &expected_server_pkt_iv);
+
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_server_hdr_key = [
+            0xc2, 0x06, 0xb8, 0xd9, 0xb9, 0xf0, 0xf3, 0x76, 0x44, 0x43, 0x0b,
+            0x49, 0x0e, 0xea, 0xa3, 0x14,
+        ];
+        assert_eq!(&hdr_key, &expected_server_hdr_key);
+    }
+
+    #[test]
+    fn derive_initial_secrets_v2() {
+        let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut secret = [0; 32];
+        let mut pkt_key = [0; 16];
+        let mut pkt_iv = [0; 12];
+        let mut hdr_key = [0; 16];
+
+        let aead = Algorithm::AES128_GCM;
+
+        let version = crate::PROTOCOL_VERSION_V2;
+
+        let initial_secret = derive_initial_secret(&dcid, version).unwrap();
+
+        // Client.
+        assert!(
+            derive_client_initial_secret(&initial_secret, &mut secret).is_ok()
+        );
+        let expected_client_initial_secret = [
+            0x14, 0xec, 0x9d, 0x6e, 0xb9, 0xfd, 0x7a, 0xf8, 0x3b, 0xf5, 0xa6,
+            0x68, 0xbc, 0x17, 0xa7, 0xe2, 0x83, 0x76, 0x6a, 0xad, 0xe7, 0xec,
+            0xd0, 0x89, 0x1f, 0x70, 0xf9, 0xff, 0x7f, 0x4b, 0xf4, 0x7b,
+        ];
+        assert_eq!(&secret, &expected_client_initial_secret);
+
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_client_pkt_key = [
+            0x8b, 0x1a, 0x0b, 0xc1, 0x21, 0x28, 0x42, 0x90, 0xa2, 0x9e, 0x09,
+            0x71, 0xb5, 0xcd, 0x04, 0x5d,
+        ];
+        assert_eq!(&pkt_key, &expected_client_pkt_key);
+
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_client_pkt_iv = [
+            0x91, 0xf7, 0x3e, 0x23, 0x51, 0xd8, 0xfa, 0x91, 0x66, 0x0e, 0x90,
+            0x9f,
+        ];
+        assert_eq!(&pkt_iv, &expected_client_pkt_iv);
+
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_client_hdr_key = [
+            0x45, 0xb9, 0x5e, 0x15, 0x23, 0x5d, 0x6f, 0x45, 0xa6, 0xb1, 0x9c,
+            0xbc, 0xb0, 0x29, 0x4b, 0xa9,
+        ];
+        assert_eq!(&hdr_key, &expected_client_hdr_key);
+
+        // Server.
+        assert!(
+            derive_server_initial_secret(&initial_secret, &mut secret).is_ok()
+        );
+        let expected_server_initial_secret = [
+            0x02, 0x63, 0xdb, 0x17, 0x82, 0x73, 0x1b, 0xf4, 0x58, 0x8e, 0x7e,
+            0x4d, 0x93, 0xb7, 0x46, 0x39, 0x07, 0xcb, 0x8c, 0xd8, 0x20, 0x0b,
+            0x5d, 0xa5, 0x5a, 0x8b, 0xd4, 0x88, 0xea, 0xfc, 0x37, 0xc1,
+        ];
+        assert_eq!(&secret, &expected_server_initial_secret);
+
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_server_pkt_key = [
+            0x82, 0xdb, 0x63, 0x78, 0x61, 0xd5, 0x5e, 0x1d, 0x01, 0x1f, 0x19,
+            0xea, 0x71, 0xd5, 0xd2, 0xa7,
+        ];
+        assert_eq!(&pkt_key, &expected_server_pkt_key);
+
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_server_pkt_iv = [
+            0xdd, 0x13, 0xc2, 0x76, 0x49, 0x9c, 0x02, 0x49, 0xd3, 0x31, 0x06,
+            0x52,
+        ];
+        assert_eq!(&pkt_iv, &expected_server_pkt_iv);
+
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_server_hdr_key = [
+            0xed, 0xf6, 0xd0, 0x5c, 0x83, 0x12, 0x12, 0x01, 0xb4, 0x36, 0xe1,
+            0x68, 0x77, 0x59, 0x3c, 0x3a,
+        ];
+        assert_eq!(&hdr_key, &expected_server_hdr_key);
+    }
+
     #[test]
     fn derive_initial_secrets_old() {
         let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
@@ -547,9 +831,9 @@ mod tests {
 
         let aead = Algorithm::AES128_GCM;
 
-        let initial_secret =
-            derive_initial_secret(&dcid, crate::PROTOCOL_VERSION_DRAFT28)
-                .unwrap();
+        let version = crate::PROTOCOL_VERSION_DRAFT28;
+
+        let initial_secret = derive_initial_secret(&dcid, version).unwrap();
 
         // Client.
         assert!(
@@ -562,21 +846,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key = [
             0xaf, 0x7f, 0xd7, 0xef, 0xeb, 0xd2, 0x18, 0x78, 0xff, 0x66, 0x81,
             0x12, 0x48, 0x98, 0x36, 0x94,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv = [
             0x86, 0x81, 0x35, 0x94, 0x10, 0xa7, 0x0b, 0xb9, 0xc9, 0x2f, 0x04,
             0x20,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_client_hdr_key = [
             0xa9, 0x80, 0xb8, 0xb4, 0xfb, 0x7d, 0x9f, 0xbc, 0x13, 0xe8, 0x14,
             0xc2, 0x31, 0x64, 0x25, 0x3d,
@@ -594,21 +878,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key = [
             0x5d, 0x51, 0xda, 0x9e, 0xe8, 0x97, 0xa2, 0x1b, 0x26, 0x59, 0xcc,
             0xc7, 0xe5, 0xbf, 0xa5, 0x77,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv = [
             0x5e, 0x5a, 0xe6, 0x51, 0xfd, 0x1e, 0x84, 0x95, 0xaf, 0x13, 0x50,
             0x8b,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key = [
             0xa8, 0xed, 0x82, 0xe6, 0x66, 0x4f, 0x86, 0x5a, 0xed, 0xf6, 0x10,
             0x69, 0x43, 0xf9, 0x5f, 0xb8,
@@ -626,11 +910,13 @@ mod tests {
 
         let aead = Algorithm::ChaCha20_Poly1305;
 
+        let version = crate::PROTOCOL_VERSION_V1;
+
         let mut pkt_key = [0; 32];
         let mut pkt_iv = [0; 12];
         let mut hdr_key = [0; 32];
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_pkt_key = [
             0xc6, 0xd9, 0x8f, 0xf3, 0x44, 0x1c, 0x3f, 0xe1, 0xb2, 0x18, 0x20,
             0x94, 0xf6, 0x9c, 0xaa, 0x2e, 0xd4, 0xb7, 0x16, 0xb6, 0x54, 0x88,
@@ -638,19 +924,27 @@ mod tests {
         ];
         assert_eq!(&pkt_key, &expected_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_pkt_iv = [
             0xe0, 0x45, 0x9b, 0x34, 0x74, 0xbd, 0xd0, 0xe4, 0x4a, 0x41, 0xc1,
             0x44,
         ];
         assert_eq!(&pkt_iv, &expected_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_hdr_key = [
             0x25, 0xa2, 0x82, 0xb9, 0xe8, 0x2f, 0x06, 0xf2, 0x1f, 0x48, 0x89,
             0x17, 0xa4, 0xfc, 0x8f, 0x1b, 0x73, 0x57, 0x36, 0x85, 0x60, 0x85,
             0x97, 0xd0, 0xef, 0xcb, 0x07, 0x6b, 0x0a, 0xb7, 0xa7, 0xa4,
         ];
         assert_eq!(&hdr_key, &expected_hdr_key);
+
+        let next_secret = derive_next_secret(aead, version, &secret).unwrap();
+        let expected_next_secret = [
+            0x12, 0x23, 0x50, 0x47, 0x55, 0x03, 0x6d, 0x55, 0x63, 0x42, 0xee,
+            0x93, 0x61, 0xd2, 0x53, 0x42, 0x1a, 0x82, 0x6c, 0x9e, 0xcd, 0xf3,
+            0xc7, 0x14, 0x86, 0x84, 0xb3, 0x6b, 0x71, 0x48, 0x81, 0xf9,
+        ];
+        assert_eq!(&next_secret, &expected_next_secret);
     }
 }
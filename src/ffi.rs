@@ -24,7 +24,13 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::convert::TryFrom;
 use std::ffi;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddrV4;
+use std::net::SocketAddrV6;
 use std::ptr;
 use std::slice;
 use std::sync::atomic;
@@ -37,6 +43,45 @@ use libc::c_int;
 use libc::c_void;
 use libc::size_t;
 use libc::ssize_t;
+use libc::timespec;
+
+#[cfg(not(windows))]
+use libc::in6_addr;
+#[cfg(not(windows))]
+use libc::in_addr;
+#[cfg(not(windows))]
+use libc::sa_family_t;
+#[cfg(not(windows))]
+use libc::sockaddr;
+#[cfg(not(windows))]
+use libc::sockaddr_in;
+#[cfg(not(windows))]
+use libc::sockaddr_in6;
+#[cfg(not(windows))]
+use libc::sockaddr_storage;
+#[cfg(not(windows))]
+use libc::socklen_t;
+#[cfg(not(windows))]
+use libc::AF_INET;
+#[cfg(not(windows))]
+use libc::AF_INET6;
+
+#[cfg(windows)]
+use winapi::shared::ws2def::ADDRESS_FAMILY as sa_family_t;
+#[cfg(windows)]
+use winapi::shared::ws2def::AF_INET;
+#[cfg(windows)]
+use winapi::shared::ws2def::AF_INET6;
+#[cfg(windows)]
+use winapi::shared::ws2def::SOCKADDR as sockaddr;
+#[cfg(windows)]
+use winapi::shared::ws2def::SOCKADDR_IN as sockaddr_in;
+#[cfg(windows)]
+use winapi::shared::ws2def::SOCKADDR_STORAGE_LH as sockaddr_storage;
+#[cfg(windows)]
+use winapi::shared::ws2ipdef::SOCKADDR_IN6_LH as sockaddr_in6;
+#[cfg(windows)]
+use winapi::um::ws2tcpip::socklen_t;
 
 use crate::*;
 
@@ -210,6 +255,11 @@ pub extern fn quiche_config_set_max_ack_delay(config: &mut Config, v: u64) {
     config.set_max_ack_delay(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_min_ack_delay(config: &mut Config, v: u64) {
+    config.set_min_ack_delay(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_disable_active_migration(
     config: &mut Config, v: bool,
@@ -217,6 +267,72 @@ pub extern fn quiche_config_set_disable_active_migration(
     config.set_disable_active_migration(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_active_connection_id_limit(
+    config: &mut Config, v: u64,
+) {
+    config.set_active_connection_id_limit(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_stateless_reset_token(
+    config: &mut Config, reset_token: *const u8,
+) {
+    let reset_token = if reset_token.is_null() {
+        None
+    } else {
+        let mut token = [0; 16];
+        token
+            .copy_from_slice(unsafe { slice::from_raw_parts(reset_token, 16) });
+        Some(u128::from_be_bytes(token))
+    };
+
+    config.set_stateless_reset_token(reset_token);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_preferred_address(
+    config: &mut Config, v4: *const sockaddr, v4_len: socklen_t,
+    v6: *const sockaddr, v6_len: socklen_t, cid: *const u8, cid_len: size_t,
+    reset_token: *const u8,
+) -> c_int {
+    let v4 = if v4.is_null() {
+        None
+    } else {
+        match std_addr_from_c(unsafe { &*v4 }, v4_len) {
+            Ok(SocketAddr::V4(addr)) => Some(addr),
+
+            _ => return Error::InvalidState.to_c() as c_int,
+        }
+    };
+
+    let v6 = if v6.is_null() {
+        None
+    } else {
+        match std_addr_from_c(unsafe { &*v6 }, v6_len) {
+            Ok(SocketAddr::V6(addr)) => Some(addr),
+
+            _ => return Error::InvalidState.to_c() as c_int,
+        }
+    };
+
+    let cid = unsafe { slice::from_raw_parts(cid, cid_len) };
+
+    let mut token = [0; 16];
+    token.copy_from_slice(unsafe { slice::from_raw_parts(reset_token, 16) });
+
+    match config.set_preferred_address(
+        v4,
+        v6,
+        cid,
+        u128::from_be_bytes(token),
+    ) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_cc_algorithm_name(
     config: &mut Config, name: *const c_char,
@@ -257,6 +373,40 @@ pub extern fn quiche_config_enable_hystart(config: &mut Config, v: bool) {
     config.enable_hystart(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_discover_pmtu(config: &mut Config, v: bool) {
+    config.discover_pmtu(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_ecn(config: &mut Config, v: bool) {
+    config.enable_ecn(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_pacing(config: &mut Config, v: bool) {
+    config.enable_pacing(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_packets_per_key(config: &mut Config, v: u64) {
+    config.set_max_packets_per_key(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_undecryptable_packets(
+    config: &mut Config, v: size_t,
+) {
+    config.set_max_undecryptable_packets(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_incremental_quantum(
+    config: &mut Config, v: size_t,
+) {
+    config.set_incremental_quantum(v);
+}
+
 #[no_mangle]
 #[cfg(feature = "quic-dgram")]
 pub extern fn quiche_config_set_dgram_frames_supported(
@@ -265,6 +415,13 @@ pub extern fn quiche_config_set_dgram_frames_supported(
     config.set_dgram_frames_supported(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_token_key(
+    config: &mut Config, key: &TokenKey,
+) {
+    config.set_token_key(key.clone());
+}
+
 #[no_mangle]
 pub extern fn quiche_config_free(config: *mut Config) {
     unsafe { Box::from_raw(config) };
@@ -338,7 +495,7 @@ pub extern fn quiche_header_info(
 #[no_mangle]
 pub extern fn quiche_accept(
     scid: *const u8, scid_len: size_t, odcid: *const u8, odcid_len: size_t,
-    config: &mut Config,
+    from: &sockaddr, from_len: socklen_t, config: &mut Config,
 ) -> *mut Connection {
     let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
 
@@ -348,7 +505,13 @@ pub extern fn quiche_accept(
         None
     };
 
-    match accept(scid, odcid, config) {
+    let from = match std_addr_from_c(from, from_len) {
+        Ok(v) => v,
+
+        Err(_) => return ptr::null_mut(),
+    };
+
+    match accept(scid, odcid, from, config) {
         Ok(c) => Box::into_raw(Pin::into_inner(c)),
 
         Err(_) => ptr::null_mut(),
@@ -358,7 +521,7 @@ pub extern fn quiche_accept(
 #[no_mangle]
 pub extern fn quiche_connect(
     server_name: *const c_char, scid: *const u8, scid_len: size_t,
-    config: &mut Config,
+    to: &sockaddr, to_len: socklen_t, config: &mut Config,
 ) -> *mut Connection {
     let server_name = if server_name.is_null() {
         None
@@ -368,7 +531,13 @@ pub extern fn quiche_connect(
 
     let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
 
-    match connect(server_name, scid, config) {
+    let to = match std_addr_from_c(to, to_len) {
+        Ok(v) => v,
+
+        Err(_) => return ptr::null_mut(),
+    };
+
+    match connect(server_name, scid, to, config) {
         Ok(c) => Box::into_raw(Pin::into_inner(c)),
 
         Err(_) => ptr::null_mut(),
@@ -415,10 +584,120 @@ pub extern fn quiche_retry(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_is_new_token(token: *const u8, token_len: size_t) -> bool {
+    let token = unsafe { slice::from_raw_parts(token, token_len) };
+
+    is_new_token(token)
+}
+
+#[no_mangle]
+pub extern fn quiche_token_key_new(
+    key: *const u8, key_len: size_t,
+) -> *mut TokenKey {
+    let key = unsafe { slice::from_raw_parts(key, key_len) };
+
+    match TokenKey::new(key) {
+        Ok(v) => Box::into_raw(Box::new(v)),
+
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_token_key_rotate(
+    token_key: &mut TokenKey, key: *const u8, key_len: size_t,
+) -> c_int {
+    let key = unsafe { slice::from_raw_parts(key, key_len) };
+
+    match token_key.rotate(key) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_token_key_mint_retry_token(
+    token_key: &TokenKey, from: &sockaddr, from_len: socklen_t,
+    odcid: *const u8, odcid_len: size_t, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    let from = match std_addr_from_c(from, from_len) {
+        Ok(v) => v,
+
+        Err(e) => return e.to_c(),
+    };
+
+    let odcid = unsafe { slice::from_raw_parts(odcid, odcid_len) };
+
+    let token = token_key.mint_retry_token(&from, odcid);
+
+    if out_len < token.len() {
+        return Error::BufferTooShort.to_c();
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, token.len()) };
+    out.copy_from_slice(&token);
+
+    token.len() as ssize_t
+}
+
+#[no_mangle]
+pub extern fn quiche_token_key_validate_retry_token(
+    token_key: &TokenKey, from: &sockaddr, from_len: socklen_t,
+    token: *const u8, token_len: size_t, odcid: *mut u8, odcid_len: size_t,
+) -> ssize_t {
+    let from = match std_addr_from_c(from, from_len) {
+        Ok(v) => v,
+
+        Err(e) => return e.to_c(),
+    };
+
+    let token = unsafe { slice::from_raw_parts(token, token_len) };
+
+    let v = match token_key.validate_retry_token(&from, token) {
+        Some(v) => v,
+
+        None => return Error::InvalidPacket.to_c(),
+    };
+
+    if odcid_len < v.len() {
+        return Error::BufferTooShort.to_c();
+    }
+
+    let odcid = unsafe { slice::from_raw_parts_mut(odcid, v.len()) };
+    odcid.copy_from_slice(&v);
+
+    v.len() as ssize_t
+}
+
+#[no_mangle]
+pub extern fn quiche_token_key_free(token_key: *mut TokenKey) {
+    unsafe { Box::from_raw(token_key) };
+}
+
+#[no_mangle]
+pub extern fn quiche_stateless_reset(
+    reset_token: *const u8, pkt_len: size_t, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    let mut token = [0; 16];
+    token.copy_from_slice(unsafe { slice::from_raw_parts(reset_token, 16) });
+    let reset_token = u128::from_be_bytes(token);
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    match stateless_reset(reset_token, pkt_len, out) {
+        Ok(v) => v as ssize_t,
+
+        Err(e) => e.to_c(),
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_new_with_tls(
     scid: *const u8, scid_len: size_t, odcid: *const u8, odcid_len: size_t,
-    config: &mut Config, ssl: *mut c_void, is_server: bool,
+    peer: &sockaddr, peer_len: socklen_t, config: &mut Config, ssl: *mut c_void,
+    is_server: bool,
 ) -> *mut Connection {
     let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
 
@@ -428,9 +707,15 @@ pub extern fn quiche_conn_new_with_tls(
         None
     };
 
+    let peer = match std_addr_from_c(peer, peer_len) {
+        Ok(v) => v,
+
+        Err(_) => return ptr::null_mut(),
+    };
+
     let tls = unsafe { tls::Handshake::from_ptr(ssl) };
 
-    match Connection::with_tls(scid, odcid, config, tls, is_server) {
+    match Connection::with_tls(scid, odcid, peer, config, tls, is_server) {
         Ok(c) => Box::into_raw(Pin::into_inner(c)),
 
         Err(_) => ptr::null_mut(),
@@ -518,9 +803,77 @@ pub extern fn quiche_conn_set_qlog_fd(
     );
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_set_session(
+    conn: &mut Connection, buf: *const u8, buf_len: size_t,
+) -> c_int {
+    let buf = unsafe { slice::from_raw_parts(buf, buf_len) };
+
+    match conn.set_session(buf) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_token(
+    conn: &mut Connection, buf: *const u8, buf_len: size_t,
+) -> c_int {
+    let buf = unsafe { slice::from_raw_parts(buf, buf_len) };
+
+    match conn.set_token(buf) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[repr(C)]
+pub struct RecvInfo<'a> {
+    from: &'a sockaddr,
+    from_len: socklen_t,
+    ecn: u8,
+}
+
+impl<'a> TryFrom<&RecvInfo<'a>> for crate::RecvInfo {
+    type Error = Error;
+
+    fn try_from(info: &RecvInfo) -> Result<crate::RecvInfo> {
+        Ok(crate::RecvInfo {
+            from: std_addr_from_c(info.from, info.from_len)?,
+            ecn: info.ecn.into(),
+        })
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_recv(
-    conn: &mut Connection, buf: *mut u8, buf_len: size_t,
+    conn: &mut Connection, buf: *mut u8, buf_len: size_t, info: &RecvInfo,
+) -> ssize_t {
+    if buf_len > <ssize_t>::max_value() as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let buf = unsafe { slice::from_raw_parts_mut(buf, buf_len) };
+
+    let info = match crate::RecvInfo::try_from(info) {
+        Ok(v) => v,
+
+        Err(e) => return e.to_c(),
+    };
+
+    match conn.recv(buf, info) {
+        Ok(v) => v as ssize_t,
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_recv_batch(
+    conn: &mut Connection, buf: *mut u8, buf_len: size_t, segment_size: size_t,
+    info: &RecvInfo,
 ) -> ssize_t {
     if buf_len > <ssize_t>::max_value() as usize {
         panic!("The provided buffer is too large");
@@ -528,16 +881,31 @@ pub extern fn quiche_conn_recv(
 
     let buf = unsafe { slice::from_raw_parts_mut(buf, buf_len) };
 
-    match conn.recv(buf) {
+    let info = match crate::RecvInfo::try_from(info) {
+        Ok(v) => v,
+
+        Err(e) => return e.to_c(),
+    };
+
+    match conn.recv_batch(buf, segment_size, info) {
         Ok(v) => v as ssize_t,
 
         Err(e) => e.to_c(),
     }
 }
 
+#[repr(C)]
+pub struct SendInfo {
+    to: sockaddr_storage,
+    to_len: socklen_t,
+    ecn: u8,
+    at: timespec,
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_send(
     conn: &mut Connection, out: *mut u8, out_len: size_t,
+    out_info: &mut SendInfo,
 ) -> ssize_t {
     if out_len > <ssize_t>::max_value() as usize {
         panic!("The provided buffer is too large");
@@ -546,7 +914,49 @@ pub extern fn quiche_conn_send(
     let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
 
     match conn.send(out) {
-        Ok(v) => v as ssize_t,
+        Ok((v, info)) => {
+            out_info.to_len = std_addr_to_c(&info.to, &mut out_info.to);
+            out_info.ecn = info.ecn as u8;
+            std_time_to_c(&info.at, &mut out_info.at);
+
+            v as ssize_t
+        },
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[repr(C)]
+pub struct SendBatchInfo {
+    to: sockaddr_storage,
+    to_len: socklen_t,
+    ecn: u8,
+    at: timespec,
+    segment_size: size_t,
+    segments: size_t,
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_send_batch(
+    conn: &mut Connection, out: *mut u8, out_len: size_t, max_segments: size_t,
+    out_info: &mut SendBatchInfo,
+) -> ssize_t {
+    if out_len > <ssize_t>::max_value() as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    match conn.send_batch(out, max_segments) {
+        Ok((v, info)) => {
+            out_info.to_len = std_addr_to_c(&info.to, &mut out_info.to);
+            out_info.ecn = info.ecn as u8;
+            std_time_to_c(&info.at, &mut out_info.at);
+            out_info.segment_size = info.segment_size;
+            out_info.segments = info.segments;
+
+            v as ssize_t
+        },
 
         Err(e) => e.to_c(),
     }
@@ -703,6 +1113,20 @@ pub extern fn quiche_conn_application_proto(
     *out_len = proto.len();
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_session(
+    conn: &mut Connection, out: &mut *const u8, out_len: &mut size_t,
+) {
+    match &conn.session {
+        Some(session) => {
+            *out = session.as_ptr();
+            *out_len = session.len();
+        },
+
+        None => *out_len = 0,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_is_established(conn: &mut Connection) -> bool {
     conn.is_established()
@@ -713,11 +1137,115 @@ pub extern fn quiche_conn_is_in_early_data(conn: &mut Connection) -> bool {
     conn.is_in_early_data()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_initiate_key_update(conn: &mut Connection) -> c_int {
+    match conn.initiate_key_update() {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_send_new_token(conn: &mut Connection) -> c_int {
+    match conn.send_new_token() {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_new_token(
+    conn: &mut Connection, out: &mut *const u8, out_len: &mut size_t,
+) {
+    match &conn.new_token {
+        Some(token) => {
+            *out = token.as_ptr();
+            *out_len = token.len();
+        },
+
+        None => *out_len = 0,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_is_closed(conn: &mut Connection) -> bool {
     conn.is_closed()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_is_reset(conn: &mut Connection) -> bool {
+    conn.is_reset()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_local_error(
+    conn: &mut Connection, error_code: &mut u64,
+) -> bool {
+    match conn.local_error() {
+        Some(v) => {
+            *error_code = v;
+            true
+        },
+
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_new_source_cid(
+    conn: &mut Connection, scid: *const u8, scid_len: size_t,
+    reset_token: *const u8, retire_if_needed: bool, seq: &mut u64,
+) -> c_int {
+    let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
+
+    let mut token = [0; 16];
+    token.copy_from_slice(unsafe { slice::from_raw_parts(reset_token, 16) });
+    let reset_token = u128::from_be_bytes(token);
+
+    match conn.new_source_cid(scid, reset_token, retire_if_needed) {
+        Ok(v) => {
+            *seq = v;
+
+            0
+        },
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_source_cids_left(conn: &Connection) -> size_t {
+    conn.source_cids_left()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_retired_scid_next(
+    conn: &mut Connection, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    if out_len < MAX_CONN_ID_LEN {
+        return Error::BufferTooShort.to_c();
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    match conn.retired_scid_next() {
+        Some(v) => {
+            out[..v.len()].copy_from_slice(&v);
+
+            v.len() as ssize_t
+        },
+
+        None => Error::Done.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_available_dcids(conn: &Connection) -> size_t {
+    conn.available_dcids()
+}
+
 #[no_mangle]
 pub extern fn quiche_stream_iter_next(
     iter: &mut StreamIter, stream_id: *mut u64,
@@ -740,9 +1268,14 @@ pub struct Stats {
     pub recv: usize,
     pub sent: usize,
     pub lost: usize,
+    pub spurious_lost: usize,
     pub rtt: u64,
     pub cwnd: usize,
     pub delivery_rate: u64,
+    pub undecryptable_buffered: usize,
+    pub undecryptable_dropped: usize,
+    pub pmtu: usize,
+    pub pacing_rate: u64,
 }
 
 #[no_mangle]
@@ -752,9 +1285,14 @@ pub extern fn quiche_conn_stats(conn: &Connection, out: &mut Stats) {
     out.recv = stats.recv;
     out.sent = stats.sent;
     out.lost = stats.lost;
+    out.spurious_lost = stats.spurious_lost;
     out.rtt = stats.rtt.as_nanos() as u64;
     out.cwnd = stats.cwnd;
     out.delivery_rate = stats.delivery_rate;
+    out.undecryptable_buffered = stats.undecryptable_buffered;
+    out.undecryptable_dropped = stats.undecryptable_dropped;
+    out.pmtu = stats.pmtu;
+    out.pacing_rate = stats.pacing_rate;
 }
 
 #[no_mangle]
@@ -822,3 +1360,144 @@ pub extern fn quiche_conn_dgram_purge_outgoing(
 pub extern fn quiche_conn_free(conn: *mut Connection) {
     unsafe { Box::from_raw(conn) };
 }
+
+fn std_addr_from_c(
+    addr: &sockaddr, addr_len: socklen_t,
+) -> Result<SocketAddr> {
+    match addr.sa_family as i32 {
+        AF_INET => {
+            if (addr_len as usize) < mem::size_of::<sockaddr_in>() {
+                return Err(Error::InvalidState);
+            }
+
+            let sin = unsafe { &*(addr as *const _ as *const sockaddr_in) };
+
+            let ip = Ipv4Addr::from(in_addr_to_u32(sin));
+            let port = u16::from_be(sin.sin_port);
+
+            Ok(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+        },
+
+        AF_INET6 => {
+            if (addr_len as usize) < mem::size_of::<sockaddr_in6>() {
+                return Err(Error::InvalidState);
+            }
+
+            let sin6 = unsafe { &*(addr as *const _ as *const sockaddr_in6) };
+
+            let ip = Ipv6Addr::from(in6_addr_to_octets(sin6));
+            let port = u16::from_be(sin6.sin6_port);
+
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                ip,
+                port,
+                sin6.sin6_flowinfo,
+                in6_scope_id(sin6),
+            )))
+        },
+
+        _ => Err(Error::InvalidState),
+    }
+}
+
+fn std_addr_to_c(addr: &SocketAddr, out: &mut sockaddr_storage) -> socklen_t {
+    *out = unsafe { mem::zeroed() };
+
+    match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(out as *mut _ as *mut sockaddr_in) };
+
+            sin.sin_family = AF_INET as sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            set_in_addr(sin, u32::from(*addr.ip()));
+
+            mem::size_of::<sockaddr_in>() as socklen_t
+        },
+
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(out as *mut _ as *mut sockaddr_in6) };
+
+            sin6.sin6_family = AF_INET6 as sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            set_in6_addr(sin6, addr.ip().octets(), addr.scope_id());
+
+            mem::size_of::<sockaddr_in6>() as socklen_t
+        },
+    }
+}
+
+#[cfg(not(windows))]
+fn std_time_to_c(time: &std::time::Instant, out: &mut timespec) {
+    // `Instant` is opaque, so the time is converted relative to the current
+    // value of the monotonic clock, which `Instant` is based on.
+    let delay = time.saturating_duration_since(std::time::Instant::now());
+
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, out);
+    }
+
+    let nsec = out.tv_nsec as u64 + u64::from(delay.subsec_nanos());
+
+    out.tv_sec += (delay.as_secs() + nsec / 1_000_000_000) as libc::time_t;
+    out.tv_nsec = (nsec % 1_000_000_000) as libc::c_long;
+}
+
+#[cfg(windows)]
+fn std_time_to_c(_time: &std::time::Instant, out: &mut timespec) {
+    *out = unsafe { mem::zeroed() };
+}
+
+#[cfg(not(windows))]
+fn in_addr_to_u32(sin: &sockaddr_in) -> u32 {
+    u32::from_be(sin.sin_addr.s_addr)
+}
+
+#[cfg(not(windows))]
+fn in6_addr_to_octets(sin6: &sockaddr_in6) -> [u8; 16] {
+    sin6.sin6_addr.s6_addr
+}
+
+#[cfg(not(windows))]
+fn in6_scope_id(sin6: &sockaddr_in6) -> u32 {
+    sin6.sin6_scope_id
+}
+
+#[cfg(not(windows))]
+fn set_in_addr(sin: &mut sockaddr_in, ip: u32) {
+    sin.sin_addr = in_addr { s_addr: ip.to_be() };
+}
+
+#[cfg(not(windows))]
+fn set_in6_addr(sin6: &mut sockaddr_in6, ip: [u8; 16], scope_id: u32) {
+    sin6.sin6_addr = in6_addr { s6_addr: ip };
+    sin6.sin6_scope_id = scope_id;
+}
+
+#[cfg(windows)]
+fn in_addr_to_u32(sin: &sockaddr_in) -> u32 {
+    u32::from_be(unsafe { *sin.sin_addr.S_un.S_addr() })
+}
+
+#[cfg(windows)]
+fn in6_addr_to_octets(sin6: &sockaddr_in6) -> [u8; 16] {
+    unsafe { *sin6.sin6_addr.u.Byte() }
+}
+
+#[cfg(windows)]
+fn in6_scope_id(sin6: &sockaddr_in6) -> u32 {
+    unsafe { *sin6.u.sin6_scope_id() }
+}
+
+#[cfg(windows)]
+fn set_in_addr(sin: &mut sockaddr_in, ip: u32) {
+    unsafe { *sin.sin_addr.S_un.S_addr_mut() = ip.to_be() };
+}
+
+#[cfg(windows)]
+fn set_in6_addr(sin6: &mut sockaddr_in6, ip: [u8; 16], scope_id: u32) {
+    unsafe {
+        *sin6.sin6_addr.u.Byte_mut() = ip;
+        *sin6.u.sin6_scope_id_mut() = scope_id;
+    }
+}
@@ -39,6 +39,13 @@ pub const MAX_STREAM_SIZE: u64 = 1 << 62;
 #[cfg(feature = "quic-dgram")]
 pub const MAX_DGRAM_OVERHEAD: usize = 8;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EcnCounts {
+    pub ect0_count: u64,
+    pub ect1_count: u64,
+    pub ecn_ce_count: u64,
+}
+
 #[derive(Clone, PartialEq)]
 pub enum Frame {
     Padding {
@@ -50,6 +57,7 @@ pub enum Frame {
     ACK {
         ack_delay: u64,
         ranges: ranges::RangeSet,
+        ecn_counts: Option<EcnCounts>,
     },
 
     ResetStream {
@@ -142,6 +150,15 @@ pub enum Frame {
 
     HandshakeDone,
 
+    AckFrequency {
+        seq_num: u64,
+        ack_eliciting_threshold: u64,
+        request_max_ack_delay: u64,
+        reordering_threshold: u64,
+    },
+
+    ImmediateAck,
+
     #[cfg(feature = "quic-dgram")]
     Datagram {
         data: Vec<u8>,
@@ -171,7 +188,7 @@ impl Frame {
 
             0x01 => Frame::Ping,
 
-            0x02 => parse_ack_frame(frame_type, b)?,
+            0x02..=0x03 => parse_ack_frame(frame_type, b)?,
 
             0x04 => Frame::ResetStream {
                 stream_id: b.get_varint()?,
@@ -264,6 +281,15 @@ impl Frame {
 
             0x1e => Frame::HandshakeDone,
 
+            0x1f => Frame::ImmediateAck,
+
+            0xaf => Frame::AckFrequency {
+                seq_num: b.get_varint()?,
+                ack_eliciting_threshold: b.get_varint()?,
+                request_max_ack_delay: b.get_varint()?,
+                reordering_threshold: b.get_varint()?,
+            },
+
             #[cfg(feature = "quic-dgram")]
             0x30 | 0x31 => parse_datagram_frame(frame_type, b)?,
 
@@ -284,6 +310,11 @@ impl Frame {
             (packet::Type::ZeroRTT, Frame::RetireConnectionId { .. }) => false,
             (packet::Type::ZeroRTT, Frame::ConnectionClose { .. }) => false,
 
+            // ACK_FREQUENCY and IMMEDIATE_ACK can only be sent once the
+            // peer's transport parameters are known.
+            (packet::Type::ZeroRTT, Frame::AckFrequency { .. }) => false,
+            (packet::Type::ZeroRTT, Frame::ImmediateAck) => false,
+
             // ACK, CRYPTO and CONNECTION_CLOSE can be sent on all other packet
             // types.
             (_, Frame::ACK { .. }) => true,
@@ -323,8 +354,16 @@ impl Frame {
                 b.put_varint(0x01)?;
             },
 
-            Frame::ACK { ack_delay, ranges } => {
-                b.put_varint(0x02)?;
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                if ecn_counts.is_none() {
+                    b.put_varint(0x02)?;
+                } else {
+                    b.put_varint(0x03)?;
+                }
 
                 let mut it = ranges.iter().rev();
 
@@ -347,6 +386,12 @@ impl Frame {
 
                     smallest_ack = block.start;
                 }
+
+                if let Some(ecn) = ecn_counts {
+                    b.put_varint(ecn.ect0_count)?;
+                    b.put_varint(ecn.ect1_count)?;
+                    b.put_varint(ecn.ecn_ce_count)?;
+                }
             },
 
             Frame::ResetStream {
@@ -515,6 +560,24 @@ impl Frame {
                 b.put_varint(0x1e)?;
             },
 
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                b.put_varint(0xaf)?;
+
+                b.put_varint(*seq_num)?;
+                b.put_varint(*ack_eliciting_threshold)?;
+                b.put_varint(*request_max_ack_delay)?;
+                b.put_varint(*reordering_threshold)?;
+            },
+
+            Frame::ImmediateAck => {
+                b.put_varint(0x1f)?;
+            },
+
             #[cfg(feature = "quic-dgram")]
             Frame::Datagram { data } => {
                 let mut ty: u8 = 0x30;
@@ -538,7 +601,11 @@ impl Frame {
 
             Frame::Ping => 1,
 
-            Frame::ACK { ack_delay, ranges } => {
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
                 let mut it = ranges.iter().rev();
 
                 let first = it.next().unwrap();
@@ -562,6 +629,12 @@ impl Frame {
                     smallest_ack = block.start;
                 }
 
+                if let Some(ecn) = ecn_counts {
+                    len += octets::varint_len(ecn.ect0_count) +
+                        octets::varint_len(ecn.ect1_count) +
+                        octets::varint_len(ecn.ecn_ce_count);
+                }
+
                 len
             },
 
@@ -701,6 +774,23 @@ impl Frame {
                 1 // frame type
             },
 
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                2 + // frame type
+                octets::varint_len(*seq_num) + // seq_num
+                octets::varint_len(*ack_eliciting_threshold) + // threshold
+                octets::varint_len(*request_max_ack_delay) + // max_ack_delay
+                octets::varint_len(*reordering_threshold) // reordering
+            },
+
+            Frame::ImmediateAck => {
+                1 // frame type
+            },
+
             #[cfg(feature = "quic-dgram")]
             Frame::Datagram { data } => {
                 1 + // frame type
@@ -721,9 +811,23 @@ impl Frame {
         }
     }
 
+    pub fn probing(&self) -> bool {
+        match self {
+            Frame::Padding { .. } |
+            Frame::NewConnectionId { .. } |
+            Frame::PathChallenge { .. } |
+            Frame::PathResponse { .. } => true,
+
+            _ => false,
+        }
+    }
+
     pub fn retransmittable(&self) -> bool {
-        #[allow(clippy::match_single_binding)]
         match self {
+            // Path validation frames are never retransmitted, new challenges
+            // are generated instead.
+            Frame::PathChallenge { .. } | Frame::PathResponse { .. } => false,
+
             #[cfg(feature = "quic-dgram")]
             Frame::Datagram { .. } => false,
 
@@ -738,15 +842,19 @@ impl Frame {
 
             Frame::Ping { .. } => qlog::QuicFrame::ping(),
 
-            Frame::ACK { ack_delay, ranges } => {
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
                 let ack_ranges =
                     ranges.iter().map(|r| (r.start, r.end - 1)).collect();
                 qlog::QuicFrame::ack(
                     Some(ack_delay.to_string()),
                     Some(ack_ranges),
-                    None,
-                    None,
-                    None,
+                    ecn_counts.map(|ecn| ecn.ect1_count.to_string()),
+                    ecn_counts.map(|ecn| ecn.ect0_count.to_string()),
+                    ecn_counts.map(|ecn| ecn.ecn_ce_count.to_string()),
                 )
             },
 
@@ -877,6 +985,10 @@ impl Frame {
 
             Frame::HandshakeDone => qlog::QuicFrame::handshake_done(),
 
+            Frame::AckFrequency { .. } => qlog::QuicFrame::unknown(0xaf),
+
+            Frame::ImmediateAck => qlog::QuicFrame::unknown(0x1f),
+
             #[cfg(feature = "quic-dgram")]
             Frame::Datagram { .. } => qlog::QuicFrame::unknown(0x30),
         }
@@ -894,8 +1006,20 @@ impl std::fmt::Debug for Frame {
                 write!(f, "PING")?;
             },
 
-            Frame::ACK { ack_delay, ranges } => {
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
                 write!(f, "ACK delay={} blocks={:?}", ack_delay, ranges)?;
+
+                if let Some(ecn) = ecn_counts {
+                    write!(
+                        f,
+                        " ect0={} ect1={} ce={}",
+                        ecn.ect0_count, ecn.ect1_count, ecn.ecn_ce_count
+                    )?;
+                }
             },
 
             Frame::ResetStream {
@@ -1016,6 +1140,27 @@ impl std::fmt::Debug for Frame {
                 write!(f, "HANDSHAKE_DONE")?;
             },
 
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                write!(
+                    f,
+                    "ACK_FREQUENCY seq_num={} threshold={} max_ack_delay={} \
+                     reordering={}",
+                    seq_num,
+                    ack_eliciting_threshold,
+                    request_max_ack_delay,
+                    reordering_threshold
+                )?;
+            },
+
+            Frame::ImmediateAck => {
+                write!(f, "IMMEDIATE_ACK")?;
+            },
+
             #[cfg(feature = "quic-dgram")]
             Frame::Datagram { data } => {
                 write!(f, "DATAGRAM len={}", data.len(),)?;
@@ -1026,7 +1171,9 @@ impl std::fmt::Debug for Frame {
     }
 }
 
-fn parse_ack_frame(_ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+    let first = ty as u8;
+
     let largest_ack = b.get_varint()?;
     let ack_delay = b.get_varint()?;
     let block_count = b.get_varint()?;
@@ -1063,7 +1210,23 @@ fn parse_ack_frame(_ty: u64, b: &mut octets::Octets) -> Result<Frame> {
         ranges.insert(smallest_ack..largest_ack + 1);
     }
 
-    Ok(Frame::ACK { ack_delay, ranges })
+    let ecn_counts = if first & 0x01 != 0 {
+        let ecn = EcnCounts {
+            ect0_count: b.get_varint()?,
+            ect1_count: b.get_varint()?,
+            ecn_ce_count: b.get_varint()?,
+        };
+
+        Some(ecn)
+    } else {
+        None
+    };
+
+    Ok(Frame::ACK {
+        ack_delay,
+        ranges,
+        ecn_counts,
+    })
 }
 
 fn parse_stream_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
@@ -1182,6 +1345,7 @@ mod tests {
         let frame = Frame::ACK {
             ack_delay: 874_656_534,
             ranges,
+            ecn_counts: None,
         };
 
         let wire_len = {
@@ -1204,6 +1368,49 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
+    #[test]
+    fn ack_ecn() {
+        let mut d = [42; 128];
+
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(4..7);
+        ranges.insert(9..12);
+        ranges.insert(15..19);
+        ranges.insert(3000..5000);
+
+        let ecn_counts = Some(EcnCounts {
+            ect0_count: 100,
+            ect1_count: 200,
+            ecn_ce_count: 300,
+        });
+
+        let frame = Frame::ACK {
+            ack_delay: 874_656_534,
+            ranges,
+            ecn_counts,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 23);
+        assert_eq!(d[0], 0x03);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
+    }
+
     #[test]
     fn reset_stream() {
         let mut d = [42; 128];
@@ -1789,6 +1996,64 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
+    #[test]
+    fn ack_frequency() {
+        let mut d = [42; 128];
+
+        let frame = Frame::AckFrequency {
+            seq_num: 1,
+            ack_eliciting_threshold: 9,
+            request_max_ack_delay: 25000,
+            reordering_threshold: 1,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 9);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn immediate_ack() {
+        let mut d = [42; 128];
+
+        let frame = Frame::ImmediateAck;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 1);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
     #[test]
     #[cfg(feature = "quic-dgram")]
     fn datagram() {
@@ -109,6 +109,8 @@ pub extern fn quiche_h3_event_type(ev: &h3::Event) -> u32 {
         h3::Event::Data { .. } => 1,
 
         h3::Event::Finished { .. } => 2,
+
+        h3::Event::Reset { .. } => 3,
     }
 }
 
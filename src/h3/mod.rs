@@ -60,7 +60,8 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::connect(None, &scid, &mut config).unwrap();
+//! # let to = "127.0.0.1:4433".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, to, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! let h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! # Ok::<(), quiche::h3::Error>(())
@@ -75,7 +76,8 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::connect(None, &scid, &mut config).unwrap();
+//! # let to = "127.0.0.1:4433".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, to, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! let req = vec![
@@ -96,7 +98,8 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::connect(None, &scid, &mut config).unwrap();
+//! # let to = "127.0.0.1:4433".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, to, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! let req = vec![
@@ -124,7 +127,8 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::accept(&scid, None, &mut config).unwrap();
+//! # let from = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, from, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! loop {
@@ -158,6 +162,10 @@
 //!             // Peer terminated stream, handle it.
 //!         },
 //!
+//!         Ok((stream_id, quiche::h3::Event::Reset(err))) => {
+//!             // Peer reset the stream, handle it.
+//!         },
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -177,7 +185,8 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::connect(None, &scid, &mut config).unwrap();
+//! # let to = "127.0.0.1:4433".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, to, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! loop {
@@ -203,6 +212,10 @@
 //!             // Peer terminated stream, handle it.
 //!         }
 //!
+//!         Ok((stream_id, quiche::h3::Event::Reset(err))) => {
+//!             // Peer reset the stream, handle it.
+//!         },
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -258,7 +271,7 @@ use crate::octets;
 ///
 /// [`Config::set_application_protos()`]:
 /// ../struct.Config.html#method.set_application_protos
-pub const APPLICATION_PROTOCOL: &[u8] = b"\x05h3-29\x05h3-28\x05h3-27";
+pub const APPLICATION_PROTOCOL: &[u8] = b"\x02h3\x05h3-29\x05h3-28\x05h3-27";
 
 // The offset used when converting HTTP/3 urgency to quiche urgency.
 const PRIORITY_URGENCY_OFFSET: u8 = 124;
@@ -474,6 +487,12 @@ pub enum Event {
 
     /// Stream was closed,
     Finished,
+
+    /// Stream was reset by the peer with the given error code.
+    ///
+    /// No more events will be reported for the stream, and any data not yet
+    /// read by the application is discarded.
+    Reset(u64),
 }
 
 #[cfg(feature = "h3-dgram")]
@@ -519,6 +538,8 @@ pub struct Connection {
 
     finished_streams: VecDeque<u64>,
 
+    reset_streams: VecDeque<(u64, u64)>,
+
     frames_greased: bool,
 }
 
@@ -567,6 +588,8 @@ impl Connection {
 
             finished_streams: VecDeque::new(),
 
+            reset_streams: VecDeque::new(),
+
             frames_greased: false,
         })
     }
@@ -918,7 +941,20 @@ impl Connection {
             return Err(Error::Done);
         }
 
-        let read = stream.try_consume_data(conn, out)?;
+        let read = match stream.try_consume_data(conn, out) {
+            Ok(v) => v,
+
+            // The peer reset the stream while the application was reading the
+            // body, so report it on the next call to `poll()`.
+            Err(Error::TransportError(super::Error::StreamReset(e))) => {
+                self.streams.remove(&stream_id);
+                self.reset_streams.push_back((stream_id, e));
+
+                return Err(Error::Done);
+            },
+
+            Err(e) => return Err(e),
+        };
 
         // While body is being received, the stream is marked as finished only
         // when all data is read by the application.
@@ -962,6 +998,11 @@ impl Connection {
             self.process_control_stream(conn, stream_id)?;
         }
 
+        // Process reset streams list.
+        if let Some((reset, e)) = self.reset_streams.pop_front() {
+            return Ok((reset, Event::Reset(e)));
+        }
+
         // Process finished streams list.
         if let Some(finished) = self.finished_streams.pop_front() {
             return Ok((finished, Event::Finished));
@@ -976,6 +1017,19 @@ impl Connection {
 
                 Err(Error::Done) => None,
 
+                // Resets of request streams are reported to the application,
+                // while other unidirectional streams are simply forgotten, as
+                // resets of critical streams are handled above.
+                Err(Error::TransportError(super::Error::StreamReset(e))) => {
+                    self.streams.remove(&s);
+
+                    if crate::stream::is_bidi(s) {
+                        return Ok((s, Event::Reset(e)));
+                    }
+
+                    continue;
+                },
+
                 Err(e) => return Err(e),
             };
 
@@ -1198,6 +1252,16 @@ impl Connection {
 
             Err(Error::Done) => (),
 
+            Err(Error::TransportError(super::Error::StreamReset(_))) => {
+                conn.close(
+                    true,
+                    Error::ClosedCriticalStream.to_wire(),
+                    b"Critical stream reset.",
+                )?;
+
+                return Err(Error::ClosedCriticalStream);
+            },
+
             Err(e) => return Err(e),
         };
 
@@ -1430,7 +1494,11 @@ impl Connection {
 
                 stream::State::Drain => {
                     // Discard incoming data on the stream.
-                    conn.stream_shutdown(stream_id, crate::Shutdown::Read, 0)?;
+                    conn.stream_shutdown(
+                        stream_id,
+                        crate::Shutdown::Read,
+                        Error::StreamCreationError.to_wire(),
+                    )?;
 
                     break;
                 },
@@ -2809,6 +2877,71 @@ mod tests {
         assert_eq!(s.poll_server(), Err(Error::ExcessiveLoad));
     }
 
+    #[test]
+    /// Client resets a request stream before the server reads the headers.
+    fn reset_request_stream() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, _) = s.send_request(false).unwrap();
+
+        s.pipe
+            .client
+            .stream_shutdown(stream, crate::Shutdown::Write, 42)
+            .unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // The connection is still usable for other requests.
+        let (stream, req) = s.send_request(true).unwrap();
+        assert_eq!(stream, 4);
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Client resets a request stream while the server reads the body.
+    fn reset_request_stream_body() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        s.send_body_client(stream, false).unwrap();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+
+        s.pipe
+            .client
+            .stream_shutdown(stream, crate::Shutdown::Write, 42)
+            .unwrap();
+
+        s.advance().ok();
+
+        let mut recv_buf = vec![0; 10];
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
+
     #[test]
     /// Tests that Error::TransportError contains a transport error.
     fn transport_error() {
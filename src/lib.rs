@@ -55,11 +55,13 @@
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let server_name = "quic.tech";
 //! # let scid = [0xba; 16];
+//! # let to = "127.0.0.1:4433".parse().unwrap();
+//! # let from = "127.0.0.1:1234".parse().unwrap();
 //! // Client connection.
-//! let conn = quiche::connect(Some(&server_name), &scid, &mut config)?;
+//! let conn = quiche::connect(Some(&server_name), &scid, to, &mut config)?;
 //!
 //! // Server connection.
-//! let conn = quiche::accept(&scid, None, &mut config)?;
+//! let conn = quiche::accept(&scid, None, from, &mut config)?;
 //! # Ok::<(), quiche::Error>(())
 //! ```
 //!
@@ -73,11 +75,17 @@
 //! # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::accept(&scid, None, &mut config)?;
+//! # let from = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
 //! loop {
-//!     let read = socket.recv(&mut buf).unwrap();
+//!     let (read, from) = socket.recv_from(&mut buf).unwrap();
 //!
-//!     let read = match conn.recv(&mut buf[..read]) {
+//!     let recv_info = quiche::RecvInfo {
+//!         from,
+//!         ecn: quiche::Ecn::NotEct,
+//!     };
+//!
+//!     let read = match conn.recv(&mut buf[..read], recv_info) {
 //!         Ok(v) => v,
 //!
 //!         Err(quiche::Error::Done) => {
@@ -97,16 +105,17 @@
 //! ## Generating outgoing packets
 //!
 //! Outgoing packet are generated using the connection's [`send()`] method
-//! instead:
+//! instead, which also returns the address each packet should be sent to:
 //!
 //! ```no_run
 //! # let mut out = [0; 512];
 //! # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::accept(&scid, None, &mut config)?;
+//! # let from = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
 //! loop {
-//!     let write = match conn.send(&mut out) {
+//!     let (write, send_info) = match conn.send(&mut out) {
 //!         Ok(v) => v,
 //!
 //!         Err(quiche::Error::Done) => {
@@ -120,7 +129,7 @@
 //!         },
 //!     };
 //!
-//!     socket.send(&out[..write]).unwrap();
+//!     socket.send_to(&out[..write], &send_info.to).unwrap();
 //! }
 //! # Ok::<(), quiche::Error>(())
 //! ```
@@ -132,7 +141,8 @@
 //! ```
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::accept(&scid, None, &mut config)?;
+//! # let from = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
 //! let timeout = conn.timeout();
 //! # Ok::<(), quiche::Error>(())
 //! ```
@@ -147,13 +157,14 @@
 //! # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::accept(&scid, None, &mut config)?;
+//! # let from = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
 //! // Timeout expired, handle it.
 //! conn.on_timeout();
 //!
 //! // Send more packets as needed after timeout.
 //! loop {
-//!     let write = match conn.send(&mut out) {
+//!     let (write, send_info) = match conn.send(&mut out) {
 //!         Ok(v) => v,
 //!
 //!         Err(quiche::Error::Done) => {
@@ -167,7 +178,7 @@
 //!         },
 //!     };
 //!
-//!     socket.send(&out[..write]).unwrap();
+//!     socket.send_to(&out[..write], &send_info.to).unwrap();
 //! }
 //! # Ok::<(), quiche::Error>(())
 //! ```
@@ -182,7 +193,8 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::accept(&scid, None, &mut config)?;
+//! # let from = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
 //! if conn.is_established() {
 //!     // Handshake completed, send some data on stream 0.
 //!     conn.stream_send(0, b"hello", true)?;
@@ -201,7 +213,8 @@
 //! # let mut buf = [0; 512];
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = [0xba; 16];
-//! # let mut conn = quiche::accept(&scid, None, &mut config)?;
+//! # let from = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
 //! if conn.is_established() {
 //!     // Iterate over readable streams.
 //!     for stream_id in conn.readable() {
@@ -254,10 +267,16 @@
 //! config.set_cc_algorithm_name("reno").unwrap();
 //! ```
 //!
+//! A custom congestion controller can also be used instead, by implementing
+//! the [`CongestionControl`] trait and registering it with
+//! [`set_custom_cc()`].
+//!
 //! Note that the CC algorithm should be configured before calling [`connect()`]
 //! or [`accept()`]. Otherwise the connection will use a default CC algorithm.
 //!
 //! [`CongestionControlAlgorithm`]: enum.CongestionControlAlgorithm.html
+//! [`CongestionControl`]: trait.CongestionControl.html
+//! [`set_custom_cc()`]: struct.Config.html#method.set_custom_cc
 
 #![allow(improper_ctypes)]
 #![warn(missing_docs)]
@@ -268,11 +287,27 @@ extern crate log;
 use std::cmp;
 use std::time;
 
+use std::collections::VecDeque;
+
+use std::convert::TryFrom;
+
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::SocketAddrV4;
+use std::net::SocketAddrV6;
+
 use std::pin::Pin;
 use std::str::FromStr;
 
 /// The current QUIC wire version.
-pub const PROTOCOL_VERSION: u32 = PROTOCOL_VERSION_DRAFT29;
+pub const PROTOCOL_VERSION: u32 = PROTOCOL_VERSION_V1;
+
+/// The QUIC version 1 wire version, as defined in RFC 9000.
+pub const PROTOCOL_VERSION_V1: u32 = 0x0000_0001;
+
+/// The QUIC version 2 wire version, as defined in RFC 9369.
+pub const PROTOCOL_VERSION_V2: u32 = 0x6b33_43cf;
 
 /// Supported QUIC versions.
 ///
@@ -281,6 +316,15 @@ const PROTOCOL_VERSION_DRAFT27: u32 = 0xff00_001b;
 const PROTOCOL_VERSION_DRAFT28: u32 = 0xff00_001c;
 const PROTOCOL_VERSION_DRAFT29: u32 = 0xff00_001d;
 
+/// Supported QUIC versions, in order of preference.
+const SUPPORTED_VERSIONS: [u32; 5] = [
+    PROTOCOL_VERSION_V1,
+    PROTOCOL_VERSION_V2,
+    PROTOCOL_VERSION_DRAFT29,
+    PROTOCOL_VERSION_DRAFT28,
+    PROTOCOL_VERSION_DRAFT27,
+];
+
 /// The maximum length of a connection ID.
 pub const MAX_CONN_ID_LEN: usize = crate::packet::MAX_CID_LEN as usize;
 
@@ -306,6 +350,14 @@ const MAX_ACK_RANGES: usize = 68;
 // The highest possible stream ID allowed.
 const MAX_STREAM_ID: u64 = 1 << 60;
 
+// The default number of 1-RTT packets sent with the same keys before
+// initiating a key update. This is the confidentiality limit of AES-GCM.
+const DEFAULT_MAX_PACKETS_PER_KEY: u64 = 1 << 23;
+
+// The default number of received packets buffered while waiting for the keys
+// needed to decrypt them.
+const DEFAULT_MAX_UNDECRYPTABLE_PACKETS: usize = 10;
+
 #[cfg(feature = "quic-dgram")]
 // The default length of DATAGRAM queues if not specified by the user in config.
 const DEFAULT_DGRAM_MAX_QUEUE_LEN: usize = 1000;
@@ -325,52 +377,69 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 /// A QUIC error.
 #[derive(Clone, Copy, Debug, PartialEq)]
-#[repr(C)]
 pub enum Error {
     /// There is no more work to do.
-    Done               = -1,
+    Done,
 
     /// The provided buffer is too short.
-    BufferTooShort     = -2,
+    BufferTooShort,
 
     /// The provided packet cannot be parsed because its version is unknown.
-    UnknownVersion     = -3,
+    UnknownVersion,
 
     /// The provided packet cannot be parsed because it contains an invalid
     /// frame.
-    InvalidFrame       = -4,
+    InvalidFrame,
 
     /// The provided packet cannot be parsed.
-    InvalidPacket      = -5,
+    InvalidPacket,
 
     /// The operation cannot be completed because the connection is in an
     /// invalid state.
-    InvalidState       = -6,
+    InvalidState,
 
     /// The operation cannot be completed because the stream is in an
     /// invalid state.
-    InvalidStreamState = -7,
+    InvalidStreamState,
 
     /// The peer's transport params cannot be parsed.
-    InvalidTransportParam = -8,
+    InvalidTransportParam,
 
     /// A cryptographic operation failed.
-    CryptoFail         = -9,
+    CryptoFail,
 
     /// The TLS handshake failed.
-    TlsFail            = -10,
+    TlsFail,
 
     /// The peer violated the local flow control limits.
-    FlowControl        = -11,
+    FlowControl,
 
     /// The peer violated the local stream limits.
-    StreamLimit        = -12,
+    StreamLimit,
 
     /// The received data exceeds the stream's final size.
-    FinalSize          = -13,
+    FinalSize,
 
     /// Error in congestion control.
-    CongestionControl  = -14,
+    CongestionControl,
+
+    /// Too many connection IDs were provided.
+    IdLimit,
+
+    /// The peer's version information doesn't match the negotiated version.
+    VersionNegotiation,
+
+    /// The specified stream was stopped by the peer.
+    ///
+    /// The error code sent as part of the `STOP_SENDING` frame is provided as
+    /// associated data.
+    StreamStopped(u64),
+
+    /// The specified stream was reset by the peer.
+    ///
+    /// The error code sent as part of the `RESET_STREAM` frame is provided as
+    /// associated data.
+    StreamReset(u64),
 }
 
 impl Error {
@@ -378,17 +447,39 @@ impl Error {
         match self {
             Error::Done => 0x0,
             Error::InvalidFrame => 0x7,
+            Error::InvalidPacket => 0xa,
             Error::InvalidStreamState => 0x5,
             Error::InvalidTransportParam => 0x8,
             Error::FlowControl => 0x3,
             Error::StreamLimit => 0x4,
             Error::FinalSize => 0x6,
+            Error::IdLimit => 0x9,
+            Error::VersionNegotiation => 0x11,
             _ => 0xa,
         }
     }
 
     fn to_c(self) -> libc::ssize_t {
-        self as _
+        match self {
+            Error::Done => -1,
+            Error::BufferTooShort => -2,
+            Error::UnknownVersion => -3,
+            Error::InvalidFrame => -4,
+            Error::InvalidPacket => -5,
+            Error::InvalidState => -6,
+            Error::InvalidStreamState => -7,
+            Error::InvalidTransportParam => -8,
+            Error::CryptoFail => -9,
+            Error::TlsFail => -10,
+            Error::FlowControl => -11,
+            Error::StreamLimit => -12,
+            Error::FinalSize => -13,
+            Error::CongestionControl => -14,
+            Error::IdLimit => -15,
+            Error::VersionNegotiation => -16,
+            Error::StreamStopped { .. } => -17,
+            Error::StreamReset { .. } => -18,
+        }
     }
 }
 
@@ -438,8 +529,26 @@ pub struct Config {
 
     cc_algorithm: CongestionControlAlgorithm,
 
+    custom_cc: Option<fn() -> Box<dyn CongestionControl>>,
+
     hystart: bool,
 
+    pmtud: bool,
+
+    ecn: bool,
+
+    pacing: bool,
+
+    max_packets_per_key: u64,
+
+    max_undecryptable_pkts: usize,
+
+    incremental_quantum: usize,
+
+    custom_scheduler: Option<fn() -> Box<dyn StreamScheduler>>,
+
+    token_key: TokenKey,
+
     #[cfg(feature = "quic-dgram")]
     dgram_recv_max_queue_len: usize,
     #[cfg(feature = "quic-dgram")]
@@ -465,7 +574,21 @@ impl Config {
             application_protos: Vec::new(),
             grease: true,
             cc_algorithm: CongestionControlAlgorithm::CUBIC,
+            custom_cc: None,
             hystart: true,
+            pmtud: true,
+            ecn: false,
+            pacing: true,
+
+            max_packets_per_key: DEFAULT_MAX_PACKETS_PER_KEY,
+
+            max_undecryptable_pkts: DEFAULT_MAX_UNDECRYPTABLE_PACKETS,
+
+            incremental_quantum: 0,
+
+            custom_scheduler: None,
+
+            token_key: TokenKey::generate(),
 
             #[cfg(feature = "quic-dgram")]
             dgram_recv_max_queue_len: DEFAULT_DGRAM_MAX_QUEUE_LEN,
@@ -719,6 +842,19 @@ impl Config {
         self.local_transport_params.max_ack_delay = v;
     }
 
+    /// Sets the `min_ack_delay` transport parameter, in microseconds.
+    ///
+    /// Advertising this parameter allows the peer to request ACKs to be sent
+    /// less often with ACK_FREQUENCY frames. When the peer advertises it too,
+    /// the congestion controller decides how often the peer is asked to send
+    /// ACKs. It must not be larger than the `max_ack_delay` transport
+    /// parameter.
+    ///
+    /// The default value is `0`, meaning that the parameter is not sent.
+    pub fn set_min_ack_delay(&mut self, v: u64) {
+        self.local_transport_params.min_ack_delay = v;
+    }
+
     /// Sets the `disable_active_migration` transport parameter.
     ///
     /// The default value is `false`.
@@ -726,6 +862,67 @@ impl Config {
         self.local_transport_params.disable_active_migration = v;
     }
 
+    /// Sets the `active_connection_id_limit` transport parameter.
+    ///
+    /// This is the maximum number of the peer's connection IDs that will be
+    /// stored at the same time. Values lower than `2` are ignored.
+    ///
+    /// The default value is `2`.
+    pub fn set_active_connection_id_limit(&mut self, v: u64) {
+        if v >= 2 {
+            self.local_transport_params.active_conn_id_limit = v;
+        }
+    }
+
+    /// Sets the `stateless_reset_token` transport parameter.
+    ///
+    /// This is the token the peer can use to reset the connection using the
+    /// server's initial connection ID, and is ignored by clients.
+    ///
+    /// The default value is `None`.
+    pub fn set_stateless_reset_token(&mut self, v: Option<u128>) {
+        self.local_transport_params.stateless_reset_token =
+            v.map(|v| v.to_be_bytes().to_vec());
+    }
+
+    /// Sets the `preferred_address` transport parameter.
+    ///
+    /// This is the server's address, for each address family, that clients
+    /// migrate to once the handshake is confirmed, along with the connection ID
+    /// and stateless reset token to use on the new path. It's ignored by
+    /// clients.
+    ///
+    /// As the connection ID is advertised by all connections created with
+    /// this configuration, it should be changed before accepting each new
+    /// connection. It needs to have the same length as the connection's source
+    /// connection ID.
+    ///
+    /// On error `Error::InvalidState` is returned, if no address is given or
+    /// the connection ID's length is not valid.
+    ///
+    /// The default value is `None`.
+    pub fn set_preferred_address(
+        &mut self, v4: Option<SocketAddrV4>, v6: Option<SocketAddrV6>,
+        cid: &[u8], reset_token: u128,
+    ) -> Result<()> {
+        if v4.is_none() && v6.is_none() {
+            return Err(Error::InvalidState);
+        }
+
+        if cid.is_empty() || cid.len() > MAX_CONN_ID_LEN {
+            return Err(Error::InvalidState);
+        }
+
+        self.local_transport_params.preferred_address = Some(PreferredAddress {
+            addr_v4: v4,
+            addr_v6: v6,
+            connection_id: cid.to_vec(),
+            stateless_reset_token: reset_token,
+        });
+
+        Ok(())
+    }
+
     /// Sets the congestion control algorithm used by string.
     ///
     /// The default value is `reno`. On error `Error::CongestionControl`
@@ -740,6 +937,7 @@ impl Config {
     /// ```
     pub fn set_cc_algorithm_name(&mut self, name: &str) -> Result<()> {
         self.cc_algorithm = CongestionControlAlgorithm::from_str(name)?;
+        self.custom_cc = None;
 
         Ok(())
     }
@@ -749,6 +947,49 @@ impl Config {
     /// The default value is `CongestionControlAlgorithm::CUBIC`.
     pub fn set_cc_algorithm(&mut self, algo: CongestionControlAlgorithm) {
         self.cc_algorithm = algo;
+        self.custom_cc = None;
+    }
+
+    /// Sets a custom congestion controller, used instead of the built-in
+    /// congestion control algorithms.
+    ///
+    /// `new_cc` is called to create a new controller for every connection.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # use std::time::Instant;
+    /// struct FixedWindow;
+    ///
+    /// impl quiche::CongestionControl for FixedWindow {
+    ///     fn on_packet_sent(
+    ///         &mut self, _r: &mut quiche::Recovery, _sent_bytes: usize,
+    ///         _now: Instant,
+    ///     ) {
+    ///     }
+    ///
+    ///     fn on_packet_acked(
+    ///         &mut self, r: &mut quiche::Recovery, _packet: &quiche::Acked,
+    ///         _epoch: usize, _now: Instant,
+    ///     ) {
+    ///         r.set_cwnd(100_000);
+    ///     }
+    ///
+    ///     fn congestion_event(
+    ///         &mut self, _r: &mut quiche::Recovery, _time_sent: Instant,
+    ///         _epoch: usize, _now: Instant,
+    ///     ) {
+    ///     }
+    ///
+    ///     fn collapse_cwnd(&mut self, _r: &mut quiche::Recovery) {}
+    /// }
+    ///
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_custom_cc(|| Box::new(FixedWindow));
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_custom_cc(&mut self, new_cc: fn() -> Box<dyn CongestionControl>) {
+        self.custom_cc = Some(new_cc);
     }
 
     /// Enables support for receiving DATAGRAM frames. When enabled, the
@@ -772,6 +1013,137 @@ impl Config {
         self.hystart = v;
     }
 
+    /// Configures whether to discover the path MTU.
+    ///
+    /// When enabled, packets are limited to 1200 bytes until larger sizes,
+    /// up to the peer's `max_udp_payload_size`, are confirmed by probing the
+    /// path. When disabled, packets are only limited by the peer's
+    /// `max_udp_payload_size`.
+    ///
+    /// The default value is `true`.
+    pub fn discover_pmtu(&mut self, v: bool) {
+        self.pmtud = v;
+    }
+
+    /// Configures whether to mark outgoing packets with ECN.
+    ///
+    /// When enabled, [`SendInfo`] carries the ECN codepoint to set on the
+    /// outgoing packet, as long as the path is validated as supporting ECN,
+    /// and CE marks reported by the peer are treated as congestion signals.
+    ///
+    /// ECN counts are always reported to the peer, as long as the codepoint
+    /// of incoming packets is passed in [`RecvInfo`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`RecvInfo`]: struct.RecvInfo.html
+    pub fn enable_ecn(&mut self, v: bool) {
+        self.ecn = v;
+    }
+
+    /// Configures whether to pace outgoing packets.
+    ///
+    /// When enabled, [`SendInfo`] carries the time at which each packet
+    /// should be sent, so that packets are spread over the round-trip time
+    /// instead of being sent in bursts.
+    ///
+    /// The default value is `true`.
+    ///
+    /// [`SendInfo`]: struct.SendInfo.html
+    pub fn enable_pacing(&mut self, v: bool) {
+        self.pacing = v;
+    }
+
+    /// Sets the maximum number of 1-RTT packets sent with the same keys.
+    ///
+    /// Once the limit is reached, a key update is initiated automatically.
+    ///
+    /// The default value is `8388608` (2^23).
+    pub fn set_max_packets_per_key(&mut self, v: u64) {
+        self.max_packets_per_key = v;
+    }
+
+    /// Sets the maximum number of received 0-RTT and Handshake packets that
+    /// are buffered while the keys needed to decrypt them are not available.
+    ///
+    /// Packets received when the limit is reached are dropped.
+    ///
+    /// The default value is `10`.
+    pub fn set_max_undecryptable_packets(&mut self, v: usize) {
+        self.max_undecryptable_pkts = v;
+    }
+
+    /// Sets the number of bytes an incremental stream can send before the
+    /// next incremental stream with the same urgency is scheduled.
+    ///
+    /// This only applies to the default stream scheduler.
+    ///
+    /// The default value is `0`, meaning that incremental streams with the
+    /// same urgency take turns after every STREAM frame.
+    pub fn set_incremental_quantum(&mut self, v: usize) {
+        self.incremental_quantum = v;
+    }
+
+    /// Sets a custom stream scheduler, used instead of the default one based
+    /// on the streams' urgency and incremental parameters.
+    ///
+    /// `new_scheduler` is called to create a new scheduler for every
+    /// connection.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # use std::collections::BTreeSet;
+    /// // Sends the stream with the lowest ID first, 1000 bytes at a time.
+    /// #[derive(Default)]
+    /// struct Lowest(BTreeSet<u64>);
+    ///
+    /// impl quiche::StreamScheduler for Lowest {
+    ///     fn push(&mut self, stream_id: u64, _urgency: u8, _incr: bool) {
+    ///         self.0.insert(stream_id);
+    ///     }
+    ///
+    ///     fn pop(&mut self) -> Option<(u64, usize)> {
+    ///         let stream_id = *self.0.iter().next()?;
+    ///         self.0.remove(&stream_id);
+    ///
+    ///         Some((stream_id, 1000))
+    ///     }
+    ///
+    ///     fn remove(
+    ///         &mut self, stream_id: u64, _urgency: u8, _incr: bool,
+    ///     ) -> bool {
+    ///         self.0.remove(&stream_id)
+    ///     }
+    ///
+    ///     fn is_empty(&self) -> bool {
+    ///         self.0.is_empty()
+    ///     }
+    /// }
+    ///
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_stream_scheduler(|| Box::new(Lowest::default()));
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_stream_scheduler(
+        &mut self, new_scheduler: fn() -> Box<dyn StreamScheduler>,
+    ) {
+        self.custom_scheduler = Some(new_scheduler);
+    }
+
+    /// Sets the key used to mint and validate tokens sent in NEW_TOKEN frames.
+    ///
+    /// Servers should use the same key across all instances that clients
+    /// could connect to, so that tokens minted by one instance are accepted by
+    /// the others. The same key can also be used for Retry tokens, as the two
+    /// kinds of tokens can't be confused.
+    ///
+    /// By default a random key is used.
+    pub fn set_token_key(&mut self, key: TokenKey) {
+        self.token_key = key;
+    }
+
     /// Sets the maximum length of the DATAGRAM send queue.
     ///
     /// The default is `1000`.
@@ -789,11 +1161,101 @@ impl Config {
     }
 }
 
+/// The ECN codepoint of an IP packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Ecn {
+    /// Not ECN-Capable Transport.
+    NotEct = 0,
+
+    /// ECN Capable Transport, ECT(1).
+    Ect1   = 1,
+
+    /// ECN Capable Transport, ECT(0).
+    Ect0   = 2,
+
+    /// Congestion Experienced.
+    Ce     = 3,
+}
+
+impl From<u8> for Ecn {
+    /// Returns the ECN codepoint in the two least significant bits of the
+    /// given IP traffic class (or TOS) byte.
+    fn from(v: u8) -> Self {
+        match v & 0x03 {
+            0x01 => Ecn::Ect1,
+
+            0x02 => Ecn::Ect0,
+
+            0x03 => Ecn::Ce,
+
+            _ => Ecn::NotEct,
+        }
+    }
+}
+
+/// Ancillary information about incoming packets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecvInfo {
+    /// The address the packet was received from.
+    pub from: SocketAddr,
+
+    /// The ECN codepoint the packet was received with.
+    pub ecn: Ecn,
+}
+
+/// Ancillary information about outgoing packets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SendInfo {
+    /// The address the packet should be sent to.
+    pub to: SocketAddr,
+
+    /// The ECN codepoint the packet should be sent with.
+    pub ecn: Ecn,
+
+    /// The earliest time the packet should be sent at.
+    pub at: time::Instant,
+}
+
+/// Ancillary information about batches of outgoing packets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SendBatchInfo {
+    /// The address the packets should be sent to.
+    pub to: SocketAddr,
+
+    /// The ECN codepoint the packets should be sent with.
+    pub ecn: Ecn,
+
+    /// The earliest time the packets should be sent at.
+    pub at: time::Instant,
+
+    /// The size of each packet, except for the last one which can be
+    /// shorter.
+    pub segment_size: usize,
+
+    /// The number of packets in the batch.
+    pub segments: usize,
+}
+
 /// A QUIC connection.
 pub struct Connection {
     /// QUIC wire version used for the connection.
     version: u32,
 
+    /// QUIC wire version of the first Initial packet sent (client) or received
+    /// (server), before any compatible version upgrade.
+    initial_version: u32,
+
+    /// Initial packet decryption key for `initial_version`, kept by servers
+    /// that upgraded to a compatible version until the client is seen using
+    /// the negotiated version, as it might retransmit Initial packets using
+    /// the original version in the meantime.
+    initial_version_open: Option<crypto::Open>,
+
+    /// QUIC wire version from the endpoint's configuration. Servers upgrade
+    /// compatible clients to this version.
+    preferred_version: u32,
+
     /// Peer's connection ID.
     dcid: Vec<u8>,
 
@@ -846,10 +1308,6 @@ pub struct Connection {
     /// Peer's flow control limit for the connection.
     max_tx_data: u64,
 
-    /// Total number of bytes the server can send before the peer's address
-    /// is verified.
-    max_send_bytes: usize,
-
     /// Streams map, indexed by stream ID.
     streams: stream::StreamMap,
 
@@ -864,6 +1322,15 @@ pub struct Connection {
     /// Received address verification token.
     token: Option<Vec<u8>>,
 
+    /// Token received in a NEW_TOKEN frame, for use in future connections.
+    new_token: Option<Vec<u8>>,
+
+    /// Key used to mint and validate tokens sent in NEW_TOKEN frames.
+    token_key: TokenKey,
+
+    /// Whether a NEW_TOKEN frame needs to be sent.
+    new_token_pending: bool,
+
     /// Error code to be sent to the peer in CONNECTION_CLOSE.
     error: Option<u64>,
 
@@ -873,8 +1340,11 @@ pub struct Connection {
     /// Error reason to be sent to the peer in APPLICATION_CLOSE.
     app_reason: Vec<u8>,
 
-    /// Received path challenge.
-    challenge: Option<Vec<u8>>,
+    /// Network paths to the peer, indexed by the peer's address.
+    paths: path::PathMap,
+
+    /// Connection IDs issued by both endpoints.
+    ids: cid::ConnectionIdentifiers,
 
     /// The connection-level limit at which send blocking occurred.
     blocked_limit: Option<u64>,
@@ -885,6 +1355,10 @@ pub struct Connection {
     /// Draining timeout expiration time.
     draining_timer: Option<time::Instant>,
 
+    /// Error hit while writing a partial batch of packets, to be returned by
+    /// the next call to `send_batch()`.
+    send_batch_err: Option<Error>,
+
     /// Whether this is a server-side connection.
     is_server: bool,
 
@@ -898,12 +1372,18 @@ pub struct Connection {
     /// Whether stateless retry has been performed.
     did_retry: bool,
 
+    /// Sequence number of the server's connection ID used before switching to
+    /// the one advertised with its preferred address, until the latter is
+    /// validated. Only used by clients.
+    pre_migration_dcid_seq: Option<u64>,
+
+    /// Whether validation of the server's preferred address was started. Only
+    /// relevant for client connections.
+    did_probe_preferred_address: bool,
+
     /// Whether the peer already updated its connection ID.
     got_peer_conn_id: bool,
 
-    /// Whether the peer's address has been verified.
-    verified_peer_address: bool,
-
     /// Whether the peer's transport parameters were parsed.
     parsed_peer_transport_params: bool,
 
@@ -917,15 +1397,60 @@ pub struct Connection {
     /// packet.
     ack_eliciting_sent: bool,
 
+    /// ACK frequency state, for delaying ACKs as requested by the peer and
+    /// requesting the peer to delay its own.
+    ack_freq: ack_freq::AckFrequency,
+
     /// Whether the connection is closed.
     closed: bool,
 
+    /// Whether the connection was reset by the peer with a stateless reset.
+    reset: bool,
+
+    /// The key phase of outgoing 1-RTT packets.
+    key_phase: bool,
+
+    /// The key phase of the keys currently used to decrypt 1-RTT packets.
+    rx_key_phase: bool,
+
+    /// The packet number of the first packet received in the current
+    /// incoming key phase.
+    rx_key_phase_pn: u64,
+
+    /// When the keys of the previous incoming key phase are discarded.
+    prev_key_discard_timer: Option<time::Instant>,
+
+    /// The packet number of the first packet sent after the last key update,
+    /// until a packet sent with the new keys is acknowledged by the peer.
+    key_update_pn: Option<u64>,
+
+    /// The number of 1-RTT packets sent with the current keys.
+    pkts_sent_with_key: u64,
+
+    /// The number of 1-RTT packets after which keys are updated.
+    max_packets_per_key: u64,
+
+    /// Received packets that couldn't be decrypted yet, along with their type.
+    undecryptable_pkts: VecDeque<(packet::Type, Vec<u8>, RecvInfo)>,
+
+    /// The maximum number of packets in `undecryptable_pkts`.
+    max_undecryptable_pkts: usize,
+
+    /// Total number of packets buffered in `undecryptable_pkts`.
+    undecryptable_buffered_count: usize,
+
+    /// Total number of packets dropped because `undecryptable_pkts` was full.
+    undecryptable_dropped_count: usize,
+
     /// Whether to send GREASE.
     grease: bool,
 
     /// TLS keylog writer.
     keylog: Option<Box<dyn std::io::Write + Send>>,
 
+    /// Serialized TLS session and peer transport parameters, for resumption.
+    session: Option<Vec<u8>>,
+
     /// Qlog streaming output.
     #[cfg(feature = "qlog")]
     qlog_streamer: Option<qlog::QlogStreamer>,
@@ -946,7 +1471,8 @@ pub struct Connection {
 /// The `scid` parameter represents the server's source connection ID, while
 /// the optional `odcid` parameter represents the original destination ID the
 /// client sent before a stateless retry (this is only required when using
-/// the [`retry()`] function).
+/// the [`retry()`] function). The `from` parameter is the address the client's
+/// first packet was received from.
 ///
 /// [`retry()`]: fn.retry.html
 ///
@@ -955,13 +1481,14 @@ pub struct Connection {
 /// ```no_run
 /// # let mut config = quiche::Config::new(0xbabababa)?;
 /// # let scid = [0xba; 16];
-/// let conn = quiche::accept(&scid, None, &mut config)?;
+/// # let from = "127.0.0.1:1234".parse().unwrap();
+/// let conn = quiche::accept(&scid, None, from, &mut config)?;
 /// # Ok::<(), quiche::Error>(())
 /// ```
 pub fn accept(
-    scid: &[u8], odcid: Option<&[u8]>, config: &mut Config,
+    scid: &[u8], odcid: Option<&[u8]>, from: SocketAddr, config: &mut Config,
 ) -> Result<Pin<Box<Connection>>> {
-    let conn = Connection::new(scid, odcid, config, true)?;
+    let conn = Connection::new(scid, odcid, from, config, true)?;
 
     Ok(conn)
 }
@@ -970,7 +1497,7 @@ pub fn accept(
 ///
 /// The `scid` parameter is used as the connection's source connection ID,
 /// while the optional `server_name` parameter is used to verify the peer's
-/// certificate.
+/// certificate. The `to` parameter is the address of the server.
 ///
 /// ## Examples:
 ///
@@ -978,13 +1505,14 @@ pub fn accept(
 /// # let mut config = quiche::Config::new(0xbabababa)?;
 /// # let server_name = "quic.tech";
 /// # let scid = [0xba; 16];
-/// let conn = quiche::connect(Some(&server_name), &scid, &mut config)?;
+/// # let to = "127.0.0.1:4433".parse().unwrap();
+/// let conn = quiche::connect(Some(&server_name), &scid, to, &mut config)?;
 /// # Ok::<(), quiche::Error>(())
 /// ```
 pub fn connect(
-    server_name: Option<&str>, scid: &[u8], config: &mut Config,
+    server_name: Option<&str>, scid: &[u8], to: SocketAddr, config: &mut Config,
 ) -> Result<Pin<Box<Connection>>> {
-    let conn = Connection::new(scid, None, config, false)?;
+    let conn = Connection::new(scid, None, to, config, false)?;
 
     if let Some(server_name) = server_name {
         conn.handshake.set_host_name(server_name)?;
@@ -1033,9 +1561,11 @@ pub fn negotiate_version(
 /// token to be sent to the client, and verifying tokens sent back by the
 /// client. The generated token should include the `dcid` parameter, such
 /// that it can be later extracted from the token and passed to the
-/// [`accept()`] function as its `odcid` parameter.
+/// [`accept()`] function as its `odcid` parameter. A [`TokenKey`] can be
+/// used to mint and validate such tokens.
 ///
 /// [`accept()`]: fn.accept.html
+/// [`TokenKey`]: struct.TokenKey.html
 ///
 /// ## Examples:
 ///
@@ -1045,12 +1575,8 @@ pub fn negotiate_version(
 /// # let mut out = [0; 512];
 /// # let scid = [0xba; 16];
 /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-/// # fn mint_token(hdr: &quiche::Header, src: &std::net::SocketAddr) -> Vec<u8> {
-/// #     vec![]
-/// # }
-/// # fn validate_token<'a>(src: &std::net::SocketAddr, token: &'a [u8]) -> Option<&'a [u8]> {
-/// #     None
-/// # }
+/// let token_key = quiche::TokenKey::generate();
+///
 /// let (len, src) = socket.recv_from(&mut buf).unwrap();
 ///
 /// let hdr = quiche::Header::from_slice(&mut buf[..len], quiche::MAX_CONN_ID_LEN)?;
@@ -1059,7 +1585,7 @@ pub fn negotiate_version(
 ///
 /// // No token sent by client, create a new one.
 /// if token.is_empty() {
-///     let new_token = mint_token(&hdr, &src);
+///     let new_token = token_key.mint_retry_token(&src, &hdr.dcid);
 ///
 ///     let len = quiche::retry(
 ///         &hdr.scid, &hdr.dcid, &scid, &new_token, hdr.version, &mut out,
@@ -1070,14 +1596,14 @@ pub fn negotiate_version(
 /// }
 ///
 /// // Client sent token, validate it.
-/// let odcid = validate_token(&src, token);
+/// let odcid = token_key.validate_retry_token(&src, token);
 ///
 /// if odcid == None {
 ///     // Invalid address validation token.
 ///     return Ok(());
 /// }
 ///
-/// let conn = quiche::accept(&scid, odcid, &mut config)?;
+/// let conn = quiche::accept(&scid, odcid.as_deref(), src, &mut config)?;
 /// # Ok::<(), quiche::Error>(())
 /// ```
 pub fn retry(
@@ -1087,8 +1613,73 @@ pub fn retry(
     packet::retry(scid, dcid, new_scid, token, version, out)
 }
 
+/// Returns true if the given token was issued in a NEW_TOKEN frame.
+///
+/// Tokens issued with [`send_new_token()`] are validated by the connection
+/// itself when received in the client's Initial packet, so servers should not
+/// perform stateless retry for those, and should not treat them as Retry
+/// tokens.
+///
+/// [`send_new_token()`]: struct.Connection.html#method.send_new_token
+pub fn is_new_token(token: &[u8]) -> bool {
+    token::is_new_token(token)
+}
+
+/// Writes a stateless reset packet.
+///
+/// The `reset_token` is the stateless reset token associated with the
+/// destination connection ID of the received packet, and `pkt_len` is the
+/// length of that packet.
+///
+/// This is meant to be used by servers that receive a short header packet
+/// for a connection they don't have state for (e.g. after a restart), to
+/// let the peer know the connection can't be used anymore. The token should
+/// be derived from the connection ID using a static key, such that it can
+/// be regenerated without any per-connection state.
+///
+/// The returned packet is always smaller than `pkt_len`, so that resets
+/// can't be used to amplify traffic. If `pkt_len` is too small for a reset
+/// to be generated, [`Done`] is returned.
+///
+/// [`Done`]: enum.Error.html#variant.Done
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut buf = [0; 512];
+/// # let mut out = [0; 512];
+/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// # fn reset_token_for(cid: &[u8]) -> u128 {
+/// #     0
+/// # }
+/// let (len, src) = socket.recv_from(&mut buf).unwrap();
+///
+/// let hdr =
+///     quiche::Header::from_slice(&mut buf[..len], quiche::MAX_CONN_ID_LEN)?;
+///
+/// // No connection found for the received connection ID.
+/// let token = reset_token_for(&hdr.dcid);
+///
+/// let len = quiche::stateless_reset(token, len, &mut out)?;
+/// socket.send_to(&out[..len], &src).unwrap();
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub fn stateless_reset(
+    reset_token: u128, pkt_len: usize, out: &mut [u8],
+) -> Result<usize> {
+    packet::stateless_reset(reset_token, pkt_len, out)
+}
+
 /// Returns true if the given protocol version is supported.
 pub fn version_is_supported(version: u32) -> bool {
+    SUPPORTED_VERSIONS.contains(&version)
+}
+
+/// Returns true if the given protocol version is a pre-RFC draft version.
+///
+/// Draft versions use a different codepoint for the transport parameters TLS
+/// extension.
+fn version_is_draft(version: u32) -> bool {
     match version {
         PROTOCOL_VERSION_DRAFT27 |
         PROTOCOL_VERSION_DRAFT28 |
@@ -1098,6 +1689,16 @@ pub fn version_is_supported(version: u32) -> bool {
     }
 }
 
+/// Returns true if a connection using the `from` version can be upgraded to
+/// the `to` version without an additional round-trip.
+///
+/// QUIC version 1 and version 2 are compatible with each other.
+fn version_is_compatible(from: u32, to: u32) -> bool {
+    let compatible = [PROTOCOL_VERSION_V1, PROTOCOL_VERSION_V2];
+
+    from == to || (compatible.contains(&from) && compatible.contains(&to))
+}
+
 /// Pushes a frame to the output packet if there is enough space.
 ///
 /// Returns `true` on success, `false` otherwise. In case of failure it means
@@ -1135,15 +1736,16 @@ macro_rules! qlog_with {
 
 impl Connection {
     fn new(
-        scid: &[u8], odcid: Option<&[u8]>, config: &mut Config, is_server: bool,
+        scid: &[u8], odcid: Option<&[u8]>, peer_addr: SocketAddr,
+        config: &mut Config, is_server: bool,
     ) -> Result<Pin<Box<Connection>>> {
         let tls = config.tls_ctx.new_handshake()?;
-        Connection::with_tls(scid, odcid, config, tls, is_server)
+        Connection::with_tls(scid, odcid, peer_addr, config, tls, is_server)
     }
 
     fn with_tls(
-        scid: &[u8], odcid: Option<&[u8]>, config: &mut Config,
-        tls: tls::Handshake, is_server: bool,
+        scid: &[u8], odcid: Option<&[u8]>, peer_addr: SocketAddr,
+        config: &mut Config, tls: tls::Handshake, is_server: bool,
     ) -> Result<Pin<Box<Connection>>> {
         let max_rx_data = config.local_transport_params.initial_max_data;
 
@@ -1153,6 +1755,12 @@ impl Connection {
         let mut conn = Box::pin(Connection {
             version: config.version,
 
+            initial_version: config.version,
+
+            initial_version_open: None,
+
+            preferred_version: config.version,
+
             dcid: Vec::new(),
             scid: scid.to_vec(),
 
@@ -1185,11 +1793,16 @@ impl Connection {
             tx_data: 0,
             max_tx_data: 0,
 
-            max_send_bytes: 0,
-
             streams: stream::StreamMap::new(
                 config.local_transport_params.initial_max_streams_bidi,
                 config.local_transport_params.initial_max_streams_uni,
+                match config.custom_scheduler {
+                    Some(new_scheduler) => new_scheduler(),
+
+                    None => Box::new(stream::UrgencyScheduler::new(
+                        config.incremental_quantum,
+                    )),
+                },
             ),
 
             odcid: None,
@@ -1198,12 +1811,25 @@ impl Connection {
 
             token: None,
 
+            new_token: None,
+
+            token_key: config.token_key.clone(),
+
+            new_token_pending: false,
+
             error: None,
 
             app_error: None,
             app_reason: Vec::new(),
 
-            challenge: None,
+            // If we did stateless retry assume the peer's address is verified,
+            // and the client always trusts the server's address.
+            paths: path::PathMap::new(peer_addr, odcid.is_some() || !is_server),
+
+            ids: cid::ConnectionIdentifiers::new(
+                scid,
+                config.local_transport_params.active_conn_id_limit,
+            ),
 
             blocked_limit: None,
 
@@ -1211,6 +1837,8 @@ impl Connection {
 
             draining_timer: None,
 
+            send_batch_err: None,
+
             is_server,
 
             derived_initial_secrets: false,
@@ -1219,10 +1847,11 @@ impl Connection {
 
             did_retry: false,
 
-            got_peer_conn_id: false,
+            pre_migration_dcid_seq: None,
 
-            // If we did stateless retry assume the peer's address is verified.
-            verified_peer_address: odcid.is_some(),
+            did_probe_preferred_address: false,
+
+            got_peer_conn_id: false,
 
             parsed_peer_transport_params: false,
 
@@ -1232,25 +1861,53 @@ impl Connection {
 
             ack_eliciting_sent: false,
 
+            ack_freq: ack_freq::AckFrequency::default(),
+
             closed: false,
 
-            grease: config.grease,
+            reset: false,
 
-            keylog: None,
+            key_phase: false,
 
-            #[cfg(feature = "qlog")]
-            qlog_streamer: None,
+            rx_key_phase: false,
 
-            #[cfg(feature = "qlog")]
-            qlogged_peer_params: false,
+            rx_key_phase_pn: 0,
 
-            #[cfg(feature = "quic-dgram")]
-            dgram_recv_queue: dgram::DatagramQueue::new(
-                config.dgram_recv_max_queue_len,
-            ),
+            prev_key_discard_timer: None,
 
-            #[cfg(feature = "quic-dgram")]
-            dgram_send_queue: dgram::DatagramQueue::new(
+            key_update_pn: None,
+
+            pkts_sent_with_key: 0,
+
+            max_packets_per_key: config.max_packets_per_key,
+
+            undecryptable_pkts: VecDeque::new(),
+
+            max_undecryptable_pkts: config.max_undecryptable_pkts,
+
+            undecryptable_buffered_count: 0,
+
+            undecryptable_dropped_count: 0,
+
+            grease: config.grease,
+
+            keylog: None,
+
+            session: None,
+
+            #[cfg(feature = "qlog")]
+            qlog_streamer: None,
+
+            #[cfg(feature = "qlog")]
+            qlogged_peer_params: false,
+
+            #[cfg(feature = "quic-dgram")]
+            dgram_recv_queue: dgram::DatagramQueue::new(
+                config.dgram_recv_max_queue_len,
+            ),
+
+            #[cfg(feature = "quic-dgram")]
+            dgram_send_queue: dgram::DatagramQueue::new(
                 config.dgram_send_max_queue_len,
             ),
         });
@@ -1268,6 +1925,23 @@ impl Connection {
         conn.local_transport_params.initial_source_connection_id =
             Some(scid.to_vec());
 
+        // The connection ID advertised with the preferred address has sequence
+        // number 1.
+        if let Some(pa) = &config.local_transport_params.preferred_address {
+            if is_server {
+                // Short header packets don't carry the connection ID's length,
+                // so it needs to be the same size as the initial one.
+                if pa.connection_id.len() != scid.len() {
+                    return Err(Error::InvalidState);
+                }
+
+                conn.ids.set_preferred_address_scid(
+                    &pa.connection_id,
+                    pa.stateless_reset_token,
+                );
+            }
+        }
+
         conn.handshake.init(&conn)?;
 
         conn.encode_transport_params()?;
@@ -1297,6 +1971,79 @@ impl Connection {
         Ok(conn)
     }
 
+    /// Configures the given session for resumption.
+    ///
+    /// On the client, this can be used to offer the given serialized session,
+    /// as returned by [`session()`], for resumption. The peer's transport
+    /// parameters stored with the session are also restored, so that 0-RTT
+    /// data can be sent under the limits remembered from the previous
+    /// connection, if early data is enabled.
+    ///
+    /// If the server rejects 0-RTT, the streams opened in early data are
+    /// discarded, and their data needs to be sent again by the application
+    /// once the handshake completes.
+    ///
+    /// This must only be called immediately after creating a connection, that
+    /// is, before any packet is sent or received.
+    ///
+    /// [`session()`]: struct.Connection.html#method.session
+    pub fn set_session(&mut self, session: &[u8]) -> Result<()> {
+        if self.is_server {
+            return Err(Error::InvalidState);
+        }
+
+        let mut b = octets::Octets::with_slice(session);
+
+        let session_len = b.get_u64()? as usize;
+        let session_bytes = b.get_bytes(session_len)?;
+
+        self.handshake.set_session(session_bytes.as_ref())?;
+
+        let raw_params_len = b.get_u64()? as usize;
+        let raw_params_bytes = b.get_bytes(raw_params_len)?;
+
+        let peer_params =
+            TransportParams::decode(raw_params_bytes.as_ref(), self.is_server)?;
+
+        // Apply the remembered flow control limits, so that 0-RTT data can be
+        // sent. These are updated once the actual transport parameters are
+        // received from the server.
+        self.max_tx_data = peer_params.initial_max_data;
+
+        self.streams
+            .update_peer_max_streams_bidi(peer_params.initial_max_streams_bidi);
+        self.streams
+            .update_peer_max_streams_uni(peer_params.initial_max_streams_uni);
+
+        self.peer_transport_params = peer_params;
+
+        Ok(())
+    }
+
+    /// Configures the path characteristics of a previous connection.
+    ///
+    /// The given parameters, as returned by [`path_params()`] on a previous
+    /// connection to the same peer, are used to ramp up the congestion window
+    /// faster than slow start would. The saved window is only used once the
+    /// RTT confirms that the path is likely the same, and the window is
+    /// reduced again if using it causes any loss.
+    ///
+    /// This must only be called immediately after creating a connection, that
+    /// is, before any packet is sent or received.
+    ///
+    /// [`path_params()`]: struct.Connection.html#method.path_params
+    pub fn set_path_params(&mut self, params: &[u8]) -> Result<()> {
+        let mut b = octets::Octets::with_slice(params);
+
+        let min_rtt = time::Duration::from_micros(b.get_u64()?);
+        let cwnd = usize::try_from(b.get_u64()?)
+            .map_err(|_| Error::InvalidState)?;
+
+        self.recovery.set_path_params(min_rtt, cwnd);
+
+        Ok(())
+    }
+
     /// Sets keylog output to the designated [`Writer`].
     ///
     /// This needs to be called as soon as the connection is created, to avoid
@@ -1372,6 +2119,10 @@ impl Connection {
     ///
     /// Coalesced packets will be processed as necessary.
     ///
+    /// The `info` parameter carries the address the packets were received
+    /// from. Packets received from a new address might cause the connection
+    /// to validate that address and migrate to it.
+    ///
     /// Note that the contents of the input buffer `buf` might be modified by
     /// this function due to, for example, in-place decryption.
     ///
@@ -1384,11 +2135,17 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// loop {
-    ///     let read = socket.recv(&mut buf).unwrap();
+    ///     let (read, from) = socket.recv_from(&mut buf).unwrap();
+    ///
+    ///     let recv_info = quiche::RecvInfo {
+    ///         from,
+    ///         ecn: quiche::Ecn::NotEct,
+    ///     };
     ///
-    ///     let read = match conn.recv(&mut buf[..read]) {
+    ///     let read = match conn.recv(&mut buf[..read], recv_info) {
     ///         Ok(v) => v,
     ///
     ///         Err(e) => {
@@ -1399,26 +2156,35 @@ impl Connection {
     /// }
     /// # Ok::<(), quiche::Error>(())
     /// ```
-    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+    pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
         let len = buf.len();
 
-        // Keep track of how many bytes we received from the client, so we
-        // can limit bytes sent back before address validation, to a multiple
-        // of this. The limit needs to be increased early on, so that if there
-        // is an error there is enough credit to send a CONNECTION_CLOSE.
+        // Keep track of how many bytes we received from the peer on each path,
+        // so we can limit bytes sent back before address validation, to a
+        // multiple of this. The limit needs to be increased early on, so that
+        // if there is an error there is enough credit to send a
+        // CONNECTION_CLOSE.
         //
         // It doesn't matter if the packets received were valid or not, we only
         // need to track the total amount of bytes received.
-        if !self.verified_peer_address {
-            self.max_send_bytes += len * MAX_AMPLIFICATION_FACTOR;
-        }
+        let is_new_addr = match self.paths.get_mut(info.from) {
+            Some(path) => {
+                if !path.validated {
+                    path.max_send_bytes += len * MAX_AMPLIFICATION_FACTOR;
+                }
+
+                false
+            },
+
+            None => true,
+        };
 
         let mut done = 0;
         let mut left = len;
 
         // Process coalesced packets.
         while left > 0 {
-            let read = match self.recv_single(&mut buf[len - left..len]) {
+            let read = match self.recv_single(&mut buf[len - left..len], info) {
                 Ok(v) => v,
 
                 Err(Error::Done) => left,
@@ -1435,6 +2201,57 @@ impl Connection {
             left -= read;
         }
 
+        // Packets buffered earlier might be decryptable now that more keys
+        // could have been installed while processing this datagram.
+        if let Err(e) = self.recv_undecryptable() {
+            self.close(false, e.to_wire(), b"").ok();
+            return Err(e);
+        }
+
+        // A path for a new address is only created once a packet received
+        // from it was successfully processed, so credit it afterwards.
+        if is_new_addr {
+            if let Some(path) = self.paths.get_mut(info.from) {
+                path.max_send_bytes += len * MAX_AMPLIFICATION_FACTOR;
+            }
+        }
+
+        Ok(done)
+    }
+
+    /// Processes a batch of UDP datagrams received from the peer at once.
+    ///
+    /// The input buffer holds datagrams coalesced by UDP generic receive
+    /// offload (e.g. `UDP_GRO` on Linux), each `segment_size` bytes long
+    /// except for the last one, which can be shorter. Each datagram is then
+    /// processed as by [`recv()`].
+    ///
+    /// On success the number of bytes processed from the input buffer is
+    /// returned. On error the connection will be closed by calling [`close()`]
+    /// with the appropriate error code.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`close()`]: struct.Connection.html#method.close
+    pub fn recv_batch(
+        &mut self, buf: &mut [u8], segment_size: usize, info: RecvInfo,
+    ) -> Result<usize> {
+        // The whole buffer is a single datagram.
+        if segment_size == 0 || segment_size >= buf.len() {
+            return self.recv(buf, info);
+        }
+
+        let mut done = 0;
+
+        for datagram in buf.chunks_mut(segment_size) {
+            match self.recv(datagram, info) {
+                Ok(v) => done += v,
+
+                Err(Error::Done) => done += datagram.len(),
+
+                Err(e) => return Err(e),
+            }
+        }
+
         Ok(done)
     }
 
@@ -1447,7 +2264,7 @@ impl Connection {
     /// On error, an error other than [`Done`] is returned.
     ///
     /// [`Done`]: enum.Error.html#variant.Done
-    fn recv_single(&mut self, buf: &mut [u8]) -> Result<usize> {
+    fn recv_single(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
         let now = time::Instant::now();
 
         if buf.is_empty() {
@@ -1464,6 +2281,15 @@ impl Connection {
             return Err(Error::Done);
         }
 
+        // Remember the trailing bytes of the datagram before they are
+        // modified by header protection removal, in case the packet turns out
+        // to be a stateless reset.
+        let reset_token = if buf.len() >= packet::MIN_STATELESS_RESET_LEN {
+            buf[buf.len() - packet::RESET_TOKEN_LEN..].to_vec()
+        } else {
+            Vec::new()
+        };
+
         let mut b = octets::OctetsMut::with_slice(buf);
 
         let mut hdr =
@@ -1511,7 +2337,7 @@ impl Connection {
                 return Err(Error::Done);
             }
 
-            match versions.iter().filter(|&&v| version_is_supported(v)).max() {
+            match SUPPORTED_VERSIONS.iter().find(|v| versions.contains(v)) {
                 Some(v) => self.version = *v,
 
                 None => {
@@ -1526,8 +2352,12 @@ impl Connection {
                 },
             };
 
+            self.initial_version = self.version;
             self.did_version_negotiation = true;
 
+            self.handshake
+                .use_legacy_codepoint(version_is_draft(self.version));
+
             // Derive Initial secrets based on the new version.
             let (aead_open, aead_seal) = crypto::derive_initial_key_material(
                 &self.dcid,
@@ -1613,19 +2443,65 @@ impl Connection {
             }
 
             self.version = hdr.version;
+            self.initial_version = hdr.version;
             self.did_version_negotiation = true;
 
+            self.handshake
+                .use_legacy_codepoint(version_is_draft(self.version));
+
             // Encode transport parameters again, as the new version might be
             // using a different format.
             self.encode_transport_params()?;
         }
 
-        if hdr.ty != packet::Type::Short && hdr.version != self.version {
+        // The server might have upgraded the connection to a compatible version
+        // that the client offered in its transport parameters, in which case
+        // the client follows it.
+        if !self.is_server &&
+            !self.got_peer_conn_id &&
+            hdr.ty == packet::Type::Initial &&
+            hdr.version != self.version &&
+            version_is_compatible(self.version, hdr.version)
+        {
+            self.set_compatible_version(hdr.version)?;
+        }
+
+        // After upgrading to a compatible version, the server still accepts
+        // the client's Initial packets using the original version, until the
+        // client switches to the negotiated one. Handshake packets can only
+        // use the negotiated version, as the client learns about it from the
+        // server's first Initial packet.
+        let is_initial_version_pkt = hdr.ty == packet::Type::Initial &&
+            hdr.version != self.version &&
+            hdr.version == self.initial_version &&
+            self.initial_version_open.is_some();
+
+        if hdr.ty != packet::Type::Short &&
+            hdr.version != self.version &&
+            !is_initial_version_pkt
+        {
             // At this point version negotiation was already performed, so
             // ignore packets that don't match the connection's version.
             return Err(Error::Done);
         }
 
+        // Short header packets need to be addressed to one of the connection
+        // IDs we issued that the peer didn't retire yet.
+        if hdr.ty == packet::Type::Short && !self.ids.has_scid(&hdr.dcid) {
+            if self.is_stateless_reset(&hdr, &reset_token) {
+                self.on_stateless_reset(now);
+                return Err(Error::Done);
+            }
+
+            trace!(
+                "{} ignored packet for unknown connection ID {:?}",
+                self.trace_id,
+                hdr.dcid
+            );
+
+            return Err(Error::Done);
+        }
+
         // Long header packets have an explicit payload length, but short
         // packets don't so just use the remaining capacity in the buffer.
         let payload_len = if hdr.ty == packet::Type::Short {
@@ -1653,12 +2529,32 @@ impl Connection {
         // Select packet number space epoch based on the received packet's type.
         let epoch = hdr.ty.to_epoch()?;
 
-        // TODO: somehow deal with re-ordered 0-RTT data.
-        let aead = if hdr.ty == packet::Type::ZeroRTT &&
+        // Buffer packets whose keys are not available yet, so they can be
+        // processed once they are, instead of having to be retransmitted.
+        if self.pkt_keys_pending(hdr.ty) {
+            let pkt_len = b.off() + payload_len;
+
+            if pkt_len > b.buf().len() {
+                return Err(drop_pkt_on_err(
+                    Error::BufferTooShort,
+                    self.recv_count,
+                    self.is_server,
+                    &self.trace_id,
+                ));
+            }
+
+            let pkt = b.buf()[..pkt_len].to_vec();
+
+            self.buffer_undecryptable(hdr.ty, pkt, info);
+
+            return Ok(pkt_len);
+        }
+
+        let aead = if is_initial_version_pkt {
+            self.initial_version_open.as_ref().unwrap()
+        } else if hdr.ty == packet::Type::ZeroRTT &&
             self.pkt_num_spaces[epoch].crypto_0rtt_open.is_some()
         {
-            // TODO: buffer 0-RTT packets instead of discarding when key is not
-            // available yet, as an optimization.
             self.pkt_num_spaces[epoch]
                 .crypto_0rtt_open
                 .as_ref()
@@ -1689,9 +2585,19 @@ impl Connection {
 
         let aead_tag_len = aead.alg().tag_len();
 
-        packet::decrypt_hdr(&mut b, &mut hdr, &aead).map_err(|e| {
-            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
-        })?;
+        if let Err(e) = packet::decrypt_hdr(&mut b, &mut hdr, &aead) {
+            if self.is_stateless_reset(&hdr, &reset_token) {
+                self.on_stateless_reset(now);
+                return Err(Error::Done);
+            }
+
+            return Err(drop_pkt_on_err(
+                e,
+                self.recv_count,
+                self.is_server,
+                &self.trace_id,
+            ));
+        }
 
         let pn = packet::decode_pkt_num(
             self.pkt_num_spaces[epoch].largest_rx_pkt_num,
@@ -1701,6 +2607,38 @@ impl Connection {
 
         let pn_len = hdr.pkt_num_len;
 
+        // A 1-RTT packet with a key phase other than the current one was
+        // either sent before the last key update and reordered, or it was
+        // sent after the peer updated its keys.
+        let mut is_key_update = false;
+
+        let aead = if hdr.ty == packet::Type::Short &&
+            hdr.key_phase != self.rx_key_phase
+        {
+            let space = &self.pkt_num_spaces[epoch];
+
+            match space.crypto_open_prev {
+                Some(ref v) if pn < self.rx_key_phase_pn => v,
+
+                _ => match space.crypto_open_next {
+                    Some(ref v) => {
+                        is_key_update = true;
+                        v
+                    },
+
+                    None =>
+                        return Err(drop_pkt_on_err(
+                            Error::CryptoFail,
+                            self.recv_count,
+                            self.is_server,
+                            &self.trace_id,
+                        )),
+                },
+            }
+        } else {
+            aead
+        };
+
         trace!(
             "{} rx pkt {:?} len={} pn={}",
             self.trace_id,
@@ -1733,22 +2671,78 @@ impl Connection {
             .ok();
         });
 
-        let mut payload = packet::decrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            &aead,
-        )
-        .map_err(|e| {
-            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
-        })?;
+        let mut payload =
+            match packet::decrypt_pkt(&mut b, pn, pn_len, payload_len, &aead) {
+                Ok(v) => v,
+
+                Err(e) => {
+                    if self.is_stateless_reset(&hdr, &reset_token) {
+                        self.on_stateless_reset(now);
+                        return Err(Error::Done);
+                    }
+
+                    return Err(drop_pkt_on_err(
+                        e,
+                        self.recv_count,
+                        self.is_server,
+                        &self.trace_id,
+                    ));
+                },
+            };
+
+        if is_key_update {
+            self.on_key_update_received(pn, now)?;
+        }
+
+        // The client switched to the negotiated version, so it won't send any
+        // more packets using the original one.
+        if hdr.ty != packet::Type::Short && hdr.version == self.version {
+            self.initial_version_open = None;
+        }
 
         if self.pkt_num_spaces[epoch].recv_pkt_num.contains(pn) {
             trace!("{} ignored duplicate packet {}", self.trace_id, pn);
             return Err(Error::Done);
         }
 
+        // A packet received from an address other than the one currently in
+        // use either probes a new path, or means that the peer migrated.
+        let from_new_addr = info.from != self.paths.active().peer_addr;
+
+        if from_new_addr {
+            // Only clients can migrate, and only once the handshake is
+            // confirmed. Clients only expect packets from the server's
+            // preferred address, once they started validating it.
+            if (!self.is_server && self.paths.get(info.from).is_none()) ||
+                !self.handshake_confirmed ||
+                hdr.ty != packet::Type::Short
+            {
+                trace!(
+                    "{} ignored packet from unexpected address {}",
+                    self.trace_id,
+                    info.from
+                );
+
+                return Err(Error::Done);
+            }
+
+            // We asked the peer not to migrate, so drop packets coming from
+            // new addresses without generating any response.
+            if self.local_transport_params.disable_active_migration &&
+                self.paths.get(info.from).is_none()
+            {
+                trace!(
+                    "{} ignored packet from {} as migration is disabled",
+                    self.trace_id,
+                    info.from
+                );
+
+                return Err(Error::Done);
+            }
+
+            self.paths.get_or_insert(info.from).request_validation();
+        }
+
         if !self.is_server && !self.got_peer_conn_id {
             if self.odcid.is_none() {
                 self.odcid = Some(self.dcid.clone());
@@ -1765,7 +2759,23 @@ impl Connection {
         if self.is_server && !self.got_peer_conn_id {
             self.dcid.extend_from_slice(&hdr.scid);
 
-            if !self.did_retry && self.version >= PROTOCOL_VERSION_DRAFT28 {
+            // A token issued in a previous connection proves that the client
+            // owns its address, so it doesn't need to be validated again.
+            if let Some(token) = &hdr.token {
+                if self.token_key.validate_new_token(
+                    &info.from,
+                    token,
+                    time::SystemTime::now(),
+                ) {
+                    trace!("{} validated address with token", self.trace_id);
+
+                    if let Some(path) = self.paths.get_mut(info.from) {
+                        path.validated = true;
+                    }
+                }
+            }
+
+            if !self.did_retry && self.version != PROTOCOL_VERSION_DRAFT27 {
                 self.local_transport_params
                     .original_destination_connection_id = Some(hdr.dcid.to_vec());
 
@@ -1780,6 +2790,10 @@ impl Connection {
         // ACK and PADDING.
         let mut ack_elicited = false;
 
+        // Whether the packet only contains probing frames, in which case it
+        // doesn't cause the peer's address to change.
+        let mut probing = true;
+
         // Process packet payload.
         while payload.cap() > 0 {
             let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
@@ -1792,7 +2806,13 @@ impl Connection {
                 ack_elicited = true;
             }
 
-            if let Err(e) = self.process_frame(frame, epoch, now) {
+            if !frame.probing() {
+                probing = false;
+            }
+
+            if let Err(e) =
+                self.process_frame(frame, epoch, &hdr.dcid, info.from, now)
+            {
                 qlog_with!(self.qlog_streamer, q, {
                     // Always conclude frame writing on error.
                     q.finish_frames().ok();
@@ -1810,6 +2830,10 @@ impl Connection {
         qlog_with!(self.qlog_streamer, q, {
             let ev = self.recovery.to_qlog();
             q.add_event(ev).ok();
+
+            if let Some(ev) = self.recovery.state_to_qlog() {
+                q.add_event(ev).ok();
+            }
         });
 
         // Only log the remote transport parameters once the connection is
@@ -1868,6 +2892,21 @@ impl Connection {
                     }
                 },
 
+                frame::Frame::ResetStream { stream_id, .. } => {
+                    let stream = match self.streams.get_mut(stream_id) {
+                        Some(v) => v,
+
+                        None => continue,
+                    };
+
+                    stream.send.reset_ack();
+
+                    if stream.is_complete() {
+                        let local = stream.local;
+                        self.streams.collect(stream_id, local);
+                    }
+                },
+
                 _ => (),
             }
         }
@@ -1880,14 +2919,50 @@ impl Connection {
 
         self.pkt_num_spaces[epoch].recv_pkt_num.insert(pn);
 
+        let ecn_counts = &mut self.pkt_num_spaces[epoch].ecn_counts;
+
+        match info.ecn {
+            Ecn::NotEct => (),
+
+            Ecn::Ect0 => ecn_counts.ect0_count += 1,
+
+            Ecn::Ect1 => ecn_counts.ect1_count += 1,
+
+            Ecn::Ce => ecn_counts.ecn_ce_count += 1,
+        }
+
         self.pkt_num_spaces[epoch].recv_pkt_need_ack.push_item(pn);
 
+        // Application packets might not need to be acked right away, if the
+        // peer requested ACKs to be sent less often.
+        let ack_elicited = if epoch == packet::EPOCH_APPLICATION {
+            self.ack_freq.on_packet_received(
+                ack_elicited,
+                pn,
+                self.pkt_num_spaces[epoch].largest_rx_pkt_num,
+                now,
+            )
+        } else {
+            ack_elicited
+        };
+
         self.pkt_num_spaces[epoch].ack_elicited =
             cmp::max(self.pkt_num_spaces[epoch].ack_elicited, ack_elicited);
 
         self.pkt_num_spaces[epoch].largest_rx_pkt_num =
             cmp::max(self.pkt_num_spaces[epoch].largest_rx_pkt_num, pn);
 
+        // Only a non-probing packet with the largest packet number received so
+        // far can make the connection switch to the peer's new address, so
+        // that reordered packets don't cause spurious migrations.
+        if self.is_server &&
+            from_new_addr &&
+            !probing &&
+            pn == self.pkt_num_spaces[epoch].largest_rx_pkt_num
+        {
+            self.on_peer_migrated(info.from);
+        }
+
         if let Some(idle_timeout) = self.idle_timeout() {
             self.idle_timer = Some(now + idle_timeout);
         }
@@ -1902,7 +2977,7 @@ impl Connection {
         if self.is_server && hdr.ty == packet::Type::Handshake {
             self.drop_epoch_state(packet::EPOCH_INITIAL);
 
-            self.verified_peer_address = true;
+            self.paths.active_mut().validated = true;
         }
 
         self.ack_eliciting_sent = false;
@@ -1913,7 +2988,13 @@ impl Connection {
     /// Writes a single QUIC packet to be sent to the peer.
     ///
     /// On success the number of bytes written to the output buffer is
-    /// returned, or [`Done`] if there was nothing to write.
+    /// returned, together with a [`SendInfo`] carrying the address the packet
+    /// should be sent to, or [`Done`] if there was nothing to write.
+    ///
+    /// The destination address might change during the lifetime of the
+    /// connection, for example when the peer migrates to a new address or
+    /// when a new path needs to be validated, so applications should always
+    /// use the one returned by this method.
     ///
     /// The application should call `send()` multiple times until [`Done`] is
     /// returned, indicating that there are no more packets to send. It is
@@ -1928,6 +3009,7 @@ impl Connection {
     ///  * When the application sends data to the peer (for examples, any time
     ///    [`stream_send()`] or [`stream_shutdown()`] are called).
     ///
+    /// [`SendInfo`]: struct.SendInfo.html
     /// [`Done`]: enum.Error.html#variant.Done
     /// [`recv()`]: struct.Connection.html#method.recv
     /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
@@ -1941,9 +3023,10 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// loop {
-    ///     let write = match conn.send(&mut out) {
+    ///     let (write, send_info) = match conn.send(&mut out) {
     ///         Ok(v) => v,
     ///
     ///         Err(quiche::Error::Done) => {
@@ -1957,11 +3040,100 @@ impl Connection {
     ///         },
     ///     };
     ///
-    ///     socket.send(&out[..write]).unwrap();
+    ///     socket.send_to(&out[..write], &send_info.to).unwrap();
     /// }
     /// # Ok::<(), quiche::Error>(())
     /// ```
-    pub fn send(&mut self, out: &mut [u8]) -> Result<usize> {
+    pub fn send(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        self.send_single(out, None)
+    }
+
+    /// Writes a batch of QUIC packets to be sent to the peer at once.
+    ///
+    /// Up to `max_segments` packets are written back to back in the output
+    /// buffer. All of them are sent to the same address with the same ECN
+    /// codepoint at the same time, and have the same size except for the last
+    /// one, which can be shorter. The output buffer can then be sent with a
+    /// single system call using UDP generic segmentation offload (e.g.
+    /// `UDP_SEGMENT` on Linux).
+    ///
+    /// On success the total number of bytes written to the output buffer is
+    /// returned, together with a [`SendBatchInfo`] carrying the size and
+    /// number of packets in the batch, or [`Done`] if there was nothing to
+    /// write.
+    ///
+    /// The application should call `send_batch()` multiple times until
+    /// [`Done`] is returned, in the same cases as [`send()`]. If an error
+    /// occurs after some packets were already written, those are returned
+    /// first, and the error is returned by the next call.
+    ///
+    /// [`SendBatchInfo`]: struct.SendBatchInfo.html
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn send_batch(
+        &mut self, out: &mut [u8], max_segments: usize,
+    ) -> Result<(usize, SendBatchInfo)> {
+        if max_segments == 0 {
+            return Err(Error::BufferTooShort);
+        }
+
+        if let Some(e) = self.send_batch_err.take() {
+            return Err(e);
+        }
+
+        let max_len = self.max_send_udp_payload_len();
+
+        let (mut written, info) = self.send_single(out, None)?;
+
+        let segment_size = written;
+        let mut segments = 1;
+
+        // Only full-sized packets can be followed by more packets, otherwise
+        // the first packet is the last one.
+        let mut done = written != max_len;
+
+        while !done && segments < max_segments && written < out.len() {
+            let end = cmp::min(written + segment_size, out.len());
+
+            match self.send_single(&mut out[written..end], Some(&info)) {
+                Ok((len, _)) => {
+                    written += len;
+                    segments += 1;
+
+                    done = len < segment_size;
+                },
+
+                // The next packet can't be part of this batch, or there is
+                // nothing more to write.
+                Err(Error::Done) | Err(Error::BufferTooShort) => break,
+
+                // Packets were already written and recorded as sent, so return
+                // them, and the error on the next call.
+                Err(e) => {
+                    self.send_batch_err = Some(e);
+                    break;
+                },
+            }
+        }
+
+        let batch_info = SendBatchInfo {
+            to: info.to,
+            ecn: info.ecn,
+            at: info.at,
+            segment_size,
+            segments,
+        };
+
+        Ok((written, batch_info))
+    }
+
+    // Writes a single QUIC packet, as part of a batch of packets like the
+    // given one if any, in which case `Done` is returned if the packet would
+    // need to be sent to a different address, with a different ECN codepoint
+    // or later than the batch.
+    fn send_single(
+        &mut self, out: &mut [u8], batch: Option<&SendInfo>,
+    ) -> Result<(usize, SendInfo)> {
         let now = time::Instant::now();
 
         if out.is_empty() {
@@ -1988,7 +3160,17 @@ impl Connection {
 
         let epoch = self.write_epoch()?;
 
-        let pkt_type = packet::Type::from_epoch(epoch);
+        let mut pkt_type = packet::Type::from_epoch(epoch);
+
+        // Application data is sent in 0-RTT packets by the client until the
+        // handshake completes.
+        if pkt_type == packet::Type::Short &&
+            !self.is_server &&
+            !self.is_established() &&
+            self.is_in_early_data()
+        {
+            pkt_type = packet::Type::ZeroRTT;
+        }
 
         // Process lost frames.
         for lost in self.recovery.lost[epoch].drain(..) {
@@ -2034,32 +3216,138 @@ impl Connection {
                     self.handshake_done_sent = false;
                 },
 
+                frame::Frame::AckFrequency { seq_num, .. } => {
+                    self.ack_freq.on_frame_lost(seq_num);
+                },
+
+                frame::Frame::NewToken { .. } => {
+                    self.new_token_pending = true;
+                },
+
                 frame::Frame::MaxStreamData { stream_id, .. } => {
                     if self.streams.get(stream_id).is_some() {
                         self.streams.mark_almost_full(stream_id, true);
                     }
                 },
 
+                frame::Frame::ResetStream {
+                    stream_id,
+                    error_code,
+                    final_size,
+                } if self.streams.get(stream_id).is_some() => {
+                    self.streams
+                        .mark_reset(stream_id, true, error_code, final_size);
+                },
+
+                frame::Frame::StopSending {
+                    stream_id,
+                    error_code,
+                } if self.streams.get(stream_id).is_some() => {
+                    self.streams.mark_stopped(stream_id, true, error_code);
+                },
+
                 frame::Frame::MaxData { .. } => {
                     self.almost_full = true;
                 },
 
+                frame::Frame::NewConnectionId { seq_num, .. } => {
+                    self.ids.on_new_connection_id_lost(seq_num);
+                },
+
+                frame::Frame::RetireConnectionId { seq_num } => {
+                    self.ids.on_retire_connection_id_lost(seq_num);
+                },
+
                 _ => (),
             }
         }
 
+        // New application packets are held back while the send time of the
+        // next paced packet is too far in the future, except for PTO probes.
+        let pacing_blocked = epoch == packet::EPOCH_APPLICATION &&
+            !is_closing &&
+            self.recovery.loss_probes[epoch] == 0 &&
+            self.recovery.pacing_timer(now).is_some();
+
+        // Path validation frames for paths other than the active one are sent
+        // in dedicated probing packets. These can only be 1-RTT packets.
+        let probe_addr = if pkt_type == packet::Type::Short &&
+            !is_closing &&
+            !pacing_blocked
+        {
+            self.paths.probe_addr()
+        } else {
+            None
+        };
+
+        // PMTU probes are sent in dedicated packets on the active path, once
+        // the handshake is confirmed, as long as the output buffer and the
+        // congestion window have room for them.
+        let pmtu_probe = if pkt_type == packet::Type::Short &&
+            !is_closing &&
+            !pacing_blocked &&
+            probe_addr.is_none() &&
+            self.handshake_confirmed &&
+            self.paths.active().validated
+        {
+            let cwnd_available = self.recovery.cwnd_available();
+
+            self.recovery
+                .pmtu_probe_size(now)
+                .filter(|&size| size <= b.cap() && size <= cwnd_available)
+        } else {
+            None
+        };
+
+        let is_probing = probe_addr.is_some() || pmtu_probe.is_some();
+
+        let send_addr =
+            probe_addr.unwrap_or_else(|| self.paths.active().peer_addr);
+
+        let ecn_marked = self.recovery.ecn_should_mark();
+        let ecn = if ecn_marked { Ecn::Ect0 } else { Ecn::NotEct };
+
+        if let Some(info) = batch {
+            if info.to != send_addr || info.ecn != ecn {
+                return Err(Error::Done);
+            }
+
+            // Paced packets can't be sent much earlier than scheduled, so the
+            // batch ends with the last packet released within the pacing
+            // horizon of the batch's time.
+            if epoch == packet::EPOCH_APPLICATION &&
+                !self.recovery.can_send_at(info.at, now)
+            {
+                return Err(Error::Done);
+            }
+        }
+
         let mut left = b.cap();
 
-        // Limit output packet size to respect peer's max_packet_size limit.
-        left = cmp::min(left, self.max_send_udp_payload_len());
+        // Limit output packet size to respect peer's max_packet_size limit,
+        // unless probing for a larger PMTU.
+        left = cmp::min(
+            left,
+            pmtu_probe.unwrap_or_else(|| self.max_send_udp_payload_len()),
+        );
 
         // Limit output packet size by congestion window size.
         left = cmp::min(left, self.recovery.cwnd_available());
 
-        // Limit data sent by the server based on the amount of data received
-        // from the client before its address is validated.
-        if !self.verified_peer_address && self.is_server {
-            left = cmp::min(left, self.max_send_bytes);
+        // Limit data sent on a path based on the amount of data received from
+        // the peer on it, before the peer's address is validated. Clients
+        // don't need to, as they only probe addresses advertised by the server.
+        if let Some(path) = self.paths.get(send_addr) {
+            if self.is_server && !path.validated {
+                left = cmp::min(left, path.max_send_bytes);
+            }
+        }
+
+        // Update the 1-RTT keys once too many packets were sent with them.
+        if pkt_type == packet::Type::Short &&
+            self.pkts_sent_with_key >= self.max_packets_per_key
+        {
+            self.initiate_key_update().ok();
         }
 
         let pn = self.pkt_num_spaces[epoch].next_pkt_num;
@@ -2096,7 +3384,7 @@ impl Connection {
             },
 
             versions: None,
-            key_phase: false,
+            key_phase: self.key_phase,
         };
 
         hdr.to_bytes(&mut b)?;
@@ -2139,7 +3427,8 @@ impl Connection {
         if self.pkt_num_spaces[epoch].recv_pkt_need_ack.len() > 0 &&
             (self.pkt_num_spaces[epoch].ack_elicited ||
                 self.recovery.loss_probes[epoch] > 0) &&
-            !is_closing
+            !is_closing &&
+            !is_probing
         {
             let ack_delay =
                 self.pkt_num_spaces[epoch].largest_rx_pkt_time.elapsed();
@@ -2148,17 +3437,35 @@ impl Connection {
                 2_u64
                     .pow(self.local_transport_params.ack_delay_exponent as u32);
 
+            // ECN counts are only sent once any marked packet was received.
+            let ecn_counts = self.pkt_num_spaces[epoch].ecn_counts;
+            let ecn_counts = if ecn_counts == frame::EcnCounts::default() {
+                None
+            } else {
+                Some(ecn_counts)
+            };
+
             let frame = frame::Frame::ACK {
                 ack_delay,
                 ranges: self.pkt_num_spaces[epoch].recv_pkt_need_ack.clone(),
+                ecn_counts,
             };
 
             if push_frame_to_pkt!(frames, frame, payload_len, left) {
                 self.pkt_num_spaces[epoch].ack_elicited = false;
+
+                if epoch == packet::EPOCH_APPLICATION {
+                    self.ack_freq.on_ack_sent();
+                }
             }
         }
 
-        if pkt_type == packet::Type::Short && !is_closing {
+        // Only ACKs can be sent while paced packets are held back.
+        if pacing_blocked {
+            left = 0;
+        }
+
+        if pkt_type == packet::Type::Short && !is_closing && !is_probing {
             // Create HANDSHAKE_DONE frame.
             if self.is_established() &&
                 !self.handshake_done_sent &&
@@ -2174,6 +3481,59 @@ impl Connection {
                 }
             }
 
+            // Create NEW_TOKEN frame.
+            if self.new_token_pending {
+                let token = self.token_key.mint_new_token(
+                    &self.paths.active().peer_addr,
+                    time::SystemTime::now(),
+                );
+
+                let frame = frame::Frame::NewToken { token };
+
+                if push_frame_to_pkt!(frames, frame, payload_len, left) {
+                    self.new_token_pending = false;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create ACK_FREQUENCY frame.
+            if self.ack_freq_negotiated() && self.handshake_confirmed {
+                let min_ack_delay = time::Duration::from_micros(
+                    self.peer_transport_params.min_ack_delay,
+                );
+
+                let params = self.recovery.ack_frequency(min_ack_delay);
+
+                if self.ack_freq.should_request(params, self.recovery.rtt()) {
+                    let max_ack_delay = params.max_ack_delay.as_micros() as u64;
+
+                    let frame = frame::Frame::AckFrequency {
+                        seq_num: self.ack_freq.next_seq_num(),
+                        ack_eliciting_threshold: params.ack_eliciting_threshold,
+                        request_max_ack_delay: max_ack_delay,
+                        reordering_threshold: params.reordering_threshold,
+                    };
+
+                    if push_frame_to_pkt!(frames, frame, payload_len, left) {
+                        self.ack_freq.on_frame_sent(params);
+
+                        // Loss detection needs to allow for the peer delaying
+                        // ACKs by more than its `max_ack_delay`.
+                        self.recovery.max_ack_delay = cmp::max(
+                            time::Duration::from_millis(
+                                self.peer_transport_params.max_ack_delay,
+                            ),
+                            params.max_ack_delay,
+                        );
+
+                        ack_eliciting = true;
+                        in_flight = true;
+                    }
+                }
+            }
+
             // Create MAX_STREAMS_BIDI frame.
             if self.streams.should_update_max_streams_bidi() {
                 let frame = frame::Frame::MaxStreamsBidi {
@@ -2275,6 +3635,71 @@ impl Connection {
                     in_flight = true;
                 }
             }
+
+            // Create RESET_STREAM frames as needed.
+            for (stream_id, (error_code, final_size)) in self
+                .streams
+                .reset()
+                .map(|(&k, &v)| (k, v))
+                .collect::<Vec<(u64, (u64, u64))>>()
+            {
+                let frame = frame::Frame::ResetStream {
+                    stream_id,
+                    error_code,
+                    final_size,
+                };
+
+                if push_frame_to_pkt!(frames, frame, payload_len, left) {
+                    self.streams.mark_reset(stream_id, false, 0, 0);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create STOP_SENDING frames as needed.
+            for (stream_id, error_code) in self
+                .streams
+                .stopped()
+                .map(|(&k, &v)| (k, v))
+                .collect::<Vec<(u64, u64)>>()
+            {
+                let frame = frame::Frame::StopSending {
+                    stream_id,
+                    error_code,
+                };
+
+                if push_frame_to_pkt!(frames, frame, payload_len, left) {
+                    self.streams.mark_stopped(stream_id, false, 0);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create NEW_CONNECTION_ID frames as needed.
+            while let Some(frame) = self.ids.next_new_connection_id() {
+                if !push_frame_to_pkt!(frames, frame, payload_len, left) {
+                    break;
+                }
+
+                self.ids.on_new_connection_id_sent();
+
+                ack_eliciting = true;
+                in_flight = true;
+            }
+
+            // Create RETIRE_CONNECTION_ID frames as needed.
+            while let Some(frame) = self.ids.next_retire_connection_id() {
+                if !push_frame_to_pkt!(frames, frame, payload_len, left) {
+                    break;
+                }
+
+                self.ids.on_retire_connection_id_sent();
+
+                ack_eliciting = true;
+                in_flight = true;
+            }
         }
 
         // Create CONNECTION_CLOSE frame.
@@ -2310,24 +3735,53 @@ impl Connection {
             }
         }
 
-        // Create PATH_RESPONSE frame.
-        if let Some(ref challenge) = self.challenge {
-            let frame = frame::Frame::PathResponse {
-                data: challenge.clone(),
-            };
+        // Whether the packet carries path validation frames.
+        let mut has_path_frames = false;
 
-            if push_frame_to_pkt!(frames, frame, payload_len, left) {
-                self.challenge = None;
+        if pkt_type == packet::Type::Short && !is_closing {
+            let challenge_timeout =
+                cmp::max(self.recovery.pto(), recovery::INITIAL_RTT * 2);
 
-                ack_eliciting = true;
-                in_flight = true;
+            if let Some(path) = self.paths.get_mut(send_addr) {
+                // Create PATH_RESPONSE frames.
+                while let Some(data) = path.responses.front() {
+                    let frame =
+                        frame::Frame::PathResponse { data: data.clone() };
+
+                    if !push_frame_to_pkt!(frames, frame, payload_len, left) {
+                        break;
+                    }
+
+                    path.responses.pop_front();
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                    has_path_frames = true;
+                }
+
+                // Create PATH_CHALLENGE frame.
+                if path.challenge_pending {
+                    let data = path.new_challenge();
+
+                    let frame =
+                        frame::Frame::PathChallenge { data: data.clone() };
+
+                    if push_frame_to_pkt!(frames, frame, payload_len, left) {
+                        path.on_challenge_sent(data, challenge_timeout, now);
+
+                        ack_eliciting = true;
+                        in_flight = true;
+                        has_path_frames = true;
+                    }
+                }
             }
         }
 
         // Create CRYPTO frame.
         if self.pkt_num_spaces[epoch].crypto_stream.is_flushable() &&
             left > frame::MAX_CRYPTO_OVERHEAD &&
-            !is_closing
+            !is_closing &&
+            !is_probing
         {
             let crypto_len = left - frame::MAX_CRYPTO_OVERHEAD;
             let crypto_buf = self.pkt_num_spaces[epoch]
@@ -2348,7 +3802,8 @@ impl Connection {
         #[cfg(feature = "quic-dgram")]
         if pkt_type == packet::Type::Short &&
             left > frame::MAX_DGRAM_OVERHEAD &&
-            !is_closing
+            !is_closing &&
+            !is_probing
         {
             if let Some(max_dgram_payload) = self.dgram_max_writable_len() {
                 while let Some(len) = self.dgram_send_queue.peek() {
@@ -2380,12 +3835,15 @@ impl Connection {
             }
         }
 
-        // Create a single STREAM frame for the first stream that is flushable.
-        if pkt_type == packet::Type::Short &&
+        // Create a single STREAM frame for the first stream that is flushable,
+        // or more if the scheduler limits how much a stream can send.
+        if (pkt_type == packet::Type::Short ||
+            pkt_type == packet::Type::ZeroRTT) &&
             left > frame::MAX_STREAM_OVERHEAD &&
-            !is_closing
+            !is_closing &&
+            !is_probing
         {
-            while let Some(stream_id) = self.streams.pop_flushable() {
+            while let Some((stream_id, limit)) = self.streams.pop_flushable() {
                 let stream = match self.streams.get_mut(stream_id) {
                     Some(v) => v,
 
@@ -2402,18 +3860,31 @@ impl Connection {
                     octets::varint_len(off) +
                     octets::varint_len(left as u64);
 
+                // If there is no room left for the stream's data, keep it
+                // queued so it can be sent in the next packet.
                 let max_len = match left.checked_sub(overhead) {
-                    Some(v) => v,
+                    Some(v) => cmp::min(v, limit),
 
-                    None => continue,
+                    _ => {
+                        self.streams.requeue_flushable(stream_id, 0);
+                        break;
+                    },
                 };
 
+                let limited = max_len == limit && limit < left - overhead;
+
                 let stream_buf = stream.send.pop(max_len)?;
 
                 if stream_buf.is_empty() && !stream_buf.fin() {
-                    continue;
+                    if stream.is_flushable() {
+                        self.streams.requeue_flushable(stream_id, 0);
+                    }
+
+                    break;
                 }
 
+                let sent = stream_buf.len();
+
                 let frame = frame::Frame::Stream {
                     stream_id,
                     data: stream_buf,
@@ -2425,17 +3896,18 @@ impl Connection {
                     has_data = true;
                 }
 
-                // If the stream is still flushable, push it to the back of the
-                // queue again.
+                // If the stream is still flushable, push it to the queue again,
+                // according to its priority.
                 if stream.is_flushable() {
-                    let urgency = stream.urgency;
-                    let incremental = stream.incremental;
-                    self.streams.push_flushable(stream_id, urgency, incremental);
+                    self.streams.requeue_flushable(stream_id, sent);
                 }
 
-                // When fuzzing, try to coalesce multiple STREAM frames in the
-                // same packet, so it's easier to generate fuzz corpora.
-                if cfg!(feature = "fuzzing") && left > frame::MAX_STREAM_OVERHEAD
+                // When the stream was limited by the scheduler, use the rest
+                // of the packet for the next stream. When fuzzing, also try to
+                // coalesce multiple STREAM frames in the same packet, so it's
+                // easier to generate fuzz corpora.
+                if (limited || cfg!(feature = "fuzzing")) &&
+                    left > frame::MAX_STREAM_OVERHEAD
                 {
                     continue;
                 }
@@ -2444,13 +3916,40 @@ impl Connection {
             }
         }
 
+        // Create PING and PADDING frames for the PMTU probe, filling the
+        // packet up to the probe size.
+        if pmtu_probe.is_some() {
+            let frame = frame::Frame::Ping;
+
+            if push_frame_to_pkt!(frames, frame, payload_len, left) {
+                ack_eliciting = true;
+                in_flight = true;
+            }
+
+            if left > 0 {
+                let frame = frame::Frame::Padding { len: left };
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
         // Create PING for PTO probe if no other ack-elicitng frame is sent.
+        // IMMEDIATE_ACK is used instead if the peer might be delaying ACKs.
         if self.recovery.loss_probes[epoch] > 0 &&
             !ack_eliciting &&
             left >= 1 &&
             !is_closing
         {
-            let frame = frame::Frame::Ping;
+            let frame = if pkt_type == packet::Type::Short &&
+                self.ack_freq_negotiated()
+            {
+                frame::Frame::ImmediateAck
+            } else {
+                frame::Frame::Ping
+            };
 
             if push_frame_to_pkt!(frames, frame, payload_len, left) {
                 ack_eliciting = true;
@@ -2470,6 +3969,23 @@ impl Connection {
             return Err(Error::Done);
         }
 
+        // Pad packets carrying path validation frames to the minimum QUIC
+        // datagram size, as long as the anti-amplification limit allows it.
+        if has_path_frames {
+            let pad_len = cmp::min(
+                MIN_CLIENT_INITIAL_LEN.saturating_sub(overhead + payload_len),
+                left,
+            );
+
+            if pad_len > 0 {
+                let frame = frame::Frame::Padding { len: pad_len };
+
+                payload_len += frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
         // Pad the client's initial packet.
         if !self.is_server && pkt_type == packet::Type::Initial {
             let pkt_len = pn_len + payload_len + crypto_overhead;
@@ -2573,6 +4089,13 @@ impl Connection {
         // So drop them here.
         frames.retain(|f| f.retransmittable());
 
+        // Paced packets are released at their scheduled send time.
+        let at = if in_flight && epoch == packet::EPOCH_APPLICATION {
+            self.recovery.get_packet_send_time(now)
+        } else {
+            now
+        };
+
         let sent_pkt = recovery::Sent {
             pkt_num: pn,
             frames,
@@ -2587,6 +4110,8 @@ impl Connection {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data,
+            pmtud_probe: pmtu_probe.is_some(),
+            ecn_marked,
         };
 
         self.recovery.on_packet_sent(
@@ -2600,10 +4125,18 @@ impl Connection {
         qlog_with!(self.qlog_streamer, q, {
             let ev = self.recovery.to_qlog();
             q.add_event(ev).ok();
+
+            if let Some(ev) = self.recovery.state_to_qlog() {
+                q.add_event(ev).ok();
+            }
         });
 
         self.pkt_num_spaces[epoch].next_pkt_num += 1;
 
+        if pkt_type == packet::Type::Short {
+            self.pkts_sent_with_key += 1;
+        }
+
         self.sent_count += 1;
 
         #[cfg(feature = "quic-dgram")]
@@ -2617,7 +4150,9 @@ impl Connection {
             self.drop_epoch_state(packet::EPOCH_INITIAL);
         }
 
-        self.max_send_bytes = self.max_send_bytes.saturating_sub(written);
+        if let Some(path) = self.paths.get_mut(send_addr) {
+            path.max_send_bytes = path.max_send_bytes.saturating_sub(written);
+        }
 
         // (Re)start the idle timer if we are sending the first ack-eliciting
         // packet since last receiving a packet.
@@ -2631,18 +4166,26 @@ impl Connection {
             self.ack_eliciting_sent = true;
         }
 
-        Ok(written)
+        Ok((written, SendInfo {
+            to: send_addr,
+            ecn,
+            at,
+        }))
     }
 
     // Returns the maximum len of a packet to be sent. This is max_packet_size
-    // as sent by the peer, except during the handshake when we haven't parsed
-    // transport parameters yet, so use a default value then.
+    // as sent by the peer, or the discovered PMTU if smaller, except during the
+    // handshake when we haven't parsed transport parameters yet, so use a
+    // default value then.
     fn max_send_udp_payload_len(&self) -> usize {
         if self.is_established() {
-            // We cap the maximum packet size to 16KB or so, so that it can be
-            // always encoded with a 2-byte varint.
-            cmp::min(16383, self.peer_transport_params.max_udp_payload_size)
-                as usize
+            let max_len = self.peer_max_udp_payload_len();
+
+            match self.recovery.pmtu() {
+                Some(pmtu) => cmp::min(max_len, pmtu),
+
+                None => max_len,
+            }
         } else {
             // Allow for 1200 bytes (minimum QUIC packet size) during the
             // handshake.
@@ -2650,6 +4193,42 @@ impl Connection {
         }
     }
 
+    // Returns whether both endpoints support the ACK frequency extension.
+    fn ack_freq_negotiated(&self) -> bool {
+        self.local_transport_params.min_ack_delay != 0 &&
+            self.peer_transport_params.min_ack_delay != 0
+    }
+
+    // Returns the peer's max_packet_size limit.
+    fn peer_max_udp_payload_len(&self) -> usize {
+        // We cap the maximum packet size to 16KB or so, so that it can be
+        // always encoded with a 2-byte varint.
+        cmp::min(16383, self.peer_transport_params.max_udp_payload_size) as usize
+    }
+
+    // Reports changes to the path MTU found by path MTU discovery.
+    fn check_pmtu_update(&mut self) {
+        let old = match self.recovery.pmtu_update() {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        let new = self.max_send_udp_payload_len();
+
+        trace!("{} pmtu updated from {} to {}", self.trace_id, old, new);
+
+        qlog_with!(self.qlog_streamer, q, {
+            let ev = qlog::event::Event::mtu_updated(
+                Some(old as u64),
+                new as u64,
+                None,
+            );
+
+            q.add_event(ev).ok();
+        });
+    }
+
     /// Reads contiguous data from a stream into the provided slice.
     ///
     /// The slice must be sized by the caller and will be populated up to its
@@ -2658,7 +4237,11 @@ impl Connection {
     /// On success the amount of bytes read and a flag indicating the fin state
     /// is returned as a tuple, or [`Done`] if there is no data to read.
     ///
+    /// If the peer reset the stream, any data that was not read yet is dropped
+    /// and [`StreamReset`] is returned with the peer's error code.
+    ///
     /// [`Done`]: enum.Error.html#variant.Done
+    /// [`StreamReset`]: enum.Error.html#variant.StreamReset
     ///
     /// ## Examples:
     ///
@@ -2667,7 +4250,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// # let stream_id = 0;
     /// while let Ok((read, fin)) = conn.stream_recv(stream_id, &mut buf) {
     ///     println!("Got {} bytes on stream {}", read, stream_id);
@@ -2696,7 +4280,25 @@ impl Connection {
         #[cfg(feature = "qlog")]
         let offset = stream.recv.off_back();
 
-        let (read, fin) = stream.recv.pop(out)?;
+        let (read, fin) = match stream.recv.pop(out) {
+            Ok(v) => v,
+
+            Err(e) => {
+                // The stream was reset by the peer, so it won't be readable
+                // anymore, and can be collected once complete.
+                let complete = stream.is_complete();
+
+                let local = stream.local;
+
+                self.streams.mark_readable(stream_id, false);
+
+                if complete {
+                    self.streams.collect(stream_id, local);
+                }
+
+                return Err(e);
+            },
+        };
 
         self.max_rx_data_next = self.max_rx_data_next.saturating_add(read as u64);
 
@@ -2756,7 +4358,11 @@ impl Connection {
     /// completed (whenever [`is_established()`] returns `true`) or during
     /// early data if enabled (whenever [`is_in_early_data()`] returns `true`).
     ///
+    /// If the peer asked to stop sending data on the stream, [`StreamStopped`]
+    /// is returned with the peer's error code.
+    ///
     /// [`Done`]: enum.Error.html#variant.Done
+    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
     /// [`is_established()`]: struct.Connection.html#method.is_established
     /// [`is_in_early_data()`]: struct.Connection.html#method.is_in_early_data
     ///
@@ -2767,7 +4373,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// # let stream_id = 0;
     /// conn.stream_send(stream_id, b"hello", true)?;
     /// # Ok::<(), quiche::Error>(())
@@ -2782,6 +4389,15 @@ impl Connection {
             return Err(Error::InvalidStreamState);
         }
 
+        // The peer asked us to stop sending data on the stream.
+        let error = self.streams.get(stream_id).and_then(|s| s.send.error());
+
+        if let Some(e) = error {
+            self.streams.mark_writable(stream_id, false);
+
+            return Err(Error::StreamStopped(e));
+        }
+
         // Mark the connection as blocked if the connection-level flow control
         // limit doesn't let us buffer all the data.
         //
@@ -2885,49 +4501,85 @@ impl Connection {
             return Ok(());
         }
 
-        stream.urgency = urgency;
-        stream.incremental = incremental;
-
-        // TODO: reprioritization
+        self.streams.update_priority(stream_id, urgency, incremental);
 
         Ok(())
     }
 
+    /// Replaces the stream scheduler of the connection.
+    ///
+    /// Streams that have data ready to be sent are handed to the new
+    /// scheduler.
+    ///
+    /// See [`Config::set_stream_scheduler()`] for configuring the scheduler
+    /// of new connections.
+    ///
+    /// [`Config::set_stream_scheduler()`]: struct.Config.html#method.set_stream_scheduler
+    pub fn set_stream_scheduler(&mut self, scheduler: Box<dyn StreamScheduler>) {
+        self.streams.set_scheduler(scheduler);
+    }
+
     /// Shuts down reading or writing from/to the specified stream.
     ///
     /// When the `direction` argument is set to [`Shutdown::Read`], outstanding
     /// data in the stream's receive buffer is dropped, and no additional data
     /// is added to it. Data received after calling this method is still
     /// validated and acked but not stored, and [`stream_recv()`] will not
-    /// return it to the application.
+    /// return it to the application. Unless all data was already received,
+    /// a `STOP_SENDING` frame with the `err` error code is sent to the peer.
     ///
     /// When the `direction` argument is set to [`Shutdown::Write`], outstanding
     /// data in the stream's send buffer is dropped, and no additional data
     /// is added to it. Data passed to [`stream_send()`] after calling this
-    /// method will be ignored.
+    /// method will be ignored. A `RESET_STREAM` frame with the `err` error
+    /// code is sent to the peer.
     ///
     /// [`Shutdown::Read`]: enum.Shutdown.html#variant.Read
     /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
     /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
     /// [`stream_send()`]: struct.Connection.html#method.stream_send
     pub fn stream_shutdown(
-        &mut self, stream_id: u64, direction: Shutdown, _err: u64,
+        &mut self, stream_id: u64, direction: Shutdown, err: u64,
     ) -> Result<()> {
         // Get existing stream.
         let stream = self.streams.get_mut(stream_id).ok_or(Error::Done)?;
 
         match direction {
-            // TODO: send STOP_SENDING
             Shutdown::Read => {
+                // We can't read on our own unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    stream::is_local(stream_id, self.is_server)
+                {
+                    return Err(Error::InvalidStreamState);
+                }
+
                 stream.recv.shutdown()?;
 
+                // Ask the peer to stop sending, unless there's nothing left
+                // to send.
+                if !stream.recv.is_fin() {
+                    self.streams.mark_stopped(stream_id, true, err);
+                }
+
                 // Once shutdown, the stream is guaranteed to be non-readable.
                 self.streams.mark_readable(stream_id, false);
             },
 
-            // TODO: send RESET_STREAM
             Shutdown::Write => {
-                stream.send.shutdown()?;
+                // We can't write on the peer's unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    !stream::is_local(stream_id, self.is_server)
+                {
+                    return Err(Error::InvalidStreamState);
+                }
+
+                let (final_size, unsent) = stream.send.shutdown()?;
+
+                // Data that was buffered but never sent doesn't count towards
+                // the connection flow control limit anymore.
+                self.tx_data = self.tx_data.saturating_sub(unsent);
+
+                self.streams.mark_reset(stream_id, true, err, final_size);
 
                 // Once shutdown, the stream is guaranteed to be non-writable.
                 self.streams.mark_writable(stream_id, false);
@@ -3026,7 +4678,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// // Iterate over readable streams.
     /// for stream_id in conn.readable() {
     ///     // Stream is readable, read until there's no more data.
@@ -3059,7 +4712,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// // Iterate over writable streams.
     /// for stream_id in conn.writable() {
     ///     // Stream is writable, write some data.
@@ -3093,7 +4747,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// let mut dgram_buf = [0; 512];
     /// while let Ok((len)) = conn.dgram_recv(&mut dgram_buf) {
     ///     println!("Got {} bytes of DATAGRAM", len);
@@ -3129,7 +4784,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// conn.dgram_send(b"hello")?;
     /// # Ok::<(), quiche::Error>(())
     /// ```
@@ -3171,7 +4827,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// conn.dgram_send(b"hello")?;
     /// conn.dgram_purge_outgoing(&|d: &[u8]| -> bool { d[0] == 0 });
     /// # Ok::<(), quiche::Error>(())
@@ -3195,7 +4852,8 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = [0xba; 16];
-    /// # let mut conn = quiche::accept(&scid, None, &mut config)?;
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
     /// if let Some(payload_size) = conn.dgram_max_writable_len() {
     ///     if payload_size > 5 {
     ///         conn.dgram_send(b"hello")?;
@@ -3248,11 +4906,19 @@ impl Connection {
             // processing the other timers.
             self.draining_timer
         } else {
-            // Use the lowest timer value (i.e. "sooner") among idle and loss
-            // detection timers. If they are both unset (i.e. `None`) then the
-            // result is `None`, but if at least one of them is set then a
-            // `Some(...)` value is returned.
-            let timers = [self.idle_timer, self.recovery.loss_detection_timer()];
+            // Use the lowest timer value (i.e. "sooner") among idle, loss
+            // detection, path validation, pacing, ACK and key discard timers.
+            // If they are all unset (i.e. `None`) then the result is `None`,
+            // but if at least one of them is set then a `Some(...)` value is
+            // returned.
+            let timers = [
+                self.idle_timer,
+                self.recovery.loss_detection_timer(),
+                self.paths.challenge_timer(),
+                self.recovery.pacing_timer(time::Instant::now()),
+                self.ack_freq.ack_timer(),
+                self.prev_key_discard_timer,
+            ];
 
             timers.iter().filter_map(|&x| x).min()
         };
@@ -3306,24 +4972,100 @@ impl Connection {
             }
         }
 
-        if let Some(timer) = self.recovery.loss_detection_timer() {
-            if timer <= now {
-                trace!("{} loss detection timeout expired", self.trace_id);
+        for outcome in self.paths.on_challenge_timeout(now) {
+            let peer_addr = match outcome {
+                path::ChallengeTimeout::Failed(v) => v,
 
-                self.recovery.on_loss_detection_timeout(
-                    self.is_established(),
-                    now,
-                    &self.trace_id,
-                );
+                path::ChallengeTimeout::Retry => continue,
+            };
 
-                qlog_with!(self.qlog_streamer, q, {
-                    let ev = self.recovery.to_qlog();
-                    q.add_event(ev).ok();
-                });
+            trace!("{} path validation to {} failed", self.trace_id, peer_addr);
 
-                return;
-            }
-        }
+            if peer_addr != self.paths.active().peer_addr {
+                // Clients only validate other paths to move to the server's
+                // preferred address.
+                if !self.is_server {
+                    self.on_preferred_address_failed();
+                }
+
+                continue;
+            }
+
+            // The peer migrated to an address that couldn't be validated, so
+            // go back to the last validated one.
+            match self.paths.revert_to_fallback() {
+                Some(fallback) =>
+                    if fallback.ip() != peer_addr.ip() {
+                        self.recovery.on_path_change();
+                    },
+
+                // There is no validated path left to use.
+                None => {
+                    trace!(
+                        "{} no viable path left, closing connection",
+                        self.trace_id
+                    );
+
+                    // Record NO_VIABLE_PATH as the reason the connection was
+                    // closed, even though no CONNECTION_CLOSE can be sent.
+                    self.error = Some(0x10);
+
+                    qlog_with!(self.qlog_streamer, q, {
+                        let ev = qlog::event::Event::connection_state_updated_min(
+                            qlog::ConnectionState::Closed,
+                        );
+                        q.add_event(ev).ok();
+
+                        q.finish_log().ok();
+                    });
+
+                    self.closed = true;
+                    return;
+                },
+            }
+        }
+
+        if let Some(timer) = self.prev_key_discard_timer {
+            if timer <= now {
+                trace!("{} discarding previous 1-RTT keys", self.trace_id);
+
+                let space = &mut self.pkt_num_spaces[packet::EPOCH_APPLICATION];
+                space.crypto_open_prev = None;
+
+                self.prev_key_discard_timer = None;
+            }
+        }
+
+        if self.ack_freq.on_timeout(now) {
+            trace!("{} ack delay timeout expired", self.trace_id);
+
+            self.pkt_num_spaces[packet::EPOCH_APPLICATION].ack_elicited = true;
+        }
+
+        if let Some(timer) = self.recovery.loss_detection_timer() {
+            if timer <= now {
+                trace!("{} loss detection timeout expired", self.trace_id);
+
+                self.recovery.on_loss_detection_timeout(
+                    self.is_established(),
+                    now,
+                    &self.trace_id,
+                );
+
+                self.check_pmtu_update();
+
+                qlog_with!(self.qlog_streamer, q, {
+                    let ev = self.recovery.to_qlog();
+                    q.add_event(ev).ok();
+
+                    if let Some(ev) = self.recovery.state_to_qlog() {
+                        q.add_event(ev).ok();
+                    }
+                });
+
+                return;
+            }
+        }
     }
 
     /// Closes the connection with the given error and reason.
@@ -3386,6 +5128,34 @@ impl Connection {
         self.handshake.peer_cert()
     }
 
+    /// Returns the serialized TLS session and the peer's transport parameters,
+    /// which can be used to resume a later connection with [`set_session()`].
+    ///
+    /// This is only available on the client, once the server has sent a
+    /// session ticket after the handshake completed.
+    ///
+    /// [`set_session()`]: struct.Connection.html#method.set_session
+    pub fn session(&self) -> Option<Vec<u8>> {
+        self.session.clone()
+    }
+
+    /// Returns the serialized path characteristics of the connection, which
+    /// can be used to ramp up a later connection with [`set_path_params()`].
+    ///
+    /// This is only available once the RTT of the path was measured.
+    ///
+    /// [`set_path_params()`]: struct.Connection.html#method.set_path_params
+    pub fn path_params(&self) -> Option<Vec<u8>> {
+        let (min_rtt, cwnd) = self.recovery.path_params()?;
+
+        let mut buffer = Vec::with_capacity(16);
+
+        buffer.extend_from_slice(&(min_rtt.as_micros() as u64).to_be_bytes());
+        buffer.extend_from_slice(&(cwnd as u64).to_be_bytes());
+
+        Some(buffer)
+    }
+
     /// Returns true if the connection handshake is complete.
     pub fn is_established(&self) -> bool {
         self.handshake.is_completed()
@@ -3402,6 +5172,96 @@ impl Connection {
         self.handshake.is_in_early_data()
     }
 
+    /// Initiates an update of the keys used to protect 1-RTT packets.
+    ///
+    /// Keys are also updated automatically once the number of packets
+    /// configured with [`set_max_packets_per_key()`] is reached.
+    ///
+    /// [`InvalidState`] is returned if the handshake is not confirmed yet.
+    /// [`Done`] is returned if a previous key update is still in progress,
+    /// that is the peer didn't acknowledge a packet sent with the current
+    /// keys, or didn't update its own keys yet.
+    ///
+    /// [`set_max_packets_per_key()`]:
+    /// struct.Config.html#method.set_max_packets_per_key
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn initiate_key_update(&mut self) -> Result<()> {
+        if !self.handshake_confirmed {
+            return Err(Error::InvalidState);
+        }
+
+        if self.key_update_pn.is_some() || self.key_phase != self.rx_key_phase
+        {
+            return Err(Error::Done);
+        }
+
+        self.update_seal_key()
+    }
+
+    /// Schedules a NEW_TOKEN frame to be sent to the client.
+    ///
+    /// The token allows the client to skip address validation in a future
+    /// connection to the same server (or to another server sharing the same
+    /// [`Config`]), as the peer's address is considered validated when a
+    /// valid token is received in its Initial packet.
+    ///
+    /// [`InvalidState`] is returned if the connection is not a server one, or
+    /// if the handshake is not confirmed yet.
+    ///
+    /// [`Config`]: struct.Config.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn send_new_token(&mut self) -> Result<()> {
+        if !self.is_server || !self.handshake_confirmed {
+            return Err(Error::InvalidState);
+        }
+
+        self.new_token_pending = true;
+
+        Ok(())
+    }
+
+    /// Returns the latest token received from the server in a NEW_TOKEN frame.
+    ///
+    /// The token can be passed to [`set_token()`] on a future connection to the
+    /// same server, so that the client's address doesn't need to be validated
+    /// again.
+    ///
+    /// [`set_token()`]: struct.Connection.html#method.set_token
+    pub fn new_token(&self) -> Option<Vec<u8>> {
+        self.new_token.clone()
+    }
+
+    /// Configures the address validation token to send in Initial packets.
+    ///
+    /// This is meant to be used by clients with a token previously returned by
+    /// [`new_token()`], and must be called immediately after creating the
+    /// connection with [`connect()`], before any packet is sent.
+    ///
+    /// [`InvalidState`] is returned if the connection is a server one.
+    ///
+    /// [`new_token()`]: struct.Connection.html#method.new_token
+    /// [`connect()`]: fn.connect.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_token(&mut self, token: &[u8]) -> Result<()> {
+        if self.is_server {
+            return Err(Error::InvalidState);
+        }
+
+        self.token = Some(token.to_vec());
+
+        Ok(())
+    }
+
+    /// Returns true if the connection was reset by the peer.
+    ///
+    /// This happens when a stateless reset is received, in which case the
+    /// connection immediately enters the draining state, and will be closed
+    /// once the draining timeout expires.
+    pub fn is_reset(&self) -> bool {
+        self.reset
+    }
+
     /// Returns true if the connection is closed.
     ///
     /// If this returns true, the connection object can be dropped.
@@ -3409,20 +5269,114 @@ impl Connection {
         self.closed
     }
 
+    /// Returns the transport error code the connection was closed with by
+    /// the local endpoint, if any.
+    ///
+    /// This is the error code sent in the local CONNECTION_CLOSE frame, or
+    /// the reason the connection was closed without sending one (e.g. when
+    /// no network path to the peer could be validated). Application errors
+    /// passed to [`close()`] are not reported.
+    ///
+    /// [`close()`]: struct.Connection.html#method.close
+    pub fn local_error(&self) -> Option<u64> {
+        self.error
+    }
+
+    /// Provides an additional source connection ID that the peer can use to
+    /// reach this endpoint.
+    ///
+    /// The `reset_token` is the stateless reset token the peer can use to
+    /// detect that the connection was lost, and needs to be unique to the
+    /// connection ID. The connection ID needs to have the same length as the
+    /// one used to create the connection.
+    ///
+    /// The peer limits how many connection IDs it's willing to store at the
+    /// same time (see [`source_cids_left()`]). When this limit is reached,
+    /// [`IdLimit`] is returned, unless `retire_if_needed` is `true`, in which
+    /// case the peer is asked to retire the oldest connection ID still in use.
+    ///
+    /// On success the connection ID's sequence number is returned. Providing
+    /// the same connection ID again is a no-op.
+    ///
+    /// [`source_cids_left()`]: struct.Connection.html#method.source_cids_left
+    /// [`IdLimit`]: enum.Error.html#variant.IdLimit
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = [0xba; 16];
+    /// # let from = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// let new_scid = [0xbb; 16];
+    /// let reset_token = 0xcafe;
+    ///
+    /// conn.new_source_cid(&new_scid, reset_token, false)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn new_source_cid(
+        &mut self, scid: &[u8], reset_token: u128, retire_if_needed: bool,
+    ) -> Result<u64> {
+        // Short header packets don't carry the connection ID's length, so all
+        // of them need to be the same size.
+        if self.scid.is_empty() || scid.len() != self.scid.len() {
+            return Err(Error::InvalidState);
+        }
+
+        self.ids.new_scid(scid, reset_token, retire_if_needed)
+    }
+
+    /// Returns the number of additional source connection IDs the peer is
+    /// willing to store.
+    pub fn source_cids_left(&self) -> usize {
+        self.ids.scids_left()
+    }
+
+    /// Returns the next source connection ID that was retired by the peer,
+    /// if any.
+    ///
+    /// Packets addressed to retired connection IDs are not accepted anymore,
+    /// so the application can stop routing them to this connection.
+    pub fn retired_scid_next(&mut self) -> Option<Vec<u8>> {
+        self.ids.retired_scid_next()
+    }
+
+    /// Returns the number of destination connection IDs provided by the peer
+    /// that are not in use.
+    pub fn available_dcids(&self) -> usize {
+        self.ids.spare_dcids()
+    }
+
     /// Collects and returns statistics about the connection.
     pub fn stats(&self) -> Stats {
         Stats {
             recv: self.recv_count,
             sent: self.sent_count,
             lost: self.recovery.lost_count,
+            spurious_lost: self.recovery.spurious_lost_count,
             cwnd: self.recovery.cwnd(),
             rtt: self.recovery.rtt(),
             delivery_rate: self.recovery.delivery_rate(),
+            undecryptable_buffered: self.undecryptable_buffered_count,
+            undecryptable_dropped: self.undecryptable_dropped_count,
+            pmtu: self.max_send_udp_payload_len(),
+            pacing_rate: self.recovery.pacing_rate(),
         }
     }
 
     fn encode_transport_params(&mut self) -> Result<()> {
-        let mut raw_params = [0; 128];
+        let mut raw_params = [0; 256];
+
+        // Draft versions predate the version_information transport parameter.
+        self.local_transport_params.version_information =
+            if version_is_draft(self.version) {
+                None
+            } else {
+                Some(VersionInformation {
+                    chosen_version: self.version,
+                    available_versions: SUPPORTED_VERSIONS.to_vec(),
+                })
+            };
 
         let raw_params = TransportParams::encode(
             &self.local_transport_params,
@@ -3435,13 +5389,149 @@ impl Connection {
         Ok(())
     }
 
+    /// Switches the connection to a compatible version and derives the
+    /// Initial keys for it.
+    fn set_compatible_version(&mut self, version: u32) -> Result<()> {
+        // Initial keys are always derived from the destination connection ID
+        // used by the client, which the server might have replaced in a Retry.
+        let dcid = if self.is_server {
+            let tp = &self.local_transport_params;
+
+            tp.retry_source_connection_id
+                .as_ref()
+                .or(tp.original_destination_connection_id.as_ref())
+                .ok_or(Error::InvalidState)?
+                .clone()
+        } else {
+            self.dcid.clone()
+        };
+
+        trace!(
+            "{} switching from version {:x} to {:x}",
+            self.trace_id,
+            self.version,
+            version
+        );
+
+        self.version = version;
+
+        // Servers keep the original version's key around, to decrypt Initial
+        // packets the client retransmits before switching.
+        if self.is_server {
+            self.initial_version_open =
+                self.pkt_num_spaces[packet::EPOCH_INITIAL].crypto_open.take();
+        }
+
+        let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+            &dcid,
+            self.version,
+            self.is_server,
+        )?;
+
+        self.pkt_num_spaces[packet::EPOCH_INITIAL].crypto_open =
+            Some(aead_open);
+        self.pkt_num_spaces[packet::EPOCH_INITIAL].crypto_seal =
+            Some(aead_seal);
+
+        Ok(())
+    }
+
+    /// Upgrades the connection to the server's preferred version, if it's
+    /// compatible with the client's and the client offered it.
+    ///
+    /// This is called by the TLS stack as soon as the ClientHello is parsed,
+    /// so that the server's transport parameters and Handshake keys use the
+    /// upgraded version.
+    fn on_client_hello(&mut self) -> Result<()> {
+        let raw_params = self.handshake.quic_transport_params();
+
+        let peer_params = TransportParams::decode(raw_params, self.is_server)?;
+
+        let info = match peer_params.version_information {
+            Some(v) => v,
+
+            None => return Ok(()),
+        };
+
+        if self.preferred_version == self.version ||
+            info.chosen_version != self.version ||
+            !info.available_versions.contains(&self.preferred_version) ||
+            !version_is_compatible(self.version, self.preferred_version)
+        {
+            return Ok(());
+        }
+
+        self.set_compatible_version(self.preferred_version)?;
+
+        self.encode_transport_params()
+    }
+
+    /// Validates the peer's version_information transport parameter, so that
+    /// version downgrades are detected.
+    fn validate_version_information(
+        &self, peer_params: &TransportParams,
+    ) -> Result<()> {
+        if version_is_draft(self.version) {
+            return Ok(());
+        }
+
+        let info = match &peer_params.version_information {
+            Some(v) => v,
+
+            // The client can't validate a version negotiation without the
+            // server's version information.
+            None if !self.is_server && self.did_version_negotiation =>
+                return Err(Error::VersionNegotiation),
+
+            None => return Ok(()),
+        };
+
+        if self.is_server {
+            // The client's chosen version must be the one of its first Initial
+            // packet.
+            if info.chosen_version != self.initial_version {
+                return Err(Error::VersionNegotiation);
+            }
+
+            return Ok(());
+        }
+
+        if info.chosen_version != self.version {
+            return Err(Error::VersionNegotiation);
+        }
+
+        // Following a Version Negotiation packet, the client must have chosen
+        // the same version given the server's full list of versions, which
+        // also can't include the version the client originally attempted.
+        if self.did_version_negotiation {
+            if info.available_versions.contains(&self.preferred_version) {
+                return Err(Error::VersionNegotiation);
+            }
+
+            let selected = SUPPORTED_VERSIONS
+                .iter()
+                .find(|v| info.available_versions.contains(v));
+
+            if selected != Some(&self.initial_version) {
+                return Err(Error::VersionNegotiation);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Continues the handshake.
     ///
     /// If the connection is already established, it does nothing.
     fn do_handshake(&mut self) -> Result<()> {
-        // Handshake is already complete, there's nothing to do.
+        // Handshake is already complete, so only process post-handshake
+        // messages, such as session tickets.
         if self.is_established() {
-            return Ok(());
+            match self.handshake.process_post_handshake() {
+                Ok(_) | Err(Error::Done) => return Ok(()),
+
+                Err(e) => return Err(e),
+            }
         }
 
         match self.handshake.do_handshake() {
@@ -3471,6 +5561,35 @@ impl Connection {
         Ok(())
     }
 
+    /// Discards the state created for early data, after the server rejected
+    /// 0-RTT.
+    ///
+    /// None of the streams opened, or the data sent, in 0-RTT packets were
+    /// processed by the server, and the limits remembered from the previous
+    /// connection no longer apply until the server's transport parameters
+    /// are received.
+    fn on_early_data_rejected(&mut self) {
+        trace!("{} 0-RTT rejected", self.trace_id);
+
+        self.streams.clear();
+
+        self.tx_data = 0;
+        self.max_tx_data = 0;
+        self.blocked_limit = None;
+
+        self.peer_transport_params = TransportParams::default();
+
+        // The 0-RTT keys can't be used anymore, and the 0-RTT packets in
+        // flight won't be acknowledged, nor should their frames be
+        // retransmitted.
+        self.pkt_num_spaces[packet::EPOCH_APPLICATION].crypto_seal = None;
+
+        self.recovery.on_pkt_num_space_discarded(
+            packet::EPOCH_APPLICATION,
+            self.is_established(),
+        );
+    }
+
     /// Selects the packet number space for outgoing packets.
     fn write_epoch(&self) -> Result<packet::Epoch> {
         // On error send packet in the latest epoch available, but only send
@@ -3520,17 +5639,22 @@ impl Connection {
         #[cfg(feature = "quic-dgram")]
         let dgram_pending = self.dgram_send_queue.has_pending();
 
-        // If there are flushable, almost full or blocked streams, use the
-        // Application epoch.
+        // If there are flushable, almost full, blocked, reset or stopped
+        // streams, use the Application epoch.
         if (self.is_established() || self.is_in_early_data()) &&
             (self.almost_full ||
+                self.new_token_pending ||
                 self.blocked_limit.is_some() ||
                 dgram_pending ||
+                self.paths.has_pending_frames() ||
+                self.ids.has_pending_frames() ||
                 self.streams.should_update_max_streams_bidi() ||
                 self.streams.should_update_max_streams_uni() ||
                 self.streams.has_flushable() ||
                 self.streams.has_almost_full() ||
-                self.streams.has_blocked())
+                self.streams.has_blocked() ||
+                self.streams.has_reset() ||
+                self.streams.has_stopped())
         {
             return Ok(packet::EPOCH_APPLICATION);
         }
@@ -3554,7 +5678,8 @@ impl Connection {
 
     /// Processes an incoming frame.
     fn process_frame(
-        &mut self, frame: frame::Frame, epoch: packet::Epoch, now: time::Instant,
+        &mut self, frame: frame::Frame, epoch: packet::Epoch, dcid: &[u8],
+        from: SocketAddr, now: time::Instant,
     ) -> Result<()> {
         trace!("{} rx frm {:?}", self.trace_id, frame);
 
@@ -3563,7 +5688,11 @@ impl Connection {
 
             frame::Frame::Ping => (),
 
-            frame::Frame::ACK { ranges, ack_delay } => {
+            frame::Frame::ACK {
+                ranges,
+                ack_delay,
+                ecn_counts,
+            } => {
                 let ack_delay = ack_delay
                     .checked_mul(2_u64.pow(
                         self.peer_transport_params.ack_delay_exponent as u32,
@@ -3573,12 +5702,25 @@ impl Connection {
                 self.recovery.on_ack_received(
                     &ranges,
                     ack_delay,
+                    ecn_counts,
                     epoch,
                     self.is_established(),
                     now,
                     &self.trace_id,
                 )?;
 
+                // Another key update can be initiated once a packet sent with
+                // the current keys is acknowledged.
+                if epoch == packet::EPOCH_APPLICATION {
+                    if let (Some(pn), Some(largest)) =
+                        (self.key_update_pn, ranges.last())
+                    {
+                        if largest >= pn {
+                            self.key_update_pn = None;
+                        }
+                    }
+                }
+
                 // When we receive an ACK for a 1-RTT packet after handshake
                 // completion, it means the handshake has been confirmed.
                 if epoch == packet::EPOCH_APPLICATION && self.is_established() {
@@ -3587,13 +5729,19 @@ impl Connection {
                     // Once the handshake is confirmed, we can drop Handshake
                     // keys.
                     self.drop_epoch_state(packet::EPOCH_HANDSHAKE);
+
+                    self.probe_preferred_address();
+
+                    self.recovery.start_pmtud(self.peer_max_udp_payload_len());
                 }
+
+                self.check_pmtu_update();
             },
 
             frame::Frame::ResetStream {
                 stream_id,
+                error_code,
                 final_size,
-                ..
             } => {
                 // Peer can't send on our unidirectional streams.
                 if !stream::is_bidi(stream_id) &&
@@ -3620,20 +5768,69 @@ impl Connection {
                     Err(e) => return Err(e),
                 };
 
-                self.rx_data += stream.recv.reset(final_size)? as u64;
+                let (max_off_delta, unread) =
+                    stream.recv.reset(error_code, final_size)?;
+
+                // The stream is readable until the application is notified
+                // of the reset.
+                let readable = stream.is_readable();
+
+                self.rx_data += max_off_delta as u64;
+
+                // Data that won't be read anymore can be replaced by new data.
+                self.max_rx_data_next =
+                    self.max_rx_data_next.saturating_add(unread as u64);
+
+                if readable {
+                    self.streams.mark_readable(stream_id, true);
+                }
 
                 if self.rx_data > self.max_rx_data {
                     return Err(Error::FlowControl);
                 }
             },
 
-            frame::Frame::StopSending { stream_id, .. } => {
+            frame::Frame::StopSending {
+                stream_id,
+                error_code,
+            } => {
                 // STOP_SENDING on a receive-only stream is a fatal error.
                 if !stream::is_local(stream_id, self.is_server) &&
                     !stream::is_bidi(stream_id)
                 {
                     return Err(Error::InvalidStreamState);
                 }
+
+                // Get existing stream or create a new one, but if the stream
+                // has already been closed and collected, ignore the frame.
+                let stream = match self.get_or_create_stream(stream_id, false) {
+                    Ok(v) => v,
+
+                    Err(Error::Done) => return Ok(()),
+
+                    Err(e) => return Err(e),
+                };
+
+                // Reset the stream with the same error code, unless sending
+                // was already shut down.
+                let (final_size, unsent) = match stream.send.stop(error_code) {
+                    Ok(v) => v,
+
+                    Err(Error::Done) => return Ok(()),
+
+                    Err(e) => return Err(e),
+                };
+
+                // Data that was buffered but never sent doesn't count towards
+                // the connection flow control limit anymore.
+                self.tx_data = self.tx_data.saturating_sub(unsent);
+
+                self.streams
+                    .mark_reset(stream_id, true, error_code, final_size);
+
+                // Mark the stream as writable, so that the application is
+                // notified of the error by `stream_send()`.
+                self.streams.mark_writable(stream_id, true);
             },
 
             frame::Frame::Crypto { data } => {
@@ -3667,7 +5864,7 @@ impl Connection {
                     let peer_params =
                         TransportParams::decode(&raw_params, self.is_server)?;
 
-                    if self.version >= PROTOCOL_VERSION_DRAFT28 {
+                    if self.version != PROTOCOL_VERSION_DRAFT27 {
                         // Validate initial_source_connection_id.
                         match &peer_params.initial_source_connection_id {
                             Some(v) if v != &self.dcid =>
@@ -3722,6 +5919,8 @@ impl Connection {
                         }
                     }
 
+                    self.validate_version_information(&peer_params)?;
+
                     // Update flow control limits.
                     self.max_tx_data = peer_params.initial_max_data;
 
@@ -3735,18 +5934,49 @@ impl Connection {
                     self.recovery.max_ack_delay =
                         time::Duration::from_millis(peer_params.max_ack_delay);
 
+                    // The peer's connection ID is final by now, so record it
+                    // with the server's stateless reset token, if any.
+                    let reset_token = peer_params
+                        .stateless_reset_token
+                        .as_ref()
+                        .and_then(|v| reset_token_from_slice(v));
+
+                    self.ids.set_initial_dcid(&self.dcid, reset_token);
+
+                    if let Some(pa) = &peer_params.preferred_address {
+                        self.ids.on_new_connection_id(
+                            pa.connection_id.clone(),
+                            1,
+                            0,
+                            pa.stateless_reset_token,
+                        )?;
+                    }
+
+                    self.ids.set_source_conn_id_limit(
+                        peer_params.active_conn_id_limit,
+                    );
+
                     self.peer_transport_params = peer_params;
 
                     self.parsed_peer_transport_params = true;
                 }
             },
 
-            // TODO: implement stateless retry
-            frame::Frame::NewToken { .. } => (),
+            frame::Frame::NewToken { token } => {
+                if self.is_server {
+                    return Err(Error::InvalidPacket);
+                }
 
-            frame::Frame::Stream { stream_id, data } => {
-                // Peer can't send on our unidirectional streams.
-                if !stream::is_bidi(stream_id) &&
+                if token.is_empty() {
+                    return Err(Error::InvalidFrame);
+                }
+
+                self.new_token = Some(token);
+            },
+
+            frame::Frame::Stream { stream_id, data } => {
+                // Peer can't send on our unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
                     stream::is_local(stream_id, self.is_server)
                 {
                     return Err(Error::InvalidStreamState);
@@ -3858,17 +6088,49 @@ impl Connection {
                     return Err(Error::InvalidFrame);
                 },
 
-            // TODO: implement connection migration
-            frame::Frame::NewConnectionId { .. } => (),
+            frame::Frame::NewConnectionId {
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                let reset_token = reset_token_from_slice(&reset_token)
+                    .ok_or(Error::InvalidFrame)?;
+
+                self.ids.on_new_connection_id(
+                    conn_id,
+                    seq_num,
+                    retire_prior_to,
+                    reset_token,
+                )?;
 
-            // TODO: implement connection migration
-            frame::Frame::RetireConnectionId { .. } => (),
+                // The peer might have retired the connection ID in use.
+                self.dcid = self.ids.active_dcid().to_vec();
+            },
+
+            frame::Frame::RetireConnectionId { seq_num } => {
+                self.ids.on_retire_connection_id(seq_num, dcid)?;
+            },
 
             frame::Frame::PathChallenge { data } => {
-                self.challenge = Some(data);
+                if let Some(path) = self.paths.get_mut(from) {
+                    path.on_challenge_received(data);
+                }
             },
 
-            frame::Frame::PathResponse { .. } => (),
+            frame::Frame::PathResponse { data } => {
+                if let Some(peer_addr) = self.paths.on_response_received(&data) {
+                    trace!("{} validated path to {}", self.trace_id, peer_addr);
+
+                    // Clients only validate other paths to move to the server's
+                    // preferred address.
+                    if !self.is_server &&
+                        peer_addr != self.paths.active().peer_addr
+                    {
+                        self.on_preferred_address_validated(peer_addr);
+                    }
+                }
+            },
 
             frame::Frame::ConnectionClose { .. } => {
                 self.draining_timer = Some(now + (self.recovery.pto() * 3));
@@ -3887,6 +6149,42 @@ impl Connection {
 
                 // Once the handshake is confirmed, we can drop Handshake keys.
                 self.drop_epoch_state(packet::EPOCH_HANDSHAKE);
+
+                self.probe_preferred_address();
+
+                self.recovery.start_pmtud(self.peer_max_udp_payload_len());
+            },
+
+            frame::Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                // The peer can't request ACKs to be delayed by less than the
+                // advertised minimum.
+                if self.local_transport_params.min_ack_delay == 0 ||
+                    request_max_ack_delay <
+                        self.local_transport_params.min_ack_delay
+                {
+                    return Err(Error::InvalidPacket);
+                }
+
+                self.ack_freq.on_frame_received(seq_num, ack_freq::Params {
+                    ack_eliciting_threshold,
+                    max_ack_delay: time::Duration::from_micros(
+                        request_max_ack_delay,
+                    ),
+                    reordering_threshold,
+                });
+            },
+
+            frame::Frame::ImmediateAck => {
+                if self.local_transport_params.min_ack_delay == 0 {
+                    return Err(Error::InvalidPacket);
+                }
+
+                self.pkt_num_spaces[epoch].ack_elicited = true;
             },
 
             #[cfg(feature = "quic-dgram")]
@@ -3919,6 +6217,261 @@ impl Connection {
         Ok(())
     }
 
+    /// Switches to the next keys for incoming 1-RTT packets, after a packet
+    /// protected with them was received.
+    fn on_key_update_received(
+        &mut self, pn: u64, now: time::Instant,
+    ) -> Result<()> {
+        let space = &mut self.pkt_num_spaces[packet::EPOCH_APPLICATION];
+
+        let open = space.crypto_open_next.take().ok_or(Error::InvalidState)?;
+
+        space.crypto_open_next =
+            Some(open.derive_next_packet_key(self.version)?);
+        space.crypto_open_prev = space.crypto_open.replace(open);
+
+        self.rx_key_phase = !self.rx_key_phase;
+        self.rx_key_phase_pn = pn;
+
+        // Keep the previous keys around for a while, to decrypt packets that
+        // were reordered across the key update.
+        self.prev_key_discard_timer = Some(now + (self.recovery.pto() * 3));
+
+        trace!(
+            "{} rx key update key_phase={}",
+            self.trace_id,
+            self.rx_key_phase
+        );
+
+        // The peer initiated the key update, so update outgoing keys as well.
+        if self.key_phase != self.rx_key_phase {
+            self.update_seal_key()?;
+        }
+
+        Ok(())
+    }
+
+    /// Switches to the next keys for outgoing 1-RTT packets.
+    fn update_seal_key(&mut self) -> Result<()> {
+        let space = &mut self.pkt_num_spaces[packet::EPOCH_APPLICATION];
+
+        let seal = space
+            .crypto_seal
+            .as_ref()
+            .ok_or(Error::InvalidState)?
+            .derive_next_packet_key(self.version)?;
+
+        space.crypto_seal = Some(seal);
+
+        self.key_phase = !self.key_phase;
+        self.key_update_pn = Some(space.next_pkt_num);
+        self.pkts_sent_with_key = 0;
+
+        trace!("{} tx key update key_phase={}", self.trace_id, self.key_phase);
+
+        Ok(())
+    }
+
+    /// Returns true if the given packet that couldn't be processed is a
+    /// stateless reset sent by the peer.
+    fn is_stateless_reset(&self, hdr: &Header, token: &[u8]) -> bool {
+        hdr.ty == packet::Type::Short &&
+            !token.is_empty() &&
+            self.ids.is_stateless_reset(token)
+    }
+
+    /// Moves the connection to the draining state after receiving a
+    /// stateless reset.
+    fn on_stateless_reset(&mut self, now: time::Instant) {
+        trace!("{} received stateless reset", self.trace_id);
+
+        self.reset = true;
+
+        self.draining_timer = Some(now + (self.recovery.pto() * 3));
+    }
+
+    /// Switches to the peer's new address after it migrated.
+    fn on_peer_migrated(&mut self, peer_addr: SocketAddr) {
+        let prev_addr = self.paths.active().peer_addr;
+
+        trace!(
+            "{} peer migrated from {} to {}",
+            self.trace_id,
+            prev_addr,
+            peer_addr
+        );
+
+        self.paths.set_active(peer_addr);
+        self.paths.active_mut().request_validation();
+
+        // A change of port only is most likely caused by NAT rebinding, in
+        // which case the path's characteristics don't change, so only reset
+        // the congestion state when the peer's IP address changed.
+        if prev_addr.ip() != peer_addr.ip() {
+            self.recovery.on_path_change();
+
+            // Switch to a new connection ID if the peer provided one, so the
+            // two paths can't be linked by an observer.
+            if self.ids.rotate_dcid() {
+                self.dcid = self.ids.active_dcid().to_vec();
+            }
+        }
+    }
+
+    /// Returns true if packets of the given type can't be decrypted yet, but
+    /// the keys needed to do so are expected to be installed later on.
+    fn pkt_keys_pending(&self, ty: packet::Type) -> bool {
+        if self.is_established() {
+            return false;
+        }
+
+        match ty {
+            // 0-RTT keys are installed by the server once the ClientHello is
+            // processed.
+            packet::Type::ZeroRTT =>
+                self.is_server &&
+                    self.pkt_num_spaces[packet::EPOCH_APPLICATION]
+                        .crypto_0rtt_open
+                        .is_none(),
+
+            // Handshake keys are installed once the peer's first flight is
+            // processed.
+            packet::Type::Handshake =>
+                self.pkt_num_spaces[packet::EPOCH_HANDSHAKE]
+                    .crypto_open
+                    .is_none(),
+
+            _ => false,
+        }
+    }
+
+    /// Buffers a received packet that can't be decrypted yet.
+    fn buffer_undecryptable(
+        &mut self, ty: packet::Type, pkt: Vec<u8>, info: RecvInfo,
+    ) {
+        if self.undecryptable_pkts.len() >= self.max_undecryptable_pkts {
+            trace!(
+                "{} dropped undecryptable {:?} packet, queue is full",
+                self.trace_id,
+                ty
+            );
+
+            self.undecryptable_dropped_count += 1;
+
+            return;
+        }
+
+        trace!("{} buffered undecryptable {:?} packet", self.trace_id, ty);
+
+        self.undecryptable_pkts.push_back((ty, pkt, info));
+
+        self.undecryptable_buffered_count += 1;
+    }
+
+    /// Processes the buffered packets whose keys are no longer pending.
+    ///
+    /// Once the handshake completes, any packet still buffered is processed,
+    /// and dropped if it can't be decrypted.
+    fn recv_undecryptable(&mut self) -> Result<()> {
+        let mut i = 0;
+
+        while i < self.undecryptable_pkts.len() {
+            if self.pkt_keys_pending(self.undecryptable_pkts[i].0) {
+                i += 1;
+                continue;
+            }
+
+            let (_, mut pkt, info) = match self.undecryptable_pkts.remove(i) {
+                Some(v) => v,
+
+                None => break,
+            };
+
+            match self.recv_single(&mut pkt, info) {
+                Ok(_) | Err(Error::Done) => (),
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts validating the server's preferred address, if it advertised one
+    /// for the address family in use.
+    ///
+    /// The connection only moves to the new address once it's validated.
+    fn probe_preferred_address(&mut self) {
+        if self.is_server || self.did_probe_preferred_address {
+            return;
+        }
+
+        self.did_probe_preferred_address = true;
+
+        let active_addr = self.paths.active().peer_addr;
+
+        let peer_addr = match &self.peer_transport_params.preferred_address {
+            Some(pa) => match active_addr {
+                SocketAddr::V4(_) => pa.addr_v4.map(SocketAddr::V4),
+
+                SocketAddr::V6(_) => pa.addr_v6.map(SocketAddr::V6),
+            },
+
+            None => None,
+        };
+
+        let peer_addr = match peer_addr {
+            Some(v) if v != active_addr => v,
+
+            _ => return,
+        };
+
+        trace!(
+            "{} probing server's preferred address {}",
+            self.trace_id,
+            peer_addr
+        );
+
+        self.paths.get_or_insert(peer_addr).request_validation();
+
+        // Switch to the connection ID advertised with the preferred address,
+        // which needs to be used on the new path. The previous one is only
+        // retired once the new path is validated, in case the connection has
+        // to fall back to the original path.
+        if let Some(seq) = self.ids.switch_dcid() {
+            self.dcid = self.ids.active_dcid().to_vec();
+
+            self.pre_migration_dcid_seq = Some(seq);
+        }
+    }
+
+    /// Moves the connection to the server's preferred address, once validated.
+    fn on_preferred_address_validated(&mut self, peer_addr: SocketAddr) {
+        trace!(
+            "{} migrated to server's preferred address {}",
+            self.trace_id,
+            peer_addr
+        );
+
+        self.paths.set_active(peer_addr);
+
+        self.recovery.on_path_change();
+
+        if let Some(seq) = self.pre_migration_dcid_seq.take() {
+            self.ids.retire_dcid(seq);
+        }
+    }
+
+    /// Keeps using the original path after the server's preferred address
+    /// couldn't be validated.
+    fn on_preferred_address_failed(&mut self) {
+        if let Some(seq) = self.pre_migration_dcid_seq.take() {
+            if self.ids.restore_dcid(seq) {
+                self.dcid = self.ids.active_dcid().to_vec();
+            }
+        }
+    }
+
     /// Drops the keys and recovery state for the given epoch.
     fn drop_epoch_state(&mut self, epoch: packet::Epoch) {
         if self.pkt_num_spaces[epoch].crypto_open.is_none() {
@@ -3927,8 +6480,14 @@ impl Connection {
 
         self.pkt_num_spaces[epoch].crypto_open = None;
         self.pkt_num_spaces[epoch].crypto_seal = None;
+        self.pkt_num_spaces[epoch].crypto_open_next = None;
+        self.pkt_num_spaces[epoch].crypto_open_prev = None;
         self.pkt_num_spaces[epoch].clear();
 
+        if epoch == packet::EPOCH_INITIAL {
+            self.initial_version_open = None;
+        }
+
         self.recovery
             .on_pkt_num_space_discarded(epoch, self.is_established());
 
@@ -4018,6 +6577,18 @@ fn drop_pkt_on_err(
     Error::Done
 }
 
+/// Converts a stateless reset token to an integer.
+fn reset_token_from_slice(token: &[u8]) -> Option<u128> {
+    if token.len() != 16 {
+        return None;
+    }
+
+    let mut buf = [0; 16];
+    buf.copy_from_slice(token);
+
+    Some(u128::from_be_bytes(buf))
+}
+
 /// Statistics about the connection.
 ///
 /// A connections's statistics can be collected using the [`stats()`] method.
@@ -4034,6 +6605,10 @@ pub struct Stats {
     /// The number of QUIC packets that were lost.
     pub lost: usize,
 
+    /// The number of QUIC packets that were declared lost but were acked
+    /// later on, e.g. because of reordering.
+    pub spurious_lost: usize,
+
     /// The estimated round-trip time of the connection.
     pub rtt: time::Duration,
 
@@ -4042,19 +6617,41 @@ pub struct Stats {
 
     /// The estimated data delivery rate in bytes/s.
     pub delivery_rate: u64,
+
+    /// The number of QUIC packets buffered because the keys needed to decrypt
+    /// them were not available yet.
+    pub undecryptable_buffered: usize,
+
+    /// The number of QUIC packets dropped because too many packets were
+    /// already buffered waiting for their keys.
+    pub undecryptable_dropped: usize,
+
+    /// The maximum size of UDP payloads currently sent on the connection,
+    /// as found by path MTU discovery when enabled.
+    pub pmtu: usize,
+
+    /// The rate at which packets are paced in bytes/s.
+    pub pacing_rate: u64,
 }
 
 impl std::fmt::Debug for Stats {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            "recv={} sent={} lost={} rtt={:?} cwnd={} delivery_rate={}",
+            "recv={} sent={} lost={} spurious_lost={} rtt={:?} cwnd={} \
+             delivery_rate={} undecryptable_buffered={} \
+             undecryptable_dropped={} pmtu={} pacing_rate={}",
             self.recv,
             self.sent,
             self.lost,
+            self.spurious_lost,
             self.rtt,
             self.cwnd,
-            self.delivery_rate
+            self.delivery_rate,
+            self.undecryptable_buffered,
+            self.undecryptable_dropped,
+            self.pmtu,
+            self.pacing_rate
         )
     }
 }
@@ -4074,11 +6671,29 @@ struct TransportParams {
     pub ack_delay_exponent: u64,
     pub max_ack_delay: u64,
     pub disable_active_migration: bool,
-    // pub preferred_address: ...,
+    pub preferred_address: Option<PreferredAddress>,
     pub active_conn_id_limit: u64,
     pub initial_source_connection_id: Option<Vec<u8>>,
     pub retry_source_connection_id: Option<Vec<u8>>,
     pub max_datagram_frame_size: Option<u64>,
+    pub version_information: Option<VersionInformation>,
+    pub min_ack_delay: u64,
+}
+
+/// The contents of the preferred_address transport parameter.
+#[derive(Clone, Debug, PartialEq)]
+struct PreferredAddress {
+    pub addr_v4: Option<SocketAddrV4>,
+    pub addr_v6: Option<SocketAddrV6>,
+    pub connection_id: Vec<u8>,
+    pub stateless_reset_token: u128,
+}
+
+/// The contents of the version_information transport parameter.
+#[derive(Clone, Debug, PartialEq)]
+struct VersionInformation {
+    pub chosen_version: u32,
+    pub available_versions: Vec<u32>,
 }
 
 impl Default for TransportParams {
@@ -4097,10 +6712,13 @@ impl Default for TransportParams {
             ack_delay_exponent: 3,
             max_ack_delay: 25,
             disable_active_migration: false,
+            preferred_address: None,
             active_conn_id_limit: 2,
             initial_source_connection_id: None,
             retry_source_connection_id: None,
             max_datagram_frame_size: None,
+            version_information: None,
+            min_ack_delay: 0,
         }
     }
 }
@@ -4212,7 +6830,49 @@ impl TransportParams {
                         return Err(Error::InvalidTransportParam);
                     }
 
-                    // TODO: decode preferred_address
+                    let ip_v4 = Ipv4Addr::from(val.get_u32()?);
+                    let port_v4 = val.get_u16()?;
+
+                    let mut octets_v6 = [0; 16];
+                    octets_v6.copy_from_slice(val.get_bytes(16)?.buf());
+
+                    let ip_v6 = Ipv6Addr::from(octets_v6);
+                    let port_v6 = val.get_u16()?;
+
+                    let connection_id = val.get_bytes_with_u8_length()?.to_vec();
+
+                    // The server must not provide a preferred address when
+                    // using a zero-length connection ID.
+                    if connection_id.is_empty() ||
+                        connection_id.len() > MAX_CONN_ID_LEN
+                    {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    let stateless_reset_token =
+                        reset_token_from_slice(val.get_bytes(16)?.buf())
+                            .ok_or(Error::InvalidTransportParam)?;
+
+                    // An all-zero address and port means that the server has
+                    // no preferred address for that address family.
+                    let addr_v4 = if ip_v4.is_unspecified() && port_v4 == 0 {
+                        None
+                    } else {
+                        Some(SocketAddrV4::new(ip_v4, port_v4))
+                    };
+
+                    let addr_v6 = if ip_v6.is_unspecified() && port_v6 == 0 {
+                        None
+                    } else {
+                        Some(SocketAddrV6::new(ip_v6, port_v6, 0, 0))
+                    };
+
+                    tp.preferred_address = Some(PreferredAddress {
+                        addr_v4,
+                        addr_v6,
+                        connection_id,
+                        stateless_reset_token,
+                    });
                 },
 
                 0x000e => {
@@ -4237,15 +6897,48 @@ impl TransportParams {
                     tp.retry_source_connection_id = Some(val.to_vec());
                 },
 
+                0x0011 => {
+                    if val.cap() < 4 || val.cap() % 4 != 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    let chosen_version = val.get_u32()?;
+
+                    if chosen_version == 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    let mut available_versions = Vec::new();
+
+                    while val.cap() > 0 {
+                        available_versions.push(val.get_u32()?);
+                    }
+
+                    tp.version_information = Some(VersionInformation {
+                        chosen_version,
+                        available_versions,
+                    });
+                },
+
                 0x0020 => {
                     tp.max_datagram_frame_size = Some(val.get_varint()?);
                 },
 
+                0xff04_de1b => {
+                    tp.min_ack_delay = val.get_varint()?;
+                },
+
                 // Ignore unknown parameters.
                 _ => (),
             }
         }
 
+        // The minimum ACK delay is in microseconds, while the maximum ACK
+        // delay is in milliseconds.
+        if tp.min_ack_delay > tp.max_ack_delay * 1000 {
+            return Err(Error::InvalidTransportParam);
+        }
+
         Ok(tp)
     }
 
@@ -4367,6 +7060,15 @@ impl TransportParams {
             b.put_varint(tp.max_ack_delay)?;
         }
 
+        if tp.min_ack_delay != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0xff04_de1b,
+                octets::varint_len(tp.min_ack_delay),
+            )?;
+            b.put_varint(tp.min_ack_delay)?;
+        }
+
         if let Some(max_datagram_frame_size) = tp.max_datagram_frame_size {
             TransportParams::encode_param(
                 &mut b,
@@ -4380,7 +7082,44 @@ impl TransportParams {
             TransportParams::encode_param(&mut b, 0x000c, 0)?;
         }
 
-        // TODO: encode preferred_address
+        if is_server {
+            if let Some(pa) = &tp.preferred_address {
+                TransportParams::encode_param(
+                    &mut b,
+                    0x000d,
+                    4 + 2 + 16 + 2 + 1 + pa.connection_id.len() + 16,
+                )?;
+
+                match pa.addr_v4 {
+                    Some(addr) => {
+                        b.put_u32(u32::from(*addr.ip()))?;
+                        b.put_u16(addr.port())?;
+                    },
+
+                    None => {
+                        b.put_u32(0)?;
+                        b.put_u16(0)?;
+                    },
+                }
+
+                match pa.addr_v6 {
+                    Some(addr) => {
+                        b.put_bytes(&addr.ip().octets())?;
+                        b.put_u16(addr.port())?;
+                    },
+
+                    None => {
+                        b.put_bytes(&[0; 16])?;
+                        b.put_u16(0)?;
+                    },
+                }
+
+                b.put_u8(pa.connection_id.len() as u8)?;
+                b.put_bytes(&pa.connection_id)?;
+
+                b.put_bytes(&pa.stateless_reset_token.to_be_bytes())?;
+            }
+        }
 
         if tp.active_conn_id_limit != 2 {
             TransportParams::encode_param(
@@ -4403,6 +7142,19 @@ impl TransportParams {
             }
         }
 
+        if let Some(info) = &tp.version_information {
+            TransportParams::encode_param(
+                &mut b,
+                0x0011,
+                4 + 4 * info.available_versions.len(),
+            )?;
+            b.put_u32(info.chosen_version)?;
+
+            for v in &info.available_versions {
+                b.put_u32(*v)?;
+            }
+        }
+
         let out_len = b.off();
 
         Ok(&mut out[..out_len])
@@ -4420,6 +7172,28 @@ impl TransportParams {
         let stateless_reset_token =
             qlog::HexSlice::maybe_string(self.stateless_reset_token.as_ref());
 
+        let preferred_address =
+            self.preferred_address.as_ref().map(|pa| qlog::PreferredAddress {
+                ip_v4: pa
+                    .addr_v4
+                    .map_or(Ipv4Addr::UNSPECIFIED, |a| *a.ip())
+                    .to_string(),
+                ip_v6: pa
+                    .addr_v6
+                    .map_or(Ipv6Addr::UNSPECIFIED, |a| *a.ip())
+                    .to_string(),
+
+                port_v4: pa.addr_v4.map_or(0, |a| a.port().into()),
+                port_v6: pa.addr_v6.map_or(0, |a| a.port().into()),
+
+                connection_id: qlog::HexSlice::new(&pa.connection_id)
+                    .to_string(),
+                stateless_reset_token: qlog::HexSlice::new(
+                    &pa.stateless_reset_token.to_be_bytes(),
+                )
+                .to_string(),
+            });
+
         qlog::event::Event::transport_parameters_set(
             Some(owner),
             None, // resumption
@@ -4441,7 +7215,7 @@ impl TransportParams {
             Some(self.initial_max_stream_data_uni.to_string()),
             Some(self.initial_max_streams_bidi.to_string()),
             Some(self.initial_max_streams_uni.to_string()),
-            None, // preferred address
+            preferred_address,
         )
     }
 }
@@ -4450,6 +7224,14 @@ impl TransportParams {
 pub mod testing {
     use super::*;
 
+    pub fn client_addr() -> SocketAddr {
+        "127.0.0.1:1234".parse().unwrap()
+    }
+
+    pub fn server_addr() -> SocketAddr {
+        "127.0.0.1:4433".parse().unwrap()
+    }
+
     pub struct Pipe {
         pub client: Pin<Box<Connection>>,
         pub server: Pin<Box<Connection>>,
@@ -4481,8 +7263,13 @@ pub mod testing {
             rand::rand_bytes(&mut server_scid[..]);
 
             Ok(Pipe {
-                client: connect(Some("quic.tech"), &client_scid, config)?,
-                server: accept(&server_scid, None, config)?,
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    server_addr(),
+                    config,
+                )?,
+                server: accept(&server_scid, None, client_addr(), config)?,
             })
         }
 
@@ -4504,8 +7291,13 @@ pub mod testing {
             config.set_initial_max_streams_uni(3);
 
             Ok(Pipe {
-                client: connect(Some("quic.tech"), &client_scid, client_config)?,
-                server: accept(&server_scid, None, &mut config)?,
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    server_addr(),
+                    client_config,
+                )?,
+                server: accept(&server_scid, None, client_addr(), &mut config)?,
             })
         }
 
@@ -4525,13 +7317,23 @@ pub mod testing {
             config.set_initial_max_streams_uni(3);
 
             Ok(Pipe {
-                client: connect(Some("quic.tech"), &client_scid, &mut config)?,
-                server: accept(&server_scid, None, server_config)?,
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    server_addr(),
+                    &mut config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    client_addr(),
+                    server_config,
+                )?,
             })
         }
 
         pub fn handshake(&mut self, buf: &mut [u8]) -> Result<()> {
-            let mut len = self.client.send(buf)?;
+            let (mut len, _) = self.client.send(buf)?;
 
             while !self.client.is_established() && !self.server.is_established() {
                 len = recv_send(&mut self.server, buf, len)?;
@@ -4545,7 +7347,7 @@ pub mod testing {
 
         pub fn flush_client(&mut self, buf: &mut [u8]) -> Result<()> {
             loop {
-                let len = match self.client.send(buf) {
+                let (len, send_info) = match self.client.send(buf) {
                     Ok(v) => v,
 
                     Err(Error::Done) => break,
@@ -4553,7 +7355,12 @@ pub mod testing {
                     Err(e) => return Err(e),
                 };
 
-                match self.server.recv(&mut buf[..len]) {
+                let info = RecvInfo {
+                    from: client_addr(),
+                    ecn: send_info.ecn,
+                };
+
+                match self.server.recv(&mut buf[..len], info) {
                     Ok(_) => (),
 
                     Err(Error::Done) => (),
@@ -4567,7 +7374,7 @@ pub mod testing {
 
         pub fn flush_server(&mut self, buf: &mut [u8]) -> Result<()> {
             loop {
-                let len = match self.server.send(buf) {
+                let (len, send_info) = match self.server.send(buf) {
                     Ok(v) => v,
 
                     Err(Error::Done) => break,
@@ -4575,7 +7382,12 @@ pub mod testing {
                     Err(e) => return Err(e),
                 };
 
-                match self.client.recv(&mut buf[..len]) {
+                let info = RecvInfo {
+                    from: server_addr(),
+                    ecn: send_info.ecn,
+                };
+
+                match self.client.recv(&mut buf[..len], info) {
                     Ok(_) => (),
 
                     Err(Error::Done) => (),
@@ -4618,8 +7430,13 @@ pub mod testing {
     ) -> Result<usize> {
         let mut left = len;
 
+        let info = RecvInfo {
+            from: conn.paths.active().peer_addr,
+            ecn: Ecn::NotEct,
+        };
+
         while left > 0 {
-            match conn.recv(&mut buf[len - left..len]) {
+            match conn.recv(&mut buf[len - left..len], info) {
                 Ok(read) => left -= read,
 
                 Err(Error::Done) => break,
@@ -4634,7 +7451,7 @@ pub mod testing {
 
         while off < buf.len() {
             match conn.send(&mut buf[off..]) {
-                Ok(write) => off += write,
+                Ok((write, _)) => off += write,
 
                 Err(Error::Done) => break,
 
@@ -4667,7 +7484,7 @@ pub mod testing {
             pkt_num_len: pn_len,
             token: conn.token.clone(),
             versions: None,
-            key_phase: false,
+            key_phase: conn.key_phase,
         };
 
         hdr.to_bytes(&mut b)?;
@@ -4764,16 +7581,30 @@ mod tests {
             ack_delay_exponent: 20,
             max_ack_delay: 2_u64.pow(14) - 1,
             disable_active_migration: true,
+            preferred_address: Some(PreferredAddress {
+                addr_v4: Some("127.0.0.2:4433".parse().unwrap()),
+                addr_v6: None,
+                connection_id: vec![0xcd; 16],
+                stateless_reset_token: 0xcafe,
+            }),
             active_conn_id_limit: 8,
             initial_source_connection_id: Some(b"woot woot".to_vec()),
             retry_source_connection_id: Some(b"retry".to_vec()),
             max_datagram_frame_size: Some(32),
+            version_information: Some(VersionInformation {
+                chosen_version: PROTOCOL_VERSION_V2,
+                available_versions: vec![
+                    PROTOCOL_VERSION_V2,
+                    PROTOCOL_VERSION_V1,
+                ],
+            }),
+            min_ack_delay: 1000,
         };
 
         let mut raw_params = [42; 256];
         let raw_params =
             TransportParams::encode(&tp, true, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 94);
+        assert_eq!(raw_params.len(), 178);
 
         let new_tp = TransportParams::decode(&raw_params, false).unwrap();
 
@@ -4794,16 +7625,22 @@ mod tests {
             ack_delay_exponent: 20,
             max_ack_delay: 2_u64.pow(14) - 1,
             disable_active_migration: true,
+            preferred_address: None,
             active_conn_id_limit: 8,
             initial_source_connection_id: Some(b"woot woot".to_vec()),
             retry_source_connection_id: None,
             max_datagram_frame_size: Some(32),
+            version_information: Some(VersionInformation {
+                chosen_version: PROTOCOL_VERSION_V1,
+                available_versions: vec![PROTOCOL_VERSION_V1],
+            }),
+            min_ack_delay: 1000,
         };
 
         let mut raw_params = [42; 256];
         let raw_params =
             TransportParams::encode(&tp, false, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 69);
+        assert_eq!(raw_params.len(), 90);
 
         let new_tp = TransportParams::decode(&raw_params, true).unwrap();
 
@@ -4834,12 +7671,17 @@ mod tests {
 
         let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
 
-        let mut len = pipe.client.send(&mut buf).unwrap();
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
         let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
         len = crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut buf).unwrap();
 
-        assert_eq!(pipe.client.recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
 
         assert_eq!(pipe.handshake(&mut buf), Ok(()));
 
@@ -4877,7 +7719,7 @@ mod tests {
         assert_eq!(pipe.client.encode_transport_params(), Ok(()));
 
         // Client sends initial flight.
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         // Server rejects transport parameters.
         assert_eq!(
@@ -4899,7 +7741,7 @@ mod tests {
         assert_eq!(pipe.client.encode_transport_params(), Ok(()));
 
         // Client sends initial flight.
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         // Server rejects transport parameters.
         assert_eq!(
@@ -4923,15 +7765,536 @@ mod tests {
     }
 
     #[test]
-    fn handshake_confirmation() {
+    fn handshake_versions() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::default().unwrap();
+        for version in &[
+            PROTOCOL_VERSION_V1,
+            PROTOCOL_VERSION_V2,
+            PROTOCOL_VERSION_DRAFT29,
+        ] {
+            let mut config = Config::new(*version).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(b"\x06proto1\x06proto2")
+                .unwrap();
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.verify_peer(false);
 
-        // Client sends initial flight.
-        let mut len = pipe.client.send(&mut buf).unwrap();
+            let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-        // Server sends initial flight.
+            assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+            assert_eq!(pipe.client.version, *version);
+            assert_eq!(pipe.server.version, *version);
+
+            assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+            assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+            let mut r = pipe.server.readable();
+            assert_eq!(r.next(), Some(4));
+            assert_eq!(r.next(), None);
+        }
+    }
+
+    #[test]
+    fn handshake_resumption_0rtt() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake and receive the session ticket.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert!(!pipe.client.is_resumed());
+
+        let session = pipe.client.session().unwrap();
+
+        // Resume the session and send 0-RTT data.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(&session), Ok(()));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        assert!(pipe.client.is_in_early_data());
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", true), Ok(5));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        assert_eq!(hdr.ty, packet::Type::ZeroRTT);
+
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        // Complete the handshake.
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+
+        assert!(pipe.client.is_resumed());
+        assert!(pipe.server.is_resumed());
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
+    }
+
+    #[test]
+    fn handshake_resumption_0rtt_rejected() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        // Perform initial handshake and receive the session ticket.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let session = pipe.client.session().unwrap();
+
+        // Resume the session with a server that can't decrypt the ticket, so
+        // it rejects 0-RTT.
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(&session), Ok(()));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        assert!(pipe.client.is_in_early_data());
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", true), Ok(5));
+
+        // The 0-RTT packet is never delivered, as the server couldn't read it
+        // anyway.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
+        assert_eq!(hdr.ty, packet::Type::ZeroRTT);
+
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert!(pipe.client.is_established());
+        assert!(!pipe.client.is_resumed());
+
+        // The stream opened in early data was discarded.
+        assert!(pipe.client.streams.get(4).is_none());
+        assert_eq!(pipe.client.tx_data, 0);
+        assert!(pipe.client.recovery.lost[packet::EPOCH_APPLICATION].is_empty());
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+
+        // The data can be sent again once the handshake completes.
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", true), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
+    }
+
+    #[test]
+    fn path_params() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.client.path_params(), None);
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        let params = pipe.client.path_params().unwrap();
+        assert_eq!(params.len(), 16);
+
+        // Resume a new connection from the saved parameters.
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.client.set_path_params(&params), Ok(()));
+        assert_eq!(pipe.server.set_path_params(&params), Ok(()));
+
+        assert_eq!(
+            pipe.client.set_path_params(&params[..8]),
+            Err(Error::BufferTooShort)
+        );
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+    }
+
+    #[test]
+    fn path_params_resume() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(10_000_000);
+        config.set_initial_max_stream_data_bidi_local(10_000_000);
+        config.set_initial_max_stream_data_bidi_remote(10_000_000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        config.enable_hystart(false);
+        config.enable_pacing(false);
+        config.verify_peer(false);
+
+        let send_buf = vec![0; 1_000_000];
+
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        // Measure the RTT of the path on a previous connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let params = pipe.client.path_params().unwrap();
+        let min_rtt = octets::Octets::with_slice(&params).get_u64().unwrap();
+
+        // Whether the saved RTT matches the new connection's, and whether the
+        // first packet sent after the jump is lost.
+        for &(rtt_matches, lossy) in
+            &[(true, false), (true, true), (false, false)]
+        {
+            let saved_rtt = if rtt_matches { min_rtt } else { min_rtt * 100 };
+
+            let mut params = [0; 16];
+            let mut b = octets::OctetsMut::with_slice(&mut params);
+            b.put_u64(saved_rtt).unwrap();
+            b.put_u64(1_000_000).unwrap();
+
+            let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+            assert_eq!(pipe.client.set_path_params(&params), Ok(()));
+
+            assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+            // The saved window isn't used while the connection is app-limited.
+            assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+            assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+            assert!(pipe.client.stats().cwnd < 500_000);
+
+            // Fill the congestion window, and acknowledge the whole flight.
+            assert!(pipe.client.stream_send(4, &send_buf, false).is_ok());
+            assert_eq!(pipe.flush_client(&mut buf), Ok(()));
+            assert_eq!(pipe.flush_server(&mut buf), Ok(()));
+
+            if !rtt_matches {
+                // The path is likely different, so the window grows normally.
+                assert!(pipe.client.stats().cwnd < 500_000);
+                continue;
+            }
+
+            // Half of the saved window is used after the first RTT.
+            assert_eq!(pipe.client.stats().cwnd, 500_000);
+
+            if !lossy {
+                continue;
+            }
+
+            assert!(pipe.client.stream_send(4, &send_buf, false).is_ok());
+
+            let mut dropped = false;
+
+            loop {
+                let (len, _) = match pipe.client.send(&mut buf) {
+                    Ok(v) => v,
+
+                    Err(Error::Done) => break,
+
+                    Err(e) => panic!("send failed: {:?}", e),
+                };
+
+                if !dropped {
+                    dropped = true;
+                    continue;
+                }
+
+                assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+            }
+
+            assert_eq!(pipe.flush_server(&mut buf), Ok(()));
+
+            // The jump caused losses, so the window is reduced below what a
+            // regular congestion event would leave.
+            assert!(pipe.client.stats().lost > 0);
+            assert!(pipe.client.stats().cwnd < 500_000 / 2);
+        }
+    }
+
+    #[test]
+    fn new_token() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(pipe.server.send_new_token(), Err(Error::InvalidState));
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Only servers can issue tokens.
+        assert_eq!(pipe.client.send_new_token(), Err(Error::InvalidState));
+
+        assert_eq!(pipe.server.send_new_token(), Ok(()));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let token = pipe.client.new_token().unwrap();
+        assert!(crate::is_new_token(&token));
+
+        // The token validates the client's address in a new connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_token(&token), Ok(()));
+        assert_eq!(pipe.server.set_token(&token), Err(Error::InvalidState));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        assert!(!pipe.server.paths.active().validated);
+        assert!(testing::recv_send(&mut pipe.server, &mut buf, len).is_ok());
+        assert!(pipe.server.paths.active().validated);
+
+        // Tokens are not accepted by servers using a different key.
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.client.set_token(&token), Ok(()));
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        assert!(testing::recv_send(&mut pipe.server, &mut buf, len).is_ok());
+        assert!(!pipe.server.paths.active().validated);
+    }
+
+    #[test]
+    fn handshake_compatible_version() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION_V2).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V1);
+
+        // The client's first Initial uses version 1, and the server upgrades
+        // the connection to version 2 without an additional round-trip.
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
+
+        assert_eq!(
+            pipe.client
+                .peer_transport_params
+                .version_information
+                .as_ref()
+                .map(|v| v.chosen_version),
+            Some(PROTOCOL_VERSION_V2)
+        );
+        assert_eq!(
+            pipe.server
+                .peer_transport_params
+                .version_information
+                .as_ref()
+                .map(|v| v.chosen_version),
+            Some(PROTOCOL_VERSION_V1)
+        );
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn compatible_version_original_initial() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION_V2).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
+        assert!(pipe.server.initial_version_open.is_some());
+
+        // The client didn't see the server's reply yet, so it retransmits its
+        // Initial data using the original version.
+        let frames = [frame::Frame::Ping, frame::Frame::Padding { len: 1200 }];
+
+        let len = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Initial,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V1);
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        // Once the client uses the negotiated version, packets using the
+        // original one are ignored.
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+        assert!(pipe.server.initial_version_open.is_none());
+    }
+
+    #[test]
+    fn version_negotiation_downgrade() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION_V2).unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+
+        // Forge a Version Negotiation packet that omits version 2, even though
+        // the server supports it.
+        let mut b = octets::OctetsMut::with_slice(&mut buf);
+        b.put_u8(0x80).unwrap();
+        b.put_u32(0).unwrap();
+        b.put_u8(hdr.scid.len() as u8).unwrap();
+        b.put_bytes(&hdr.scid).unwrap();
+        b.put_u8(hdr.dcid.len() as u8).unwrap();
+        b.put_bytes(&hdr.dcid).unwrap();
+        b.put_u32(PROTOCOL_VERSION_V1).unwrap();
+
+        let len = b.off();
+
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V1);
+
+        assert_eq!(pipe.handshake(&mut buf), Err(Error::VersionNegotiation));
+    }
+
+    #[test]
+    fn handshake_confirmation() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        // Client sends initial flight.
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server sends initial flight.
         len = testing::recv_send(&mut pipe.server, &mut buf, len).unwrap();
 
         assert!(!pipe.client.is_established());
@@ -5012,7 +8375,7 @@ mod tests {
 
         let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
-        let client_sent = pipe.client.send(&mut buf).unwrap();
+        let (client_sent, _) = pipe.client.send(&mut buf).unwrap();
         let server_sent =
             testing::recv_send(&mut pipe.server, &mut buf, client_sent).unwrap();
 
@@ -5125,6 +8488,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ack_frequency() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_min_ack_delay(1000);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(4, b"bbbbb", false), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Both endpoints asked their peer to send ACKs less often, once the
+        // handshake was confirmed.
+        assert_eq!(pipe.client.ack_freq.next_seq_num(), 1);
+        assert_eq!(pipe.server.ack_freq.next_seq_num(), 1);
+
+        let frames = [frame::Frame::ImmediateAck];
+
+        let pkt_type = packet::Type::Short;
+        assert!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf).is_ok());
+
+        // Requesting a delay below the advertised minimum is a protocol
+        // violation.
+        let ack_freq_frames = [frame::Frame::AckFrequency {
+            seq_num: 5,
+            ack_eliciting_threshold: 1,
+            request_max_ack_delay: 999,
+            reordering_threshold: 1,
+        }];
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &ack_freq_frames, &mut buf),
+            Err(Error::InvalidPacket)
+        );
+        assert_eq!(pipe.server.error, Some(0xa));
+
+        // The extension can't be used without advertising `min_ack_delay`.
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidPacket)
+        );
+        assert_eq!(pipe.server.error, Some(0xa));
+    }
+
     #[test]
     fn flow_control_limit() {
         let mut buf = [0; 65535];
@@ -5153,6 +8582,9 @@ mod tests {
             pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
             Err(Error::FlowControl),
         );
+
+        assert_eq!(pipe.server.local_error(), Some(0x3));
+        assert_eq!(pipe.client.local_error(), None);
     }
 
     #[test]
@@ -5620,8 +9052,13 @@ mod tests {
 
         let mut pipe = testing::Pipe::default().unwrap();
 
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         // Client sends initial flight
-        let mut len = pipe.client.send(&mut buf).unwrap();
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
         // Server sends initial flight..
         len = testing::recv_send(&mut pipe.server, &mut buf, len).unwrap();
@@ -5646,7 +9083,7 @@ mod tests {
         let written =
             testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
                 .unwrap();
-        assert_eq!(pipe.server.recv(&mut buf[..written]), Ok(written));
+        assert_eq!(pipe.server.recv(&mut buf[..written], info), Ok(written));
 
         // Send 1-RTT packet #1.
         let frames = [frame::Frame::Stream {
@@ -5657,7 +9094,7 @@ mod tests {
         let written =
             testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
                 .unwrap();
-        assert_eq!(pipe.server.recv(&mut buf[..written]), Ok(written));
+        assert_eq!(pipe.server.recv(&mut buf[..written], info), Ok(written));
 
         assert!(!pipe.server.is_established());
 
@@ -5672,7 +9109,7 @@ mod tests {
         );
 
         // Process delayed packet.
-        pipe.server.recv(&mut delayed).unwrap();
+        pipe.server.recv(&mut delayed, info).unwrap();
 
         assert!(pipe.server.is_established());
 
@@ -5683,6 +9120,68 @@ mod tests {
         );
     }
 
+    #[test]
+    /// Simulates reception of a Handshake packet on the client before the
+    /// server's Initial packet that carries the keys needed to decrypt it.
+    fn undecryptable_handshake_packet() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server processes it, but its response is delayed.
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+
+        // Client receives two Handshake packets first, but only has room to
+        // buffer one of them.
+        pipe.client.max_undecryptable_pkts = 1;
+
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        let frames = [frame::Frame::Ping, frame::Frame::Padding { len: 16 }];
+
+        let pkt_type = packet::Type::Handshake;
+
+        for _ in 0..2 {
+            let written =
+                testing::encode_pkt(&mut pipe.server, pkt_type, &frames, &mut buf)
+                    .unwrap();
+            assert_eq!(pipe.client.recv(&mut buf[..written], info), Ok(written));
+        }
+
+        assert_eq!(pipe.client.undecryptable_pkts.len(), 1);
+        assert_eq!(pipe.client.stats().undecryptable_buffered, 1);
+        assert_eq!(pipe.client.stats().undecryptable_dropped, 1);
+
+        // Server sends its delayed flight, and the buffered packet is processed
+        // once the client has Handshake keys.
+        let len = testing::recv_send(&mut pipe.server, &mut buf, 0).unwrap();
+        let len = testing::recv_send(&mut pipe.client, &mut buf, len).unwrap();
+
+        assert!(pipe.client.undecryptable_pkts.is_empty());
+        assert!(pipe.client.pkt_num_spaces[packet::EPOCH_HANDSHAKE]
+            .recv_pkt_num
+            .contains(0));
+        assert!(!pipe.client.pkt_num_spaces[packet::EPOCH_HANDSHAKE]
+            .recv_pkt_num
+            .contains(1));
+
+        testing::recv_send(&mut pipe.server, &mut buf, len).unwrap();
+
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+    }
+
     #[test]
     fn stream_shutdown_read() {
         let mut buf = [0; 65535];
@@ -5737,6 +9236,16 @@ mod tests {
         assert_eq!(pipe.client.stream_shutdown(4, Shutdown::Write, 0), Ok(()));
         assert_eq!(pipe.advance(&mut buf), Ok(()));
 
+        // The stream is readable until the reset is reported.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(
+            pipe.server.stream_recv(4, &mut b),
+            Err(Error::StreamReset(0))
+        );
+
         let mut r = pipe.server.readable();
         assert_eq!(r.next(), None);
 
@@ -5752,6 +9261,138 @@ mod tests {
         );
     }
 
+    #[test]
+    /// Tests that a stream shut down for writing is collected once the peer
+    /// acks the RESET_STREAM frame, even if it needs to be retransmitted.
+    fn stream_shutdown_write_collect() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(2, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.stream_shutdown(2, Shutdown::Write, 42), Ok(()));
+
+        // The packet carrying the RESET_STREAM frame is lost.
+        assert!(pipe.client.send(&mut buf).is_ok());
+
+        // Wait until PTO expires. Since the RTT is very low, wait a bit more.
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.client.on_timeout();
+
+        // The stream is kept until the peer acks the RESET_STREAM frame.
+        assert_eq!(pipe.client.streams.len(), 1);
+        assert!(!pipe.client.stream_finished(2));
+
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.streams.len(), 0);
+        assert!(pipe.client.stream_finished(2));
+    }
+
+    #[test]
+    /// Tests that the error code of a RESET_STREAM frame is reported to the
+    /// application.
+    fn stream_reset_error_code() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"world", false), Ok(5));
+        assert_eq!(pipe.client.stream_shutdown(4, Shutdown::Write, 42), Ok(()));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        // Data that was not read yet is dropped.
+        let mut b = [0; 15];
+        assert_eq!(
+            pipe.server.stream_recv(4, &mut b),
+            Err(Error::StreamReset(42))
+        );
+
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Err(Error::Done));
+    }
+
+    #[test]
+    /// Tests that the error code of a STOP_SENDING frame is reported to the
+    /// application, and that the stream is reset in response.
+    fn stream_stop_sending_error_code() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.server.stream_shutdown(4, Shutdown::Read, 42), Ok(()));
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+        assert!(frames.contains(&frame::Frame::StopSending {
+            stream_id: 4,
+            error_code: 42,
+        }));
+
+        // Deliver the STOP_SENDING frame to the client.
+        let frames = [frame::Frame::StopSending {
+            stream_id: 4,
+            error_code: 42,
+        }];
+
+        let pkt_type = packet::Type::Short;
+        let written =
+            testing::encode_pkt(&mut pipe.server, pkt_type, &frames, &mut buf)
+                .unwrap();
+
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+        assert_eq!(pipe.client.recv(&mut buf[..written], info), Ok(written));
+
+        // The client resets the stream with the same error code.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
+
+        assert!(frames.contains(&frame::Frame::ResetStream {
+            stream_id: 4,
+            error_code: 42,
+            final_size: 5,
+        }));
+
+        let mut w = pipe.client.writable();
+        assert_eq!(w.next(), Some(4));
+        assert_eq!(w.next(), None);
+
+        assert_eq!(
+            pipe.client.stream_send(4, b"world", false),
+            Err(Error::StreamStopped(42))
+        );
+
+        let mut w = pipe.client.writable();
+        assert_eq!(w.next(), None);
+    }
+
     #[test]
     /// Tests that the order of flushable streams scheduled on the wire is the
     /// same as the order of `stream_send()` calls done by the application.
@@ -5766,7 +9407,7 @@ mod tests {
         assert_eq!(pipe.client.stream_send(0, b"aaaaa", false), Ok(5));
         assert_eq!(pipe.client.stream_send(4, b"aaaaa", false), Ok(5));
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         let frames =
             testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
@@ -5779,7 +9420,7 @@ mod tests {
             })
         );
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         let frames =
             testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
@@ -5792,7 +9433,7 @@ mod tests {
             })
         );
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         let frames =
             testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
@@ -5993,6 +9634,11 @@ mod tests {
         let mut buf = [0; 65535];
         let mut pipe = testing::Pipe::default().unwrap();
 
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         let frames = [frame::Frame::Padding { len: 10 }];
 
         let written = testing::encode_pkt(
@@ -6011,7 +9657,7 @@ mod tests {
         assert_eq!(pipe.server.timeout(), None);
 
         assert_eq!(
-            pipe.server.recv(&mut buf[..written]),
+            pipe.server.recv(&mut buf[..written], info),
             Err(Error::CryptoFail)
         );
 
@@ -6026,10 +9672,15 @@ mod tests {
         let mut pipe = testing::Pipe::default().unwrap();
 
         // Client sends initial flight.
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
 
         // Server sends initial flight.
-        assert_eq!(pipe.server.recv(&mut buf[..len]), Ok(1200));
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(1200));
 
         let frames = [frame::Frame::Padding { len: 10 }];
 
@@ -6046,8 +9697,13 @@ mod tests {
         // cannot be authenticated during decryption).
         buf[written - 1] = !buf[written - 1];
 
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         // Client will ignore invalid packet.
-        assert_eq!(pipe.client.recv(&mut buf[..written]), Ok(68));
+        assert_eq!(pipe.client.recv(&mut buf[..written], info), Ok(68));
 
         // The connection should be alive...
         assert_eq!(pipe.client.is_closed(), false);
@@ -6063,6 +9719,11 @@ mod tests {
         let mut buf = [0; 65535];
         let mut pipe = testing::Pipe::default().unwrap();
 
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         let mut b = octets::OctetsMut::with_slice(&mut buf);
 
         let epoch = packet::Type::Initial.to_epoch().unwrap();
@@ -6121,7 +9782,7 @@ mod tests {
         assert_eq!(pipe.server.timeout(), None);
 
         assert_eq!(
-            pipe.server.recv(&mut buf[..written]),
+            pipe.server.recv(&mut buf[..written], info),
             Err(Error::BufferTooShort)
         );
 
@@ -6134,6 +9795,11 @@ mod tests {
         let mut buf = [0; 65535];
         let mut pipe = testing::Pipe::default().unwrap();
 
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         assert_eq!(pipe.handshake(&mut buf), Ok(()));
 
         let frames = [frame::Frame::Padding { len: 10 }];
@@ -6151,12 +9817,12 @@ mod tests {
         // cannot be authenticated during decryption).
         buf[written - 1] = !buf[written - 1];
 
-        assert_eq!(pipe.server.recv(&mut buf[..written]), Ok(written));
+        assert_eq!(pipe.server.recv(&mut buf[..written], info), Ok(written));
 
         // Corrupt the packets's first byte to make the header fail decoding.
         buf[0] = 255;
 
-        assert_eq!(pipe.server.recv(&mut buf[..written]), Ok(written));
+        assert_eq!(pipe.server.recv(&mut buf[..written], info), Ok(written));
     }
 
     #[test]
@@ -6450,8 +10116,13 @@ mod tests {
 
         let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         // Client sends initial flight.
-        let mut len = pipe.client.send(&mut buf).unwrap();
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
         // Server sends Retry packet.
         let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
@@ -6474,15 +10145,17 @@ mod tests {
         .unwrap();
 
         // Client receives Retry and sends new Initial.
-        assert_eq!(pipe.client.recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
 
-        len = pipe.client.send(&mut buf).unwrap();
+        len = pipe.client.send(&mut buf).unwrap().0;
 
         let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
         assert_eq!(&hdr.token.unwrap(), token);
 
         // Server accepts connection and send first flight.
-        pipe.server = accept(&scid, Some(&odcid), &mut config).unwrap();
+        pipe.server =
+            accept(&scid, Some(&odcid), testing::client_addr(), &mut config)
+                .unwrap();
 
         len = testing::recv_send(&mut pipe.server, &mut buf, len).unwrap();
         len = testing::recv_send(&mut pipe.client, &mut buf, len).unwrap();
@@ -6509,8 +10182,13 @@ mod tests {
 
         let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         // Client sends initial flight.
-        let mut len = pipe.client.send(&mut buf).unwrap();
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
         // Server sends Retry packet.
         let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
@@ -6531,18 +10209,20 @@ mod tests {
         .unwrap();
 
         // Client receives Retry and sends new Initial.
-        assert_eq!(pipe.client.recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
 
-        len = pipe.client.send(&mut buf).unwrap();
+        len = pipe.client.send(&mut buf).unwrap().0;
 
         // Server accepts connection and send first flight. But original
         // destination connection ID is ignored.
-        pipe.server = accept(&scid, None, &mut config).unwrap();
+        pipe.server =
+            accept(&scid, None, testing::client_addr(), &mut config)
+                .unwrap();
 
         len = testing::recv_send(&mut pipe.server, &mut buf, len).unwrap();
 
         assert_eq!(
-            pipe.client.recv(&mut buf[..len]),
+            pipe.client.recv(&mut buf[..len], info),
             Err(Error::InvalidTransportParam)
         );
     }
@@ -6564,8 +10244,13 @@ mod tests {
 
         let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
         // Client sends initial flight.
-        let mut len = pipe.client.send(&mut buf).unwrap();
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
         // Server sends Retry packet.
         let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
@@ -6586,18 +10271,24 @@ mod tests {
         .unwrap();
 
         // Client receives Retry and sends new Initial.
-        assert_eq!(pipe.client.recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
 
-        len = pipe.client.send(&mut buf).unwrap();
+        len = pipe.client.send(&mut buf).unwrap().0;
 
         // Server accepts connection and send first flight. But original
         // destination connection ID is invalid.
-        pipe.server = accept(&scid, Some(b"bogus value"), &mut config).unwrap();
+        pipe.server = accept(
+            &scid,
+            Some(b"bogus value"),
+            testing::client_addr(),
+            &mut config,
+        )
+        .unwrap();
 
         len = testing::recv_send(&mut pipe.server, &mut buf, len).unwrap();
 
         assert_eq!(
-            pipe.client.recv(&mut buf[..len]),
+            pipe.client.recv(&mut buf[..len], info),
             Err(Error::InvalidTransportParam)
         );
     }
@@ -6629,7 +10320,7 @@ mod tests {
         assert_eq!(pipe.client.stream_send(8, b"aaaaaaaaaaa", false), Ok(10));
         assert_eq!(pipe.client.blocked_limit, Some(30));
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(pipe.client.blocked_limit, None);
 
         let frames =
@@ -6667,7 +10358,7 @@ mod tests {
         assert_eq!(pipe.client.stream_send(0, b"aaaaaa", false), Ok(5));
         assert_eq!(pipe.client.streams.blocked().len(), 1);
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(pipe.client.streams.blocked().len(), 0);
 
         let frames =
@@ -6697,7 +10388,7 @@ mod tests {
         // again.
         assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(pipe.client.streams.blocked().len(), 0);
 
         let frames =
@@ -6720,7 +10411,7 @@ mod tests {
         assert_eq!(pipe.client.stream_send(0, b"aaaaaa", false), Ok(0));
         assert_eq!(pipe.client.streams.blocked().len(), 1);
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(pipe.client.streams.blocked().len(), 0);
 
         let frames =
@@ -6757,135 +10448,448 @@ mod tests {
 
         let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
 
-        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server sends stream data smaller than cwnd.
+        let send_buf = [0; 10000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf, false), Ok(10000));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // app_limited should be true because we send less than cwnd.
+        assert_eq!(pipe.server.recovery.app_limited(), true);
+    }
+
+    #[test]
+    fn app_limited_false() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_max_udp_payload_size(1200);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server sends stream data bigger than cwnd.
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(14085));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // We can't create a new packet header because there is no room by cwnd.
+        // app_limited should be false because we can't send more by cwnd.
+        assert_eq!(pipe.server.recovery.app_limited(), false);
+    }
+
+    #[test]
+    fn app_limited_false_no_frame() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_max_udp_payload_size(1405);
+        config.discover_pmtu(false);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server sends stream data bigger than cwnd.
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(14085));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // We can't create a new packet header because there is no room by cwnd.
+        // app_limited should be false because we can't send more by cwnd.
+        assert_eq!(pipe.server.recovery.app_limited(), false);
+    }
+
+    #[test]
+    fn app_limited_false_no_header() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(50000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_max_udp_payload_size(1406);
+        config.discover_pmtu(false);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        // Client sends stream data.
+        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server reads stream data.
+        let mut b = [0; 15];
+        pipe.server.stream_recv(0, &mut b).unwrap();
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // Server sends stream data bigger than cwnd.
+        let send_buf1 = [0; 20000];
+        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(14085));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        // We can't create a new frame because there is no room by cwnd.
+        // app_limited should be false because we can't send more by cwnd.
+        assert_eq!(pipe.server.recovery.app_limited(), false);
+    }
+
+    #[test]
+    fn pmtud() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_udp_payload_size(1350);
+        config.discover_pmtu(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        // Packets are limited to the base PMTU until probes are acked.
+        assert_eq!(pipe.client.stats().pmtu, 1200);
+        assert_eq!(pipe.server.stats().pmtu, 1200);
+
+        // Exchange 1-RTT packets in both directions to confirm the handshake.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.flush_client(&mut buf), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
+
+        // Packets are exchanged one at a time, as probes are sent in their own
+        // packets.
+        for _ in 0..3 {
+            assert_eq!(pipe.flush_server(&mut buf), Ok(()));
+            assert_eq!(pipe.flush_client(&mut buf), Ok(()));
+        }
+
+        assert_eq!(pipe.client.stats().pmtu, 1350);
+        assert_eq!(pipe.server.stats().pmtu, 1350);
+    }
+
+    #[test]
+    fn pmtud_disabled() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config.discover_pmtu(false);
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_max_udp_payload_size(1350);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        // Packets are only limited by the peer's max_udp_payload_size.
+        assert_eq!(pipe.client.stats().pmtu, 1350);
+        assert_eq!(pipe.server.stats().pmtu, 1350);
+    }
+
+    #[test]
+    fn ecn() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_ecn(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        // Packets are exchanged one at a time, so that their ECN codepoint is
+        // passed to the peer.
+        while !pipe.client.is_established() || !pipe.server.is_established() {
+            assert_eq!(pipe.flush_client(&mut buf), Ok(()));
+            assert_eq!(pipe.flush_server(&mut buf), Ok(()));
+        }
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.flush_client(&mut buf), Ok(()));
+        assert_eq!(pipe.flush_server(&mut buf), Ok(()));
+
+        assert!(pipe.client.recovery.ecn_capable());
+        assert!(pipe.server.recovery.ecn_capable());
+
+        let ecn_counts =
+            pipe.server.pkt_num_spaces[packet::EPOCH_APPLICATION].ecn_counts;
+        assert!(ecn_counts.ect0_count > 0);
+        assert_eq!(ecn_counts.ecn_ce_count, 0);
+
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
+
+        let (_, info) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(info.ecn, Ecn::Ect0);
+    }
+
+    #[test]
+    fn ecn_ce() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_ecn(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        // Packets are exchanged one at a time, so that their ECN codepoint is
+        // passed to the peer.
+        while !pipe.client.is_established() || !pipe.server.is_established() {
+            assert_eq!(pipe.flush_client(&mut buf), Ok(()));
+            assert_eq!(pipe.flush_server(&mut buf), Ok(()));
+        }
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.flush_client(&mut buf), Ok(()));
+        assert_eq!(pipe.flush_server(&mut buf), Ok(()));
+
+        assert!(pipe.client.recovery.ecn_capable());
+
+        let cwnd = pipe.client.recovery.cwnd();
+
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        let (len, info) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(info.ecn, Ecn::Ect0);
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        // The packet is marked CE by the network.
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::Ce,
+        };
 
-        // Server sends stream data smaller than cwnd.
-        let send_buf = [0; 10000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf, false), Ok(10000));
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        assert_eq!(pipe.server.recv(&mut buf[..len], info), Ok(len));
+        assert_eq!(pipe.flush_server(&mut buf), Ok(()));
 
-        // app_limited should be true because we send less than cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), true);
+        // The CE mark is treated as a congestion signal.
+        assert!(pipe.client.recovery.cwnd() < cwnd);
+        assert!(pipe.client.recovery.ecn_capable());
     }
 
     #[test]
-    fn app_limited_false() {
+    fn ecn_not_supported() {
         let mut buf = [0; 65535];
 
         let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
         config
             .set_application_protos(b"\x06proto1\x06proto2")
             .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_max_udp_payload_size(1200);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_ecn(true);
         config.verify_peer(false);
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
+        // The ECN codepoint is not passed to the peer, so the peer doesn't
+        // report ECN counts.
         assert_eq!(pipe.handshake(&mut buf), Ok(()));
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
-
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        assert!(!pipe.client.recovery.ecn_capable());
+        assert!(!pipe.server.recovery.ecn_capable());
 
-        // Server sends stream data bigger than cwnd.
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(14085));
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
 
-        // We can't create a new packet header because there is no room by cwnd.
-        // app_limited should be false because we can't send more by cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), false);
+        let (_, info) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(info.ecn, Ecn::NotEct);
     }
 
     #[test]
-    fn app_limited_false_no_frame() {
+    fn pacing() {
         let mut buf = [0; 65535];
 
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config
-            .set_application_protos(b"\x06proto1\x06proto2")
-            .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_max_udp_payload_size(1405);
-        config.verify_peer(false);
-
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-
+        let mut pipe = testing::Pipe::default().unwrap();
         assert_eq!(pipe.handshake(&mut buf), Ok(()));
 
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
         assert_eq!(pipe.advance(&mut buf), Ok(()));
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        // The pacing rate is known once an RTT sample is available.
+        assert!(pipe.client.stats().pacing_rate > 0);
 
-        // Server sends stream data bigger than cwnd.
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(14085));
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
 
-        // We can't create a new packet header because there is no room by cwnd.
-        // app_limited should be false because we can't send more by cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), false);
+        let now = time::Instant::now();
+
+        let (_, info) = pipe.client.send(&mut buf).unwrap();
+        assert!(info.at >= now);
     }
 
     #[test]
-    fn app_limited_false_no_header() {
+    fn send_batch() {
         let mut buf = [0; 65535];
 
         let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
         config
             .set_application_protos(b"\x06proto1\x06proto2")
             .unwrap();
-        config.set_initial_max_data(50000);
-        config.set_initial_max_stream_data_bidi_local(50000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
-        config.set_max_udp_payload_size(1406);
+        config.set_initial_max_data(100_000);
+        config.set_initial_max_stream_data_bidi_local(100_000);
+        config.set_initial_max_stream_data_bidi_remote(100_000);
+        config.set_initial_max_streams_bidi(3);
         config.verify_peer(false);
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
         assert_eq!(pipe.handshake(&mut buf), Ok(()));
-
-        // Client sends stream data.
-        assert_eq!(pipe.client.stream_send(0, b"a", true), Ok(1));
         assert_eq!(pipe.advance(&mut buf), Ok(()));
 
-        // Server reads stream data.
-        let mut b = [0; 15];
-        pipe.server.stream_recv(0, &mut b).unwrap();
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, &[0; 10000], true), Ok(10000));
 
-        // Server sends stream data bigger than cwnd.
-        let send_buf1 = [0; 20000];
-        assert_eq!(pipe.server.stream_send(0, &send_buf1, false), Ok(14085));
-        assert_eq!(pipe.advance(&mut buf), Ok(()));
+        let info = RecvInfo {
+            from: testing::client_addr(),
+            ecn: Ecn::NotEct,
+        };
 
-        // We can't create a new frame because there is no room by cwnd.
-        // app_limited should be false because we can't send more by cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), false);
+        // All packets are full-sized.
+        let (written, batch) = pipe.client.send_batch(&mut buf, 4).unwrap();
+
+        assert_eq!(batch.to, testing::server_addr());
+        assert_eq!(batch.segments, 4);
+        assert_eq!(batch.segment_size, pipe.client.max_send_udp_payload_len());
+        assert_eq!(written, batch.segment_size * 4);
+
+        let recv_count = pipe.server.stats().recv;
+
+        assert_eq!(
+            pipe.server.recv_batch(
+                &mut buf[..written],
+                batch.segment_size,
+                info
+            ),
+            Ok(written)
+        );
+        assert_eq!(pipe.server.stats().recv, recv_count + 4);
+
+        // Paced packets released within the same horizon share a batch, and
+        // only the last packet is shorter.
+        let (written, batch) = pipe.client.send_batch(&mut buf, 10).unwrap();
+
+        assert!(pipe.client.recovery.pacing_rate() > 0);
+        assert!(batch.segments > 1);
+        assert!(written > batch.segment_size * (batch.segments - 1));
+        assert!(written < batch.segment_size * batch.segments);
+
+        let recv_count = pipe.server.stats().recv;
+
+        assert_eq!(
+            pipe.server.recv_batch(
+                &mut buf[..written],
+                batch.segment_size,
+                info
+            ),
+            Ok(written)
+        );
+        assert_eq!(pipe.server.stats().recv, recv_count + batch.segments);
+
+        assert_eq!(pipe.client.send_batch(&mut buf, 10), Err(Error::Done));
     }
 
     #[test]
@@ -7033,7 +11037,8 @@ mod tests {
         let mut off = 0;
 
         for _ in 1..=3 {
-            let len = pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7055,7 +11060,8 @@ mod tests {
         let mut off = 0;
 
         for _ in 1..=3 {
-            let len = pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7077,7 +11083,8 @@ mod tests {
         let mut off = 0;
 
         for _ in 1..=3 {
-            let len = pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7099,7 +11106,8 @@ mod tests {
         let mut off = 0;
 
         for _ in 1..=3 {
-            let len = pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7112,7 +11120,8 @@ mod tests {
                 })
             );
 
-            let len = pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7135,7 +11144,8 @@ mod tests {
         let mut off = 0;
 
         for _ in 1..=3 {
-            let len = pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
+            let (len, _) =
+                pipe.server.send(&mut buf[..MAX_TEST_PACKET_SIZE]).unwrap();
 
             let frames =
                 testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7158,9 +11168,6 @@ mod tests {
 
     #[test]
     /// Tests that changing a stream's priority is correctly propagated.
-    ///
-    /// Re-prioritization is not supported, so this should fail.
-    #[should_panic]
     fn stream_reprioritize() {
         let mut buf = [0; 65535];
 
@@ -7219,7 +11226,7 @@ mod tests {
         assert_eq!(pipe.server.stream_priority(0, 20, true), Ok(()));
 
         // First is stream 8.
-        let len = pipe.server.send(&mut buf).unwrap();
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
         let frames =
             testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7233,7 +11240,7 @@ mod tests {
         );
 
         // Then is stream 0.
-        let len = pipe.server.send(&mut buf).unwrap();
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
         let frames =
             testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7247,7 +11254,7 @@ mod tests {
         );
 
         // Then are stream 12 and 4, with the same priority.
-        let len = pipe.server.send(&mut buf).unwrap();
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
 
         let frames =
             testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
@@ -7260,20 +11267,239 @@ mod tests {
             })
         );
 
-        let len = pipe.server.send(&mut buf).unwrap();
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+        assert_eq!(
+            frames.iter().next(),
+            Some(&frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"b", 0, false),
+            })
+        );
+
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    /// Tests that a custom stream scheduler is used, and can be replaced.
+    fn stream_scheduler() {
+        // Sends the stream with the highest ID first, 5 bytes at a time.
+        #[derive(Default)]
+        struct Highest(std::collections::BTreeSet<u64>);
+
+        impl StreamScheduler for Highest {
+            fn push(&mut self, stream_id: u64, _urgency: u8, _incr: bool) {
+                self.0.insert(stream_id);
+            }
+
+            fn pop(&mut self) -> Option<(u64, usize)> {
+                let stream_id = *self.0.iter().next_back()?;
+                self.0.remove(&stream_id);
+
+                Some((stream_id, 5))
+            }
+
+            fn remove(
+                &mut self, stream_id: u64, _urgency: u8, _incr: bool,
+            ) -> bool {
+                self.0.remove(&stream_id)
+            }
+
+            fn is_empty(&self) -> bool {
+                self.0.is_empty()
+            }
+        }
+
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(0);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(0);
+        config.verify_peer(false);
+        config.set_stream_scheduler(|| Box::new(Highest::default()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        let mut b = [0; 1];
+
+        for id in &[0, 4, 8] {
+            assert_eq!(pipe.client.stream_send(*id, b"a", false), Ok(1));
+            assert_eq!(pipe.advance(&mut buf), Ok(()));
+        }
+
+        for id in &[0, 4, 8] {
+            pipe.server.stream_recv(*id, &mut b).unwrap();
+        }
+
+        for id in &[4, 8] {
+            pipe.server.stream_send(*id, b"bbbbbbbbbb", false).unwrap();
+        }
+
+        // Streams are limited to 5 bytes at a time, so the rest of the packet
+        // is filled with more frames, starting from stream 8.
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+        let stream_frames: Vec<_> = frames
+            .into_iter()
+            .filter(|f| matches!(f, frame::Frame::Stream { .. }))
+            .collect();
+
+        assert_eq!(stream_frames, vec![
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"bbbbb", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 8,
+                data: stream::RangeBuf::from(b"bbbbb", 5, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"bbbbb", 0, false),
+            },
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"bbbbb", 5, false),
+            },
+        ]);
+
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+
+        // Switch back to the default scheduler, which keeps the order in
+        // which the incremental streams were queued, and doesn't limit them.
+        pipe.server
+            .set_stream_scheduler(Box::new(stream::UrgencyScheduler::new(0)));
+
+        for id in &[0, 4] {
+            pipe.server.stream_send(*id, b"bbbbb", false).unwrap();
+        }
+
+        for (id, off) in &[(0, 0), (4, 10)] {
+            let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+            let frames =
+                testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+            assert_eq!(
+                frames.first(),
+                Some(&frame::Frame::Stream {
+                    stream_id: *id,
+                    data: stream::RangeBuf::from(b"bbbbb", *off, false),
+                })
+            );
+        }
+
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    /// Tests that a stream stays scheduled when the STREAM frame's overhead
+    /// leaves no room for its data in the rest of the packet.
+    fn stream_scheduler_large_overhead() {
+        // Sends streams in the order they were queued, 5 bytes at a time.
+        #[derive(Default)]
+        struct Fifo(VecDeque<u64>);
+
+        impl StreamScheduler for Fifo {
+            fn push(&mut self, stream_id: u64, _urgency: u8, _incr: bool) {
+                self.0.push_back(stream_id);
+            }
+
+            fn pop(&mut self) -> Option<(u64, usize)> {
+                self.0.pop_front().map(|stream_id| (stream_id, 5))
+            }
+
+            fn remove(
+                &mut self, stream_id: u64, _urgency: u8, _incr: bool,
+            ) -> bool {
+                match self.0.iter().position(|&id| id == stream_id) {
+                    Some(i) => self.0.remove(i).is_some(),
+
+                    None => false,
+                }
+            }
+
+            fn is_empty(&self) -> bool {
+                self.0.is_empty()
+            }
+        }
+
+        // Both the stream ID and the offsets need more than 2 bytes, so the
+        // STREAM frame's overhead is larger than `MAX_STREAM_OVERHEAD`.
+        const STREAM_ID: u64 = 1 << 30;
+
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(1_000_000);
+        config.set_initial_max_stream_data_bidi_local(1_000_000);
+        config.set_initial_max_stream_data_bidi_remote(1_000_000);
+        config.set_initial_max_streams_bidi(STREAM_ID);
+        config.discover_pmtu(false);
+        config.enable_pacing(false);
+        config.verify_peer(false);
+        config.set_stream_scheduler(|| Box::new(Fifo::default()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(STREAM_ID, &[0; 20000], false),
+            Ok(20000)
+        );
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send(STREAM_ID, &[0; 5000], false),
+            Ok(5000)
+        );
+
+        // Write packets of increasing size, so that the space left after the
+        // last full STREAM frame of one of them is smaller than the overhead
+        // of the next.
+        for len in 60..120 {
+            pipe.client.send(&mut buf[..len]).ok();
+        }
+
+        // The stream still has data queued to send.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
-
-        assert_eq!(
-            frames.iter().next(),
-            Some(&frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"b", 0, false),
-            })
-        );
+            testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
 
-        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+        assert!(frames.iter().any(|f| matches!(
+            f,
+            frame::Frame::Stream { stream_id: STREAM_ID, .. }
+        )));
     }
 
     #[test]
@@ -7302,7 +11528,7 @@ mod tests {
         assert_eq!(pipe.client.recovery.loss_probes[epoch], 1);
 
         // Client retransmits stream data in PTO probe.
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(pipe.client.recovery.loss_probes[epoch], 0);
 
         let frames =
@@ -7375,7 +11601,7 @@ mod tests {
         assert!(!pipe.client.recovery.app_limited());
         assert_eq!(pipe.client.dgram_send_queue.pending_bytes(), 1_000_000);
 
-        let len = pipe.client.send(&mut buf).unwrap();
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         assert_ne!(pipe.client.dgram_send_queue.pending_bytes(), 0);
         assert_ne!(pipe.client.dgram_send_queue.pending_bytes(), 1_000_000);
@@ -7610,6 +11836,7 @@ mod tests {
         config.set_dgram_recv_max_queue_len(10);
         config.set_dgram_send_max_queue_len(10);
         config.set_max_udp_payload_size(1452);
+        config.discover_pmtu(false);
         config.verify_peer(false);
 
         let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
@@ -7633,13 +11860,400 @@ mod tests {
         let result2 = pipe.server.dgram_recv(&mut buf);
         assert_eq!(result2, Err(Error::Done));
     }
+
+    #[test]
+    fn peer_migration() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        let new_addr: SocketAddr = "127.0.0.2:5678".parse().unwrap();
+
+        let info = RecvInfo {
+            from: new_addr,
+            ecn: Ecn::NotEct,
+        };
+
+        // Client sends a non-probing packet from a new address.
+        let frames = [frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"hello, world", 0, true),
+        }];
+
+        let pkt_type = packet::Type::Short;
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        assert_eq!(pipe.server.recv(&mut buf[..written], info), Ok(written));
+
+        assert_eq!(pipe.server.paths.active().peer_addr, new_addr);
+        assert!(!pipe.server.paths.active().validated);
+
+        // Server validates the new path, without exceeding the amplification
+        // limit.
+        let (len, send_info) = pipe.server.send(&mut buf).unwrap();
+        assert_eq!(send_info.to, new_addr);
+        assert!(len <= written * MAX_AMPLIFICATION_FACTOR);
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+        let data = frames
+            .iter()
+            .find_map(|f| match f {
+                frame::Frame::PathChallenge { data } => Some(data.clone()),
+
+                _ => None,
+            })
+            .unwrap();
+
+        // Client responds to the challenge from its new address.
+        let frames = [frame::Frame::PathResponse { data }];
+
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        assert_eq!(pipe.server.recv(&mut buf[..written], info), Ok(written));
+
+        assert!(pipe.server.paths.active().validated);
+        assert_eq!(pipe.server.paths.challenge_timer(), None);
+    }
+
+    #[test]
+    fn preferred_address() {
+        let mut buf = [0; 65535];
+
+        let preferred_addr: SocketAddrV4 = "127.0.0.2:4433".parse().unwrap();
+
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config
+            .set_preferred_address(Some(preferred_addr), None, &[0xcd; 16], 0xba)
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        let initial_scid = pipe.server.scid.clone();
+
+        assert_eq!(
+            pipe.client
+                .peer_transport_params
+                .preferred_address
+                .as_ref()
+                .and_then(|pa| pa.addr_v4),
+            Some(preferred_addr)
+        );
+
+        // Once the handshake is confirmed, the client validates the preferred
+        // address using the connection ID advertised with it, and migrates.
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert!(pipe.client.handshake_confirmed);
+        assert_eq!(pipe.client.dcid, vec![0xcd; 16]);
+        assert_eq!(
+            pipe.client.paths.active().peer_addr,
+            SocketAddr::V4(preferred_addr)
+        );
+        assert!(pipe.client.paths.active().validated);
+
+        // The original connection ID is retired only after the preferred
+        // address was validated.
+        assert_eq!(pipe.client.pre_migration_dcid_seq, None);
+        assert_eq!(pipe.server.retired_scid_next(), Some(initial_scid));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+
+        let (len, send_info) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(send_info.to, SocketAddr::V4(preferred_addr));
+
+        testing::recv_send(&mut pipe.server, &mut buf, len).unwrap();
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn connection_id_rotation() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        let initial_scid = pipe.server.scid.clone();
+
+        // The peer is willing to store 2 connection IDs by default.
+        assert_eq!(pipe.server.source_cids_left(), 1);
+        assert_eq!(pipe.server.new_source_cid(&[0xbb; 16], 1, false), Ok(1));
+        assert_eq!(pipe.server.source_cids_left(), 0);
+
+        // Connection IDs need to have the same length as the initial one.
+        assert_eq!(
+            pipe.server.new_source_cid(&[0xbb; 8], 1, false),
+            Err(Error::InvalidState)
+        );
+
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.available_dcids(), 1);
+
+        assert_eq!(
+            pipe.server.new_source_cid(&[0xbc; 16], 2, false),
+            Err(Error::IdLimit)
+        );
+
+        // Ask the client to retire the initial connection ID.
+        assert_eq!(pipe.server.new_source_cid(&[0xbc; 16], 2, true), Ok(2));
+
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.dcid, vec![0xbb; 16]);
+        assert_eq!(pipe.client.available_dcids(), 1);
+
+        assert_eq!(pipe.server.retired_scid_next(), Some(initial_scid));
+        assert_eq!(pipe.server.retired_scid_next(), None);
+
+        // Streams still work using the new connection ID.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+    }
+
+    #[test]
+    fn key_update() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        assert_eq!(pipe.client.initiate_key_update(), Err(Error::InvalidState));
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        assert_eq!(pipe.client.initiate_key_update(), Err(Error::Done));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        // The server updated its own keys in response.
+        assert!(pipe.server.rx_key_phase);
+        assert!(pipe.server.key_phase);
+        assert!(pipe.client.rx_key_phase);
+
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let mut r = pipe.client.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        // A packet sent with the new keys was acknowledged, so keys can be
+        // updated again.
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(8, b"again", true), Ok(5));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(8));
+        assert_eq!(r.next(), None);
+
+        assert!(!pipe.server.rx_key_phase);
+        assert!(!pipe.server.key_phase);
+
+        // The previous keys are discarded once the timer expires.
+        assert!(pipe.server.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .crypto_open_prev
+            .is_some());
+        assert!(pipe.server.prev_key_discard_timer.is_some());
+
+        pipe.server.prev_key_discard_timer = Some(time::Instant::now());
+        pipe.server.on_timeout();
+
+        assert!(pipe.server.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .crypto_open_prev
+            .is_none());
+        assert_eq!(pipe.server.prev_key_discard_timer, None);
+    }
+
+    #[test]
+    fn key_update_max_packets() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_packets_per_key(1);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+        assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+        for stream_id in &[0, 4, 8] {
+            assert_eq!(pipe.client.stream_send(*stream_id, b"a", true), Ok(1));
+            assert_eq!(pipe.advance(&mut buf), Ok(()));
+
+            let mut r = pipe.server.readable();
+            assert_eq!(r.next(), Some(*stream_id));
+            assert_eq!(r.next(), None);
+
+            // Drain the stream so it's not readable anymore.
+            let mut b = [0; 1];
+            assert_eq!(
+                pipe.server.stream_recv(*stream_id, &mut b),
+                Ok((1, true))
+            );
+        }
+
+        // Keys were updated automatically on both sides.
+        let epoch = packet::EPOCH_APPLICATION;
+        assert!(pipe.client.pkt_num_spaces[epoch].crypto_open_prev.is_some());
+        assert!(pipe.server.pkt_num_spaces[epoch].crypto_open_prev.is_some());
+    }
+
+    #[test]
+    fn stateless_reset() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_stateless_reset_token(Some(0xcafe));
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        let info = RecvInfo {
+            from: testing::server_addr(),
+            ecn: Ecn::NotEct,
+        };
+
+        // A reset with an unknown token is ignored.
+        let len = crate::stateless_reset(0xbeef, 1200, &mut buf).unwrap();
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
+        assert!(!pipe.client.is_reset());
+
+        let len = crate::stateless_reset(0xcafe, 1200, &mut buf).unwrap();
+        assert_eq!(pipe.client.recv(&mut buf[..len], info), Ok(len));
+        assert!(pipe.client.is_reset());
+        assert!(pipe.client.draining_timer.is_some());
+
+        assert_eq!(pipe.client.send(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    fn peer_migration_disabled() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_disable_active_migration(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+
+        assert_eq!(pipe.handshake(&mut buf), Ok(()));
+
+        let new_addr: SocketAddr = "127.0.0.2:5678".parse().unwrap();
+
+        let info = RecvInfo {
+            from: new_addr,
+            ecn: Ecn::NotEct,
+        };
+
+        let frames = [frame::Frame::Ping];
+
+        let pkt_type = packet::Type::Short;
+        let written =
+            testing::encode_pkt(&mut pipe.client, pkt_type, &frames, &mut buf)
+                .unwrap();
+        assert_eq!(pipe.server.recv(&mut buf[..written], info), Ok(written));
+
+        // The packet is dropped, and the server keeps using the old path.
+        assert!(pipe.server.paths.get(new_addr).is_none());
+        assert_eq!(
+            pipe.server.paths.active().peer_addr,
+            testing::client_addr()
+        );
+    }
 }
 
 pub use crate::packet::Header;
 pub use crate::packet::Type;
+pub use crate::recovery::Acked;
+pub use crate::recovery::CongestionControl;
 pub use crate::recovery::CongestionControlAlgorithm;
+pub use crate::recovery::Recovery;
 pub use crate::stream::StreamIter;
+pub use crate::stream::StreamScheduler;
+pub use crate::stream::UrgencyScheduler;
+pub use crate::token::TokenKey;
+pub use crate::token::TOKEN_KEY_LEN;
 
+mod ack_freq;
+mod cid;
 mod crypto;
 mod dgram;
 mod ffi;
@@ -7648,8 +12262,10 @@ pub mod h3;
 mod minmax;
 mod octets;
 mod packet;
+mod path;
 mod rand;
 mod ranges;
 mod recovery;
 mod stream;
 mod tls;
+mod token;
@@ -24,6 +24,7 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::cmp;
 use std::time;
 
 use ring::aead;
@@ -32,6 +33,7 @@ use crate::Error;
 use crate::Result;
 
 use crate::crypto;
+use crate::frame;
 use crate::octets;
 use crate::rand;
 use crate::ranges;
@@ -46,6 +48,12 @@ const PKT_NUM_MASK: u8 = 0x03;
 
 pub const MAX_CID_LEN: u8 = 20;
 
+pub const RESET_TOKEN_LEN: usize = 16;
+
+pub const MIN_STATELESS_RESET_LEN: usize = 21;
+
+const MAX_STATELESS_RESET_LEN: usize = 43;
+
 pub(crate) const MAX_PKT_NUM_LEN: usize = 4;
 const SAMPLE_LEN: usize = 16;
 
@@ -214,6 +222,14 @@ impl Header {
 
         let ty = if version == 0 {
             Type::VersionNegotiation
+        } else if version == crate::PROTOCOL_VERSION_V2 {
+            match (first & TYPE_MASK) >> 4 {
+                0x01 => Type::Initial,
+                0x02 => Type::ZeroRTT,
+                0x03 => Type::Handshake,
+                0x00 => Type::Retry,
+                _ => return Err(Error::InvalidPacket),
+            }
         } else {
             match (first & TYPE_MASK) >> 4 {
                 0x00 => Type::Initial,
@@ -311,12 +327,22 @@ impl Header {
         }
 
         // Encode long header.
-        let ty: u8 = match self.ty {
-            Type::Initial => 0x00,
-            Type::ZeroRTT => 0x01,
-            Type::Handshake => 0x02,
-            Type::Retry => 0x03,
-            _ => return Err(Error::InvalidPacket),
+        let ty: u8 = if self.version == crate::PROTOCOL_VERSION_V2 {
+            match self.ty {
+                Type::Initial => 0x01,
+                Type::ZeroRTT => 0x02,
+                Type::Handshake => 0x03,
+                Type::Retry => 0x00,
+                _ => return Err(Error::InvalidPacket),
+            }
+        } else {
+            match self.ty {
+                Type::Initial => 0x00,
+                Type::ZeroRTT => 0x01,
+                Type::Handshake => 0x02,
+                Type::Retry => 0x03,
+                _ => return Err(Error::InvalidPacket),
+            }
         };
 
         first |= FORM_BIT | FIXED_BIT | (ty << 4);
@@ -588,9 +614,10 @@ pub fn negotiate_version(
     b.put_bytes(&scid)?;
     b.put_u8(dcid.len() as u8)?;
     b.put_bytes(&dcid)?;
-    b.put_u32(crate::PROTOCOL_VERSION_DRAFT29)?;
-    b.put_u32(crate::PROTOCOL_VERSION_DRAFT28)?;
-    b.put_u32(crate::PROTOCOL_VERSION_DRAFT27)?;
+
+    for version in crate::SUPPORTED_VERSIONS.iter() {
+        b.put_u32(*version)?;
+    }
 
     Ok(b.off())
 }
@@ -626,6 +653,42 @@ pub fn retry(
     Ok(b.off())
 }
 
+pub fn stateless_reset(
+    reset_token: u128, pkt_len: usize, out: &mut [u8],
+) -> Result<usize> {
+    // The reset needs to be smaller than the packet that triggered it, to
+    // prevent two endpoints from looping resets at each other.
+    if pkt_len <= MIN_STATELESS_RESET_LEN {
+        return Err(Error::Done);
+    }
+
+    let max_len = cmp::min(pkt_len - 1, out.len());
+
+    if max_len < MIN_STATELESS_RESET_LEN {
+        return Err(Error::BufferTooShort);
+    }
+
+    // Randomize the length, so that resets can't be told apart from regular
+    // short header packets by their size.
+    let max_len = cmp::min(max_len, MAX_STATELESS_RESET_LEN);
+
+    let len = MIN_STATELESS_RESET_LEN +
+        rand::rand_u64_uniform(
+            (max_len - MIN_STATELESS_RESET_LEN + 1) as u64,
+        ) as usize;
+
+    let out = &mut out[..len];
+
+    rand::rand_bytes(out);
+
+    // Make it look like a short header packet.
+    out[0] = (out[0] & !FORM_BIT) | FIXED_BIT;
+
+    out[len - RESET_TOKEN_LEN..].copy_from_slice(&reset_token.to_be_bytes());
+
+    Ok(len)
+}
+
 pub fn verify_retry_integrity(
     b: &octets::OctetsMut, odcid: &[u8], version: u32,
 ) -> Result<()> {
@@ -652,6 +715,24 @@ fn compute_retry_integrity_tag(
         0xe5, 0x49, 0x30, 0xf9, 0x7f, 0x21, 0x36, 0xf0, 0x53, 0x0a, 0x8c, 0x1c,
     ];
 
+    const RETRY_INTEGRITY_KEY_V1: [u8; 16] = [
+        0xbe, 0x0c, 0x69, 0x0b, 0x9f, 0x66, 0x57, 0x5a, 0x1d, 0x76, 0x6b, 0x54,
+        0xe3, 0x68, 0xc8, 0x4e,
+    ];
+
+    const RETRY_INTEGRITY_NONCE_V1: [u8; aead::NONCE_LEN] = [
+        0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
+    ];
+
+    const RETRY_INTEGRITY_KEY_V2: [u8; 16] = [
+        0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce,
+        0xad, 0x7c, 0xcc, 0x92,
+    ];
+
+    const RETRY_INTEGRITY_NONCE_V2: [u8; aead::NONCE_LEN] = [
+        0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a,
+    ];
+
     const RETRY_INTEGRITY_KEY_OLD: [u8; 16] = [
         0x4d, 0x32, 0xec, 0xdb, 0x2a, 0x21, 0x33, 0xc8, 0x41, 0xe4, 0x04, 0x3d,
         0xf2, 0x7d, 0x44, 0x30,
@@ -662,6 +743,12 @@ fn compute_retry_integrity_tag(
     ];
 
     let (key, nonce) = match version {
+        crate::PROTOCOL_VERSION_V1 =>
+            (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
+
+        crate::PROTOCOL_VERSION_V2 =>
+            (&RETRY_INTEGRITY_KEY_V2, RETRY_INTEGRITY_NONCE_V2),
+
         crate::PROTOCOL_VERSION_DRAFT27 | crate::PROTOCOL_VERSION_DRAFT28 =>
             (&RETRY_INTEGRITY_KEY_OLD, RETRY_INTEGRITY_NONCE_OLD),
 
@@ -704,9 +791,14 @@ pub struct PktNumSpace {
 
     pub ack_elicited: bool,
 
+    pub ecn_counts: frame::EcnCounts,
+
     pub crypto_open: Option<crypto::Open>,
     pub crypto_seal: Option<crypto::Seal>,
 
+    pub crypto_open_next: Option<crypto::Open>,
+    pub crypto_open_prev: Option<crypto::Open>,
+
     pub crypto_0rtt_open: Option<crypto::Open>,
     pub crypto_0rtt_seal: Option<crypto::Seal>,
 
@@ -728,9 +820,14 @@ impl PktNumSpace {
 
             ack_elicited: false,
 
+            ecn_counts: frame::EcnCounts::default(),
+
             crypto_open: None,
             crypto_seal: None,
 
+            crypto_open_next: None,
+            crypto_open_prev: None,
+
             crypto_0rtt_open: None,
             crypto_0rtt_seal: None,
 
@@ -839,6 +936,109 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn stateless_reset() {
+        let token = 0xbaba_baba_baba_baba_cafe_cafe_cafe_cafe;
+
+        let mut d = [0; 1500];
+
+        let len = super::stateless_reset(token, 1200, &mut d).unwrap();
+        assert!(len >= MIN_STATELESS_RESET_LEN);
+        assert!(len <= MAX_STATELESS_RESET_LEN);
+
+        assert_eq!(d[0] & FORM_BIT, 0);
+        assert_eq!(d[0] & FIXED_BIT, FIXED_BIT);
+        assert_eq!(&d[len - RESET_TOKEN_LEN..len], &token.to_be_bytes());
+
+        // Reset smaller than the triggering packet.
+        assert_eq!(
+            super::stateless_reset(token, 22, &mut d),
+            Ok(MIN_STATELESS_RESET_LEN)
+        );
+
+        assert_eq!(
+            super::stateless_reset(token, MIN_STATELESS_RESET_LEN, &mut d),
+            Err(Error::Done)
+        );
+
+        assert_eq!(
+            super::stateless_reset(token, 1200, &mut d[..20]),
+            Err(Error::BufferTooShort)
+        );
+    }
+
+    #[test]
+    fn long_header_v2() {
+        let hdr = Header {
+            ty: Type::Handshake,
+            version: crate::PROTOCOL_VERSION_V2,
+            dcid: vec![0xba, 0xba, 0xba, 0xba, 0xba, 0xba, 0xba, 0xba, 0xba],
+            scid: vec![0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb],
+            pkt_num: 0,
+            pkt_num_len: 0,
+            token: None,
+            versions: None,
+            key_phase: false,
+        };
+
+        let mut d = [0; 50];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(hdr.to_bytes(&mut b).is_ok());
+
+        // Handshake packets use type bits 0b11 in QUIC v2.
+        assert_eq!(d[0] & TYPE_MASK, 0x30);
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
+    }
+
+    #[test]
+    fn retry_integrity_v1() {
+        let odcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut pkt = [
+            0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x74, 0x6f, 0x6b, 0x65, 0x6e, 0x04, 0xa2,
+            0x65, 0xba, 0x2e, 0xff, 0x4d, 0x82, 0x90, 0x58, 0xfb, 0x3f, 0x0f,
+            0x24, 0x96, 0xba,
+        ];
+
+        let mut b = octets::OctetsMut::with_slice(&mut pkt);
+
+        let hdr = Header::from_bytes(&mut b, 0).unwrap();
+        assert_eq!(hdr.ty, Type::Retry);
+        assert_eq!(hdr.token, Some(b"token".to_vec()));
+
+        assert_eq!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V1),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn retry_integrity_v2() {
+        let odcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut pkt = [
+            0xcf, 0x6b, 0x33, 0x43, 0xcf, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x74, 0x6f, 0x6b, 0x65, 0x6e, 0xc8, 0x64,
+            0x6c, 0xe8, 0xbf, 0xe3, 0x39, 0x52, 0xd9, 0x55, 0x54, 0x36, 0x65,
+            0xdc, 0xc7, 0xb6,
+        ];
+
+        let mut b = octets::OctetsMut::with_slice(&mut pkt);
+
+        let hdr = Header::from_bytes(&mut b, 0).unwrap();
+        assert_eq!(hdr.ty, Type::Retry);
+        assert_eq!(hdr.token, Some(b"token".to_vec()));
+
+        assert_eq!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V2),
+            Ok(())
+        );
+    }
+
     #[test]
     fn initial() {
         let hdr = Header {
@@ -1497,7 +1697,9 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Open::from_secret(alg, &secret).unwrap();
+        let aead =
+            crypto::Open::from_secret(alg, crate::PROTOCOL_VERSION, &secret)
+                .unwrap();
 
         let mut hdr = Header::from_bytes(&mut b, 0).unwrap();
         assert_eq!(hdr.ty, Type::Short);
@@ -2142,7 +2344,9 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Seal::from_secret(alg, &secret).unwrap();
+        let aead =
+            crypto::Seal::from_secret(alg, crate::PROTOCOL_VERSION, &secret)
+                .unwrap();
 
         let pn = 654_360_564;
         let pn_len = 3;
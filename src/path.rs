@@ -0,0 +1,418 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::net::SocketAddr;
+use std::time;
+
+use std::collections::VecDeque;
+
+use crate::rand;
+
+/// Maximum number of peer addresses tracked at the same time.
+const MAX_PATHS: usize = 4;
+
+/// Maximum number of PATH_CHALLENGE frames sent on a path before declaring
+/// path validation failed.
+pub const MAX_PATH_CHALLENGES: usize = 3;
+
+/// Maximum number of received PATH_CHALLENGE frames kept around waiting to be
+/// answered.
+const MAX_PENDING_RESPONSES: usize = 4;
+
+/// A network path, identified by the peer's address.
+pub struct Path {
+    /// The peer's address.
+    pub peer_addr: SocketAddr,
+
+    /// Whether the peer's address has been validated, either by the
+    /// handshake or by path validation.
+    pub validated: bool,
+
+    /// Number of bytes that can still be sent on the path before the peer's
+    /// address is validated.
+    pub max_send_bytes: usize,
+
+    /// Whether a new PATH_CHALLENGE frame needs to be sent.
+    pub challenge_pending: bool,
+
+    /// Data of the PATH_CHALLENGE frames sent that are still awaiting a
+    /// response.
+    pub challenges: Vec<Vec<u8>>,
+
+    /// Time at which the last PATH_CHALLENGE is considered lost.
+    pub challenge_timer: Option<time::Instant>,
+
+    /// Data of the PATH_CHALLENGE frames received that still need to be
+    /// echoed in a PATH_RESPONSE.
+    pub responses: VecDeque<Vec<u8>>,
+}
+
+impl Path {
+    pub fn new(peer_addr: SocketAddr, validated: bool) -> Path {
+        Path {
+            peer_addr,
+            validated,
+            max_send_bytes: 0,
+            challenge_pending: false,
+            challenges: Vec::new(),
+            challenge_timer: None,
+            responses: VecDeque::new(),
+        }
+    }
+
+    /// Starts validating the path, unless validation is already in progress.
+    pub fn request_validation(&mut self) {
+        if self.validated || self.validation_in_progress() {
+            return;
+        }
+
+        self.challenge_pending = true;
+    }
+
+    /// Returns true if path validation was started and hasn't completed yet.
+    pub fn validation_in_progress(&self) -> bool {
+        self.challenge_pending || !self.challenges.is_empty()
+    }
+
+    /// Generates the data for a new PATH_CHALLENGE frame.
+    pub fn new_challenge(&mut self) -> Vec<u8> {
+        let mut data = vec![0; 8];
+        rand::rand_bytes(&mut data);
+
+        data
+    }
+
+    /// Records that a PATH_CHALLENGE frame was sent on the path.
+    pub fn on_challenge_sent(
+        &mut self, data: Vec<u8>, timeout: time::Duration, now: time::Instant,
+    ) {
+        self.challenges.push(data);
+        self.challenge_pending = false;
+        self.challenge_timer = Some(now + timeout);
+    }
+
+    /// Queues a PATH_RESPONSE frame echoing the received challenge data.
+    pub fn on_challenge_received(&mut self, data: Vec<u8>) {
+        if self.responses.len() >= MAX_PENDING_RESPONSES {
+            self.responses.pop_front();
+        }
+
+        self.responses.push_back(data);
+    }
+
+    /// Returns true if there are path validation frames to be sent.
+    pub fn has_pending_frames(&self) -> bool {
+        self.challenge_pending || !self.responses.is_empty()
+    }
+
+    fn on_validated(&mut self) {
+        self.validated = true;
+        self.challenge_pending = false;
+        self.challenges.clear();
+        self.challenge_timer = None;
+    }
+}
+
+/// Outcome of an expired PATH_CHALLENGE timer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChallengeTimeout {
+    /// A new PATH_CHALLENGE needs to be sent.
+    Retry,
+
+    /// Path validation failed.
+    Failed(SocketAddr),
+}
+
+/// The set of paths known by a connection.
+pub struct PathMap {
+    paths: Vec<Path>,
+
+    /// Index of the path currently used to send non-probing packets.
+    active: usize,
+
+    /// The last validated peer address, used when validation of the active
+    /// path fails after a migration.
+    fallback: Option<SocketAddr>,
+}
+
+impl PathMap {
+    pub fn new(peer_addr: SocketAddr, validated: bool) -> PathMap {
+        PathMap {
+            paths: vec![Path::new(peer_addr, validated)],
+            active: 0,
+            fallback: None,
+        }
+    }
+
+    pub fn active(&self) -> &Path {
+        &self.paths[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Path {
+        &mut self.paths[self.active]
+    }
+
+    pub fn get(&self, peer_addr: SocketAddr) -> Option<&Path> {
+        self.paths.iter().find(|p| p.peer_addr == peer_addr)
+    }
+
+    pub fn get_mut(&mut self, peer_addr: SocketAddr) -> Option<&mut Path> {
+        self.paths.iter_mut().find(|p| p.peer_addr == peer_addr)
+    }
+
+    /// Returns the path for the given peer address, creating it if needed.
+    ///
+    /// When too many paths are tracked, the oldest inactive one is dropped.
+    pub fn get_or_insert(&mut self, peer_addr: SocketAddr) -> &mut Path {
+        if let Some(i) = self.position(peer_addr) {
+            return &mut self.paths[i];
+        }
+
+        if self.paths.len() >= MAX_PATHS {
+            let oldest = if self.active == 0 { 1 } else { 0 };
+            self.remove(oldest);
+        }
+
+        self.paths.push(Path::new(peer_addr, false));
+
+        self.paths.last_mut().unwrap()
+    }
+
+    /// Makes the path for the given peer address the active one.
+    ///
+    /// The previously active path is remembered as a fallback, in case the
+    /// new path fails validation.
+    pub fn set_active(&mut self, peer_addr: SocketAddr) {
+        let i = match self.position(peer_addr) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if i == self.active {
+            return;
+        }
+
+        if self.paths[self.active].validated {
+            self.fallback = Some(self.paths[self.active].peer_addr);
+        }
+
+        self.active = i;
+
+        if self.paths[i].validated {
+            self.fallback = None;
+        }
+    }
+
+    /// Returns the address of a path other than the active one that has
+    /// path validation frames waiting to be sent.
+    pub fn probe_addr(&self) -> Option<SocketAddr> {
+        self.paths
+            .iter()
+            .enumerate()
+            .find(|(i, p)| *i != self.active && p.has_pending_frames())
+            .map(|(_, p)| p.peer_addr)
+    }
+
+    /// Returns true if any path has path validation frames waiting to be sent.
+    pub fn has_pending_frames(&self) -> bool {
+        self.paths.iter().any(|p| p.has_pending_frames())
+    }
+
+    /// Processes a received PATH_RESPONSE, and returns the address of the
+    /// path it validated, if any.
+    pub fn on_response_received(&mut self, data: &[u8]) -> Option<SocketAddr> {
+        let path = self
+            .paths
+            .iter_mut()
+            .find(|p| p.challenges.iter().any(|c| c.as_slice() == data))?;
+
+        path.on_validated();
+
+        let peer_addr = path.peer_addr;
+
+        if peer_addr == self.paths[self.active].peer_addr {
+            self.fallback = None;
+        }
+
+        Some(peer_addr)
+    }
+
+    /// Returns the earliest PATH_CHALLENGE timer across all paths.
+    pub fn challenge_timer(&self) -> Option<time::Instant> {
+        self.paths.iter().filter_map(|p| p.challenge_timer).min()
+    }
+
+    /// Processes expired PATH_CHALLENGE timers.
+    ///
+    /// Paths that ran out of attempts are returned as failed, and inactive
+    /// ones are forgotten. It's up to the caller to deal with failure of the
+    /// active path.
+    pub fn on_challenge_timeout(
+        &mut self, now: time::Instant,
+    ) -> Vec<ChallengeTimeout> {
+        let mut outcomes = Vec::new();
+
+        for p in self.paths.iter_mut() {
+            match p.challenge_timer {
+                Some(timer) if timer <= now => (),
+
+                _ => continue,
+            }
+
+            p.challenge_timer = None;
+
+            if p.challenges.len() < MAX_PATH_CHALLENGES {
+                p.challenge_pending = true;
+
+                outcomes.push(ChallengeTimeout::Retry);
+            } else {
+                p.challenges.clear();
+
+                outcomes.push(ChallengeTimeout::Failed(p.peer_addr));
+            }
+        }
+
+        for outcome in &outcomes {
+            if let ChallengeTimeout::Failed(peer_addr) = outcome {
+                if let Some(i) = self.position(*peer_addr) {
+                    if i != self.active {
+                        self.remove(i);
+                    }
+                }
+            }
+        }
+
+        outcomes
+    }
+
+    /// Abandons the active path in favour of the last validated one.
+    ///
+    /// Returns the address of the new active path, or `None` if there is no
+    /// validated path to go back to.
+    pub fn revert_to_fallback(&mut self) -> Option<SocketAddr> {
+        let fallback = self.fallback.take()?;
+
+        let failed = self.active;
+
+        self.active = self.position(fallback)?;
+
+        self.remove(failed);
+
+        Some(fallback)
+    }
+
+    fn position(&self, peer_addr: SocketAddr) -> Option<usize> {
+        self.paths.iter().position(|p| p.peer_addr == peer_addr)
+    }
+
+    fn remove(&mut self, i: usize) {
+        let removed = self.paths.remove(i);
+
+        if self.fallback == Some(removed.peer_addr) {
+            self.fallback = None;
+        }
+
+        if i < self.active {
+            self.active -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_and_validate() {
+        let a: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let b: SocketAddr = "127.0.0.2:1234".parse().unwrap();
+
+        let mut paths = PathMap::new(a, true);
+
+        paths.get_or_insert(b).request_validation();
+        paths.set_active(b);
+
+        assert_eq!(paths.active().peer_addr, b);
+        assert!(!paths.active().validated);
+        assert_eq!(paths.probe_addr(), None);
+
+        let now = time::Instant::now();
+
+        let data = paths.active_mut().new_challenge();
+        paths.active_mut().on_challenge_sent(
+            data.clone(),
+            time::Duration::from_millis(100),
+            now,
+        );
+
+        assert_eq!(paths.on_response_received(&[0; 8]), None);
+        assert_eq!(paths.on_response_received(&data), Some(b));
+
+        assert!(paths.active().validated);
+        assert_eq!(paths.challenge_timer(), None);
+        assert_eq!(paths.revert_to_fallback(), None);
+    }
+
+    #[test]
+    fn validation_failure() {
+        let a: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let b: SocketAddr = "127.0.0.2:1234".parse().unwrap();
+
+        let mut paths = PathMap::new(a, true);
+
+        paths.get_or_insert(b).request_validation();
+        paths.set_active(b);
+
+        let timeout = time::Duration::from_millis(100);
+        let mut now = time::Instant::now();
+
+        for _ in 0..MAX_PATH_CHALLENGES - 1 {
+            let data = paths.active_mut().new_challenge();
+            paths.active_mut().on_challenge_sent(data, timeout, now);
+
+            now += timeout;
+
+            assert_eq!(paths.on_challenge_timeout(now), vec![
+                ChallengeTimeout::Retry
+            ]);
+            assert!(paths.active().challenge_pending);
+        }
+
+        let data = paths.active_mut().new_challenge();
+        paths.active_mut().on_challenge_sent(data, timeout, now);
+
+        now += timeout;
+
+        assert_eq!(paths.on_challenge_timeout(now), vec![
+            ChallengeTimeout::Failed(b)
+        ]);
+
+        assert_eq!(paths.revert_to_fallback(), Some(a));
+        assert_eq!(paths.active().peer_addr, a);
+        assert!(paths.get(b).is_none());
+    }
+}
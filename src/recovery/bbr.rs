@@ -0,0 +1,776 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! BBR Congestion Control
+//!
+//! This implementation is based on the following I-D:
+//! https://tools.ietf.org/html/draft-cardwell-iccrg-bbr-congestion-control-00
+//!
+//! BBR paces packets at the estimated bottleneck bandwidth and bounds the
+//! amount of data in flight to a multiple of the estimated bandwidth-delay
+//! product, instead of reacting to packet loss.
+
+use std::cmp;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::minmax;
+use crate::packet;
+use crate::rand;
+
+use crate::recovery::Acked;
+use crate::recovery::CongestionControl;
+use crate::recovery::Recovery;
+
+/// BBR congestion controller.
+pub struct Bbr;
+
+impl CongestionControl for Bbr {
+    fn on_packet_sent(
+        &mut self, r: &mut Recovery, _sent_bytes: usize, _now: Instant,
+    ) {
+        on_packet_sent(r);
+    }
+
+    fn on_packet_acked(
+        &mut self, r: &mut Recovery, packet: &Acked, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        on_packet_acked(r, packet, epoch, now);
+    }
+
+    fn congestion_event(
+        &mut self, r: &mut Recovery, time_sent: Instant, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        congestion_event(r, time_sent, epoch, now);
+    }
+
+    fn collapse_cwnd(&mut self, r: &mut Recovery) {
+        collapse_cwnd(r);
+    }
+
+    fn rollback(&mut self, r: &mut Recovery) {
+        rollback(r);
+    }
+
+    fn has_custom_pacing(&self) -> bool {
+        true
+    }
+
+    fn congestion_state(&self, r: &Recovery) -> Option<&'static str> {
+        Some(congestion_state(r))
+    }
+}
+
+/// BBR Constants.
+///
+/// These are the values recommended in the I-D.
+///
+/// The gain used in Startup to double the sending rate every round (2/ln2).
+const HIGH_GAIN: f64 = 2.885;
+
+/// The pacing gains cycled through in ProbeBW.
+const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+
+/// The cwnd gain used in ProbeBW.
+const CWND_GAIN: f64 = 2.0;
+
+/// Length of the bottleneck bandwidth filter, in round trips.
+const BTLBW_FILTER_LEN: u32 = 10;
+
+/// Length of the round-trip propagation time filter.
+const RTPROP_FILTER_LEN: Duration = Duration::from_secs(10);
+
+/// Minimum time spent in ProbeRTT.
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+
+/// Startup ends once the bandwidth grows by less than this factor...
+const FULL_BW_THRESH: f64 = 1.25;
+
+/// ...over this many round trips.
+const FULL_BW_COUNT: usize = 3;
+
+/// The minimum cwnd, in packets.
+const MIN_PIPE_CWND_PACKETS: usize = 4;
+
+/// BBR Modes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Startup,
+    Drain,
+    ProbeBW,
+    ProbeRTT,
+}
+
+/// BBR State Variables.
+///
+/// We need to keep those variables across the connection.
+pub struct State {
+    mode: Mode,
+
+    // Bottleneck bandwidth estimate, in bytes per second.
+    btlbw: u64,
+
+    btlbw_filter: minmax::Minmax<u64>,
+
+    // Round-trip propagation time estimate.
+    rtprop: Option<Duration>,
+
+    rtprop_stamp: Instant,
+
+    rtprop_expired: bool,
+
+    pacing_gain: f64,
+
+    cwnd_gain: f64,
+
+    filled_pipe: bool,
+
+    full_bw: u64,
+
+    full_bw_count: usize,
+
+    round_count: u64,
+
+    next_round_delivered: usize,
+
+    round_start: bool,
+
+    cycle_index: usize,
+
+    cycle_stamp: Instant,
+
+    probe_rtt_done_stamp: Option<Instant>,
+
+    probe_rtt_round_done: bool,
+
+    packet_conservation: bool,
+
+    in_recovery: bool,
+
+    prior_cwnd: usize,
+
+    idle_restart: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let now = Instant::now();
+
+        State {
+            mode: Mode::Startup,
+
+            btlbw: 0,
+
+            btlbw_filter: minmax::Minmax::new(0),
+
+            rtprop: None,
+
+            rtprop_stamp: now,
+
+            rtprop_expired: false,
+
+            pacing_gain: HIGH_GAIN,
+
+            cwnd_gain: HIGH_GAIN,
+
+            filled_pipe: false,
+
+            full_bw: 0,
+
+            full_bw_count: 0,
+
+            round_count: 0,
+
+            next_round_delivered: 0,
+
+            round_start: false,
+
+            cycle_index: 0,
+
+            cycle_stamp: now,
+
+            probe_rtt_done_stamp: None,
+
+            probe_rtt_round_done: false,
+
+            packet_conservation: false,
+
+            in_recovery: false,
+
+            prior_cwnd: 0,
+
+            idle_restart: false,
+        }
+    }
+}
+
+fn on_packet_sent(r: &mut Recovery) {
+    // Restarting from idle, so pace at the estimated bandwidth rather than
+    // with the gain of the current ProbeBW phase.
+    if r.bytes_in_flight == 0 && r.app_limited {
+        r.bbr_state.idle_restart = true;
+
+        if r.bbr_state.mode == Mode::ProbeBW {
+            set_pacing_rate_with_gain(r, 1.0);
+        }
+    }
+
+    if r.bbr_state.btlbw == 0 {
+        init_pacing_rate(r);
+    }
+}
+
+fn on_packet_acked(
+    r: &mut Recovery, packet: &Acked, _epoch: packet::Epoch, now: Instant,
+) {
+    let prior_inflight = r.bytes_in_flight + packet.size;
+
+    if r.bbr_state.in_recovery && !r.in_congestion_recovery(packet.time_sent) {
+        r.bbr_state.in_recovery = false;
+        r.bbr_state.packet_conservation = false;
+
+        restore_cwnd(r);
+    }
+
+    update_round(r, packet);
+    update_btlbw(r, now);
+    check_cycle_phase(r, prior_inflight, now);
+    check_full_pipe(r);
+    check_drain(r, now);
+    update_rtprop(r, now);
+    check_probe_rtt(r, now);
+
+    set_pacing_rate(r);
+    set_cwnd(r, packet);
+}
+
+fn congestion_event(
+    r: &mut Recovery, time_sent: Instant, _epoch: packet::Epoch, now: Instant,
+) {
+    // Start a new congestion event if packet was sent after the
+    // start of the previous congestion recovery period.
+    if !r.in_congestion_recovery(time_sent) {
+        r.congestion_recovery_start_time = Some(now);
+
+        save_cwnd(r);
+
+        // Use packet conservation for the first round in recovery.
+        r.bbr_state.in_recovery = true;
+        r.bbr_state.packet_conservation = true;
+        r.bbr_state.next_round_delivered = r.delivery_rate.delivered();
+
+        r.congestion_window =
+            cmp::max(r.bytes_in_flight + r.max_datagram_size, min_pipe_cwnd(r));
+    }
+}
+
+fn collapse_cwnd(r: &mut Recovery) {
+    r.congestion_recovery_start_time = None;
+
+    save_cwnd(r);
+
+    r.bbr_state.in_recovery = false;
+    r.bbr_state.packet_conservation = false;
+
+    r.congestion_window = min_pipe_cwnd(r);
+}
+
+fn rollback(r: &mut Recovery) {
+    r.bbr_state.in_recovery = false;
+    r.bbr_state.packet_conservation = false;
+
+    restore_cwnd(r);
+}
+
+fn congestion_state(r: &Recovery) -> &'static str {
+    match r.bbr_state.mode {
+        Mode::Startup => "startup",
+        Mode::Drain => "drain",
+        Mode::ProbeBW => "probe_bw",
+        Mode::ProbeRTT => "probe_rtt",
+    }
+}
+
+fn min_pipe_cwnd(r: &Recovery) -> usize {
+    MIN_PIPE_CWND_PACKETS * r.max_datagram_size
+}
+
+// Returns the amount of data in flight needed to fully use the estimated
+// bandwidth-delay product with the given gain.
+fn inflight(r: &Recovery, gain: f64) -> usize {
+    let bbr = &r.bbr_state;
+
+    let rtprop = match bbr.rtprop {
+        Some(v) if bbr.btlbw > 0 => v,
+
+        _ => return super::initial_window(r.max_datagram_size),
+    };
+
+    let bdp = bbr.btlbw as f64 * rtprop.as_secs_f64();
+
+    // Allow enough data in flight to cover delayed and stretched ACKs.
+    (gain * bdp) as usize + 3 * r.max_datagram_size
+}
+
+fn update_round(r: &mut Recovery, packet: &Acked) {
+    let bbr = &mut r.bbr_state;
+
+    if packet.delivered >= bbr.next_round_delivered {
+        bbr.next_round_delivered = r.delivery_rate.delivered();
+        bbr.round_count += 1;
+        bbr.round_start = true;
+        bbr.packet_conservation = false;
+    } else {
+        bbr.round_start = false;
+    }
+}
+
+fn update_btlbw(r: &mut Recovery, now: Instant) {
+    let rate = r.delivery_rate.delivery_rate();
+    let rtt = r.bbr_state.rtprop.unwrap_or_else(|| r.rtt());
+    let bbr = &mut r.bbr_state;
+
+    // Samples taken while application limited only count if they show a
+    // higher bandwidth than the current estimate.
+    if rate >= bbr.btlbw || !r.delivery_rate.sample_is_app_limited() {
+        let win = rtt * BTLBW_FILTER_LEN;
+
+        bbr.btlbw = bbr.btlbw_filter.running_max(win, now, rate);
+    }
+}
+
+fn check_cycle_phase(r: &mut Recovery, prior_inflight: usize, now: Instant) {
+    if r.bbr_state.mode == Mode::ProbeBW &&
+        is_next_cycle_phase(r, prior_inflight, now)
+    {
+        advance_cycle_phase(r, now);
+    }
+}
+
+fn is_next_cycle_phase(
+    r: &Recovery, prior_inflight: usize, now: Instant,
+) -> bool {
+    let bbr = &r.bbr_state;
+
+    let is_full_length = match bbr.rtprop {
+        Some(rtprop) => now.saturating_duration_since(bbr.cycle_stamp) > rtprop,
+
+        None => true,
+    };
+
+    if bbr.pacing_gain > 1.0 {
+        return is_full_length && prior_inflight >= inflight(r, bbr.pacing_gain);
+    }
+
+    if bbr.pacing_gain < 1.0 {
+        return is_full_length || prior_inflight <= inflight(r, 1.0);
+    }
+
+    is_full_length
+}
+
+fn advance_cycle_phase(r: &mut Recovery, now: Instant) {
+    let bbr = &mut r.bbr_state;
+
+    bbr.cycle_stamp = now;
+    bbr.cycle_index = (bbr.cycle_index + 1) % PACING_GAIN_CYCLE.len();
+    bbr.pacing_gain = PACING_GAIN_CYCLE[bbr.cycle_index];
+}
+
+fn check_full_pipe(r: &mut Recovery) {
+    let app_limited = r.delivery_rate.sample_is_app_limited();
+    let bbr = &mut r.bbr_state;
+
+    if bbr.filled_pipe || !bbr.round_start || app_limited {
+        return;
+    }
+
+    // Still growing.
+    if bbr.btlbw as f64 >= bbr.full_bw as f64 * FULL_BW_THRESH {
+        bbr.full_bw = bbr.btlbw;
+        bbr.full_bw_count = 0;
+        return;
+    }
+
+    bbr.full_bw_count += 1;
+
+    if bbr.full_bw_count >= FULL_BW_COUNT {
+        bbr.filled_pipe = true;
+    }
+}
+
+fn check_drain(r: &mut Recovery, now: Instant) {
+    if r.bbr_state.mode == Mode::Startup && r.bbr_state.filled_pipe {
+        r.bbr_state.mode = Mode::Drain;
+        r.bbr_state.pacing_gain = 1.0 / HIGH_GAIN;
+        r.bbr_state.cwnd_gain = HIGH_GAIN;
+    }
+
+    if r.bbr_state.mode == Mode::Drain && r.bytes_in_flight <= inflight(r, 1.0) {
+        enter_probe_bw(r, now);
+    }
+}
+
+fn enter_startup(r: &mut Recovery) {
+    let bbr = &mut r.bbr_state;
+
+    bbr.mode = Mode::Startup;
+    bbr.pacing_gain = HIGH_GAIN;
+    bbr.cwnd_gain = HIGH_GAIN;
+}
+
+fn enter_probe_bw(r: &mut Recovery, now: Instant) {
+    let bbr = &mut r.bbr_state;
+
+    bbr.mode = Mode::ProbeBW;
+    bbr.pacing_gain = 1.0;
+    bbr.cwnd_gain = CWND_GAIN;
+
+    // Start at a random phase other than the draining one, so that flows
+    // sharing a bottleneck don't probe in lockstep.
+    let cycle_len = PACING_GAIN_CYCLE.len();
+    let offset = rand::rand_u64_uniform(cycle_len as u64 - 1) as usize;
+
+    bbr.cycle_index = cycle_len - 1 - offset;
+
+    advance_cycle_phase(r, now);
+}
+
+fn update_rtprop(r: &mut Recovery, now: Instant) {
+    let bbr = &mut r.bbr_state;
+
+    bbr.rtprop_expired = now > bbr.rtprop_stamp + RTPROP_FILTER_LEN;
+
+    if r.latest_rtt == Duration::new(0, 0) {
+        return;
+    }
+
+    let lower = match bbr.rtprop {
+        Some(rtprop) => r.latest_rtt <= rtprop,
+
+        None => true,
+    };
+
+    if lower || bbr.rtprop_expired {
+        bbr.rtprop = Some(r.latest_rtt);
+        bbr.rtprop_stamp = now;
+    }
+}
+
+fn check_probe_rtt(r: &mut Recovery, now: Instant) {
+    if r.bbr_state.mode != Mode::ProbeRTT &&
+        r.bbr_state.rtprop_expired &&
+        !r.bbr_state.idle_restart
+    {
+        save_cwnd(r);
+
+        let bbr = &mut r.bbr_state;
+
+        bbr.mode = Mode::ProbeRTT;
+        bbr.pacing_gain = 1.0;
+        bbr.cwnd_gain = 1.0;
+        bbr.probe_rtt_done_stamp = None;
+    }
+
+    if r.bbr_state.mode == Mode::ProbeRTT {
+        handle_probe_rtt(r, now);
+    }
+
+    r.bbr_state.idle_restart = false;
+}
+
+fn handle_probe_rtt(r: &mut Recovery, now: Instant) {
+    let min_pipe_cwnd = min_pipe_cwnd(r);
+    let delivered = r.delivery_rate.delivered();
+
+    match r.bbr_state.probe_rtt_done_stamp {
+        None if r.bytes_in_flight <= min_pipe_cwnd => {
+            let bbr = &mut r.bbr_state;
+
+            bbr.probe_rtt_done_stamp = Some(now + PROBE_RTT_DURATION);
+            bbr.probe_rtt_round_done = false;
+            bbr.next_round_delivered = delivered;
+        },
+
+        Some(done_stamp) => {
+            if r.bbr_state.round_start {
+                r.bbr_state.probe_rtt_round_done = true;
+            }
+
+            if r.bbr_state.probe_rtt_round_done && now > done_stamp {
+                r.bbr_state.rtprop_stamp = now;
+
+                restore_cwnd(r);
+
+                if r.bbr_state.filled_pipe {
+                    enter_probe_bw(r, now);
+                } else {
+                    enter_startup(r);
+                }
+            }
+        },
+
+        None => (),
+    }
+}
+
+fn init_pacing_rate(r: &mut Recovery) {
+    let rate = r.congestion_window as f64 / r.rtt().as_secs_f64();
+
+    r.pacing_rate = (HIGH_GAIN * rate) as u64;
+}
+
+fn set_pacing_rate_with_gain(r: &mut Recovery, gain: f64) {
+    let rate = (gain * r.bbr_state.btlbw as f64) as u64;
+
+    if r.bbr_state.filled_pipe || rate > r.pacing_rate {
+        r.pacing_rate = rate;
+    }
+}
+
+fn set_pacing_rate(r: &mut Recovery) {
+    if r.bbr_state.btlbw == 0 {
+        return;
+    }
+
+    set_pacing_rate_with_gain(r, r.bbr_state.pacing_gain);
+}
+
+fn set_cwnd(r: &mut Recovery, packet: &Acked) {
+    let target_cwnd = inflight(r, r.bbr_state.cwnd_gain);
+    let initial_window = super::initial_window(r.max_datagram_size);
+    let bbr = &r.bbr_state;
+
+    if bbr.packet_conservation {
+        r.congestion_window =
+            cmp::max(r.congestion_window, r.bytes_in_flight + packet.size);
+    } else if bbr.filled_pipe {
+        r.congestion_window =
+            cmp::min(r.congestion_window + packet.size, target_cwnd);
+    } else if r.congestion_window < target_cwnd ||
+        r.delivery_rate.delivered() < initial_window
+    {
+        r.congestion_window += packet.size;
+    }
+
+    r.congestion_window = cmp::max(r.congestion_window, min_pipe_cwnd(r));
+
+    if bbr.mode == Mode::ProbeRTT {
+        r.congestion_window = cmp::min(r.congestion_window, min_pipe_cwnd(r));
+    }
+}
+
+fn save_cwnd(r: &mut Recovery) {
+    let bbr = &mut r.bbr_state;
+
+    bbr.prior_cwnd = if !bbr.in_recovery && bbr.mode != Mode::ProbeRTT {
+        r.congestion_window
+    } else {
+        cmp::max(bbr.prior_cwnd, r.congestion_window)
+    };
+}
+
+fn restore_cwnd(r: &mut Recovery) {
+    r.congestion_window = cmp::max(r.congestion_window, r.bbr_state.prior_cwnd);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ranges;
+    use crate::recovery;
+
+    fn send_and_ack_round(
+        r: &mut Recovery, pkt_num: u64, count: u64, rtt: Duration, now: Instant,
+    ) -> Instant {
+        for pn in pkt_num..pkt_num + count {
+            let p = recovery::Sent {
+                pkt_num: pn,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1200,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                recent_delivered_packet_sent_time: now,
+                is_app_limited: false,
+                has_data: false,
+                pmtud_probe: false,
+                ecn_marked: false,
+            };
+
+            r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+        }
+
+        let now = now + rtt;
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(pkt_num..pkt_num + count);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                true,
+                now,
+                ""
+            ),
+            Ok(())
+        );
+
+        now
+    }
+
+    #[test]
+    fn bbr_init() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+
+        let r = Recovery::new(&cfg);
+
+        assert!(r.cwnd() > 0);
+        assert_eq!(r.bytes_in_flight, 0);
+        assert_eq!(r.bbr_state.mode, Mode::Startup);
+        assert_eq!(congestion_state(&r), "startup");
+    }
+
+    #[test]
+    fn bbr_send() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+
+        let mut r = Recovery::new(&cfg);
+
+        r.on_packet_sent_cc(1000, Instant::now());
+
+        assert_eq!(r.bytes_in_flight, 1000);
+
+        // The pacing rate is set before any bandwidth sample is available.
+        assert!(r.pacing_rate() > 0);
+    }
+
+    #[test]
+    fn bbr_startup() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+
+        let mut r = Recovery::new(&cfg);
+        let rtt = Duration::from_millis(50);
+        let cwnd_prev = r.cwnd();
+
+        send_and_ack_round(&mut r, 0, 5, rtt, Instant::now());
+
+        // In Startup, cwnd grows by the amount of acknowledged data.
+        assert_eq!(r.bbr_state.mode, Mode::Startup);
+        assert_eq!(r.cwnd(), cwnd_prev + 5 * 1200);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        assert!(r.bbr_state.btlbw > 0);
+        assert_eq!(r.bbr_state.rtprop, Some(rtt));
+        assert_eq!(
+            r.pacing_rate(),
+            (HIGH_GAIN * r.bbr_state.btlbw as f64) as u64
+        );
+    }
+
+    #[test]
+    fn bbr_probe_bw() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+
+        let mut r = Recovery::new(&cfg);
+        let rtt = Duration::from_millis(50);
+        let mut now = Instant::now();
+
+        // The bandwidth doesn't grow after the first round, so Startup ends
+        // after FULL_BW_COUNT more rounds, and Drain right after that as
+        // nothing is left in flight.
+        for i in 0..FULL_BW_COUNT as u64 {
+            now = send_and_ack_round(&mut r, i * 10, 10, rtt, now);
+
+            assert_eq!(r.bbr_state.mode, Mode::Startup);
+        }
+
+        send_and_ack_round(&mut r, 100, 10, rtt, now);
+
+        assert!(r.bbr_state.filled_pipe);
+        assert_eq!(r.bbr_state.mode, Mode::ProbeBW);
+        assert_eq!(congestion_state(&r), "probe_bw");
+    }
+
+    #[test]
+    fn bbr_congestion_event() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+        let prev_cwnd = r.cwnd();
+
+        r.on_packet_sent_cc(10000, now);
+
+        r.congestion_event(now, packet::EPOCH_APPLICATION, now);
+
+        // Packet conservation: cwnd is limited to what is in flight, plus one
+        // more packet.
+        assert_eq!(r.cwnd(), 10000 + r.max_datagram_size);
+        assert!(r.bbr_state.packet_conservation);
+
+        let acked = vec![Acked {
+            pkt_num: 0,
+            // To exit from recovery
+            time_sent: now + Duration::from_millis(1),
+            size: 1000,
+            delivered: 0,
+        }];
+
+        r.on_packets_acked(acked, packet::EPOCH_APPLICATION, now);
+
+        // The cwnd from before the congestion event is restored.
+        assert!(!r.bbr_state.in_recovery);
+        assert!(r.cwnd() >= prev_cwnd);
+    }
+
+    #[test]
+    fn bbr_collapse_cwnd() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+
+        let mut r = Recovery::new(&cfg);
+
+        r.collapse_cwnd();
+
+        assert_eq!(r.cwnd(), MIN_PIPE_CWND_PACKETS * r.max_datagram_size);
+    }
+}
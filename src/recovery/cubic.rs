@@ -37,19 +37,48 @@ use std::time::Duration;
 use std::time::Instant;
 
 use crate::packet;
-use crate::recovery;
 use crate::recovery::reno;
 
 use crate::recovery::Acked;
-use crate::recovery::CongestionControlOps;
+use crate::recovery::CongestionControl;
 use crate::recovery::Recovery;
 
-pub static CUBIC: CongestionControlOps = CongestionControlOps {
-    on_packet_sent,
-    on_packet_acked,
-    congestion_event,
-    collapse_cwnd,
-};
+/// CUBIC congestion controller.
+pub struct Cubic;
+
+impl CongestionControl for Cubic {
+    fn on_packet_sent(
+        &mut self, r: &mut Recovery, _sent_bytes: usize, now: Instant,
+    ) {
+        on_packet_sent(r, now);
+    }
+
+    fn on_packet_acked(
+        &mut self, r: &mut Recovery, packet: &Acked, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        on_packet_acked(r, packet, epoch, now);
+    }
+
+    fn congestion_event(
+        &mut self, r: &mut Recovery, time_sent: Instant, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        congestion_event(r, time_sent, epoch, now);
+    }
+
+    fn collapse_cwnd(&mut self, r: &mut Recovery) {
+        collapse_cwnd(r);
+    }
+
+    fn rollback(&mut self, r: &mut Recovery) {
+        rollback(r);
+    }
+
+    fn congestion_state(&self, r: &Recovery) -> Option<&'static str> {
+        Some(congestion_state(r))
+    }
+}
 
 /// CUBIC Constants.
 ///
@@ -72,6 +101,13 @@ pub struct State {
 
     // Used in CUBIC fix (see on_packet_sent())
     last_sent_time: Option<Instant>,
+
+    // Values from before the last congestion event, restored on rollback.
+    prior_k: f64,
+
+    prior_w_max: f64,
+
+    prior_w_last_max: f64,
 }
 
 /// CUBIC Functions.
@@ -81,27 +117,29 @@ pub struct State {
 /// Unit of t (duration) and RTT are based on seconds (f64).
 impl State {
     // K = cbrt(w_max * (1 - beta_cubic) / C) (Eq. 2)
-    fn cubic_k(&self) -> f64 {
-        let w_max = self.w_max / recovery::MAX_DATAGRAM_SIZE as f64;
+    fn cubic_k(&self, max_datagram_size: usize) -> f64 {
+        let w_max = self.w_max / max_datagram_size as f64;
         libm::cbrt(w_max * (1.0 - BETA_CUBIC) / C)
     }
 
     // W_cubic(t) = C * (t - K)^3 - w_max (Eq. 1)
-    fn w_cubic(&self, t: Duration) -> f64 {
-        let w_max = self.w_max / recovery::MAX_DATAGRAM_SIZE as f64;
+    fn w_cubic(&self, t: Duration, max_datagram_size: usize) -> f64 {
+        let w_max = self.w_max / max_datagram_size as f64;
 
         (C * (t.as_secs_f64() - self.k).powi(3) + w_max) *
-            recovery::MAX_DATAGRAM_SIZE as f64
+            max_datagram_size as f64
     }
 
     // W_est(t) = w_max * beta_cubic + 3 * (1 - beta_cubic) / (1 + beta_cubic) *
     // (t / RTT) (Eq. 4)
-    fn w_est(&self, t: Duration, rtt: Duration) -> f64 {
-        let w_max = self.w_max / recovery::MAX_DATAGRAM_SIZE as f64;
+    fn w_est(
+        &self, t: Duration, rtt: Duration, max_datagram_size: usize,
+    ) -> f64 {
+        let w_max = self.w_max / max_datagram_size as f64;
         (w_max * BETA_CUBIC +
             3.0 * (1.0 - BETA_CUBIC) / (1.0 + BETA_CUBIC) * t.as_secs_f64() /
                 rtt.as_secs_f64()) *
-            recovery::MAX_DATAGRAM_SIZE as f64
+            max_datagram_size as f64
     }
 }
 
@@ -115,12 +153,24 @@ fn collapse_cwnd(r: &mut Recovery) {
 
     // 4.7 Timeout - reduce ssthresh based on BETA_CUBIC
     r.ssthresh = (r.congestion_window as f64 * BETA_CUBIC) as usize;
-    r.ssthresh = cmp::max(r.ssthresh, recovery::MINIMUM_WINDOW);
+    r.ssthresh = cmp::max(r.ssthresh, r.minimum_window());
 
     reno::collapse_cwnd(r);
 }
 
-fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, now: Instant) {
+fn rollback(r: &mut Recovery) {
+    let cubic = &mut r.cubic_state;
+
+    cubic.k = cubic.prior_k;
+    cubic.w_max = cubic.prior_w_max;
+    cubic.w_last_max = cubic.prior_w_last_max;
+}
+
+fn congestion_state(r: &Recovery) -> &'static str {
+    reno::congestion_state(r)
+}
+
+fn on_packet_sent(r: &mut Recovery, now: Instant) {
     // See https://github.com/torvalds/linux/commit/30927520dbae297182990bb21d08762bcc35ce1d
     // First transmit when no packets in flight
     let cubic = &mut r.cubic_state;
@@ -141,8 +191,6 @@ fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, now: Instant) {
     }
 
     cubic.last_sent_time = Some(now);
-
-    reno::on_packet_sent(r, sent_bytes, now);
 }
 
 fn on_packet_acked(
@@ -151,8 +199,6 @@ fn on_packet_acked(
     let in_congestion_recovery = r.in_congestion_recovery(packet.time_sent);
     let cubic = &mut r.cubic_state;
 
-    r.bytes_in_flight = r.bytes_in_flight.saturating_sub(packet.size);
-
     if in_congestion_recovery {
         return;
     }
@@ -206,10 +252,10 @@ fn on_packet_acked(
         let t = now - ca_start_time;
 
         // w_cubic(t + rtt)
-        let w_cubic = cubic.w_cubic(t + r.min_rtt);
+        let w_cubic = cubic.w_cubic(t + r.min_rtt, r.max_datagram_size);
 
         // w_est(t)
-        let w_est = cubic.w_est(t, r.min_rtt);
+        let w_est = cubic.w_est(t, r.min_rtt, r.max_datagram_size);
 
         let mut cubic_cwnd = r.congestion_window;
 
@@ -219,7 +265,7 @@ fn on_packet_acked(
         } else if cubic_cwnd < w_cubic as usize {
             // Concave region or convex region use same increment.
             let cwnd_inc = (w_cubic - cubic_cwnd as f64) / cubic_cwnd as f64 *
-                recovery::MAX_DATAGRAM_SIZE as f64;
+                r.max_datagram_size as f64;
 
             cubic_cwnd += cwnd_inc as usize;
         }
@@ -243,6 +289,7 @@ fn congestion_event(
     r: &mut Recovery, time_sent: Instant, epoch: packet::Epoch, now: Instant,
 ) {
     let in_congestion_recovery = r.in_congestion_recovery(time_sent);
+    let minimum_window = r.minimum_window();
     let cubic = &mut r.cubic_state;
 
     // Start a new congestion event if packet was sent after the
@@ -250,6 +297,10 @@ fn congestion_event(
     if !in_congestion_recovery {
         r.congestion_recovery_start_time = Some(now);
 
+        cubic.prior_k = cubic.k;
+        cubic.prior_w_max = cubic.w_max;
+        cubic.prior_w_last_max = cubic.w_last_max;
+
         // Fast convergence
         if cubic.w_max < cubic.w_last_max {
             cubic.w_last_max = cubic.w_max;
@@ -260,9 +311,9 @@ fn congestion_event(
 
         cubic.w_max = r.congestion_window as f64;
         r.ssthresh = (cubic.w_max * BETA_CUBIC) as usize;
-        r.ssthresh = cmp::max(r.ssthresh, recovery::MINIMUM_WINDOW);
+        r.ssthresh = cmp::max(r.ssthresh, minimum_window);
         r.congestion_window = r.ssthresh;
-        cubic.k = cubic.cubic_k();
+        cubic.k = cubic.cubic_k(r.max_datagram_size);
 
         if r.hystart.enabled() && epoch == packet::EPOCH_APPLICATION {
             r.hystart.congestion_event();
@@ -273,6 +324,7 @@ fn congestion_event(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::recovery;
     use crate::recovery::hystart;
 
     #[test]
@@ -320,6 +372,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         // Send 5k x 4 = 20k, higher than default cwnd(~15k)
@@ -335,6 +389,7 @@ mod tests {
             pkt_num: p.pkt_num,
             time_sent: p.time_sent,
             size: p.size,
+            delivered: 0,
         }];
 
         r.on_packets_acked(acked, packet::EPOCH_APPLICATION, now);
@@ -359,6 +414,25 @@ mod tests {
         assert_eq!(prev_cwnd as f64 * BETA_CUBIC, r.cwnd() as f64);
     }
 
+    #[test]
+    fn cubic_rollback() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::CUBIC);
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+        let prev_cwnd = r.cwnd();
+
+        r.congestion_event(now, packet::EPOCH_APPLICATION, now);
+        assert_eq!(r.cubic_state.w_max, prev_cwnd as f64);
+
+        // The CUBIC state from before the congestion event is restored.
+        rollback(&mut r);
+        assert_eq!(r.cubic_state.w_max, 0.0);
+        assert_eq!(r.cubic_state.w_last_max, 0.0);
+        assert_eq!(r.cubic_state.k, 0.0);
+    }
+
     #[test]
     fn cubic_congestion_avoidance() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -384,6 +458,7 @@ mod tests {
             // To exit from recovery
             time_sent: now + rtt,
             size: 1000,
+            delivered: 0,
         }];
 
         // Ack 1000 bytes with rtt=100ms
@@ -410,13 +485,14 @@ mod tests {
 
         // After persistent congestion, cwnd should be MINIMUM_WINDOW
         r.collapse_cwnd();
-        assert_eq!(r.cwnd(), recovery::MINIMUM_WINDOW);
+        assert_eq!(r.cwnd(), r.minimum_window());
 
         let acked = vec![Acked {
             pkt_num: 0,
             // To exit from recovery
             time_sent: now + Duration::from_millis(1),
             size: 10000,
+            delivered: 0,
         }];
 
         // rtt = 100ms
@@ -430,7 +506,7 @@ mod tests {
         // This will make CC into congestion avoidance mode
         r.on_packets_acked(acked, packet::EPOCH_APPLICATION, now);
 
-        assert_eq!(r.cwnd(), recovery::MINIMUM_WINDOW + 10000);
+        assert_eq!(r.cwnd(), r.minimum_window() + 10000);
     }
 
     #[test]
@@ -458,6 +534,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         // 1st round.
@@ -485,6 +563,7 @@ mod tests {
                 pkt_num: p.pkt_num,
                 time_sent: p.time_sent,
                 size: p.size,
+                delivered: 0,
             }];
 
             r.on_packets_acked(acked, epoch, now);
@@ -520,6 +599,7 @@ mod tests {
                 pkt_num: p.pkt_num,
                 time_sent: p.time_sent,
                 size: p.size,
+                delivered: 0,
             }];
 
             r.on_packets_acked(acked, epoch, now);
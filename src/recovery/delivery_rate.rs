@@ -125,6 +125,14 @@ impl Rate {
     pub fn delivery_rate(&self) -> u64 {
         self.rate_sample.delivery_rate
     }
+
+    pub fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    pub fn sample_is_app_limited(&self) -> bool {
+        self.rate_sample.is_app_limited
+    }
 }
 
 impl std::fmt::Debug for Rate {
@@ -205,6 +213,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         recovery
@@ -229,6 +239,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         recovery
@@ -262,6 +274,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         recvry
@@ -284,6 +298,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         recvry.app_limited = true;
@@ -0,0 +1,321 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Explicit Congestion Notification validation
+//!
+//! This implementation is based on section 13.4 of RFC 9000:
+//!
+//! https://tools.ietf.org/html/rfc9000#section-13.4
+
+use crate::frame::EcnCounts;
+use crate::packet;
+
+/// Number of packets marked with ECT(0) before the path is known to support
+/// ECN.
+const TESTING_PACKETS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// ECN is turned off.
+    Disabled,
+
+    /// Packets are being marked to test whether the path supports ECN.
+    Testing,
+
+    /// Marking stopped after testing, waiting for the marks to be validated.
+    Unknown,
+
+    /// The path supports ECN and packets are marked.
+    Capable,
+
+    /// The path, or the peer, doesn't support ECN so packets are not marked.
+    Failed,
+}
+
+pub struct Validator {
+    state: State,
+
+    /// The number of packets marked while testing the path.
+    testing_sent: usize,
+
+    /// The number of packets marked while testing the path that were lost.
+    testing_lost: usize,
+
+    /// The number of packets marked with ECT(0) in each packet number space.
+    sent_ect0: [u64; packet::EPOCH_COUNT],
+
+    /// The latest ECN counts reported by the peer in each packet number space.
+    peer_counts: [EcnCounts; packet::EPOCH_COUNT],
+}
+
+impl Validator {
+    pub fn new(enabled: bool) -> Self {
+        let state = if enabled {
+            State::Testing
+        } else {
+            State::Disabled
+        };
+
+        Validator {
+            state,
+
+            testing_sent: 0,
+
+            testing_lost: 0,
+
+            sent_ect0: [0; packet::EPOCH_COUNT],
+
+            peer_counts: [EcnCounts::default(); packet::EPOCH_COUNT],
+        }
+    }
+
+    /// Starts validation again, after the path changed.
+    ///
+    /// The counters are cumulative for the whole connection, so they are
+    /// not reset.
+    pub fn reset(&mut self) {
+        if self.state == State::Disabled {
+            return;
+        }
+
+        self.state = State::Testing;
+        self.testing_sent = 0;
+        self.testing_lost = 0;
+    }
+
+    /// Returns whether the next packet should be marked with ECT(0).
+    pub fn should_mark(&self) -> bool {
+        self.state == State::Testing || self.state == State::Capable
+    }
+
+    /// Returns whether the path was validated as supporting ECN.
+    pub fn is_capable(&self) -> bool {
+        self.state == State::Capable
+    }
+
+    pub fn on_packet_sent(&mut self, epoch: packet::Epoch) {
+        self.sent_ect0[epoch] += 1;
+
+        if self.state == State::Testing {
+            self.testing_sent += 1;
+
+            if self.testing_sent >= TESTING_PACKETS {
+                self.state = State::Unknown;
+            }
+        }
+    }
+
+    pub fn on_packet_lost(&mut self) {
+        if self.state != State::Testing && self.state != State::Unknown {
+            return;
+        }
+
+        self.testing_lost += 1;
+
+        // All the packets marked while testing were lost, so the marks are
+        // likely being dropped by the path.
+        if self.state == State::Unknown && self.testing_lost >= self.testing_sent
+        {
+            self.state = State::Failed;
+        }
+    }
+
+    /// Validates the ECN counts carried by an ACK frame that newly
+    /// acknowledged `newly_acked_ect0` packets marked with ECT(0).
+    ///
+    /// Returns the number of new CE marks reported by the peer, which are
+    /// to be treated as congestion signals.
+    pub fn on_ack_received(
+        &mut self, epoch: packet::Epoch, largest_acked_increased: bool,
+        newly_acked_ect0: u64, ecn_counts: Option<EcnCounts>,
+    ) -> u64 {
+        if self.state == State::Disabled || self.state == State::Failed {
+            return 0;
+        }
+
+        // Reordered ACK frames can carry stale counts, so they are ignored.
+        if !largest_acked_increased {
+            return 0;
+        }
+
+        let counts = match ecn_counts {
+            Some(v) => v,
+
+            None => {
+                // The peer, or the path, cleared the marks.
+                if newly_acked_ect0 > 0 {
+                    self.state = State::Failed;
+                }
+
+                return 0;
+            },
+        };
+
+        let prev = self.peer_counts[epoch];
+
+        let valid = counts.ect0_count >= prev.ect0_count &&
+            counts.ecn_ce_count >= prev.ecn_ce_count &&
+            // Only ECT(0) is used, so ECT(1) marks were set by the path.
+            counts.ect1_count == prev.ect1_count &&
+            // The marks of all newly acked packets must be accounted for.
+            (counts.ect0_count - prev.ect0_count) +
+                (counts.ecn_ce_count - prev.ecn_ce_count) >=
+                newly_acked_ect0 &&
+            // The peer can't report more marks than were sent.
+            counts.ect0_count + counts.ecn_ce_count <= self.sent_ect0[epoch];
+
+        if !valid {
+            self.state = State::Failed;
+            return 0;
+        }
+
+        if newly_acked_ect0 > 0 && self.state != State::Capable {
+            self.state = State::Capable;
+        }
+
+        self.peer_counts[epoch] = counts;
+
+        counts.ecn_ce_count - prev.ecn_ce_count
+    }
+}
+
+impl std::fmt::Debug for Validator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "state={:?} ", self.state)?;
+        write!(f, "testing_sent={} ", self.testing_sent)?;
+        write!(f, "testing_lost={}", self.testing_lost)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(ect0_count: u64, ecn_ce_count: u64) -> Option<EcnCounts> {
+        Some(EcnCounts {
+            ect0_count,
+            ect1_count: 0,
+            ecn_ce_count,
+        })
+    }
+
+    #[test]
+    fn disabled() {
+        let mut ecn = Validator::new(false);
+
+        assert!(!ecn.should_mark());
+        assert_eq!(
+            ecn.on_ack_received(packet::EPOCH_APPLICATION, true, 0, counts(0, 1)),
+            0
+        );
+    }
+
+    #[test]
+    fn validated() {
+        let mut ecn = Validator::new(true);
+        let epoch = packet::EPOCH_APPLICATION;
+
+        for _ in 0..TESTING_PACKETS {
+            assert!(ecn.should_mark());
+            ecn.on_packet_sent(epoch);
+        }
+
+        // Marking stops until validation succeeds.
+        assert!(!ecn.should_mark());
+
+        assert_eq!(ecn.on_ack_received(epoch, true, 3, counts(2, 1)), 1);
+        assert!(ecn.is_capable());
+        assert!(ecn.should_mark());
+
+        // Counts from reordered ACKs are ignored.
+        assert_eq!(ecn.on_ack_received(epoch, false, 0, counts(1, 0)), 0);
+        assert!(ecn.is_capable());
+
+        assert_eq!(ecn.on_ack_received(epoch, true, 2, counts(4, 1)), 0);
+        assert!(ecn.is_capable());
+    }
+
+    #[test]
+    fn marks_cleared() {
+        let mut ecn = Validator::new(true);
+        let epoch = packet::EPOCH_APPLICATION;
+
+        ecn.on_packet_sent(epoch);
+        ecn.on_packet_sent(epoch);
+
+        assert_eq!(ecn.on_ack_received(epoch, true, 2, None), 0);
+        assert!(!ecn.should_mark());
+    }
+
+    #[test]
+    fn marks_undercounted() {
+        let mut ecn = Validator::new(true);
+        let epoch = packet::EPOCH_APPLICATION;
+
+        ecn.on_packet_sent(epoch);
+        ecn.on_packet_sent(epoch);
+
+        assert_eq!(ecn.on_ack_received(epoch, true, 2, counts(1, 0)), 0);
+        assert!(!ecn.should_mark());
+    }
+
+    #[test]
+    fn marks_overcounted() {
+        let mut ecn = Validator::new(true);
+        let epoch = packet::EPOCH_APPLICATION;
+
+        ecn.on_packet_sent(epoch);
+
+        assert_eq!(ecn.on_ack_received(epoch, true, 1, counts(1, 1)), 0);
+        assert!(!ecn.should_mark());
+    }
+
+    #[test]
+    fn testing_packets_lost() {
+        let mut ecn = Validator::new(true);
+        let epoch = packet::EPOCH_APPLICATION;
+
+        for _ in 0..TESTING_PACKETS {
+            ecn.on_packet_sent(epoch);
+        }
+
+        for _ in 0..TESTING_PACKETS - 1 {
+            ecn.on_packet_lost();
+            assert!(!ecn.should_mark());
+        }
+
+        ecn.on_packet_lost();
+
+        // ACKs are ignored once validation failed.
+        assert_eq!(ecn.on_ack_received(epoch, true, 1, counts(1, 0)), 0);
+        assert!(!ecn.is_capable());
+
+        ecn.reset();
+        assert!(ecn.should_mark());
+    }
+}
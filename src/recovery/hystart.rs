@@ -112,7 +112,7 @@ impl Hystart {
     // Returns a new (ssthresh, cwnd) during slow start.
     pub fn on_packet_acked(
         &mut self, packet: &recovery::Acked, rtt: Duration, cwnd: usize,
-        ssthresh: usize, now: Instant,
+        ssthresh: usize, max_datagram_size: usize, now: Instant,
     ) -> (usize, usize) {
         let mut ssthresh = ssthresh;
         let mut cwnd = cwnd;
@@ -130,7 +130,7 @@ impl Hystart {
 
             self.rtt_sample_count += 1;
 
-            if cwnd >= (LOW_CWND * recovery::MAX_DATAGRAM_SIZE) &&
+            if cwnd >= (LOW_CWND * max_datagram_size) &&
                 self.rtt_sample_count >= N_RTT_SAMPLE &&
                 self.current_round_min_rtt.is_some() &&
                 self.last_round_min_rtt.is_some()
@@ -210,6 +210,7 @@ mod tests {
             pkt_num,
             time_sent: now + Duration::from_millis(10),
             size,
+            delivered: 0,
         };
 
         let init_cwnd = 30000;
@@ -220,6 +221,7 @@ mod tests {
             Duration::from_millis(10),
             init_cwnd,
             init_ssthresh,
+            recovery::MAX_DATAGRAM_SIZE,
             now,
         );
 
@@ -253,13 +255,20 @@ mod tests {
                 pkt_num,
                 time_sent: now + Duration::from_millis(pkt_num),
                 size,
+                delivered: 0,
             };
 
             // We use a fixed rtt for 1st round.
             let rtt = Duration::from_millis(rtt_1st);
 
-            let (new_cwnd, new_ssthresh) =
-                hspp.on_packet_acked(&p, rtt, cwnd, ssthresh, now);
+            let (new_cwnd, new_ssthresh) = hspp.on_packet_acked(
+                &p,
+                rtt,
+                cwnd,
+                ssthresh,
+                recovery::MAX_DATAGRAM_SIZE,
+                now,
+            );
 
             cwnd = new_cwnd;
             ssthresh = new_ssthresh;
@@ -277,14 +286,21 @@ mod tests {
                 pkt_num,
                 time_sent: now + Duration::from_millis(pkt_num),
                 size,
+                delivered: 0,
             };
 
             // Keep increasing rtt to simulate buffer queueing delay
             // This is to exit from slow slart to LSS.
             let rtt = Duration::from_millis(rtt_2nd + pkt_num * 4);
 
-            let (new_cwnd, new_ssthresh) =
-                hspp.on_packet_acked(&p, rtt, cwnd, ssthresh, now);
+            let (new_cwnd, new_ssthresh) = hspp.on_packet_acked(
+                &p,
+                rtt,
+                cwnd,
+                ssthresh,
+                recovery::MAX_DATAGRAM_SIZE,
+                now,
+            );
 
             cwnd = new_cwnd;
             ssthresh = new_ssthresh;
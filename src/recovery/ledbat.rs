@@ -0,0 +1,245 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! LEDBAT Congestion Control
+//!
+//! This implementation is based on the following RFC:
+//! https://tools.ietf.org/html/rfc6817
+//!
+//! LEDBAT is a less-than-best-effort controller meant for background
+//! transfers. It uses the minimum RTT as the base delay, and backs off as soon
+//! as the queuing delay grows above a target, yielding to competing flows.
+
+use std::cmp;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::packet;
+use crate::recovery::reno;
+
+use crate::recovery::Acked;
+use crate::recovery::CongestionControl;
+use crate::recovery::Recovery;
+
+/// LEDBAT congestion controller.
+pub struct Ledbat;
+
+impl CongestionControl for Ledbat {
+    fn on_packet_sent(
+        &mut self, _r: &mut Recovery, _sent_bytes: usize, _now: Instant,
+    ) {
+    }
+
+    fn on_packet_acked(
+        &mut self, r: &mut Recovery, packet: &Acked, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        on_packet_acked(r, packet, epoch, now);
+    }
+
+    fn congestion_event(
+        &mut self, r: &mut Recovery, time_sent: Instant, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        congestion_event(r, time_sent, epoch, now);
+    }
+
+    fn collapse_cwnd(&mut self, r: &mut Recovery) {
+        reno::collapse_cwnd(r);
+    }
+
+    fn congestion_state(&self, r: &Recovery) -> Option<&'static str> {
+        Some(reno::congestion_state(r))
+    }
+}
+
+/// LEDBAT Constants.
+///
+/// The maximum queuing delay LEDBAT tries to introduce, as recommended in
+/// the RFC.
+const TARGET: Duration = Duration::from_millis(100);
+
+/// How fast the window reacts to the queuing delay moving away from target.
+const GAIN: f64 = 1.0;
+
+/// How much the window can exceed the bytes in flight by, in packets.
+const ALLOWED_INCREASE_PACKETS: usize = 1;
+
+/// Slow start ends once the queuing delay exceeds this fraction of the target.
+const SLOW_START_EXIT_RATIO: f64 = 0.75;
+
+// Returns the queuing delay, estimated as the difference between the latest
+// RTT and the base (minimum) RTT.
+fn queuing_delay(r: &Recovery) -> Duration {
+    r.latest_rtt.checked_sub(r.min_rtt).unwrap_or_default()
+}
+
+fn on_packet_acked(
+    r: &mut Recovery, packet: &Acked, _epoch: packet::Epoch, _now: Instant,
+) {
+    if r.in_congestion_recovery(packet.time_sent) {
+        return;
+    }
+
+    if r.app_limited {
+        return;
+    }
+
+    let queuing_delay = queuing_delay(r).as_secs_f64();
+    let target = TARGET.as_secs_f64();
+
+    if r.congestion_window < r.ssthresh {
+        // Slow start, until the queuing delay gets close to the target.
+        if queuing_delay < target * SLOW_START_EXIT_RATIO {
+            r.congestion_window += packet.size;
+            return;
+        }
+
+        r.ssthresh = r.congestion_window;
+    }
+
+    // Grow the window when below target, and shrink it when above.
+    let off_target = (target - queuing_delay) / target;
+
+    let cwnd = r.congestion_window as f64 +
+        GAIN * off_target * (packet.size * r.max_datagram_size) as f64 /
+            r.congestion_window as f64;
+
+    // Don't grow the window beyond what is actually in flight.
+    let max_allowed_cwnd = r.bytes_in_flight +
+        packet.size +
+        ALLOWED_INCREASE_PACKETS * r.max_datagram_size;
+
+    r.congestion_window = cmp::min(cwnd.max(0.0) as usize, max_allowed_cwnd);
+    r.congestion_window = cmp::max(r.congestion_window, r.minimum_window());
+}
+
+fn congestion_event(
+    r: &mut Recovery, time_sent: Instant, _epoch: packet::Epoch, now: Instant,
+) {
+    // Start a new congestion event if packet was sent after the
+    // start of the previous congestion recovery period.
+    if !r.in_congestion_recovery(time_sent) {
+        r.congestion_recovery_start_time = Some(now);
+
+        r.congestion_window =
+            cmp::max(r.congestion_window / 2, r.minimum_window());
+
+        r.ssthresh = r.congestion_window;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recovery;
+
+    #[test]
+    fn ledbat_init() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::LEDBAT);
+
+        let r = Recovery::new(&cfg);
+
+        assert!(r.cwnd() > 0);
+        assert_eq!(r.bytes_in_flight, 0);
+    }
+
+    #[test]
+    fn ledbat_slow_start() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::LEDBAT);
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+
+        r.min_rtt = Duration::from_millis(50);
+        r.latest_rtt = Duration::from_millis(60);
+
+        // Send more than cwnd to become no longer app limited.
+        r.on_packet_sent_cc(20000, now);
+
+        let cwnd_prev = r.cwnd();
+
+        let acked = vec![Acked {
+            pkt_num: 0,
+            time_sent: now,
+            size: 5000,
+            delivered: 0,
+        }];
+
+        r.on_packets_acked(acked, packet::EPOCH_APPLICATION, now);
+
+        // The queuing delay is low, so cwnd grows by packet size.
+        assert_eq!(r.cwnd(), cwnd_prev + 5000);
+    }
+
+    #[test]
+    fn ledbat_queuing_delay() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::LEDBAT);
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+
+        r.min_rtt = Duration::from_millis(50);
+        r.latest_rtt = Duration::from_millis(250);
+
+        r.on_packet_sent_cc(20000, now);
+
+        let cwnd_prev = r.cwnd();
+
+        let acked = vec![Acked {
+            pkt_num: 0,
+            time_sent: now,
+            size: 1000,
+            delivered: 0,
+        }];
+
+        r.on_packets_acked(acked, packet::EPOCH_APPLICATION, now);
+
+        // The queuing delay is above target, so slow start ends and cwnd
+        // shrinks.
+        assert_eq!(r.ssthresh, cwnd_prev);
+        assert!(r.cwnd() < cwnd_prev);
+    }
+
+    #[test]
+    fn ledbat_congestion_event() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::LEDBAT);
+
+        let mut r = Recovery::new(&cfg);
+        let now = Instant::now();
+        let prev_cwnd = r.cwnd();
+
+        r.congestion_event(now, packet::EPOCH_APPLICATION, now);
+
+        assert_eq!(r.cwnd(), prev_cwnd / 2);
+        assert_eq!(r.ssthresh, r.cwnd());
+    }
+}
@@ -37,6 +37,7 @@ use crate::Config;
 use crate::Error;
 use crate::Result;
 
+use crate::ack_freq;
 use crate::frame;
 use crate::minmax;
 use crate::packet;
@@ -47,9 +48,15 @@ const PACKET_THRESHOLD: u64 = 3;
 
 const TIME_THRESHOLD: f64 = 9.0 / 8.0;
 
+// Reordering thresholds used once spurious losses were detected, similarly to
+// RACK (RFC 8985).
+const MAX_PACKET_THRESHOLD: u64 = 20;
+
+const REORDERING_TIME_THRESHOLD: f64 = 5.0 / 4.0;
+
 const GRANULARITY: Duration = Duration::from_millis(1);
 
-const INITIAL_RTT: Duration = Duration::from_millis(333);
+pub const INITIAL_RTT: Duration = Duration::from_millis(333);
 
 const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
 
@@ -60,14 +67,35 @@ const MAX_PTO_PROBES_COUNT: usize = 2;
 // Congestion Control
 const INITIAL_WINDOW_PACKETS: usize = 10;
 
-const INITIAL_WINDOW: usize = INITIAL_WINDOW_PACKETS * MAX_DATAGRAM_SIZE;
+const INITIAL_WINDOW_MAX_BYTES: usize = 14720;
+
+const MINIMUM_WINDOW_PACKETS: usize = 2;
 
-const MINIMUM_WINDOW: usize = 2 * MAX_DATAGRAM_SIZE;
+// The largest congestion window that can be resumed from a previous
+// connection, in packets.
+const MAX_RESUME_WINDOW_PACKETS: usize = 10_000;
 
 const MAX_DATAGRAM_SIZE: usize = 1452;
 
 const LOSS_REDUCTION_FACTOR: f64 = 0.5;
 
+// ACK frequency
+const ACKS_PER_CWND: u64 = 4;
+
+const MAX_PACKETS_PER_ACK: u64 = 10;
+
+// Pacing
+const PACING_MULTIPLIER: f64 = 1.25;
+
+// Paced packets can be released at most this much before their send time.
+const PACING_HORIZON: Duration = Duration::from_millis(1);
+
+/// Loss detection and congestion control state of a connection.
+///
+/// A [`CongestionControl`] implementation is given access to it to update
+/// the congestion window.
+///
+/// [`CongestionControl`]: trait.CongestionControl.html
 pub struct Recovery {
     loss_detection_timer: Option<Instant>,
 
@@ -83,32 +111,47 @@ pub struct Recovery {
 
     smoothed_rtt: Option<Duration>,
 
+    first_rtt_sample: Option<Instant>,
+
     rttvar: Duration,
 
     minmax_filter: minmax::Minmax<Duration>,
 
     min_rtt: Duration,
 
-    pub max_ack_delay: Duration,
+    pub(crate) max_ack_delay: Duration,
 
     loss_time: [Option<Instant>; packet::EPOCH_COUNT],
 
     sent: [VecDeque<Sent>; packet::EPOCH_COUNT],
 
-    pub lost: [Vec<frame::Frame>; packet::EPOCH_COUNT],
+    pub(crate) lost: [Vec<frame::Frame>; packet::EPOCH_COUNT],
+
+    pub(crate) acked: [Vec<frame::Frame>; packet::EPOCH_COUNT],
 
-    pub acked: [Vec<frame::Frame>; packet::EPOCH_COUNT],
+    pub(crate) lost_count: usize,
 
-    pub lost_count: usize,
+    pub(crate) spurious_lost_count: usize,
 
-    pub loss_probes: [usize; packet::EPOCH_COUNT],
+    pub(crate) loss_probes: [usize; packet::EPOCH_COUNT],
 
     app_limited: bool,
 
     delivery_rate: delivery_rate::Rate,
 
-    // Congestion control.
-    cc_ops: &'static CongestionControlOps,
+    // Reordering thresholds used to declare packets lost.
+    pkt_thresh: u64,
+
+    time_thresh: f64,
+
+    // Congestion state to restore if the last congestion event turns out to
+    // be caused by spurious losses only.
+    undo: Option<CongestionUndo>,
+
+    // Congestion control. Only `None` while the controller is being called.
+    cc: Option<Box<dyn CongestionControl>>,
+
+    max_datagram_size: usize,
 
     congestion_window: usize,
 
@@ -120,12 +163,38 @@ pub struct Recovery {
 
     cubic_state: cubic::State,
 
+    bbr_state: bbr::State,
+
     // HyStart++.
     hystart: hystart::Hystart,
+
+    // Path MTU discovery.
+    pmtud: pmtud::Pmtud,
+
+    // ECN validation.
+    ecn: ecn::Validator,
+
+    // Careful resume.
+    resume: resume::Resume,
+
+    // Pacing.
+    pacing: bool,
+
+    pacing_rate: u64,
+
+    next_send_time: Instant,
+
+    // Last congestion state logged to qlog.
+    #[cfg(feature = "qlog")]
+    qlog_cc_state: &'static str,
+
+    // Whether persistent congestion was detected since the last qlog event.
+    #[cfg(feature = "qlog")]
+    qlog_persistent_congestion: bool,
 }
 
 impl Recovery {
-    pub fn new(config: &Config) -> Self {
+    pub(crate) fn new(config: &Config) -> Self {
         Recovery {
             loss_detection_timer: None,
 
@@ -145,6 +214,8 @@ impl Recovery {
             // handled by the `rtt()` method instead.
             smoothed_rtt: None,
 
+            first_rtt_sample: None,
+
             minmax_filter: minmax::Minmax::new(Duration::new(0, 0)),
 
             min_rtt: Duration::new(0, 0),
@@ -163,9 +234,11 @@ impl Recovery {
 
             lost_count: 0,
 
+            spurious_lost_count: 0,
+
             loss_probes: [0; packet::EPOCH_COUNT],
 
-            congestion_window: INITIAL_WINDOW,
+            congestion_window: initial_window(MAX_DATAGRAM_SIZE),
 
             bytes_in_flight: 0,
 
@@ -173,19 +246,51 @@ impl Recovery {
 
             congestion_recovery_start_time: None,
 
-            cc_ops: config.cc_algorithm.into(),
+            cc: Some(match config.custom_cc {
+                Some(new_cc) => new_cc(),
+
+                None => config.cc_algorithm.into(),
+            }),
+
+            max_datagram_size: MAX_DATAGRAM_SIZE,
 
             delivery_rate: delivery_rate::Rate::default(),
 
+            pkt_thresh: PACKET_THRESHOLD,
+
+            time_thresh: TIME_THRESHOLD,
+
+            undo: None,
+
             cubic_state: cubic::State::default(),
 
+            bbr_state: bbr::State::default(),
+
             app_limited: false,
 
             hystart: hystart::Hystart::new(config.hystart),
+
+            pmtud: pmtud::Pmtud::new(config.pmtud),
+
+            ecn: ecn::Validator::new(config.ecn),
+
+            resume: resume::Resume::new(),
+
+            pacing: config.pacing,
+
+            pacing_rate: 0,
+
+            next_send_time: Instant::now(),
+
+            #[cfg(feature = "qlog")]
+            qlog_cc_state: "",
+
+            #[cfg(feature = "qlog")]
+            qlog_persistent_congestion: false,
         }
     }
 
-    pub fn on_packet_sent(
+    pub(crate) fn on_packet_sent(
         &mut self, mut pkt: Sent, epoch: packet::Epoch,
         handshake_completed: bool, now: Instant, trace_id: &str,
     ) {
@@ -194,6 +299,14 @@ impl Recovery {
         let sent_bytes = pkt.size;
         let pkt_num = pkt.pkt_num;
 
+        if pkt.pmtud_probe {
+            self.pmtud.on_probe_sent(sent_bytes);
+        }
+
+        if pkt.ecn_marked {
+            self.ecn.on_packet_sent(epoch);
+        }
+
         self.delivery_rate.on_packet_sent(&mut pkt, now);
 
         self.largest_sent_pkt[epoch] =
@@ -211,6 +324,10 @@ impl Recovery {
 
             self.on_packet_sent_cc(sent_bytes, now);
 
+            self.update_pacing_rate();
+
+            self.schedule_next_packet(epoch, sent_bytes, now);
+
             self.set_loss_detection_timer(handshake_completed);
         }
 
@@ -226,13 +343,66 @@ impl Recovery {
     }
 
     fn on_packet_sent_cc(&mut self, sent_bytes: usize, now: Instant) {
-        (self.cc_ops.on_packet_sent)(self, sent_bytes, now);
+        self.with_cc(|cc, r| cc.on_packet_sent(r, sent_bytes, now));
+
+        self.bytes_in_flight += sent_bytes;
+    }
+
+    // Calls into the congestion controller, which needs mutable access to
+    // the recovery state it is stored in.
+    fn with_cc<T>(
+        &mut self, f: impl FnOnce(&mut dyn CongestionControl, &mut Self) -> T,
+    ) -> T {
+        let mut cc = self.cc.take().unwrap();
+
+        let v = f(cc.as_mut(), self);
+
+        self.cc = Some(cc);
+
+        v
+    }
+
+    // Sets the pacing rate from the congestion window and the RTT, unless
+    // the congestion controller sets its own.
+    fn update_pacing_rate(&mut self) {
+        if self.cc.as_ref().unwrap().has_custom_pacing() {
+            return;
+        }
+
+        if let Some(srtt) = self.smoothed_rtt {
+            let rate = self.congestion_window as f64 * PACING_MULTIPLIER /
+                srtt.as_secs_f64();
+
+            self.pacing_rate = rate as u64;
+        }
+    }
+
+    // Moves the send time of the next packet forward by the time it takes to
+    // send `sent_bytes` at the pacing rate.
+    fn schedule_next_packet(
+        &mut self, epoch: packet::Epoch, sent_bytes: usize, now: Instant,
+    ) {
+        // Only application data is paced, as handshake packets are few and
+        // their RTT isn't known yet.
+        if !self.pacing ||
+            epoch != packet::EPOCH_APPLICATION ||
+            sent_bytes == 0 ||
+            self.pacing_rate == 0
+        {
+            return;
+        }
+
+        let interval =
+            Duration::from_secs_f64(sent_bytes as f64 / self.pacing_rate as f64);
+
+        self.next_send_time = cmp::max(self.next_send_time, now) + interval;
     }
 
-    pub fn on_ack_received(
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn on_ack_received(
         &mut self, ranges: &ranges::RangeSet, ack_delay: u64,
-        epoch: packet::Epoch, handshake_completed: bool, now: Instant,
-        trace_id: &str,
+        ecn_counts: Option<frame::EcnCounts>, epoch: packet::Epoch,
+        handshake_completed: bool, now: Instant, trace_id: &str,
     ) -> Result<()> {
         let largest_acked = ranges.last().unwrap();
 
@@ -261,6 +431,8 @@ impl Recovery {
 
         let mut newly_acked = Vec::new();
 
+        let mut newly_acked_ect0 = 0;
+
         // Detect and mark acked packets, without removing them from the sent
         // packets list.
         for r in ranges.iter() {
@@ -273,16 +445,54 @@ impl Recovery {
                 .skip_while(|p| p.pkt_num < lowest_acked)
                 // Skip packets that follow the largest acked packet in the block.
                 .take_while(|p| p.pkt_num <= largest_acked)
-                // Skip packets that have already been acked or lost.
-                .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
+                // Skip packets that have already been acked.
+                .filter(|p| p.time_acked.is_none());
 
             for unacked in unacked_iter {
                 unacked.time_acked = Some(now);
 
+                // The packet was declared lost, but it was only reordered.
+                if let Some(time_lost) = unacked.time_lost {
+                    self.spurious_lost_count += 1;
+
+                    // Tolerate as much reordering from now on.
+                    let reordering =
+                        self.largest_acked_pkt[epoch] - unacked.pkt_num + 1;
+
+                    self.pkt_thresh = cmp::min(
+                        cmp::max(self.pkt_thresh, reordering),
+                        MAX_PACKET_THRESHOLD,
+                    );
+
+                    self.time_thresh = REORDERING_TIME_THRESHOLD;
+
+                    if let Some(undo) = &mut self.undo {
+                        if unacked.in_flight &&
+                            !unacked.pmtud_probe &&
+                            time_lost >= undo.start
+                        {
+                            undo.lost = undo.lost.saturating_sub(1);
+                        }
+                    }
+
+                    trace!(
+                        "{} packet {} spuriously lost on epoch {}",
+                        trace_id,
+                        unacked.pkt_num,
+                        epoch
+                    );
+
+                    continue;
+                }
+
                 if unacked.ack_eliciting {
                     has_ack_eliciting = true;
                 }
 
+                if unacked.ecn_marked {
+                    newly_acked_ect0 += 1;
+                }
+
                 largest_newly_acked_pkt_num = unacked.pkt_num;
                 largest_newly_acked_sent_time = unacked.time_sent;
 
@@ -292,12 +502,20 @@ impl Recovery {
                     self.delivery_rate.on_packet_acked(&unacked, now);
                 }
 
+                if unacked.pmtud_probe {
+                    self.pmtud.on_probe_acked(unacked.size, now);
+                } else {
+                    self.pmtud.on_packet_acked(unacked.size);
+                }
+
                 newly_acked.push(Acked {
                     pkt_num: unacked.pkt_num,
 
                     time_sent: unacked.time_sent,
 
                     size: unacked.size,
+
+                    delivered: unacked.delivered,
                 });
 
                 trace!("{} packet newly acked {}", trace_id, unacked.pkt_num);
@@ -306,6 +524,10 @@ impl Recovery {
 
         self.delivery_rate.estimate();
 
+        if matches!(self.undo, Some(CongestionUndo { lost: 0, .. })) {
+            self.undo_congestion_event(trace_id);
+        }
+
         if newly_acked.is_empty() {
             return Ok(());
         }
@@ -322,24 +544,57 @@ impl Recovery {
             self.update_rtt(latest_rtt, ack_delay, now);
         }
 
+        // New CE marks reported by the peer are treated like a loss.
+        let ecn_ce_count = self.ecn.on_ack_received(
+            epoch,
+            largest_newly_acked_pkt_num == largest_acked,
+            newly_acked_ect0,
+            ecn_counts,
+        );
+
+        if ecn_ce_count > 0 {
+            trace!(
+                "{} {} packets marked CE on epoch {}",
+                trace_id,
+                ecn_ce_count,
+                epoch
+            );
+
+            self.congestion_event(largest_newly_acked_sent_time, epoch, now);
+
+            self.undo = None;
+        }
+
         // Detect and mark lost packets without removing them from the sent
         // packets list.
         self.detect_lost_packets(epoch, now, trace_id);
 
+        let acked_bytes = newly_acked.iter().fold(0, |acc, p| acc + p.size);
+
         self.on_packets_acked(newly_acked, epoch, now);
 
+        if epoch == packet::EPOCH_APPLICATION {
+            self.resume_on_packets_acked(
+                largest_newly_acked_pkt_num,
+                acked_bytes,
+                trace_id,
+            );
+        }
+
+        self.update_pmtu();
+
         self.pto_count = 0;
 
         self.set_loss_detection_timer(handshake_completed);
 
-        self.drain_packets(epoch);
+        self.drain_packets(epoch, now);
 
         trace!("{} {:?}", trace_id, self);
 
         Ok(())
     }
 
-    pub fn on_loss_detection_timeout(
+    pub(crate) fn on_loss_detection_timeout(
         &mut self, handshake_completed: bool, now: Instant, trace_id: &str,
     ) {
         let (earliest_loss_time, epoch) =
@@ -347,6 +602,7 @@ impl Recovery {
 
         if earliest_loss_time.is_some() {
             self.detect_lost_packets(epoch, now, trace_id);
+            self.update_pmtu();
             self.set_loss_detection_timer(handshake_completed);
 
             trace!("{} {:?}", trace_id, self);
@@ -390,7 +646,7 @@ impl Recovery {
         trace!("{} {:?}", trace_id, self);
     }
 
-    pub fn on_pkt_num_space_discarded(
+    pub(crate) fn on_pkt_num_space_discarded(
         &mut self, epoch: packet::Epoch, handshake_completed: bool,
     ) {
         let unacked_bytes = self.sent[epoch]
@@ -413,15 +669,177 @@ impl Recovery {
         self.set_loss_detection_timer(handshake_completed);
     }
 
-    pub fn loss_detection_timer(&self) -> Option<Instant> {
+    /// Resets the congestion controller and the RTT estimator after the
+    /// peer's address changed, as the new path's characteristics are unknown.
+    pub(crate) fn on_path_change(&mut self) {
+        self.latest_rtt = Duration::new(0, 0);
+        self.smoothed_rtt = None;
+        self.first_rtt_sample = None;
+        self.minmax_filter = minmax::Minmax::new(Duration::new(0, 0));
+        self.min_rtt = Duration::new(0, 0);
+        self.rttvar = INITIAL_RTT / 2;
+
+        self.pmtud.reset();
+        self.ecn.reset();
+        self.resume.reset();
+
+        self.pkt_thresh = PACKET_THRESHOLD;
+        self.time_thresh = TIME_THRESHOLD;
+        self.undo = None;
+
+        if self.pmtud.is_active() {
+            self.max_datagram_size = self.pmtud.pmtu();
+        }
+
+        self.congestion_window = initial_window(self.max_datagram_size);
+        self.ssthresh = std::usize::MAX;
+        self.congestion_recovery_start_time = None;
+
+        self.cubic_state = cubic::State::default();
+        self.bbr_state = bbr::State::default();
+        self.hystart = hystart::Hystart::new(self.hystart.enabled());
+
+        self.pacing_rate = 0;
+    }
+
+    /// Starts path MTU discovery, if enabled, up to the given datagram size.
+    pub(crate) fn start_pmtud(&mut self, max_datagram_size: usize) {
+        self.pmtud.start(max_datagram_size);
+
+        self.update_pmtu();
+    }
+
+    /// Returns the path MTU, if path MTU discovery is enabled.
+    pub(crate) fn pmtu(&self) -> Option<usize> {
+        if self.pmtud.is_enabled() {
+            Some(self.pmtud.pmtu())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the size of the PMTU probe to send next, if any.
+    pub(crate) fn pmtu_probe_size(&mut self, now: Instant) -> Option<usize> {
+        self.pmtud.probe_size(now)
+    }
+
+    /// Returns the previous path MTU, if it changed since the last call.
+    pub(crate) fn pmtu_update(&mut self) -> Option<usize> {
+        self.pmtud.take_update()
+    }
+
+    /// Returns whether the next packet should be marked with ECT(0).
+    pub(crate) fn ecn_should_mark(&self) -> bool {
+        self.ecn.should_mark()
+    }
+
+    /// Returns whether the path was validated as supporting ECN.
+    pub fn ecn_capable(&self) -> bool {
+        self.ecn.is_capable()
+    }
+
+    /// Returns the earliest time at which a packet sent now should go on the
+    /// wire.
+    pub(crate) fn get_packet_send_time(&self, now: Instant) -> Instant {
+        cmp::max(self.next_send_time, now)
+    }
+
+    /// Returns whether a packet sent now can go on the wire together with one
+    /// sent at `at`, that is whether it's released within the same pacing
+    /// horizon.
+    pub(crate) fn can_send_at(&self, at: Instant, now: Instant) -> bool {
+        self.get_packet_send_time(now) <= cmp::max(at, now) + PACING_HORIZON
+    }
+
+    /// Returns the time at which new packets are no longer held back by
+    /// pacing, if they currently are.
+    pub(crate) fn pacing_timer(&self, now: Instant) -> Option<Instant> {
+        let release_time = self.next_send_time.checked_sub(PACING_HORIZON)?;
+
+        if release_time > now {
+            Some(release_time)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the pacing rate, in bytes per second.
+    pub fn pacing_rate(&self) -> u64 {
+        self.pacing_rate
+    }
+
+    /// Sets the pacing rate, in bytes per second.
+    ///
+    /// This is only taken into account if the congestion controller has
+    /// custom pacing, otherwise the rate is derived from the congestion
+    /// window and the RTT.
+    pub fn set_pacing_rate(&mut self, rate: u64) {
+        self.pacing_rate = rate;
+    }
+
+    /// Returns the maximum size of the packets sent on the path, in bytes.
+    pub fn max_datagram_size(&self) -> usize {
+        self.max_datagram_size
+    }
+
+    // Makes congestion control follow changes to the path MTU.
+    fn update_pmtu(&mut self) {
+        self.pmtud.check_black_hole();
+
+        let pmtu = self.pmtud.pmtu();
+
+        if !self.pmtud.is_active() || pmtu == self.max_datagram_size {
+            return;
+        }
+
+        // Only the initial window is scaled, as a window that already grew
+        // or shrank reflects the state of the path.
+        if self.congestion_window == initial_window(self.max_datagram_size) {
+            self.congestion_window = initial_window(pmtu);
+        }
+
+        self.max_datagram_size = pmtu;
+
+        self.congestion_window =
+            cmp::max(self.congestion_window, self.minimum_window());
+    }
+
+    /// Returns the smallest congestion window allowed, in bytes.
+    pub fn minimum_window(&self) -> usize {
+        MINIMUM_WINDOW_PACKETS * self.max_datagram_size
+    }
+
+    pub(crate) fn loss_detection_timer(&self) -> Option<Instant> {
         self.loss_detection_timer
     }
 
+    /// Returns the congestion window, in bytes.
     pub fn cwnd(&self) -> usize {
         self.congestion_window
     }
 
-    pub fn cwnd_available(&self) -> usize {
+    /// Sets the congestion window, in bytes.
+    pub fn set_cwnd(&mut self, cwnd: usize) {
+        self.congestion_window = cwnd;
+    }
+
+    /// Returns the slow start threshold, in bytes.
+    pub fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    /// Sets the slow start threshold, in bytes.
+    pub fn set_ssthresh(&mut self, ssthresh: usize) {
+        self.ssthresh = ssthresh;
+    }
+
+    /// Returns the number of bytes sent but not yet acknowledged or declared
+    /// lost.
+    pub fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    pub(crate) fn cwnd_available(&self) -> usize {
         // Ignore cwnd when sending probe packets.
         if self.loss_probes.iter().any(|&x| x > 0) {
             return std::usize::MAX;
@@ -430,18 +848,47 @@ impl Recovery {
         self.congestion_window.saturating_sub(self.bytes_in_flight)
     }
 
+    /// Returns the smoothed RTT, or the initial RTT if there are no samples
+    /// yet.
     pub fn rtt(&self) -> Duration {
         self.smoothed_rtt.unwrap_or(INITIAL_RTT)
     }
 
-    pub fn pto(&self) -> Duration {
+    /// Returns the minimum RTT seen over the last RTT samples.
+    pub fn min_rtt(&self) -> Duration {
+        self.min_rtt
+    }
+
+    /// Returns the most recent RTT sample.
+    pub fn latest_rtt(&self) -> Duration {
+        self.latest_rtt
+    }
+
+    /// Returns the RTT variation.
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    pub(crate) fn pto(&self) -> Duration {
         self.rtt() + cmp::max(self.rttvar * 4, GRANULARITY) + self.max_ack_delay
     }
 
+    /// Returns the latest delivery rate sample, in bytes per second.
     pub fn delivery_rate(&self) -> u64 {
         self.delivery_rate.delivery_rate()
     }
 
+    /// Returns the total number of bytes acknowledged so far.
+    pub fn delivered(&self) -> usize {
+        self.delivery_rate.delivered()
+    }
+
+    /// Returns whether the latest delivery rate sample was taken while the
+    /// application was not sending enough data to fill the congestion window.
+    pub fn delivery_rate_app_limited(&self) -> bool {
+        self.delivery_rate.sample_is_app_limited()
+    }
+
     fn update_rtt(
         &mut self, latest_rtt: Duration, ack_delay: Duration, now: Instant,
     ) {
@@ -455,6 +902,8 @@ impl Recovery {
                 self.smoothed_rtt = Some(latest_rtt);
 
                 self.rttvar = latest_rtt / 2;
+
+                self.first_rtt_sample = Some(now);
             },
 
             Some(srtt) => {
@@ -543,7 +992,7 @@ impl Recovery {
         self.loss_time[epoch] = None;
 
         let loss_delay =
-            cmp::max(self.latest_rtt, self.rtt()).mul_f64(TIME_THRESHOLD);
+            cmp::max(self.latest_rtt, self.rtt()).mul_f64(self.time_thresh);
 
         // Minimum time of kGranularity before packets are deemed lost.
         let loss_delay = cmp::max(loss_delay, GRANULARITY);
@@ -552,6 +1001,7 @@ impl Recovery {
         let lost_send_time = now - loss_delay;
 
         let mut lost_bytes = 0;
+        let mut lost_packets = 0;
 
         let mut largest_lost_pkt = None;
 
@@ -565,14 +1015,30 @@ impl Recovery {
         for unacked in unacked_iter {
             // Mark packet as lost, or set time when it should be marked.
             if unacked.time_sent <= lost_send_time ||
-                largest_acked >= unacked.pkt_num + PACKET_THRESHOLD
+                largest_acked >= unacked.pkt_num + self.pkt_thresh
             {
                 self.lost[epoch].append(&mut unacked.frames);
 
                 unacked.time_lost = Some(now);
 
-                if unacked.in_flight {
+                if unacked.in_flight && unacked.pmtud_probe {
+                    // Lost PMTU probes are not a sign of congestion.
+                    self.bytes_in_flight =
+                        self.bytes_in_flight.saturating_sub(unacked.size);
+
+                    self.pmtud.on_probe_lost(unacked.size, now);
+
+                    trace!(
+                        "{} pmtu probe {} of size {} lost",
+                        trace_id,
+                        unacked.pkt_num,
+                        unacked.size
+                    );
+                } else if unacked.in_flight {
                     lost_bytes += unacked.size;
+                    lost_packets += 1;
+
+                    self.pmtud.on_packet_lost(unacked.size);
 
                     // Frames have already been removed from the packet, so
                     // cloning the whole packet should be relatively cheap.
@@ -586,6 +1052,10 @@ impl Recovery {
                     );
                 }
 
+                if unacked.ecn_marked {
+                    self.ecn.on_packet_lost();
+                }
+
                 self.lost_count += 1;
             } else {
                 let loss_time = match self.loss_time[epoch] {
@@ -600,13 +1070,20 @@ impl Recovery {
         }
 
         if let Some(pkt) = largest_lost_pkt {
-            self.on_packets_lost(lost_bytes, &pkt, epoch, now);
+            self.on_packets_lost(
+                lost_bytes,
+                lost_packets,
+                &pkt,
+                epoch,
+                now,
+                trace_id,
+            );
         }
 
-        self.drain_packets(epoch);
+        self.drain_packets(epoch, now);
     }
 
-    fn drain_packets(&mut self, epoch: packet::Epoch) {
+    fn drain_packets(&mut self, epoch: packet::Epoch, now: Instant) {
         let mut lowest_non_expired_pkt_index = self.sent[epoch].len();
 
         // In order to avoid removing elements from the middle of the list
@@ -617,10 +1094,23 @@ impl Recovery {
         // This means that acked or lost elements coming after this will not
         // be removed at this point, but their removal is delayed for a later
         // time, once the gaps have been filled.
+        //
+        // Lost elements are kept for an additional RTT, so that an ACK that
+        // arrives late because of reordering can still be matched to them, and
+        // the loss detected as spurious.
 
-        // First, find the first element that is neither acked nor lost.
+        // First, find the first element that is neither acked nor expired.
         for (i, pkt) in self.sent[epoch].iter().enumerate() {
-            if pkt.time_acked.is_none() && pkt.time_lost.is_none() {
+            if let Some(time_lost) = pkt.time_lost {
+                if pkt.time_acked.is_none() && time_lost + self.rtt() > now {
+                    lowest_non_expired_pkt_index = i;
+                    break;
+                }
+
+                continue;
+            }
+
+            if pkt.time_acked.is_none() {
                 lowest_non_expired_pkt_index = i;
                 break;
             }
@@ -634,11 +1124,15 @@ impl Recovery {
         &mut self, acked: Vec<Acked>, epoch: packet::Epoch, now: Instant,
     ) {
         for pkt in acked {
-            (self.cc_ops.on_packet_acked)(self, &pkt, epoch, now);
+            self.bytes_in_flight = self.bytes_in_flight.saturating_sub(pkt.size);
+
+            self.with_cc(|cc, r| cc.on_packet_acked(r, &pkt, epoch, now));
         }
     }
 
-    fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
+    /// Returns whether a packet sent at `sent_time` was sent before the start
+    /// of the current congestion recovery period.
+    pub fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
         match self.congestion_recovery_start_time {
             Some(congestion_recovery_start_time) =>
                 sent_time <= congestion_recovery_start_time,
@@ -647,39 +1141,231 @@ impl Recovery {
         }
     }
 
-    fn in_persistent_congestion(&mut self, _largest_lost_pkt_num: u64) -> bool {
-        let _congestion_period = self.pto() * PERSISTENT_CONGESTION_THRESHOLD;
+    // Returns whether the packets that were just declared lost establish
+    // persistent congestion, as defined in RFC 9002, section 7.6.
+    fn in_persistent_congestion(
+        &self, epoch: packet::Epoch, now: Instant,
+    ) -> bool {
+        // Persistent congestion can't be established before the first RTT
+        // sample, as the PTO would be based on the initial RTT.
+        let first_rtt_sample = match self.first_rtt_sample {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        let congestion_period = self.pto() * PERSISTENT_CONGESTION_THRESHOLD;
+
+        let mut period_start = None;
+
+        let sent_iter = self.sent[epoch]
+            .iter()
+            .filter(|p| p.time_sent > first_rtt_sample);
+
+        for p in sent_iter {
+            // An acknowledged packet in the middle of the lost ones shows the
+            // path was working, and an in-flight packet that wasn't declared
+            // lost yet might still be, so the period starts over.
+            if p.time_acked.is_some() || (p.in_flight && p.time_lost.is_none())
+            {
+                period_start = None;
+                continue;
+            }
+
+            let newly_lost = p.time_lost == Some(now) &&
+                p.ack_eliciting &&
+                p.in_flight &&
+                !p.pmtud_probe;
+
+            if !newly_lost {
+                continue;
+            }
+
+            match period_start {
+                Some(start) if p.time_sent - start > congestion_period =>
+                    return true,
+
+                Some(_) => (),
+
+                None => period_start = Some(p.time_sent),
+            }
+        }
 
-        // TODO: properly detect persistent congestion
         false
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn on_packets_lost(
-        &mut self, lost_bytes: usize, largest_lost_pkt: &Sent,
-        epoch: packet::Epoch, now: Instant,
+        &mut self, lost_bytes: usize, lost_packets: usize,
+        largest_lost_pkt: &Sent, epoch: packet::Epoch, now: Instant,
+        trace_id: &str,
     ) {
         self.bytes_in_flight = self.bytes_in_flight.saturating_sub(lost_bytes);
 
+        // Save the congestion state before a new congestion event starts.
+        if !self.in_congestion_recovery(largest_lost_pkt.time_sent) {
+            self.undo = Some(CongestionUndo {
+                cwnd: self.congestion_window,
+
+                ssthresh: self.ssthresh,
+
+                recovery_start_time: self.congestion_recovery_start_time,
+
+                start: now,
+
+                lost: 0,
+            });
+        }
+
+        if let Some(undo) = &mut self.undo {
+            undo.lost += lost_packets;
+        }
+
         self.congestion_event(largest_lost_pkt.time_sent, epoch, now);
 
-        if self.in_persistent_congestion(largest_lost_pkt.pkt_num) {
+        // Retreat if jumping to the saved congestion window caused losses.
+        let largest_sent = self.largest_sent_pkt[packet::EPOCH_APPLICATION];
+
+        if let Some(cwnd) = self.resume.on_packets_lost(largest_sent) {
+            self.congestion_window = cmp::max(cwnd, self.minimum_window());
+            self.ssthresh = self.congestion_window;
+
+            self.undo = None;
+
+            trace!("{} careful resume retreat cwnd={}", trace_id, self.cwnd());
+        }
+
+        if self.in_persistent_congestion(epoch, now) {
+            trace!("{} persistent congestion on epoch {}", trace_id, epoch);
+
             self.collapse_cwnd();
+
+            self.undo = None;
+
+            #[cfg(feature = "qlog")]
+            {
+                self.qlog_persistent_congestion = true;
+            }
         }
     }
 
     fn congestion_event(
         &mut self, time_sent: Instant, epoch: packet::Epoch, now: Instant,
     ) {
-        (self.cc_ops.congestion_event)(self, time_sent, epoch, now);
+        self.with_cc(|cc, r| cc.congestion_event(r, time_sent, epoch, now));
     }
 
     fn collapse_cwnd(&mut self) {
-        (self.cc_ops.collapse_cwnd)(self);
+        self.with_cc(|cc, r| cc.collapse_cwnd(r));
+    }
+
+    // Restores the congestion state from before the last congestion event, as
+    // all the packets that were lost during it have since been acked.
+    fn undo_congestion_event(&mut self, trace_id: &str) {
+        let undo = match self.undo.take() {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        self.congestion_window = cmp::max(self.congestion_window, undo.cwnd);
+        self.ssthresh = cmp::max(self.ssthresh, undo.ssthresh);
+        self.congestion_recovery_start_time = undo.recovery_start_time;
+
+        self.with_cc(|cc, r| cc.rollback(r));
+
+        trace!("{} congestion event undone cwnd={}", trace_id, self.cwnd());
     }
 
-    pub fn rate_check_app_limited(&mut self) {
+    /// Returns the ACK frequency to request from the peer, which can't delay
+    /// ACKs by less than `min_ack_delay`.
+    pub(crate) fn ack_frequency(
+        &self, min_ack_delay: Duration,
+    ) -> ack_freq::Params {
+        let (ack_eliciting_threshold, max_ack_delay) =
+            self.cc.as_ref().unwrap().ack_frequency(self);
+
+        ack_freq::Params {
+            ack_eliciting_threshold,
+
+            max_ack_delay: cmp::max(max_ack_delay, min_ack_delay),
+
+            // Out of order packets are acked right away when they could
+            // trigger loss detection.
+            reordering_threshold: self.pkt_thresh - 1,
+        }
+    }
+
+    /// Reuses the minimum RTT and congestion window of a previous connection
+    /// to the same peer, to skip most of slow start if the path is the same.
+    pub(crate) fn set_path_params(&mut self, min_rtt: Duration, cwnd: usize) {
+        // The saved window isn't trusted to be sane, so cap it to avoid
+        // flooding the path.
+        let max_cwnd = MAX_RESUME_WINDOW_PACKETS * self.max_datagram_size;
+
+        self.resume.setup(min_rtt, cmp::min(cwnd, max_cwnd));
+    }
+
+    /// Returns the minimum RTT and congestion window to resume from in a
+    /// later connection, once an RTT sample was taken.
+    pub(crate) fn path_params(&self) -> Option<(Duration, usize)> {
+        self.first_rtt_sample?;
+
+        Some((self.min_rtt, self.congestion_window))
+    }
+
+    // Careful resume: jumps to the saved congestion window once the RTT shows
+    // that the path is likely the same, and validates it afterwards.
+    fn resume_on_packets_acked(
+        &mut self, largest_acked: u64, acked_bytes: usize, trace_id: &str,
+    ) {
+        let largest_sent = self.largest_sent_pkt[packet::EPOCH_APPLICATION];
+
+        if self.resume.in_reconnaissance() {
+            // Only jump from slow start, and if there is enough data to send
+            // to use the larger window.
+            if self.first_rtt_sample.is_none() ||
+                self.app_limited ||
+                self.congestion_window >= self.ssthresh
+            {
+                return;
+            }
+
+            if let Some(cwnd) = self.resume.jump(
+                self.min_rtt,
+                self.congestion_window,
+                self.bytes_in_flight,
+                largest_sent,
+            ) {
+                self.congestion_window = cwnd;
+
+                trace!("{} careful resume jump cwnd={}", trace_id, cwnd);
+            }
+
+            return;
+        }
+
+        if let Some(cwnd) = self.resume.on_packets_acked(
+            largest_acked,
+            acked_bytes,
+            largest_sent,
+            self.bytes_in_flight,
+            self.congestion_window,
+        ) {
+            self.congestion_window = cmp::max(cwnd, self.minimum_window());
+        }
+    }
+
+    /// Starts a new congestion recovery period, if `start` is set.
+    pub fn set_congestion_recovery_start_time(&mut self, start: Option<Instant>) {
+        self.congestion_recovery_start_time = start;
+    }
+
+    pub(crate) fn rate_check_app_limited(&mut self) {
         if self.app_limited {
-            self.delivery_rate.check_app_limited(self.bytes_in_flight)
+            self.delivery_rate.check_app_limited(self.bytes_in_flight);
+
+            self.with_cc(|cc, r| cc.on_app_limited(r));
         }
     }
 
@@ -691,20 +1377,23 @@ impl Recovery {
             self.latest_rtt,
             self.congestion_window,
             self.ssthresh,
+            self.max_datagram_size,
             now,
         )
     }
 
-    pub fn update_app_limited(&mut self, v: bool) {
+    pub(crate) fn update_app_limited(&mut self, v: bool) {
         self.app_limited = v;
     }
 
-    pub fn app_limited(&mut self) -> bool {
+    /// Returns whether the application did not send enough data to fill the
+    /// congestion window.
+    pub fn app_limited(&self) -> bool {
         self.app_limited
     }
 
     #[cfg(feature = "qlog")]
-    pub fn to_qlog(&self) -> qlog::event::Event {
+    pub(crate) fn to_qlog(&self) -> qlog::event::Event {
         // QVis can't use all these fields and they can be large.
         qlog::event::Event::metrics_updated(
             Some(self.min_rtt.as_millis() as u64),
@@ -718,9 +1407,39 @@ impl Recovery {
             None, // ssthresh
             None, // packets_in_flight
             None, // in_recovery
-            None, // pacing_rate
+            Some(self.pacing_rate),
         )
     }
+
+    /// Returns a congestion state update event if the congestion controller
+    /// changed state since the last call.
+    #[cfg(feature = "qlog")]
+    pub(crate) fn state_to_qlog(&mut self) -> Option<qlog::event::Event> {
+        let state = if self.qlog_persistent_congestion {
+            self.qlog_persistent_congestion = false;
+
+            "persistent_congestion"
+        } else {
+            self.cc.as_ref().unwrap().congestion_state(self)?
+        };
+
+        if state == self.qlog_cc_state {
+            return None;
+        }
+
+        let old = if self.qlog_cc_state.is_empty() {
+            None
+        } else {
+            Some(self.qlog_cc_state.to_string())
+        };
+
+        self.qlog_cc_state = state;
+
+        Some(qlog::event::Event::congestion_state_updated(
+            old,
+            state.to_string(),
+        ))
+    }
 }
 
 /// Available congestion control algorithms.
@@ -730,9 +1449,14 @@ impl Recovery {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum CongestionControlAlgorithm {
     /// Reno congestion control algorithm. `reno` in a string form.
-    Reno  = 0,
+    Reno   = 0,
     /// CUBIC congestion control algorithm (default). `cubic` in a string form.
-    CUBIC = 1,
+    CUBIC  = 1,
+    /// BBR congestion control algorithm. `bbr` in a string form.
+    BBR    = 2,
+    /// LEDBAT congestion control algorithm, for background transfers that
+    /// yield to other traffic. `ledbat` in a string form.
+    LEDBAT = 3,
 }
 
 impl FromStr for CongestionControlAlgorithm {
@@ -745,33 +1469,103 @@ impl FromStr for CongestionControlAlgorithm {
         match name {
             "reno" => Ok(CongestionControlAlgorithm::Reno),
             "cubic" => Ok(CongestionControlAlgorithm::CUBIC),
+            "bbr" => Ok(CongestionControlAlgorithm::BBR),
+            "ledbat" => Ok(CongestionControlAlgorithm::LEDBAT),
 
             _ => Err(crate::Error::CongestionControl),
         }
     }
 }
 
-pub struct CongestionControlOps {
-    pub on_packet_sent: fn(r: &mut Recovery, sent_bytes: usize, now: Instant),
-
-    pub on_packet_acked:
-        fn(r: &mut Recovery, packet: &Acked, epoch: packet::Epoch, now: Instant),
+/// A congestion controller.
+///
+/// The controller is notified of the packets that are sent, acknowledged and
+/// lost on a connection, and updates the congestion window of the connection's
+/// [`Recovery`] state accordingly. Bytes in flight are accounted for by the
+/// `Recovery` state itself.
+///
+/// The built-in algorithms listed in [`CongestionControlAlgorithm`]
+/// implement this trait, and a custom implementation can be used instead with
+/// [`set_custom_cc()`].
+///
+/// [`Recovery`]: struct.Recovery.html
+/// [`CongestionControlAlgorithm`]: enum.CongestionControlAlgorithm.html
+/// [`set_custom_cc()`]: struct.Config.html#method.set_custom_cc
+pub trait CongestionControl: Send {
+    /// Called when an in-flight packet of `sent_bytes` bytes is sent, before
+    /// it is added to the bytes in flight.
+    fn on_packet_sent(
+        &mut self, r: &mut Recovery, sent_bytes: usize, now: Instant,
+    );
+
+    /// Called for every newly acknowledged in-flight packet, after it is
+    /// removed from the bytes in flight.
+    fn on_packet_acked(
+        &mut self, r: &mut Recovery, packet: &Acked, epoch: packet::Epoch,
+        now: Instant,
+    );
 
-    pub congestion_event: fn(
-        r: &mut Recovery,
-        time_sent: Instant,
-        epoch: packet::Epoch,
+    /// Called when packets are declared lost or reported as CE marked by the
+    /// peer. `time_sent` is the time the most recent of them was sent.
+    fn congestion_event(
+        &mut self, r: &mut Recovery, time_sent: Instant, epoch: packet::Epoch,
         now: Instant,
-    ),
+    );
+
+    /// Called on persistent congestion.
+    fn collapse_cwnd(&mut self, r: &mut Recovery);
+
+    /// Called when the last congestion event is undone, because all the
+    /// packets that were declared lost during it have since been acked.
+    ///
+    /// The congestion window, slow start threshold and congestion recovery
+    /// period were already restored to their values from before the event.
+    fn rollback(&mut self, _r: &mut Recovery) {}
+
+    /// Called when the application doesn't send enough data to fill the
+    /// congestion window.
+    fn on_app_limited(&mut self, _r: &mut Recovery) {}
+
+    /// Returns whether the controller sets the pacing rate itself, using
+    /// [`Recovery::set_pacing_rate()`].
+    ///
+    /// [`Recovery::set_pacing_rate()`]: struct.Recovery.html#method.set_pacing_rate
+    fn has_custom_pacing(&self) -> bool {
+        false
+    }
+
+    /// Returns the name of the controller's current state, which is logged
+    /// to qlog when it changes.
+    fn congestion_state(&self, _r: &Recovery) -> Option<&'static str> {
+        None
+    }
 
-    pub collapse_cwnd: fn(r: &mut Recovery),
+    /// Returns the ACK frequency to request from the peer, as the number of
+    /// ack-eliciting packets it can receive without sending an ACK, and the
+    /// maximum time it can delay an ACK by.
+    ///
+    /// This is only used if both endpoints support the ACK frequency
+    /// extension. By default, the peer is asked to send about four ACKs per
+    /// congestion window, and to delay them by a quarter of the RTT at most.
+    fn ack_frequency(&self, r: &Recovery) -> (u64, Duration) {
+        let cwnd_packets = (r.cwnd() / r.max_datagram_size()) as u64;
+
+        let packets_per_ack =
+            (cwnd_packets / ACKS_PER_CWND).clamp(2, MAX_PACKETS_PER_ACK);
+
+        let max_ack_delay = (r.rtt() / 4).as_millis() as u64;
+
+        (packets_per_ack - 1, Duration::from_millis(max_ack_delay))
+    }
 }
 
-impl From<CongestionControlAlgorithm> for &'static CongestionControlOps {
+impl From<CongestionControlAlgorithm> for Box<dyn CongestionControl> {
     fn from(algo: CongestionControlAlgorithm) -> Self {
         match algo {
-            CongestionControlAlgorithm::Reno => &reno::RENO,
-            CongestionControlAlgorithm::CUBIC => &cubic::CUBIC,
+            CongestionControlAlgorithm::Reno => Box::new(reno::Reno),
+            CongestionControlAlgorithm::CUBIC => Box::new(cubic::Cubic),
+            CongestionControlAlgorithm::BBR => Box::new(bbr::Bbr),
+            CongestionControlAlgorithm::LEDBAT => Box::new(ledbat::Ledbat),
         }
     }
 }
@@ -802,6 +1596,7 @@ impl std::fmt::Debug for Recovery {
         write!(f, "loss_time={:?} ", self.loss_time)?;
         write!(f, "loss_probes={:?} ", self.loss_probes)?;
         write!(f, "cwnd={} ", self.congestion_window)?;
+        write!(f, "max_datagram_size={} ", self.max_datagram_size)?;
         write!(f, "ssthresh={} ", self.ssthresh)?;
         write!(f, "bytes_in_flight={} ", self.bytes_in_flight)?;
         write!(f, "app_limited={} ", self.app_limited)?;
@@ -816,6 +1611,14 @@ impl std::fmt::Debug for Recovery {
             write!(f, "hystart={:?} ", self.hystart)?;
         }
 
+        if self.pmtud.is_active() {
+            write!(f, "pmtud={:?} ", self.pmtud)?;
+        }
+
+        if self.resume.is_active() {
+            write!(f, "resume={:?} ", self.resume)?;
+        }
+
         Ok(())
     }
 }
@@ -847,6 +1650,10 @@ pub struct Sent {
     pub is_app_limited: bool,
 
     pub has_data: bool,
+
+    pub pmtud_probe: bool,
+
+    pub ecn_marked: bool,
 }
 
 impl std::fmt::Debug for Sent {
@@ -863,18 +1670,51 @@ impl std::fmt::Debug for Sent {
         )?;
         write!(f, "is_app_limited={} ", self.is_app_limited)?;
         write!(f, "has_data={} ", self.has_data)?;
+        write!(f, "pmtud_probe={} ", self.pmtud_probe)?;
+        write!(f, "ecn_marked={} ", self.ecn_marked)?;
 
         Ok(())
     }
 }
 
+// Congestion state saved when a congestion event starts.
+struct CongestionUndo {
+    cwnd: usize,
+
+    ssthresh: usize,
+
+    recovery_start_time: Option<Instant>,
+
+    // When the congestion event started.
+    start: Instant,
+
+    // Number of packets lost during the congestion event that were not acked
+    // since.
+    lost: usize,
+}
+
+/// A packet that was newly acknowledged.
 #[derive(Clone)]
 pub struct Acked {
+    /// The packet number.
     pub pkt_num: u64,
 
+    /// The time the packet was sent.
     pub time_sent: Instant,
 
+    /// The size of the packet, in bytes.
     pub size: usize,
+
+    /// The number of bytes acknowledged when the packet was sent.
+    pub delivered: usize,
+}
+
+// The initial congestion window, as defined in RFC 9002.
+fn initial_window(max_datagram_size: usize) -> usize {
+    cmp::min(
+        INITIAL_WINDOW_PACKETS * max_datagram_size,
+        cmp::max(INITIAL_WINDOW_MAX_BYTES, 2 * max_datagram_size),
+    )
 }
 
 fn sub_abs(lhs: Duration, rhs: Duration) -> Duration {
@@ -893,6 +1733,12 @@ mod tests {
     fn lookup_cc_algo_ok() {
         let algo = CongestionControlAlgorithm::from_str("reno").unwrap();
         assert_eq!(algo, CongestionControlAlgorithm::Reno);
+
+        let algo = CongestionControlAlgorithm::from_str("bbr").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::BBR);
+
+        let algo = CongestionControlAlgorithm::from_str("ledbat").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::LEDBAT);
     }
 
     #[test]
@@ -912,7 +1758,94 @@ mod tests {
 
         // cwnd will be reset.
         r.collapse_cwnd();
-        assert_eq!(r.cwnd(), MINIMUM_WINDOW);
+        assert_eq!(r.cwnd(), r.minimum_window());
+    }
+
+    #[test]
+    fn custom_cc() {
+        struct FixedWindow;
+
+        impl CongestionControl for FixedWindow {
+            fn on_packet_sent(
+                &mut self, r: &mut Recovery, _sent_bytes: usize, _now: Instant,
+            ) {
+                r.set_cwnd(20000);
+            }
+
+            fn on_packet_acked(
+                &mut self, r: &mut Recovery, packet: &Acked,
+                _epoch: packet::Epoch, _now: Instant,
+            ) {
+                r.set_cwnd(r.cwnd() + packet.size);
+            }
+
+            fn congestion_event(
+                &mut self, r: &mut Recovery, _time_sent: Instant,
+                _epoch: packet::Epoch, _now: Instant,
+            ) {
+                r.set_cwnd(r.minimum_window());
+            }
+
+            fn collapse_cwnd(&mut self, _r: &mut Recovery) {}
+        }
+
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_custom_cc(|| Box::new(FixedWindow));
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = Instant::now();
+
+        let p = Sent {
+            pkt_num: 0,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            recent_delivered_packet_sent_time: now,
+            is_app_limited: false,
+            has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
+        };
+
+        r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+        assert_eq!(r.cwnd(), 20000);
+        assert_eq!(r.bytes_in_flight(), 1000);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                true,
+                now + Duration::from_millis(10),
+                ""
+            ),
+            Ok(())
+        );
+
+        assert_eq!(r.cwnd(), 21000);
+        assert_eq!(r.bytes_in_flight(), 0);
+
+        r.congestion_event(now, packet::EPOCH_APPLICATION, now);
+        assert_eq!(r.cwnd(), r.minimum_window());
+
+        // Selecting a built-in algorithm replaces the custom controller.
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let r = Recovery::new(&cfg);
+        let cc = r.cc.as_ref().unwrap();
+        assert_eq!(cc.congestion_state(&r), Some("slow_start"));
     }
 
     #[test]
@@ -941,6 +1874,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -961,6 +1896,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -981,6 +1918,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1001,6 +1940,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1018,6 +1959,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 true,
                 now,
@@ -1053,6 +1995,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1073,6 +2017,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1091,6 +2037,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 true,
                 now,
@@ -1099,10 +2046,17 @@ mod tests {
             Ok(())
         );
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
         assert_eq!(r.bytes_in_flight, 0);
 
         assert_eq!(r.lost_count, 2);
+
+        // Wait 1 RTT.
+        now += r.rtt();
+
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now, "");
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
     }
 
     #[test]
@@ -1131,6 +2085,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1151,6 +2107,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1171,6 +2129,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1191,6 +2151,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1209,6 +2171,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 true,
                 now,
@@ -1228,10 +2191,17 @@ mod tests {
         r.on_loss_detection_timeout(true, now, "");
         assert_eq!(r.loss_probes[packet::EPOCH_APPLICATION], 0);
 
-        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 2);
         assert_eq!(r.bytes_in_flight, 0);
 
         assert_eq!(r.lost_count, 1);
+
+        // Wait 1 RTT.
+        now += r.rtt();
+
+        r.detect_lost_packets(packet::EPOCH_APPLICATION, now, "");
+
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 0);
     }
 
     #[test]
@@ -1260,6 +2230,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1280,6 +2252,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1300,6 +2274,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1320,6 +2296,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
@@ -1337,6 +2315,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 true,
                 now,
@@ -1345,6 +2324,13 @@ mod tests {
             Ok(())
         );
 
+        // The lost packet at the head of the list is kept, so the late ACK can
+        // still be matched to it.
+        assert_eq!(r.sent[packet::EPOCH_APPLICATION].len(), 4);
+        assert_eq!(r.bytes_in_flight, 1000);
+        assert_eq!(r.lost_count, 1);
+        assert_eq!(r.cwnd(), initial_window(MAX_DATAGRAM_SIZE) / 2);
+
         now += Duration::from_millis(10);
 
         let mut acked = ranges::RangeSet::default();
@@ -1354,6 +2340,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 true,
                 now,
@@ -1367,10 +2354,345 @@ mod tests {
 
         // Spurious loss.
         assert_eq!(r.lost_count, 1);
+        assert_eq!(r.spurious_lost_count, 1);
+
+        // The reordering thresholds adapt to the observed reordering.
+        assert_eq!(r.pkt_thresh, 4);
+        assert_eq!(r.time_thresh, REORDERING_TIME_THRESHOLD);
+
+        // The congestion event caused by the spurious loss is undone.
+        assert_eq!(r.cwnd(), initial_window(MAX_DATAGRAM_SIZE));
+        assert_eq!(r.congestion_recovery_start_time, None);
+    }
+
+    #[test]
+    fn persistent_congestion() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        // Whether the packet sent in the middle of the lost ones is acked.
+        for &middle_acked in &[false, true] {
+            let mut r = Recovery::new(&cfg);
+
+            let start = Instant::now();
+
+            // Packet 0 provides the first RTT sample, packets 1 to 3 are sent
+            // over a longer period than the persistent congestion duration,
+            // and packets 4 to 6 cause them to be declared lost.
+            let send_times = [0, 60, 360, 660, 670, 670, 670];
+
+            for (pkt_num, &t) in send_times.iter().enumerate() {
+                let p = Sent {
+                    pkt_num: pkt_num as u64,
+                    frames: vec![],
+                    time_sent: start + Duration::from_millis(t),
+                    time_acked: None,
+                    time_lost: None,
+                    size: 1000,
+                    ack_eliciting: true,
+                    in_flight: true,
+                    delivered: 0,
+                    delivered_time: start,
+                    recent_delivered_packet_sent_time: start,
+                    is_app_limited: false,
+                    has_data: false,
+                    pmtud_probe: false,
+                    ecn_marked: false,
+                };
+
+                let now = p.time_sent;
+
+                r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+
+                if pkt_num == 0 {
+                    let mut acked = ranges::RangeSet::default();
+                    acked.insert(0..1);
+
+                    assert_eq!(
+                        r.on_ack_received(
+                            &acked,
+                            0,
+                            None,
+                            packet::EPOCH_APPLICATION,
+                            true,
+                            now + Duration::from_millis(50),
+                            ""
+                        ),
+                        Ok(())
+                    );
+                }
+            }
+
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(6..7);
+
+            if middle_acked {
+                acked.insert(2..3);
+            }
+
+            assert_eq!(
+                r.on_ack_received(
+                    &acked,
+                    0,
+                    None,
+                    packet::EPOCH_APPLICATION,
+                    true,
+                    start + Duration::from_millis(720),
+                    ""
+                ),
+                Ok(())
+            );
+
+            assert!(
+                r.pto() * PERSISTENT_CONGESTION_THRESHOLD <
+                    Duration::from_millis(600)
+            );
+
+            if middle_acked {
+                assert_eq!(r.lost_count, 2);
+                assert!(r.cwnd() > r.minimum_window());
+            } else {
+                assert_eq!(r.lost_count, 3);
+                assert_eq!(r.cwnd(), r.minimum_window());
+            }
+        }
+    }
+
+    #[test]
+    fn persistent_congestion_outstanding_packet() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let start = Instant::now();
+
+        // Packet 0 provides the first RTT sample, and packets 1 to 3 are sent
+        // over a longer period than the persistent congestion duration.
+        let send_times = [0, 60, 360, 660];
+
+        for (pkt_num, &t) in send_times.iter().enumerate() {
+            let p = Sent {
+                pkt_num: pkt_num as u64,
+                frames: vec![],
+                time_sent: start + Duration::from_millis(t),
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: start,
+                recent_delivered_packet_sent_time: start,
+                is_app_limited: false,
+                has_data: false,
+                pmtud_probe: false,
+                ecn_marked: false,
+            };
+
+            let now = p.time_sent;
+
+            r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+
+            if pkt_num == 0 {
+                let mut acked = ranges::RangeSet::default();
+                acked.insert(0..1);
+
+                assert_eq!(
+                    r.on_ack_received(
+                        &acked,
+                        0,
+                        None,
+                        packet::EPOCH_APPLICATION,
+                        true,
+                        now + Duration::from_millis(50),
+                        ""
+                    ),
+                    Ok(())
+                );
+            }
+        }
+
+        let now = start + Duration::from_millis(720);
+
+        // Packets 1 and 3 are lost, but packet 2 is still outstanding.
+        for p in r.sent[packet::EPOCH_APPLICATION].iter_mut() {
+            if p.pkt_num == 1 || p.pkt_num == 3 {
+                p.time_lost = Some(now);
+            }
+        }
+
+        assert!(!r.in_persistent_congestion(packet::EPOCH_APPLICATION, now));
+
+        // Once packet 2 is lost as well, all the packets in the period are.
+        for p in r.sent[packet::EPOCH_APPLICATION].iter_mut() {
+            if p.pkt_num == 2 {
+                p.time_lost = Some(now);
+            }
+        }
+
+        assert!(r.in_persistent_congestion(packet::EPOCH_APPLICATION, now));
+    }
+
+    #[test]
+    fn pacing() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = Instant::now();
+
+        // Packets are not paced until the RTT is known.
+        let p = Sent {
+            pkt_num: 0,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            recent_delivered_packet_sent_time: now,
+            is_app_limited: false,
+            has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
+        };
+
+        r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+        assert_eq!(r.pacing_rate(), 0);
+        assert_eq!(r.get_packet_send_time(now), now);
+        assert_eq!(r.pacing_timer(now), None);
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                true,
+                now,
+                ""
+            ),
+            Ok(())
+        );
+
+        assert_eq!(r.rtt(), Duration::from_millis(50));
+
+        let p = Sent {
+            pkt_num: 1,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            recent_delivered_packet_sent_time: now,
+            is_app_limited: false,
+            has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
+        };
+
+        r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+
+        let pacing_rate =
+            (r.cwnd() as f64 * PACING_MULTIPLIER / r.rtt().as_secs_f64()) as u64;
+        assert_eq!(r.pacing_rate(), pacing_rate);
+
+        // The next packet is sent once the previous one was paced out.
+        let interval = Duration::from_secs_f64(1000.0 / pacing_rate as f64);
+        assert!(interval > PACING_HORIZON);
+
+        assert_eq!(r.get_packet_send_time(now), now + interval);
+        assert_eq!(r.pacing_timer(now), Some(now + interval - PACING_HORIZON));
+        assert_eq!(r.pacing_timer(now + interval - PACING_HORIZON), None);
+
+        // It can't be batched with a packet sent now, only with one sent
+        // within the pacing horizon of its send time.
+        assert!(!r.can_send_at(now, now));
+        assert!(r.can_send_at(now + interval - PACING_HORIZON, now));
+
+        let p = Sent {
+            pkt_num: 2,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            recent_delivered_packet_sent_time: now,
+            is_app_limited: false,
+            has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
+        };
+
+        r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+        assert_eq!(r.get_packet_send_time(now), now + interval * 2);
+
+        // Packets that are not in flight don't delay the next packets.
+        now += interval * 2;
+
+        let p = Sent {
+            pkt_num: 3,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 0,
+            ack_eliciting: false,
+            in_flight: false,
+            delivered: 0,
+            delivered_time: now,
+            recent_delivered_packet_sent_time: now,
+            is_app_limited: false,
+            has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
+        };
+
+        r.on_packet_sent(p, packet::EPOCH_APPLICATION, true, now, "");
+        assert_eq!(r.get_packet_send_time(now), now);
+    }
+
+    #[test]
+    fn path_params_max_cwnd() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let rtt = Duration::from_millis(50);
+
+        r.set_path_params(rtt, usize::MAX);
+
+        // Only half of the capped window is used.
+        let max_cwnd = MAX_RESUME_WINDOW_PACKETS * r.max_datagram_size;
+        assert_eq!(r.resume.jump(rtt, r.cwnd(), 0, 0), Some(max_cwnd / 2));
     }
 }
 
+mod bbr;
 mod cubic;
 mod delivery_rate;
+mod ecn;
 mod hystart;
+mod ledbat;
+mod pmtud;
 mod reno;
+mod resume;
@@ -0,0 +1,435 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Datagram Packetization Layer Path MTU Discovery
+//!
+//! This implementation is based on the following RFC:
+//!
+//! https://tools.ietf.org/html/rfc8899
+
+use std::cmp;
+
+use std::time::Duration;
+use std::time::Instant;
+
+/// The smallest PMTU supported by all QUIC paths, used before a larger one is
+/// confirmed and as fallback when a black hole is detected.
+pub const BASE_PLPMTU: usize = 1200;
+
+/// Number of times a probe of a given size is lost before the size is
+/// considered too big for the path.
+const MAX_PROBES: usize = 3;
+
+/// Number of consecutive losses of packets larger than `BASE_PLPMTU`, without
+/// any such packet being acked, after which a black hole is detected.
+const BLACK_HOLE_THRESHOLD: usize = 6;
+
+/// The search stops once the PMTU is known within this many bytes.
+const SEARCH_GRANULARITY: usize = 16;
+
+/// Time after which a completed search is started again, as the path might
+/// support larger packets by then.
+const PMTU_RAISE_TIMER: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Discovery is turned off, or hasn't been started yet.
+    Disabled,
+
+    /// Probes are being sent to find a larger PMTU.
+    Searching,
+
+    /// The largest PMTU supported by the path was found.
+    SearchComplete,
+}
+
+pub struct Pmtud {
+    enabled: bool,
+
+    state: State,
+
+    /// The largest datagram size known to be supported by the path.
+    pmtu: usize,
+
+    /// The largest datagram size that can be probed.
+    max_pmtu: usize,
+
+    /// The smallest datagram size known not to be supported by the path.
+    search_high: usize,
+
+    /// The size of the probe currently in flight, if any.
+    probe_in_flight: Option<usize>,
+
+    /// The number of probes of the next probe size that were lost.
+    probe_count: usize,
+
+    /// The number of large packets lost since one was last acked.
+    black_hole_count: usize,
+
+    /// The time at which a completed search is started again.
+    raise_timer: Option<Instant>,
+
+    /// The previous PMTU, if it changed since the last call to
+    /// `take_update()`.
+    updated: Option<usize>,
+}
+
+impl Pmtud {
+    pub fn new(enabled: bool) -> Self {
+        Pmtud {
+            enabled,
+
+            state: State::Disabled,
+
+            pmtu: BASE_PLPMTU,
+
+            max_pmtu: BASE_PLPMTU,
+
+            search_high: BASE_PLPMTU + 1,
+
+            probe_in_flight: None,
+
+            probe_count: 0,
+
+            black_hole_count: 0,
+
+            raise_timer: None,
+
+            updated: None,
+        }
+    }
+
+    /// Starts searching for the PMTU, up to the given datagram size.
+    pub fn start(&mut self, max_pmtu: usize) {
+        if !self.enabled || self.is_active() {
+            return;
+        }
+
+        self.max_pmtu = cmp::max(max_pmtu, BASE_PLPMTU);
+
+        self.restart_search();
+    }
+
+    /// Forgets the PMTU of the current path, and starts a new search if one
+    /// was already started.
+    pub fn reset(&mut self) {
+        self.set_pmtu(BASE_PLPMTU);
+
+        self.probe_in_flight = None;
+        self.black_hole_count = 0;
+
+        if self.is_active() {
+            self.restart_search();
+        }
+    }
+
+    /// Returns true if the PMTU is being discovered.
+    pub fn is_active(&self) -> bool {
+        self.state != State::Disabled
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn pmtu(&self) -> usize {
+        self.pmtu
+    }
+
+    /// Returns the size of the next probe to send, if any.
+    pub fn probe_size(&mut self, now: Instant) -> Option<usize> {
+        if self.state == State::SearchComplete {
+            match self.raise_timer {
+                Some(timer) if timer <= now => self.restart_search(),
+
+                _ => return None,
+            }
+        }
+
+        if self.state != State::Searching || self.probe_in_flight.is_some() {
+            return None;
+        }
+
+        if self.is_search_done() {
+            self.complete_search(now);
+            return None;
+        }
+
+        Some(self.next_probe_size())
+    }
+
+    pub fn on_probe_sent(&mut self, size: usize) {
+        self.probe_in_flight = Some(size);
+    }
+
+    pub fn on_probe_acked(&mut self, size: usize, now: Instant) {
+        self.probe_in_flight = None;
+        self.probe_count = 0;
+        self.black_hole_count = 0;
+
+        if size > self.pmtu {
+            self.set_pmtu(size);
+        }
+
+        if self.state == State::Searching && self.is_search_done() {
+            self.complete_search(now);
+        }
+    }
+
+    pub fn on_probe_lost(&mut self, size: usize, now: Instant) {
+        self.probe_in_flight = None;
+
+        if self.state != State::Searching || size != self.next_probe_size() {
+            return;
+        }
+
+        self.probe_count += 1;
+
+        if self.probe_count >= MAX_PROBES {
+            self.search_high = size;
+            self.probe_count = 0;
+
+            if self.is_search_done() {
+                self.complete_search(now);
+            }
+        }
+    }
+
+    /// Tracks acked packets other than probes, for black hole detection.
+    pub fn on_packet_acked(&mut self, size: usize) {
+        if size > BASE_PLPMTU {
+            self.black_hole_count = 0;
+        }
+    }
+
+    /// Tracks lost packets other than probes, for black hole detection.
+    pub fn on_packet_lost(&mut self, size: usize) {
+        if self.is_active() && size > BASE_PLPMTU && size <= self.pmtu {
+            self.black_hole_count += 1;
+        }
+    }
+
+    /// Falls back to `BASE_PLPMTU` if too many large packets were lost, and
+    /// searches again for a PMTU below the one that stopped working.
+    pub fn check_black_hole(&mut self) {
+        if self.black_hole_count < BLACK_HOLE_THRESHOLD {
+            return;
+        }
+
+        let search_high = self.pmtu;
+
+        self.reset();
+
+        self.search_high = search_high;
+    }
+
+    /// Returns the previous PMTU, if it changed since the last call.
+    pub fn take_update(&mut self) -> Option<usize> {
+        self.updated.take()
+    }
+
+    fn next_probe_size(&self) -> usize {
+        // Try the largest size first, as it's likely to be supported when
+        // the peer advertised a small enough limit.
+        if self.search_high > self.max_pmtu {
+            return self.max_pmtu;
+        }
+
+        (self.pmtu + self.search_high) / 2
+    }
+
+    fn is_search_done(&self) -> bool {
+        self.pmtu >= self.max_pmtu ||
+            self.search_high - self.pmtu <= SEARCH_GRANULARITY
+    }
+
+    fn restart_search(&mut self) {
+        self.state = State::Searching;
+        self.search_high = self.max_pmtu + 1;
+        self.probe_count = 0;
+        self.raise_timer = None;
+    }
+
+    fn complete_search(&mut self, now: Instant) {
+        self.state = State::SearchComplete;
+        self.raise_timer = Some(now + PMTU_RAISE_TIMER);
+    }
+
+    fn set_pmtu(&mut self, pmtu: usize) {
+        if pmtu == self.pmtu {
+            return;
+        }
+
+        if self.updated.is_none() {
+            self.updated = Some(self.pmtu);
+        }
+
+        self.pmtu = pmtu;
+    }
+}
+
+impl std::fmt::Debug for Pmtud {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "state={:?} ", self.state)?;
+        write!(f, "pmtu={} ", self.pmtu)?;
+        write!(f, "search_high={} ", self.search_high)?;
+        write!(f, "probe_in_flight={:?} ", self.probe_in_flight)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled() {
+        let mut pmtud = Pmtud::new(false);
+        let now = Instant::now();
+
+        pmtud.start(1500);
+
+        assert!(!pmtud.is_active());
+        assert_eq!(pmtud.probe_size(now), None);
+        assert_eq!(pmtud.pmtu(), BASE_PLPMTU);
+    }
+
+    #[test]
+    fn probe_max_first() {
+        let mut pmtud = Pmtud::new(true);
+        let now = Instant::now();
+
+        pmtud.start(1500);
+
+        assert_eq!(pmtud.probe_size(now), Some(1500));
+
+        pmtud.on_probe_sent(1500);
+
+        // Only one probe is in flight at a time.
+        assert_eq!(pmtud.probe_size(now), None);
+
+        pmtud.on_probe_acked(1500, now);
+
+        assert_eq!(pmtud.pmtu(), 1500);
+        assert_eq!(pmtud.take_update(), Some(BASE_PLPMTU));
+        assert_eq!(pmtud.take_update(), None);
+
+        // The search is complete.
+        assert_eq!(pmtud.probe_size(now), None);
+        assert_eq!(pmtud.probe_size(now + PMTU_RAISE_TIMER), None);
+    }
+
+    #[test]
+    fn search() {
+        let mut pmtud = Pmtud::new(true);
+        let now = Instant::now();
+
+        pmtud.start(9000);
+
+        // The maximum size is lost too many times.
+        for _ in 0..MAX_PROBES {
+            assert_eq!(pmtud.probe_size(now), Some(9000));
+
+            pmtud.on_probe_sent(9000);
+            pmtud.on_probe_lost(9000, now);
+        }
+
+        // Path MTU is 1500 bytes, search between the known bounds.
+        while let Some(size) = pmtud.probe_size(now) {
+            assert!(size > pmtud.pmtu());
+            assert!(size < 9000);
+
+            pmtud.on_probe_sent(size);
+
+            if size <= 1500 {
+                pmtud.on_probe_acked(size, now);
+            } else {
+                pmtud.on_probe_lost(size, now);
+            }
+        }
+
+        assert!(pmtud.pmtu() <= 1500);
+        assert!(pmtud.pmtu() > 1500 - SEARCH_GRANULARITY);
+
+        // A new search starts once the raise timer expires.
+        assert_eq!(pmtud.probe_size(now + PMTU_RAISE_TIMER), Some(9000));
+    }
+
+    #[test]
+    fn black_hole() {
+        let mut pmtud = Pmtud::new(true);
+        let now = Instant::now();
+
+        pmtud.start(1500);
+
+        pmtud.on_probe_sent(1500);
+        pmtud.on_probe_acked(1500, now);
+
+        assert_eq!(pmtud.pmtu(), 1500);
+        pmtud.take_update();
+
+        // Losses interleaved with acks are not a black hole.
+        for _ in 0..BLACK_HOLE_THRESHOLD {
+            pmtud.on_packet_lost(1500);
+            pmtud.on_packet_acked(1500);
+        }
+
+        pmtud.check_black_hole();
+        assert_eq!(pmtud.pmtu(), 1500);
+
+        // Small packets being acked doesn't help either.
+        for _ in 0..BLACK_HOLE_THRESHOLD {
+            pmtud.on_packet_lost(1500);
+            pmtud.on_packet_acked(100);
+        }
+
+        pmtud.check_black_hole();
+        assert_eq!(pmtud.pmtu(), BASE_PLPMTU);
+        assert_eq!(pmtud.take_update(), Some(1500));
+
+        // The size that stopped working isn't probed again.
+        let size = pmtud.probe_size(now).unwrap();
+        assert!(size < 1500);
+        assert!(size > BASE_PLPMTU);
+    }
+
+    #[test]
+    fn reset() {
+        let mut pmtud = Pmtud::new(true);
+        let now = Instant::now();
+
+        pmtud.start(1500);
+
+        pmtud.on_probe_sent(1500);
+        pmtud.on_probe_acked(1500, now);
+
+        pmtud.reset();
+
+        assert_eq!(pmtud.pmtu(), BASE_PLPMTU);
+        assert_eq!(pmtud.probe_size(now), Some(1500));
+    }
+}
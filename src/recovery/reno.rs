@@ -35,25 +35,44 @@ use crate::packet;
 use crate::recovery;
 
 use crate::recovery::Acked;
-use crate::recovery::CongestionControlOps;
+use crate::recovery::CongestionControl;
 use crate::recovery::Recovery;
 
-pub static RENO: CongestionControlOps = CongestionControlOps {
-    on_packet_sent,
-    on_packet_acked,
-    congestion_event,
-    collapse_cwnd,
-};
+/// Reno congestion controller.
+pub struct Reno;
 
-pub fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, _now: Instant) {
-    r.bytes_in_flight += sent_bytes;
+impl CongestionControl for Reno {
+    fn on_packet_sent(
+        &mut self, _r: &mut Recovery, _sent_bytes: usize, _now: Instant,
+    ) {
+    }
+
+    fn on_packet_acked(
+        &mut self, r: &mut Recovery, packet: &Acked, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        on_packet_acked(r, packet, epoch, now);
+    }
+
+    fn congestion_event(
+        &mut self, r: &mut Recovery, time_sent: Instant, epoch: packet::Epoch,
+        now: Instant,
+    ) {
+        congestion_event(r, time_sent, epoch, now);
+    }
+
+    fn collapse_cwnd(&mut self, r: &mut Recovery) {
+        collapse_cwnd(r);
+    }
+
+    fn congestion_state(&self, r: &Recovery) -> Option<&'static str> {
+        Some(congestion_state(r))
+    }
 }
 
 fn on_packet_acked(
     r: &mut Recovery, packet: &Acked, epoch: packet::Epoch, now: Instant,
 ) {
-    r.bytes_in_flight = r.bytes_in_flight.saturating_sub(packet.size);
-
     if r.in_congestion_recovery(packet.time_sent) {
         return;
     }
@@ -76,7 +95,7 @@ fn on_packet_acked(
         // Congestion avoidance.
         let mut reno_cwnd = r.congestion_window;
 
-        reno_cwnd += (recovery::MAX_DATAGRAM_SIZE * packet.size) / reno_cwnd;
+        reno_cwnd += (r.max_datagram_size * packet.size) / reno_cwnd;
 
         // When in Limited Slow Start, take the max of CA cwnd and
         // LSS cwnd.
@@ -105,8 +124,7 @@ fn congestion_event(
             recovery::LOSS_REDUCTION_FACTOR)
             as usize;
 
-        r.congestion_window =
-            cmp::max(r.congestion_window, recovery::MINIMUM_WINDOW);
+        r.congestion_window = cmp::max(r.congestion_window, r.minimum_window());
 
         r.ssthresh = r.congestion_window;
 
@@ -117,7 +135,15 @@ fn congestion_event(
 }
 
 pub fn collapse_cwnd(r: &mut Recovery) {
-    r.congestion_window = recovery::MINIMUM_WINDOW;
+    r.congestion_window = r.minimum_window();
+}
+
+pub fn congestion_state(r: &Recovery) -> &'static str {
+    if r.congestion_window < r.ssthresh {
+        "slow_start"
+    } else {
+        "congestion_avoidance"
+    }
 }
 
 #[cfg(test)]
@@ -172,6 +198,8 @@ mod tests {
             recent_delivered_packet_sent_time: std::time::Instant::now(),
             is_app_limited: false,
             has_data: false,
+            pmtud_probe: false,
+            ecn_marked: false,
         };
 
         // Send 5k x 4 = 20k, higher than default cwnd(~15k)
@@ -187,6 +215,7 @@ mod tests {
             pkt_num: p.pkt_num,
             time_sent: p.time_sent,
             size: p.size,
+            delivered: 0,
         }];
 
         r.on_packets_acked(acked, packet::EPOCH_APPLICATION, now);
@@ -235,6 +264,7 @@ mod tests {
             pkt_num: 0,
             time_sent: now,
             size: 5000,
+            delivered: 0,
         }];
 
         let prev_cwnd = r.cwnd();
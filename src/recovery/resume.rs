@@ -0,0 +1,307 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Careful Resume
+//!
+//! This implementation is based on the following draft:
+//!
+//! https://tools.ietf.org/html/draft-ietf-tsvwg-careful-resume
+//!
+//! The congestion window of a previous connection to the same peer is reused
+//! to skip most of slow start, once the RTT confirms that the path is likely
+//! the same. The jump is validated by the acknowledgements of the packets
+//! sent with the larger window, and the window is reduced if this causes any
+//! loss.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// No saved path parameters, or resuming is over.
+    Normal,
+
+    /// The saved parameters are checked against the first RTT of the
+    /// connection, while the congestion controller runs normally.
+    Reconnaissance,
+
+    /// The congestion window jumped to the saved one, but it is unknown
+    /// whether the path can sustain it.
+    Unvalidated,
+
+    /// Packets sent with the larger window are being acknowledged.
+    Validating,
+
+    /// Loss was detected after the jump, and the window was reduced.
+    SafeRetreat,
+}
+
+pub struct Resume {
+    state: State,
+
+    /// The minimum RTT of the previous connection.
+    saved_rtt: Duration,
+
+    /// The congestion window of the previous connection.
+    saved_cwnd: usize,
+
+    /// The capacity the path was shown to support since the jump.
+    pipesize: usize,
+
+    /// The first packet sent with the larger window.
+    first_unvalidated_pkt: u64,
+
+    /// The last packet sent before the jump was validated.
+    last_unvalidated_pkt: u64,
+}
+
+impl Resume {
+    pub fn new() -> Self {
+        Resume {
+            state: State::Normal,
+
+            saved_rtt: Duration::new(0, 0),
+
+            saved_cwnd: 0,
+
+            pipesize: 0,
+
+            first_unvalidated_pkt: 0,
+
+            last_unvalidated_pkt: 0,
+        }
+    }
+
+    /// Starts resuming with the parameters saved from a previous connection.
+    pub fn setup(&mut self, saved_rtt: Duration, saved_cwnd: usize) {
+        if saved_rtt == Duration::new(0, 0) || saved_cwnd == 0 {
+            return;
+        }
+
+        self.saved_rtt = saved_rtt;
+        self.saved_cwnd = saved_cwnd;
+
+        self.state = State::Reconnaissance;
+    }
+
+    /// Stops resuming, as the path changed.
+    pub fn reset(&mut self) {
+        self.state = State::Normal;
+    }
+
+    /// Returns true if the saved path parameters are being used.
+    pub fn is_active(&self) -> bool {
+        self.state != State::Normal
+    }
+
+    /// Returns true if the jump to the saved window wasn't done yet.
+    pub fn in_reconnaissance(&self) -> bool {
+        self.state == State::Reconnaissance
+    }
+
+    /// Returns the congestion window to jump to, if the current RTT matches
+    /// the saved one.
+    pub fn jump(
+        &mut self, rtt: Duration, cwnd: usize, flight_size: usize,
+        largest_sent: u64,
+    ) -> Option<usize> {
+        if self.state != State::Reconnaissance {
+            return None;
+        }
+
+        // The path is likely not the same if the RTT changed too much.
+        if rtt < self.saved_rtt / 2 || rtt > self.saved_rtt * 10 {
+            self.state = State::Normal;
+            return None;
+        }
+
+        // Only half of the saved window is used, until it is validated.
+        let jump_cwnd = self.saved_cwnd / 2;
+
+        if jump_cwnd <= cwnd {
+            self.state = State::Normal;
+            return None;
+        }
+
+        self.state = State::Unvalidated;
+
+        self.pipesize = flight_size;
+
+        self.first_unvalidated_pkt = largest_sent + 1;
+
+        Some(jump_cwnd)
+    }
+
+    /// Updates the state when packets are acked, and returns the new
+    /// congestion window, if it needs to change.
+    pub fn on_packets_acked(
+        &mut self, largest_acked: u64, acked_bytes: usize, largest_sent: u64,
+        flight_size: usize, cwnd: usize,
+    ) -> Option<usize> {
+        match self.state {
+            State::Unvalidated => {
+                self.pipesize += acked_bytes;
+
+                if largest_acked < self.first_unvalidated_pkt {
+                    return None;
+                }
+
+                // A round trip elapsed since the jump, only the capacity that
+                // is actually in use is kept while validating it.
+                self.state = State::Validating;
+
+                self.last_unvalidated_pkt = largest_sent;
+
+                if flight_size < cwnd {
+                    return Some(flight_size);
+                }
+            },
+
+            State::Validating | State::SafeRetreat => {
+                self.pipesize += acked_bytes;
+
+                if largest_acked >= self.last_unvalidated_pkt {
+                    self.state = State::Normal;
+                }
+            },
+
+            State::Normal | State::Reconnaissance => (),
+        }
+
+        None
+    }
+
+    /// Updates the state when packets are lost, and returns the reduced
+    /// congestion window if the jump was unsafe.
+    pub fn on_packets_lost(&mut self, largest_sent: u64) -> Option<usize> {
+        match self.state {
+            State::Reconnaissance => {
+                self.state = State::Normal;
+            },
+
+            State::Unvalidated | State::Validating => {
+                if self.state == State::Unvalidated {
+                    self.last_unvalidated_pkt = largest_sent;
+                }
+
+                self.state = State::SafeRetreat;
+
+                return Some(self.pipesize / 2);
+            },
+
+            State::Normal | State::SafeRetreat => (),
+        }
+
+        None
+    }
+}
+
+impl std::fmt::Debug for Resume {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "state={:?} ", self.state)?;
+        write!(f, "saved_rtt={:?} ", self.saved_rtt)?;
+        write!(f, "saved_cwnd={} ", self.saved_cwnd)?;
+        write!(f, "pipesize={} ", self.pipesize)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_jump() {
+        let mut resume = Resume::new();
+
+        resume.setup(Duration::from_millis(50), 100000);
+        assert!(resume.in_reconnaissance());
+
+        assert_eq!(
+            resume.jump(Duration::from_millis(60), 14520, 14520, 9),
+            Some(50000)
+        );
+        assert!(!resume.in_reconnaissance());
+
+        // Packets sent before the jump are acked.
+        assert_eq!(resume.on_packets_acked(9, 14520, 40, 40000, 50000), None);
+        assert_eq!(resume.state, State::Unvalidated);
+
+        // The first packet sent after the jump is acked, the window is reduced
+        // to what is in flight.
+        assert_eq!(
+            resume.on_packets_acked(10, 1452, 40, 40000, 50000),
+            Some(40000)
+        );
+        assert_eq!(resume.state, State::Validating);
+
+        assert_eq!(resume.on_packets_acked(40, 40000, 60, 40000, 40000), None);
+        assert_eq!(resume.state, State::Normal);
+    }
+
+    #[test]
+    fn resume_rtt_mismatch() {
+        let mut resume = Resume::new();
+
+        resume.setup(Duration::from_millis(50), 100000);
+
+        assert_eq!(
+            resume.jump(Duration::from_millis(600), 14520, 14520, 9),
+            None
+        );
+        assert_eq!(resume.state, State::Normal);
+
+        let mut resume = Resume::new();
+
+        resume.setup(Duration::from_millis(50), 100000);
+
+        assert_eq!(resume.jump(Duration::from_millis(20), 14520, 14520, 9), None);
+        assert_eq!(resume.state, State::Normal);
+    }
+
+    #[test]
+    fn resume_safe_retreat() {
+        let mut resume = Resume::new();
+
+        resume.setup(Duration::from_millis(50), 100000);
+
+        assert_eq!(
+            resume.jump(Duration::from_millis(50), 14520, 14520, 9),
+            Some(50000)
+        );
+
+        assert_eq!(resume.on_packets_acked(9, 14520, 40, 40000, 50000), None);
+
+        // Loss after the jump retreats to half of the validated capacity.
+        assert_eq!(resume.on_packets_lost(40), Some(14520));
+        assert_eq!(resume.state, State::SafeRetreat);
+
+        assert_eq!(resume.on_packets_lost(45), None);
+
+        assert_eq!(resume.on_packets_acked(40, 1452, 45, 20000, 14520), None);
+        assert_eq!(resume.state, State::Normal);
+    }
+}
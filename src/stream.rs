@@ -43,7 +43,6 @@ const DEFAULT_URGENCY: u8 = 127;
 const MAX_WRITE_SIZE: usize = 1000;
 
 /// Keeps track of QUIC streams and enforces stream limits.
-#[derive(Default)]
 pub struct StreamMap {
     /// Map of streams indexed by stream ID.
     streams: HashMap<u64, Stream>,
@@ -81,18 +80,10 @@ pub struct StreamMap {
     /// The total number of unidirectional streams opened by the local endpoint.
     local_opened_streams_uni: u64,
 
-    /// Queue of stream IDs corresponding to streams that have buffered data
-    /// ready to be sent to the peer. This also implies that the stream has
-    /// enough flow control credits to send at least some of that data.
-    ///
-    /// Streams are grouped by their priority, where each urgency level has two
-    /// queues, one for non-incremental streams and one for incremental ones.
-    ///
-    /// Streams with lower urgency level are scheduled first, and within the
-    /// same urgency level Non-incremental streams are scheduled first, in the
-    /// order of their stream IDs, and incremental streams are scheduled in a
-    /// round-robin fashion after all non-incremental streams have been flushed.
-    flushable: BTreeMap<u8, (BinaryHeap<std::cmp::Reverse<u64>>, VecDeque<u64>)>,
+    /// Scheduler of the streams that have buffered data ready to be sent to
+    /// the peer. This also implies that the stream has enough flow control
+    /// credits to send at least some of that data.
+    scheduler: Box<dyn StreamScheduler>,
 
     /// Set of stream IDs corresponding to streams that have outstanding data
     /// to read. This is used to generate a `StreamIter` of streams without
@@ -115,18 +106,50 @@ pub struct StreamMap {
     /// of the map elements represents the offset of the stream at which the
     /// blocking occurred.
     blocked: HashMap<u64, u64>,
+
+    /// Set of stream IDs corresponding to streams that are reset. The value of
+    /// the map elements is a tuple of the error code and final size values to
+    /// include in the RESET_STREAM frame.
+    reset: HashMap<u64, (u64, u64)>,
+
+    /// Set of stream IDs corresponding to streams that are shut down on the
+    /// receive side, and need to send a STOP_SENDING frame. The value of the
+    /// map elements is the error code to include in the STOP_SENDING frame.
+    stopped: HashMap<u64, u64>,
 }
 
 impl StreamMap {
-    pub fn new(max_streams_bidi: u64, max_streams_uni: u64) -> StreamMap {
+    pub fn new(
+        max_streams_bidi: u64, max_streams_uni: u64,
+        scheduler: Box<dyn StreamScheduler>,
+    ) -> StreamMap {
         StreamMap {
+            streams: HashMap::new(),
+            collected: HashSet::new(),
+
+            peer_max_streams_bidi: 0,
+            peer_max_streams_uni: 0,
+
+            peer_opened_streams_bidi: 0,
+            peer_opened_streams_uni: 0,
+
             local_max_streams_bidi: max_streams_bidi,
             local_max_streams_bidi_next: max_streams_bidi,
 
             local_max_streams_uni: max_streams_uni,
             local_max_streams_uni_next: max_streams_uni,
 
-            ..StreamMap::default()
+            local_opened_streams_bidi: 0,
+            local_opened_streams_uni: 0,
+
+            scheduler,
+
+            readable: HashSet::new(),
+            writable: HashSet::new(),
+            almost_full: HashSet::new(),
+            blocked: HashMap::new(),
+            reset: HashMap::new(),
+            stopped: HashMap::new(),
         }
     }
 
@@ -246,8 +269,8 @@ impl StreamMap {
         Ok(stream)
     }
 
-    /// Pushes the stream ID to the back of the flushable streams queue with
-    /// the specified urgency.
+    /// Pushes the stream ID to the flushable streams queue with the specified
+    /// priority.
     ///
     /// Note that the caller is responsible for checking that the specified
     /// stream ID was not in the queue already before calling this.
@@ -256,57 +279,105 @@ impl StreamMap {
     /// unfairly scheduled more often than other streams, and might also cause
     /// spurious cycles through the queue, so it should be avoided.
     pub fn push_flushable(&mut self, stream_id: u64, urgency: u8, incr: bool) {
-        // Push the element to the back of the queue corresponding to the given
-        // urgency. If the queue doesn't exist yet, create it first.
-        let queues = self
-            .flushable
-            .entry(urgency)
-            .or_insert_with(|| (BinaryHeap::new(), VecDeque::new()));
+        self.scheduler.push(stream_id, urgency, incr);
+    }
 
-        if !incr {
-            // Non-incremental streams are scheduled in order of their stream ID.
-            queues.0.push(std::cmp::Reverse(stream_id))
-        } else {
-            // Incremental streams are scheduled in a round-robin fashion.
-            queues.1.push_back(stream_id)
+    /// Removes and returns the next stream ID from the flushable streams
+    /// queue, along with the maximum number of bytes to send from it.
+    ///
+    /// Note that if the stream is still flushable after sending some of its
+    /// outstanding data, it needs to be added back to the queue with
+    /// `requeue_flushable()`.
+    pub fn pop_flushable(&mut self) -> Option<(u64, usize)> {
+        // A zero limit would prevent the stream from ever sending data, so
+        // allow at least one byte instead.
+        self.scheduler
+            .pop()
+            .map(|(stream_id, limit)| (stream_id, cmp::max(limit, 1)))
+    }
+
+    /// Pushes the stream ID back to the flushable streams queue, after `sent`
+    /// bytes of its outstanding data were sent.
+    pub fn requeue_flushable(&mut self, stream_id: u64, sent: usize) {
+        let stream = match self.streams.get(&stream_id) {
+            Some(v) => v,
+
+            None => return,
         };
+
+        self.scheduler.requeue(
+            stream_id,
+            stream.urgency,
+            stream.incremental,
+            sent,
+        );
     }
 
-    /// Removes and returns the first stream ID from the flushable streams
-    /// queue with the specified urgency.
+    /// Changes the priority of the stream with the given ID.
     ///
-    /// Note that if the stream is still flushable after sending some of its
-    /// outstanding data, it needs to be added back to the queue.
-    pub fn pop_flushable(&mut self) -> Option<u64> {
-        // Remove the first element from the queue corresponding to the lowest
-        // urgency that has elements.
-        let (node, clear) =
-            if let Some((urgency, queues)) = self.flushable.iter_mut().next() {
-                let node = if !queues.0.is_empty() {
-                    queues.0.pop().map(|x| x.0)
-                } else {
-                    queues.1.pop_front()
-                };
+    /// If the stream is already in the flushable streams queue, it is pushed
+    /// to the queue again with its new priority, so that the change takes
+    /// effect immediately.
+    pub fn update_priority(&mut self, stream_id: u64, urgency: u8, incr: bool) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(v) => v,
 
-                let clear = if queues.0.is_empty() && queues.1.is_empty() {
-                    Some(*urgency)
-                } else {
-                    None
-                };
+            None => return,
+        };
 
-                (node, clear)
-            } else {
-                (None, None)
-            };
+        let old_urgency = stream.urgency;
+        let old_incr = stream.incremental;
 
-        // Remove the queue from the list of queues if it is now empty, so that
-        // the next time `pop_flushable()` is called the next queue with elements
-        // is used.
-        if let Some(urgency) = &clear {
-            self.flushable.remove(urgency);
+        stream.urgency = urgency;
+        stream.incremental = incr;
+
+        if self.scheduler.remove(stream_id, old_urgency, old_incr) {
+            self.scheduler.push(stream_id, urgency, incr);
         }
+    }
+
+    /// Discards all streams and the peer's stream limits, as if no stream was
+    /// ever opened.
+    ///
+    /// This is used by clients when the server rejects 0-RTT, as the streams
+    /// opened in early data were never seen by the server.
+    pub fn clear(&mut self) {
+        for (stream_id, stream) in self.streams.drain() {
+            self.scheduler
+                .remove(stream_id, stream.urgency, stream.incremental);
+        }
+
+        self.collected.clear();
+
+        self.peer_max_streams_bidi = 0;
+        self.peer_max_streams_uni = 0;
+
+        self.peer_opened_streams_bidi = 0;
+        self.peer_opened_streams_uni = 0;
+
+        self.local_opened_streams_bidi = 0;
+        self.local_opened_streams_uni = 0;
 
-        node
+        self.readable.clear();
+        self.writable.clear();
+        self.almost_full.clear();
+        self.blocked.clear();
+        self.reset.clear();
+        self.stopped.clear();
+    }
+
+    /// Replaces the stream scheduler.
+    ///
+    /// Streams that are in the flushable streams queue are moved to the new
+    /// scheduler.
+    pub fn set_scheduler(&mut self, mut scheduler: Box<dyn StreamScheduler>) {
+        while let Some((stream_id, _)) = self.scheduler.pop() {
+            if let Some(stream) = self.streams.get(&stream_id) {
+                scheduler.push(stream_id, stream.urgency, stream.incremental);
+            }
+        }
+
+        self.scheduler = scheduler;
     }
 
     /// Adds or removes the stream ID to/from the readable streams set.
@@ -357,6 +428,34 @@ impl StreamMap {
         }
     }
 
+    /// Adds or removes the stream ID to/from the reset streams set with the
+    /// given error code and final size values.
+    ///
+    /// If the stream was already in the list, this does nothing.
+    pub fn mark_reset(
+        &mut self, stream_id: u64, reset: bool, error_code: u64, final_size: u64,
+    ) {
+        if reset {
+            self.reset.insert(stream_id, (error_code, final_size));
+        } else {
+            self.reset.remove(&stream_id);
+        }
+    }
+
+    /// Adds or removes the stream ID to/from the stopped streams set with the
+    /// given error code.
+    ///
+    /// If the stream was already in the list, this does nothing.
+    pub fn mark_stopped(
+        &mut self, stream_id: u64, stopped: bool, error_code: u64,
+    ) {
+        if stopped {
+            self.stopped.insert(stream_id, error_code);
+        } else {
+            self.stopped.remove(&stream_id);
+        }
+    }
+
     /// Updates the peer's maximum bidirectional stream count limit.
     pub fn update_peer_max_streams_bidi(&mut self, v: u64) {
         self.peer_max_streams_bidi = cmp::max(self.peer_max_streams_bidi, v);
@@ -428,9 +527,19 @@ impl StreamMap {
         self.blocked.iter()
     }
 
+    /// Creates an iterator over streams that need to send RESET_STREAM.
+    pub fn reset(&self) -> hash_map::Iter<'_, u64, (u64, u64)> {
+        self.reset.iter()
+    }
+
+    /// Creates an iterator over streams that need to send STOP_SENDING.
+    pub fn stopped(&self) -> hash_map::Iter<'_, u64, u64> {
+        self.stopped.iter()
+    }
+
     /// Returns true if there are any streams that have data to write.
     pub fn has_flushable(&self) -> bool {
-        !self.flushable.is_empty()
+        !self.scheduler.is_empty()
     }
 
     /// Returns true if there are any streams that need to update the local
@@ -444,6 +553,16 @@ impl StreamMap {
         !self.blocked.is_empty()
     }
 
+    /// Returns true if there are any streams that need to send RESET_STREAM.
+    pub fn has_reset(&self) -> bool {
+        !self.reset.is_empty()
+    }
+
+    /// Returns true if there are any streams that need to send STOP_SENDING.
+    pub fn has_stopped(&self) -> bool {
+        !self.stopped.is_empty()
+    }
+
     /// Returns true if the max bidirectional streams count needs to be updated
     /// by sending a MAX_STREAMS frame to the peer.
     pub fn should_update_max_streams_bidi(&self) -> bool {
@@ -467,6 +586,199 @@ impl StreamMap {
     }
 }
 
+/// A stream scheduler.
+///
+/// The scheduler is handed the IDs of the streams that have data ready to be
+/// sent ("flushable" streams), along with their priority, and decides which
+/// stream is sent next, and how many bytes are sent from it.
+///
+/// The default scheduler implements the urgency and incremental parameters
+/// set with [`stream_priority()`], and a custom implementation can be used
+/// instead with [`set_stream_scheduler()`].
+///
+/// [`stream_priority()`]: struct.Connection.html#method.stream_priority
+/// [`set_stream_scheduler()`]: struct.Config.html#method.set_stream_scheduler
+pub trait StreamScheduler: Send {
+    /// Adds the stream to the flushable streams, with the given priority.
+    ///
+    /// This is called when a stream gets data ready to be sent, and when the
+    /// priority of a flushable stream is changed.
+    fn push(&mut self, stream_id: u64, urgency: u8, incremental: bool);
+
+    /// Removes and returns the stream to send data from next, along with the
+    /// maximum number of bytes to send from it. A limit of zero is treated as
+    /// a limit of one byte.
+    fn pop(&mut self) -> Option<(u64, usize)>;
+
+    /// Adds the stream returned by the last call to [`pop()`] back to the
+    /// flushable streams, after `sent` bytes of its data were sent.
+    ///
+    /// This is only called if the stream still has data ready to be sent.
+    ///
+    /// [`pop()`]: trait.StreamScheduler.html#tymethod.pop
+    fn requeue(
+        &mut self, stream_id: u64, urgency: u8, incremental: bool, _sent: usize,
+    ) {
+        self.push(stream_id, urgency, incremental);
+    }
+
+    /// Removes the stream from the flushable streams, and returns whether it
+    /// was flushable. `urgency` and `incremental` are the priority the stream
+    /// was added with.
+    fn remove(&mut self, stream_id: u64, urgency: u8, incremental: bool) -> bool;
+
+    /// Returns true if there are no flushable streams.
+    fn is_empty(&self) -> bool;
+}
+
+/// The non-incremental and incremental streams queues of an urgency level.
+type Queues = (BinaryHeap<std::cmp::Reverse<u64>>, VecDeque<(u64, usize)>);
+
+/// The default stream scheduler.
+///
+/// Streams are grouped by their priority, where each urgency level has two
+/// queues, one for non-incremental streams and one for incremental ones.
+///
+/// Streams with lower urgency level are scheduled first, and within the
+/// same urgency level Non-incremental streams are scheduled first, in the
+/// order of their stream IDs, and incremental streams are scheduled in a
+/// round-robin fashion after all non-incremental streams have been flushed.
+///
+/// Incremental streams keep their turn at the front of the queue until they
+/// have sent at least `quantum` bytes, and are then moved to the back of the
+/// queue.
+#[derive(Default)]
+pub struct UrgencyScheduler {
+    /// Queues of stream IDs indexed by urgency. Incremental streams are stored
+    /// along with the number of bytes sent during their current turn.
+    queues: BTreeMap<u8, Queues>,
+
+    /// The number of bytes an incremental stream can send before the next
+    /// incremental stream with the same urgency is scheduled. A value of `0`
+    /// means that streams take turns after every STREAM frame.
+    quantum: usize,
+
+    /// The last incremental stream popped from the queues, along with the
+    /// number of bytes sent during its current turn.
+    turn: Option<(u64, usize)>,
+}
+
+impl UrgencyScheduler {
+    /// Creates a new scheduler with the given round-robin quantum.
+    pub fn new(quantum: usize) -> UrgencyScheduler {
+        UrgencyScheduler {
+            quantum,
+
+            ..UrgencyScheduler::default()
+        }
+    }
+
+    fn queues(&mut self, urgency: u8) -> &mut Queues {
+        self.queues
+            .entry(urgency)
+            .or_insert_with(|| (BinaryHeap::new(), VecDeque::new()))
+    }
+}
+
+impl StreamScheduler for UrgencyScheduler {
+    fn push(&mut self, stream_id: u64, urgency: u8, incremental: bool) {
+        // Push the element to the back of the queue corresponding to the given
+        // urgency. If the queue doesn't exist yet, create it first.
+        let queues = self.queues(urgency);
+
+        if !incremental {
+            // Non-incremental streams are scheduled in order of their stream ID.
+            queues.0.push(std::cmp::Reverse(stream_id))
+        } else {
+            // Incremental streams are scheduled in a round-robin fashion.
+            queues.1.push_back((stream_id, 0))
+        };
+    }
+
+    fn pop(&mut self) -> Option<(u64, usize)> {
+        // Remove the first element from the queue corresponding to the lowest
+        // urgency that has elements.
+        let (node, clear) =
+            if let Some((urgency, queues)) = self.queues.iter_mut().next() {
+                let node = if !queues.0.is_empty() {
+                    queues.0.pop().map(|x| (x.0, None))
+                } else {
+                    queues.1.pop_front().map(|x| (x.0, Some(x)))
+                };
+
+                let clear = if queues.0.is_empty() && queues.1.is_empty() {
+                    Some(*urgency)
+                } else {
+                    None
+                };
+
+                (node, clear)
+            } else {
+                (None, None)
+            };
+
+        // Remove the queue from the list of queues if it is now empty, so that
+        // the next time `pop()` is called the next queue with elements is used.
+        if let Some(urgency) = &clear {
+            self.queues.remove(urgency);
+        }
+
+        node.map(|(stream_id, turn)| {
+            self.turn = turn;
+
+            (stream_id, usize::MAX)
+        })
+    }
+
+    fn requeue(
+        &mut self, stream_id: u64, urgency: u8, incremental: bool, sent: usize,
+    ) {
+        if incremental {
+            let turn_sent = match self.turn.take() {
+                Some((id, turn_sent)) if id == stream_id => turn_sent + sent,
+
+                _ => sent,
+            };
+
+            // The stream keeps its turn until it sent a full quantum.
+            if turn_sent < self.quantum {
+                self.queues(urgency).1.push_front((stream_id, turn_sent));
+                return;
+            }
+        }
+
+        self.push(stream_id, urgency, incremental);
+    }
+
+    fn remove(&mut self, stream_id: u64, urgency: u8, incremental: bool) -> bool {
+        let queues = match self.queues.get_mut(&urgency) {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        let len = queues.0.len() + queues.1.len();
+
+        if !incremental {
+            queues.0.retain(|id| id.0 != stream_id);
+        } else {
+            queues.1.retain(|id| id.0 != stream_id);
+        }
+
+        let removed = queues.0.len() + queues.1.len() < len;
+
+        if queues.0.is_empty() && queues.1.is_empty() {
+            self.queues.remove(&urgency);
+        }
+
+        removed
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+}
+
 /// A QUIC stream.
 #[derive(Default)]
 pub struct Stream {
@@ -617,6 +929,9 @@ pub struct RecvBuf {
     /// The final stream offset received from the peer, if any.
     fin_off: Option<u64>,
 
+    /// The error code received via RESET_STREAM, if any.
+    error: Option<u64>,
+
     /// Whether incoming data is validated but not buffered.
     drain: bool,
 }
@@ -752,6 +1067,14 @@ impl RecvBuf {
             return Err(Error::Done);
         }
 
+        // The stream was reset by the peer, so report the error code to the
+        // application, once.
+        if let Some(e) = self.error {
+            self.data.clear();
+
+            return Err(Error::StreamReset(e));
+        }
+
         while cap > 0 && self.ready() {
             let mut buf = match self.data.pop() {
                 Some(v) => v,
@@ -784,8 +1107,17 @@ impl RecvBuf {
         Ok((len, self.is_fin()))
     }
 
-    /// Resets the stream at the given offset.
-    pub fn reset(&mut self, final_size: u64) -> Result<usize> {
+    /// Resets the stream at the given offset, with the given error code.
+    ///
+    /// Any data not yet read by the application is dropped, and the next
+    /// call to `pop()` returns the `StreamReset` error.
+    ///
+    /// On success, returns how many bytes need to be added to the connection
+    /// flow control as received, and how many bytes were discarded without
+    /// being read, which need to be credited back to it.
+    pub fn reset(
+        &mut self, error_code: u64, final_size: u64,
+    ) -> Result<(usize, usize)> {
         // Stream's size is already known, forbid changing it.
         if let Some(fin_off) = self.fin_off {
             if fin_off != final_size {
@@ -798,11 +1130,28 @@ impl RecvBuf {
             return Err(Error::FinalSize);
         }
 
+        // Ignore duplicate RESET_STREAM frames.
+        if self.error.is_some() {
+            return Ok((0, 0));
+        }
+
         self.fin_off = Some(final_size);
 
-        // Return how many bytes need to be removed from the connection flow
-        // control.
-        Ok((final_size - self.len) as usize)
+        self.error = Some(error_code);
+
+        let unread = final_size - self.off;
+
+        // Drop any data that was not read yet.
+        self.off = final_size;
+        self.data.clear();
+
+        // Queue an empty buffer at the final offset, so that the stream is
+        // readable until the application is notified of the reset.
+        if !self.drain {
+            self.data.push(RangeBuf::from(b"", final_size, true));
+        }
+
+        Ok(((final_size - self.len) as usize, unread as usize))
     }
 
     /// Commits the new max_data limit.
@@ -884,6 +1233,9 @@ pub struct SendBuf {
     /// The maximum offset of data buffered in the stream.
     off: u64,
 
+    /// The maximum offset of data sent to the peer.
+    emit_off: u64,
+
     /// The amount of data that was ever written to this stream.
     len: u64,
 
@@ -896,8 +1248,14 @@ pub struct SendBuf {
     /// Whether the stream's send-side has been shut down.
     shutdown: bool,
 
+    /// The error code received via STOP_SENDING, if any.
+    error: Option<u64>,
+
     /// Ranges of data offsets that have been acked.
     acked: ranges::RangeSet,
+
+    /// Whether the RESET_STREAM frame sent after shutting down was acked.
+    reset_acked: bool,
 }
 
 impl SendBuf {
@@ -1048,6 +1406,10 @@ impl SendBuf {
         // propagate the final size.
         out.fin = self.fin_off == Some(out.max_off());
 
+        if !out.is_empty() {
+            self.emit_off = cmp::max(self.emit_off, out.max_off());
+        }
+
         Ok(out)
     }
 
@@ -1061,8 +1423,17 @@ impl SendBuf {
         self.acked.insert(off..off + len as u64);
     }
 
+    /// Records that the peer acked the RESET_STREAM frame.
+    pub fn reset_ack(&mut self) {
+        self.reset_acked = true;
+    }
+
     /// Shuts down sending data.
-    pub fn shutdown(&mut self) -> Result<()> {
+    ///
+    /// On success the stream's final size, that is the maximum offset of data
+    /// sent to the peer, and the amount of buffered data that was never sent
+    /// are returned as a tuple.
+    pub fn shutdown(&mut self) -> Result<(u64, u64)> {
         if self.shutdown {
             return Err(Error::Done);
         }
@@ -1071,7 +1442,27 @@ impl SendBuf {
 
         self.data.clear();
 
-        Ok(())
+        Ok((self.emit_off, self.off - self.emit_off))
+    }
+
+    /// Shuts down sending data in response to a STOP_SENDING frame with the
+    /// given error code.
+    ///
+    /// This returns the same values as `shutdown()`, or `Done` if sending was
+    /// already shut down.
+    pub fn stop(&mut self, error_code: u64) -> Result<(u64, u64)> {
+        if self.error.is_some() {
+            return Err(Error::Done);
+        }
+
+        self.error = Some(error_code);
+
+        self.shutdown()
+    }
+
+    /// Returns the error code received via STOP_SENDING, if any.
+    pub fn error(&self) -> Option<u64> {
+        self.error
     }
 
     /// Returns the largest offset of data buffered.
@@ -1109,8 +1500,13 @@ impl SendBuf {
     /// Returns true if the send-side of the stream is complete.
     ///
     /// This happens when the stream's send final size is known, and the peer
-    /// has already acked all stream data up to that point.
+    /// has already acked all stream data up to that point, or when the peer
+    /// acked the RESET_STREAM frame sent after shutting down the stream.
     pub fn is_complete(&self) -> bool {
+        if self.reset_acked {
+            return true;
+        }
+
         if let Some(fin_off) = self.fin_off {
             if self.acked == (0..fin_off) {
                 return true;
@@ -2085,7 +2481,7 @@ mod tests {
         let first = RangeBuf::from(b"hello", 0, true);
 
         assert_eq!(stream.recv.push(first), Ok(()));
-        assert_eq!(stream.recv.reset(10), Err(Error::FinalSize));
+        assert_eq!(stream.recv.reset(0, 10), Err(Error::FinalSize));
     }
 
     #[test]
@@ -2096,8 +2492,8 @@ mod tests {
         let first = RangeBuf::from(b"hello", 0, false);
 
         assert_eq!(stream.recv.push(first), Ok(()));
-        assert_eq!(stream.recv.reset(5), Ok(0));
-        assert_eq!(stream.recv.reset(5), Ok(0));
+        assert_eq!(stream.recv.reset(0, 5), Ok((0, 5)));
+        assert_eq!(stream.recv.reset(0, 5), Ok((0, 0)));
     }
 
     #[test]
@@ -2108,8 +2504,8 @@ mod tests {
         let first = RangeBuf::from(b"hello", 0, false);
 
         assert_eq!(stream.recv.push(first), Ok(()));
-        assert_eq!(stream.recv.reset(5), Ok(0));
-        assert_eq!(stream.recv.reset(10), Err(Error::FinalSize));
+        assert_eq!(stream.recv.reset(0, 5), Ok((0, 5)));
+        assert_eq!(stream.recv.reset(0, 10), Err(Error::FinalSize));
     }
 
     #[test]
@@ -2120,7 +2516,7 @@ mod tests {
         let first = RangeBuf::from(b"hello", 0, false);
 
         assert_eq!(stream.recv.push(first), Ok(()));
-        assert_eq!(stream.recv.reset(4), Err(Error::FinalSize));
+        assert_eq!(stream.recv.reset(0, 4), Err(Error::FinalSize));
     }
 
     #[test]
@@ -2378,4 +2774,102 @@ mod tests {
         assert_eq!(write.fin(), true);
         assert_eq!(write.data, b"");
     }
+
+    #[test]
+    fn flushable_reprioritize() {
+        let scheduler = Box::new(UrgencyScheduler::new(0));
+        let mut streams = StreamMap::new(10, 10, scheduler);
+
+        for id in &[0, 4, 8] {
+            streams.streams.insert(*id, Stream::new(0, 15, true, true));
+            streams.push_flushable(*id, DEFAULT_URGENCY, true);
+        }
+
+        // Move the last stream ahead of the others.
+        streams.update_priority(8, 3, true);
+
+        // Make the first stream non-incremental, which is scheduled ahead of
+        // the incremental streams with the same urgency.
+        streams.update_priority(4, DEFAULT_URGENCY, false);
+
+        assert_eq!(streams.pop_flushable(), Some((8, usize::MAX)));
+        assert_eq!(streams.pop_flushable(), Some((4, usize::MAX)));
+        assert_eq!(streams.pop_flushable(), Some((0, usize::MAX)));
+        assert_eq!(streams.pop_flushable(), None);
+
+        // Streams that are not queued are not added to the queue.
+        streams.update_priority(0, 0, true);
+        assert_eq!(streams.pop_flushable(), None);
+    }
+
+    #[test]
+    fn flushable_zero_limit() {
+        // Always limits streams to zero bytes.
+        #[derive(Default)]
+        struct Zero(VecDeque<u64>);
+
+        impl StreamScheduler for Zero {
+            fn push(&mut self, stream_id: u64, _urgency: u8, _incr: bool) {
+                self.0.push_back(stream_id);
+            }
+
+            fn pop(&mut self) -> Option<(u64, usize)> {
+                Some((self.0.pop_front()?, 0))
+            }
+
+            fn remove(
+                &mut self, stream_id: u64, _urgency: u8, _incr: bool,
+            ) -> bool {
+                let len = self.0.len();
+                self.0.retain(|&id| id != stream_id);
+                self.0.len() < len
+            }
+
+            fn is_empty(&self) -> bool {
+                self.0.is_empty()
+            }
+        }
+
+        let mut streams = StreamMap::new(10, 10, Box::new(Zero::default()));
+
+        streams.streams.insert(0, Stream::new(0, 15, true, true));
+        streams.push_flushable(0, DEFAULT_URGENCY, true);
+
+        // Streams can always send at least one byte.
+        assert_eq!(streams.pop_flushable(), Some((0, 1)));
+        assert_eq!(streams.pop_flushable(), None);
+    }
+
+    #[test]
+    fn incremental_quantum() {
+        let mut scheduler = UrgencyScheduler::new(1000);
+
+        scheduler.push(0, DEFAULT_URGENCY, true);
+        scheduler.push(4, DEFAULT_URGENCY, true);
+
+        // The first stream keeps its turn until it sent a full quantum.
+        assert_eq!(scheduler.pop(), Some((0, usize::MAX)));
+        scheduler.requeue(0, DEFAULT_URGENCY, true, 600);
+
+        assert_eq!(scheduler.pop(), Some((0, usize::MAX)));
+        scheduler.requeue(0, DEFAULT_URGENCY, true, 600);
+
+        assert_eq!(scheduler.pop(), Some((4, usize::MAX)));
+        scheduler.requeue(4, DEFAULT_URGENCY, true, 1200);
+
+        // A more urgent stream doesn't end the current turn.
+        assert_eq!(scheduler.pop(), Some((0, usize::MAX)));
+        scheduler.requeue(0, DEFAULT_URGENCY, true, 600);
+
+        scheduler.push(8, 0, false);
+        assert_eq!(scheduler.pop(), Some((8, usize::MAX)));
+
+        assert_eq!(scheduler.pop(), Some((0, usize::MAX)));
+        scheduler.requeue(0, DEFAULT_URGENCY, true, 600);
+
+        assert_eq!(scheduler.pop(), Some((4, usize::MAX)));
+        assert_eq!(scheduler.pop(), Some((0, usize::MAX)));
+        assert_eq!(scheduler.pop(), None);
+        assert!(scheduler.is_empty());
+    }
 }
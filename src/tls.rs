@@ -62,6 +62,10 @@ struct SSL(c_void);
 #[repr(transparent)]
 struct SSL_CIPHER(c_void);
 
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct SSL_SESSION(c_void);
+
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 struct X509_VERIFY_PARAM(c_void);
@@ -130,6 +134,8 @@ impl Context {
 
             let mut ctx = Context(ctx_raw);
 
+            ctx.set_session_callback();
+
             ctx.load_ca_certs()?;
 
             Ok(ctx)
@@ -284,6 +290,19 @@ impl Context {
         }
     }
 
+    fn set_session_callback(&mut self) {
+        unsafe {
+            // This is needed to enable the session callback on the client. On
+            // the server it doesn't do anything.
+            SSL_CTX_set_session_cache_mode(
+                self.as_ptr(),
+                0x0001, // SSL_SESS_CACHE_CLIENT
+            );
+
+            SSL_CTX_sess_set_new_cb(self.as_ptr(), new_session);
+        }
+    }
+
     fn as_ptr(&self) -> *mut SSL_CTX {
         self.0
     }
@@ -319,6 +338,12 @@ impl Handshake {
 
         self.set_quic_method()?;
 
+        self.use_legacy_codepoint(crate::version_is_draft(conn.version));
+
+        if conn.is_server {
+            self.set_cert_cb();
+        }
+
         // TODO: the early data context should include transport parameters and
         // HTTP/3 SETTINGS in wire format.
         self.set_quic_early_data_context(b"quiche")?;
@@ -345,6 +370,34 @@ impl Handshake {
         })
     }
 
+    pub fn set_cert_cb(&self) {
+        unsafe {
+            SSL_set_cert_cb(self.as_ptr(), select_version, ptr::null_mut());
+        }
+    }
+
+    pub fn set_session(&self, session: &[u8]) -> Result<()> {
+        unsafe {
+            let ctx = SSL_get_SSL_CTX(self.as_ptr());
+
+            if ctx.is_null() {
+                return Err(Error::TlsFail);
+            }
+
+            let session =
+                SSL_SESSION_from_bytes(session.as_ptr(), session.len(), ctx);
+
+            if session.is_null() {
+                return Err(Error::TlsFail);
+            }
+
+            let rc = SSL_set_session(self.as_ptr(), session);
+            SSL_SESSION_free(session);
+
+            map_result(rc)
+        }
+    }
+
     pub fn set_quic_method(&self) -> Result<()> {
         map_result(unsafe {
             SSL_set_quic_method(self.as_ptr(), &QUICHE_STREAM_METHOD)
@@ -369,6 +422,15 @@ impl Handshake {
         unsafe { SSL_set_max_proto_version(self.as_ptr(), version) }
     }
 
+    pub fn use_legacy_codepoint(&self, use_legacy: bool) {
+        unsafe {
+            SSL_set_quic_use_legacy_codepoint(
+                self.as_ptr(),
+                if use_legacy { 1 } else { 0 },
+            )
+        }
+    }
+
     pub fn set_quiet_shutdown(&self, mode: bool) {
         unsafe { SSL_set_quiet_shutdown(self.as_ptr(), if mode { 1 } else { 0 }) }
     }
@@ -429,7 +491,32 @@ impl Handshake {
     }
 
     pub fn do_handshake(&self) -> Result<()> {
-        map_result_ssl(self, unsafe { SSL_do_handshake(self.as_ptr()) })
+        let rc = unsafe { SSL_do_handshake(self.as_ptr()) };
+
+        // The server rejected 0-RTT, so reset the handshake state and continue
+        // without early data.
+        if rc <= 0 && self.get_error(rc) == 15 {
+            // SSL_ERROR_EARLY_DATA_REJECTED
+            unsafe { SSL_reset_early_data_reject(self.as_ptr()) };
+
+            // Discard the connection state created for early data.
+            if let Some(conn) = get_ex_data_from_ptr::<Connection>(
+                self.as_ptr(),
+                *QUICHE_EX_DATA_INDEX,
+            ) {
+                conn.on_early_data_rejected();
+            }
+
+            return self.do_handshake();
+        }
+
+        map_result_ssl(self, rc)
+    }
+
+    pub fn process_post_handshake(&self) -> Result<()> {
+        map_result_ssl(self, unsafe {
+            SSL_process_quic_post_handshake(self.as_ptr())
+        })
     }
 
     pub fn write_level(&self) -> crypto::Level {
@@ -586,7 +673,7 @@ extern fn set_read_secret(
     if level != crypto::Level::ZeroRTT || conn.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let open = match crypto::Open::from_secret(aead, &secret) {
+        let open = match crypto::Open::from_secret(aead, conn.version, secret) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -597,6 +684,17 @@ extern fn set_read_secret(
             return 1;
         }
 
+        // Prepare the keys for the next key phase in advance, so that the
+        // time it takes to process a key update can't be observed.
+        if level == crypto::Level::OneRTT {
+            space.crypto_open_next =
+                match open.derive_next_packet_key(conn.version) {
+                    Ok(v) => Some(v),
+
+                    Err(_) => return 0,
+                };
+        }
+
         space.crypto_open = Some(open);
     }
 
@@ -636,7 +734,7 @@ extern fn set_write_secret(
     if level != crypto::Level::ZeroRTT || !conn.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let seal = match crypto::Seal::from_secret(aead, &secret) {
+        let seal = match crypto::Seal::from_secret(aead, conn.version, secret) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -730,6 +828,64 @@ extern fn keylog(ssl: *mut SSL, line: *const c_char) {
     }
 }
 
+extern fn select_version(ssl: *mut SSL, _arg: *mut c_void) -> c_int {
+    let conn =
+        match get_ex_data_from_ptr::<Connection>(ssl, *QUICHE_EX_DATA_INDEX) {
+            Some(v) => v,
+
+            None => return 0,
+        };
+
+    // The ClientHello was parsed, but the server's transport parameters were
+    // not sent yet, so this is the last chance to upgrade the version.
+    match conn.on_client_hello() {
+        Ok(_) => 1,
+
+        Err(_) => 0,
+    }
+}
+
+extern fn new_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int {
+    let conn =
+        match get_ex_data_from_ptr::<Connection>(ssl, *QUICHE_EX_DATA_INDEX) {
+            Some(v) => v,
+
+            None => return 0,
+        };
+
+    let session_bytes = unsafe {
+        let mut out: *mut u8 = ptr::null_mut();
+        let mut out_len: usize = 0;
+
+        if SSL_SESSION_to_bytes(session, &mut out, &mut out_len) == 0 {
+            return 0;
+        }
+
+        let session_bytes = slice::from_raw_parts(out, out_len).to_vec();
+
+        OPENSSL_free(out as *mut c_void);
+
+        session_bytes
+    };
+
+    let peer_params = conn.handshake.quic_transport_params();
+
+    // Serialize the session together with the peer's transport parameters, as
+    // they are needed to send 0-RTT data when resuming.
+    let mut buffer =
+        Vec::with_capacity(8 + session_bytes.len() + 8 + peer_params.len());
+
+    buffer.extend_from_slice(&(session_bytes.len() as u64).to_be_bytes());
+    buffer.extend_from_slice(&session_bytes);
+    buffer.extend_from_slice(&(peer_params.len() as u64).to_be_bytes());
+    buffer.extend_from_slice(peer_params);
+
+    conn.session = Some(buffer);
+
+    // Don't take ownership of the session, as it's not used after this.
+    0
+}
+
 extern fn select_alpn(
     ssl: *mut SSL, out: *mut *const u8, out_len: *mut u8, inp: *mut u8,
     in_len: c_uint, _arg: *mut c_void,
@@ -903,6 +1059,13 @@ extern {
 
     fn SSL_CTX_set_early_data_enabled(ctx: *mut SSL_CTX, enabled: i32);
 
+    fn SSL_CTX_set_session_cache_mode(ctx: *mut SSL_CTX, mode: c_int) -> c_int;
+
+    fn SSL_CTX_sess_set_new_cb(
+        ctx: *mut SSL_CTX,
+        cb: extern fn(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int,
+    );
+
     // SSL
     fn SSL_get_ex_new_index(
         argl: c_long, argp: *const c_void, unused: *const c_void,
@@ -921,6 +1084,10 @@ extern {
     fn SSL_set_ex_data(ssl: *mut SSL, idx: c_int, ptr: *const c_void) -> c_int;
     fn SSL_get_ex_data(ssl: *mut SSL, idx: c_int) -> *mut c_void;
 
+    fn SSL_get_SSL_CTX(ssl: *mut SSL) -> *mut SSL_CTX;
+
+    fn SSL_set_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int;
+
     fn SSL_get_current_cipher(ssl: *mut SSL) -> *const SSL_CIPHER;
 
     fn SSL_get_curve_id(ssl: *mut SSL) -> u16;
@@ -944,10 +1111,18 @@ extern {
         ssl: *mut SSL, params: *const u8, params_len: usize,
     ) -> c_int;
 
+    fn SSL_set_cert_cb(
+        ssl: *mut SSL,
+        cb: extern fn(ssl: *mut SSL, arg: *mut c_void) -> c_int,
+        arg: *mut c_void,
+    );
+
     fn SSL_set_quic_method(
         ssl: *mut SSL, quic_method: *const SSL_QUIC_METHOD,
     ) -> c_int;
 
+    fn SSL_set_quic_use_legacy_codepoint(ssl: *mut SSL, use_legacy: c_int);
+
     fn SSL_set_quic_early_data_context(
         ssl: *mut SSL, context: *const u8, context_len: usize,
     ) -> c_int;
@@ -966,6 +1141,10 @@ extern {
 
     fn SSL_do_handshake(ssl: *mut SSL) -> c_int;
 
+    fn SSL_process_quic_post_handshake(ssl: *mut SSL) -> c_int;
+
+    fn SSL_reset_early_data_reject(ssl: *mut SSL);
+
     fn SSL_quic_write_level(ssl: *mut SSL) -> crypto::Level;
 
     fn SSL_session_reused(ssl: *mut SSL) -> c_int;
@@ -981,6 +1160,17 @@ extern {
     // SSL_CIPHER
     fn SSL_CIPHER_get_id(cipher: *const SSL_CIPHER) -> c_uint;
 
+    // SSL_SESSION
+    fn SSL_SESSION_to_bytes(
+        session: *const SSL_SESSION, out: *mut *mut u8, out_len: *mut usize,
+    ) -> c_int;
+
+    fn SSL_SESSION_from_bytes(
+        input: *const u8, input_len: usize, ctx: *const SSL_CTX,
+    ) -> *mut SSL_SESSION;
+
+    fn SSL_SESSION_free(session: *mut SSL_SESSION);
+
     // X509_VERIFY_PARAM
     fn X509_VERIFY_PARAM_set1_host(
         param: *mut X509_VERIFY_PARAM, name: *const c_char, namelen: usize,
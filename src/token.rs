@@ -0,0 +1,432 @@
+// Copyright (C) 2020, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::time;
+
+use ring::aead;
+
+use crate::Error;
+use crate::Result;
+
+use crate::rand;
+
+/// Length of the keys used to seal tokens.
+pub const TOKEN_KEY_LEN: usize = 32;
+
+/// The first byte of Retry tokens.
+const TOKEN_TYPE_RETRY: u8 = 0xfe;
+
+/// The first byte of tokens sent in NEW_TOKEN frames.
+const TOKEN_TYPE_NEW_TOKEN: u8 = 0xff;
+
+/// Length of the token type and key ID, followed by the nonce.
+const TOKEN_HDR_LEN: usize = 2 + aead::NONCE_LEN;
+
+/// Length of the shortest possible token, with an empty ODCID.
+const MIN_TOKEN_LEN: usize = TOKEN_HDR_LEN + 8 + 16;
+
+/// Default lifetime of Retry tokens. These are only used on the connection
+/// attempt that immediately follows the Retry packet.
+const DEFAULT_RETRY_TOKEN_LIFETIME: time::Duration =
+    time::Duration::from_secs(10);
+
+/// Default lifetime of tokens sent in NEW_TOKEN frames.
+const DEFAULT_NEW_TOKEN_LIFETIME: time::Duration =
+    time::Duration::from_secs(86400);
+
+/// A key used to mint and validate address validation tokens.
+///
+/// Tokens are encrypted and authenticated with AES-256-GCM, and include the
+/// time they were minted at, the original destination connection ID (for
+/// Retry tokens) and the token type. The client's address is authenticated as
+/// well, but not included in the token.
+///
+/// The key can be rotated with [`rotate()`], in which case tokens minted with
+/// the previous key are still accepted, so that clients that received a
+/// token just before rotation are not penalized.
+///
+/// [`rotate()`]: struct.TokenKey.html#method.rotate
+#[derive(Clone)]
+pub struct TokenKey {
+    /// The key used to mint new tokens, and its ID.
+    current: (u8, Vec<u8>),
+
+    /// The key used before the last rotation, if any.
+    previous: Option<(u8, Vec<u8>)>,
+
+    retry_token_lifetime: time::Duration,
+
+    new_token_lifetime: time::Duration,
+}
+
+impl TokenKey {
+    /// Creates a token key with the given secret.
+    ///
+    /// The secret must be [`TOKEN_KEY_LEN`] bytes long, and should be shared
+    /// by all servers that need to validate each other's tokens.
+    ///
+    /// [`TOKEN_KEY_LEN`]: constant.TOKEN_KEY_LEN.html
+    pub fn new(key: &[u8]) -> Result<TokenKey> {
+        if key.len() != TOKEN_KEY_LEN {
+            return Err(Error::CryptoFail);
+        }
+
+        Ok(TokenKey {
+            current: (0, key.to_vec()),
+
+            previous: None,
+
+            retry_token_lifetime: DEFAULT_RETRY_TOKEN_LIFETIME,
+
+            new_token_lifetime: DEFAULT_NEW_TOKEN_LIFETIME,
+        })
+    }
+
+    /// Creates a token key with a random secret.
+    pub fn generate() -> TokenKey {
+        let mut key = [0; TOKEN_KEY_LEN];
+        rand::rand_bytes(&mut key);
+
+        // The key has the right length, so this can't fail.
+        TokenKey::new(&key).unwrap()
+    }
+
+    /// Replaces the key used to mint tokens with the given one.
+    ///
+    /// Tokens minted with the key being replaced are still accepted, until the
+    /// next rotation.
+    pub fn rotate(&mut self, key: &[u8]) -> Result<()> {
+        if key.len() != TOKEN_KEY_LEN {
+            return Err(Error::CryptoFail);
+        }
+
+        let id = self.current.0.wrapping_add(1);
+
+        let previous = std::mem::replace(&mut self.current, (id, key.to_vec()));
+        self.previous = Some(previous);
+
+        Ok(())
+    }
+
+    /// Sets how long Retry tokens are accepted for after they are minted.
+    ///
+    /// The default value is 10 seconds.
+    pub fn set_retry_token_lifetime(&mut self, v: time::Duration) {
+        self.retry_token_lifetime = v;
+    }
+
+    /// Sets how long tokens sent in NEW_TOKEN frames are accepted for after
+    /// they are minted.
+    ///
+    /// The default value is 24 hours.
+    pub fn set_new_token_lifetime(&mut self, v: time::Duration) {
+        self.new_token_lifetime = v;
+    }
+
+    /// Mints a token to be sent to the client in a Retry packet.
+    ///
+    /// `addr` is the address the client's Initial packet was received from,
+    /// and `odcid` is the destination connection ID of that packet.
+    pub fn mint_retry_token(&self, addr: &SocketAddr, odcid: &[u8]) -> Vec<u8> {
+        self.mint(TOKEN_TYPE_RETRY, addr, odcid, time::SystemTime::now())
+    }
+
+    /// Validates a token received in a client's Initial packet, following a
+    /// Retry.
+    ///
+    /// On success the original destination connection ID stored in the token
+    /// is returned, which should then be passed to [`accept()`]. `None` is
+    /// returned if the token is not a valid Retry token for the given address.
+    ///
+    /// [`accept()`]: fn.accept.html
+    pub fn validate_retry_token(
+        &self, addr: &SocketAddr, token: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.validate(TOKEN_TYPE_RETRY, addr, token, time::SystemTime::now())
+    }
+
+    pub(crate) fn mint_new_token(
+        &self, addr: &SocketAddr, now: time::SystemTime,
+    ) -> Vec<u8> {
+        self.mint(TOKEN_TYPE_NEW_TOKEN, addr, &[], now)
+    }
+
+    pub(crate) fn validate_new_token(
+        &self, addr: &SocketAddr, token: &[u8], now: time::SystemTime,
+    ) -> bool {
+        self.validate(TOKEN_TYPE_NEW_TOKEN, addr, token, now).is_some()
+    }
+
+    fn mint(
+        &self, ty: u8, addr: &SocketAddr, odcid: &[u8], now: time::SystemTime,
+    ) -> Vec<u8> {
+        let (id, key) = &self.current;
+
+        let mut nonce = [0; aead::NONCE_LEN];
+        rand::rand_bytes(&mut nonce);
+
+        let timestamp = now
+            .duration_since(time::UNIX_EPOCH)
+            .map(|v| v.as_secs())
+            .unwrap_or(0);
+
+        let mut token = vec![ty, *id];
+        token.extend_from_slice(&nonce);
+
+        let mut payload = timestamp.to_be_bytes().to_vec();
+        payload.extend_from_slice(odcid);
+
+        let aad = token_aad(ty, *id, addr);
+
+        // The key was validated when it was set, so sealing can't fail.
+        let key = make_key(key).unwrap();
+
+        key.seal_in_place_append_tag(
+            aead::Nonce::assume_unique_for_key(nonce),
+            aead::Aad::from(&aad),
+            &mut payload,
+        )
+        .unwrap();
+
+        token.extend_from_slice(&payload);
+
+        token
+    }
+
+    fn validate(
+        &self, ty: u8, addr: &SocketAddr, token: &[u8], now: time::SystemTime,
+    ) -> Option<Vec<u8>> {
+        if token.len() < MIN_TOKEN_LEN || token[0] != ty {
+            return None;
+        }
+
+        let id = token[1];
+
+        let key = if self.current.0 == id {
+            &self.current.1
+        } else {
+            match &self.previous {
+                Some((prev_id, prev_key)) if *prev_id == id => prev_key,
+
+                _ => return None,
+            }
+        };
+
+        let mut nonce = [0; aead::NONCE_LEN];
+        nonce.copy_from_slice(&token[2..TOKEN_HDR_LEN]);
+
+        let mut payload = token[TOKEN_HDR_LEN..].to_vec();
+
+        let aad = token_aad(ty, id, addr);
+
+        let key = make_key(key).ok()?;
+
+        let plaintext = key
+            .open_in_place(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(&aad),
+                &mut payload,
+            )
+            .ok()?;
+
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&plaintext[..8]);
+
+        let minted = time::UNIX_EPOCH.checked_add(time::Duration::from_secs(
+            u64::from_be_bytes(timestamp),
+        ))?;
+
+        let lifetime = if ty == TOKEN_TYPE_RETRY {
+            self.retry_token_lifetime
+        } else {
+            self.new_token_lifetime
+        };
+
+        match now.duration_since(minted) {
+            Ok(age) if age <= lifetime => Some(plaintext[8..].to_vec()),
+
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if the given token was minted for a NEW_TOKEN frame.
+pub fn is_new_token(token: &[u8]) -> bool {
+    token.len() >= MIN_TOKEN_LEN && token[0] == TOKEN_TYPE_NEW_TOKEN
+}
+
+fn make_key(key: &[u8]) -> Result<aead::LessSafeKey> {
+    let key = aead::UnboundKey::new(&aead::AES_256_GCM, key)
+        .map_err(|_| Error::CryptoFail)?;
+
+    Ok(aead::LessSafeKey::new(key))
+}
+
+/// Returns the additional authenticated data for a token.
+///
+/// Retry tokens are bound to the client's full address, as they are used
+/// right away. Other tokens are only bound to the client's IP address, as
+/// its port is likely to change between connections.
+fn token_aad(ty: u8, id: u8, addr: &SocketAddr) -> Vec<u8> {
+    let mut aad = vec![ty, id];
+
+    match addr.ip() {
+        IpAddr::V4(ip) => aad.extend_from_slice(&ip.octets()),
+
+        IpAddr::V6(ip) => aad.extend_from_slice(&ip.octets()),
+    }
+
+    if ty == TOKEN_TYPE_RETRY {
+        aad.extend_from_slice(&addr.port().to_be_bytes());
+    }
+
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_token() {
+        let key = TokenKey::new(&[0xba; TOKEN_KEY_LEN]).unwrap();
+
+        let addr = "127.0.0.1:1234".parse().unwrap();
+        let odcid = [0xcd; 16];
+
+        let token = key.mint_retry_token(&addr, &odcid);
+        assert!(!is_new_token(&token));
+
+        assert_eq!(key.validate_retry_token(&addr, &token), Some(odcid.to_vec()));
+
+        // Retry tokens are bound to the client's port as well.
+        let other_port = "127.0.0.1:4321".parse().unwrap();
+        assert_eq!(key.validate_retry_token(&other_port, &token), None);
+
+        let other_ip = "127.0.0.2:1234".parse().unwrap();
+        assert_eq!(key.validate_retry_token(&other_ip, &token), None);
+
+        // Tampered token.
+        let mut tampered = token.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        assert_eq!(key.validate_retry_token(&addr, &tampered), None);
+
+        let other_key = TokenKey::new(&[0xbb; TOKEN_KEY_LEN]).unwrap();
+        assert_eq!(other_key.validate_retry_token(&addr, &token), None);
+
+        // Retry tokens can't be used as NEW_TOKEN tokens.
+        let now = time::SystemTime::now();
+        assert!(!key.validate_new_token(&addr, &token, now));
+
+        assert_eq!(key.validate_retry_token(&addr, b"quiche"), None);
+    }
+
+    #[test]
+    fn new_token() {
+        let key = TokenKey::new(&[0xba; TOKEN_KEY_LEN]).unwrap();
+
+        let addr = "127.0.0.1:1234".parse().unwrap();
+        let now = time::SystemTime::now();
+
+        let token = key.mint_new_token(&addr, now);
+        assert!(is_new_token(&token));
+
+        assert!(key.validate_new_token(&addr, &token, now));
+
+        // The port is not part of the token.
+        let other_port = "127.0.0.1:4321".parse().unwrap();
+        assert!(key.validate_new_token(&other_port, &token, now));
+
+        let other_ip = "127.0.0.2:1234".parse().unwrap();
+        assert!(!key.validate_new_token(&other_ip, &token, now));
+
+        let expired =
+            now + DEFAULT_NEW_TOKEN_LIFETIME + time::Duration::from_secs(1);
+        assert!(!key.validate_new_token(&addr, &token, expired));
+
+        // NEW_TOKEN tokens can't be used as Retry tokens.
+        assert_eq!(key.validate_retry_token(&addr, &token), None);
+
+        // Retry tokens minted by applications are not NEW_TOKEN tokens.
+        assert!(!is_new_token(b"quiche test retry token"));
+    }
+
+    #[test]
+    fn rotation() {
+        let mut key = TokenKey::new(&[0xba; TOKEN_KEY_LEN]).unwrap();
+
+        let addr = "127.0.0.1:1234".parse().unwrap();
+        let odcid = [0xcd; 16];
+
+        let token_1 = key.mint_retry_token(&addr, &odcid);
+
+        assert_eq!(key.rotate(&[0xbb; 3]), Err(Error::CryptoFail));
+        assert_eq!(key.rotate(&[0xbb; TOKEN_KEY_LEN]), Ok(()));
+
+        let token_2 = key.mint_retry_token(&addr, &odcid);
+
+        // Tokens minted with the previous key are still valid.
+        assert_eq!(
+            key.validate_retry_token(&addr, &token_1),
+            Some(odcid.to_vec())
+        );
+        assert_eq!(
+            key.validate_retry_token(&addr, &token_2),
+            Some(odcid.to_vec())
+        );
+
+        assert_eq!(key.rotate(&[0xbc; TOKEN_KEY_LEN]), Ok(()));
+
+        // But not after another rotation.
+        assert_eq!(key.validate_retry_token(&addr, &token_1), None);
+        assert_eq!(
+            key.validate_retry_token(&addr, &token_2),
+            Some(odcid.to_vec())
+        );
+    }
+
+    #[test]
+    fn retry_token_expiry() {
+        let mut key = TokenKey::new(&[0xba; TOKEN_KEY_LEN]).unwrap();
+
+        let addr = "127.0.0.1:1234".parse().unwrap();
+
+        let now = time::SystemTime::now();
+        let minted = now - time::Duration::from_secs(5);
+
+        let token = key.mint(TOKEN_TYPE_RETRY, &addr, &[], minted);
+        assert_eq!(
+            key.validate(TOKEN_TYPE_RETRY, &addr, &token, now),
+            Some(vec![])
+        );
+
+        key.set_retry_token_lifetime(time::Duration::from_secs(1));
+        assert_eq!(key.validate(TOKEN_TYPE_RETRY, &addr, &token, now), None);
+    }
+}
@@ -52,6 +52,7 @@ Options:
   --http-version VERSION   HTTP version to use [default: all].
   --dump-packets PATH      Dump the incoming packets as files in the given directory.
   --dump-responses PATH    Dump response payload as files in the given directory.
+  --session-file PATH      File used to cache a TLS session for resumption.
   --no-verify              Don't verify server's certificate.
   --no-grease              Don't send GREASE.
   --cc-algorithm NAME      Specify which congestion control algorithm to use [default: cubic].
@@ -95,7 +96,6 @@ fn main() {
     // Create the UDP socket backing the QUIC connection, and register it with
     // the event loop.
     let socket = std::net::UdpSocket::bind(bind_addr).unwrap();
-    socket.connect(peer_addr).unwrap();
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
     poll.register(
@@ -149,6 +149,10 @@ fn main() {
         config.enable_hystart(false);
     }
 
+    if args.session_file.is_some() {
+        config.enable_early_data();
+    }
+
     let mut http_conn: Option<Box<dyn HttpConn>> = None;
 
     // Generate a random source connection ID for the connection.
@@ -157,7 +161,8 @@ fn main() {
 
     // Create a QUIC connection and initiate handshake.
     let mut conn =
-        quiche::connect(connect_url.domain(), &scid, &mut config).unwrap();
+        quiche::connect(connect_url.domain(), &scid, peer_addr, &mut config)
+            .unwrap();
 
     if let Some(keylog) = &mut keylog {
         if let Ok(keylog) = keylog.try_clone() {
@@ -165,6 +170,13 @@ fn main() {
         }
     }
 
+    // Resume the previous session, if one was cached.
+    if let Some(session_file) = &args.session_file {
+        if let Ok(session) = std::fs::read(session_file) {
+            conn.set_session(&session).ok();
+        }
+    }
+
     // Only bother with qlog if the user specified it.
     #[cfg(feature = "qlog")]
     {
@@ -187,9 +199,10 @@ fn main() {
         hex_dump(&scid)
     );
 
-    let write = conn.send(&mut out).expect("initial send failed");
+    let (write, send_info) =
+        conn.send(&mut out).expect("initial send failed");
 
-    while let Err(e) = socket.send(&out[..write]) {
+    while let Err(e) = socket.send_to(&out[..write], &send_info.to) {
         if e.kind() == std::io::ErrorKind::WouldBlock {
             trace!("send() would block");
             continue;
@@ -221,7 +234,7 @@ fn main() {
                 break 'read;
             }
 
-            let len = match socket.recv(&mut buf) {
+            let (len, from) = match socket.recv_from(&mut buf) {
                 Ok(v) => v,
 
                 Err(e) => {
@@ -249,8 +262,13 @@ fn main() {
 
             pkt_count += 1;
 
+            let recv_info = quiche::RecvInfo {
+                from,
+                ecn: quiche::Ecn::NotEct,
+            };
+
             // Process potentially coalesced packets.
-            let read = match conn.recv(&mut buf[..len]) {
+            let read = match conn.recv(&mut buf[..len], recv_info) {
                 Ok(v) => v,
 
                 Err(e) => {
@@ -267,6 +285,12 @@ fn main() {
         if conn.is_closed() {
             info!("connection closed, {:?}", conn.stats());
 
+            if let Some(session_file) = &args.session_file {
+                if let Some(session) = conn.session() {
+                    std::fs::write(session_file, &session).ok();
+                }
+            }
+
             if let Some(h_conn) = http_conn {
                 h_conn.report_incomplete(&req_start);
             }
@@ -274,8 +298,11 @@ fn main() {
             break;
         }
 
-        // Create a new HTTP connection once the QUIC connection is established.
-        if conn.is_established() && http_conn.is_none() {
+        // Create a new HTTP connection once the QUIC connection is established,
+        // or as soon as early data can be sent when resuming a session.
+        if (conn.is_established() || conn.is_in_early_data()) &&
+            http_conn.is_none()
+        {
             // At this stage the ALPN negotiation succeeded and selected a
             // single application protocol name. We'll use this to construct
             // the correct type of HttpConn but `application_proto()`
@@ -312,7 +339,7 @@ fn main() {
         // Generate outgoing QUIC packets and send them on the UDP socket, until
         // quiche reports that there are no more packets to be sent.
         loop {
-            let write = match conn.send(&mut out) {
+            let (write, send_info) = match conn.send(&mut out) {
                 Ok(v) => v,
 
                 Err(quiche::Error::Done) => {
@@ -328,7 +355,7 @@ fn main() {
                 },
             };
 
-            if let Err(e) = socket.send(&out[..write]) {
+            if let Err(e) = socket.send_to(&out[..write], &send_info.to) {
                 if e.kind() == std::io::ErrorKind::WouldBlock {
                     trace!("send() would block");
                     break;
@@ -343,6 +370,12 @@ fn main() {
         if conn.is_closed() {
             info!("connection closed, {:?}", conn.stats());
 
+            if let Some(session_file) = &args.session_file {
+                if let Some(session) = conn.session() {
+                    std::fs::write(session_file, &session).ok();
+                }
+            }
+
             if let Some(h_conn) = http_conn {
                 h_conn.report_incomplete(&req_start);
             }
@@ -362,6 +395,7 @@ struct ClientArgs {
     no_verify: bool,
     body: Option<Vec<u8>>,
     method: String,
+    session_file: Option<String>,
 }
 
 impl Args for ClientArgs {
@@ -404,6 +438,12 @@ impl Args for ClientArgs {
 
         let method = args.get_str("--method").to_string();
 
+        let session_file = if !args.get_str("--session-file").is_empty() {
+            Some(args.get_str("--session-file").to_string())
+        } else {
+            None
+        };
+
         ClientArgs {
             version,
             dump_response_path,
@@ -413,6 +453,7 @@ impl Args for ClientArgs {
             no_verify,
             body,
             method,
+            session_file,
         }
     }
 }
@@ -36,7 +36,6 @@ use std::io::prelude::*;
 
 use std::collections::HashMap;
 
-use std::net;
 use std::path;
 
 /// Returns a String containing a pretty printed version of the `buf` slice.
@@ -50,8 +49,9 @@ pub fn hex_dump(buf: &[u8]) -> String {
 ///
 /// This module contains constants and functions for working with ALPN.
 pub mod alpns {
-    pub const HTTP_09: [&str; 4] = ["hq-29", "hq-28", "hq-27", "http/0.9"];
-    pub const HTTP_3: [&str; 3] = ["h3-29", "h3-28", "h3-27"];
+    pub const HTTP_09: [&str; 5] =
+        ["hq-interop", "hq-29", "hq-28", "hq-27", "http/0.9"];
+    pub const HTTP_3: [&str; 4] = ["h3", "h3-29", "h3-28", "h3-27"];
 
     pub fn length_prefixed(alpns: &[&str]) -> Vec<u8> {
         let mut out = Vec::new();
@@ -176,7 +176,7 @@ pub struct Client {
     pub partial_responses: std::collections::HashMap<u64, PartialResponse>,
 }
 
-pub type ClientMap = HashMap<Vec<u8>, (net::SocketAddr, Client)>;
+pub type ClientMap = HashMap<Vec<u8>, Client>;
 
 /// Makes a buffered writer for a resource with a target URL.
 ///
@@ -893,6 +893,19 @@ impl HttpConn for Http3Conn {
                     }
                 },
 
+                Ok((_stream_id, quiche::h3::Event::Reset(e))) => {
+                    error!("request was reset by peer with {}, closing...", e);
+
+                    match conn.close(true, 0x00, b"kthxbye") {
+                        // Already closed.
+                        Ok(_) | Err(quiche::Error::Done) => (),
+
+                        Err(e) => panic!("error closing conn: {:?}", e),
+                    }
+
+                    break;
+                },
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
@@ -1011,6 +1024,8 @@ impl HttpConn for Http3Conn {
 
                 Ok((_stream_id, quiche::h3::Event::Finished)) => (),
 
+                Ok((_stream_id, quiche::h3::Event::Reset { .. })) => (),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
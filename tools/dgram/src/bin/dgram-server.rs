@@ -63,7 +63,7 @@ struct Client {
     http3_conn: Option<quiche::h3::Connection>,
 }
 
-type ClientMap = HashMap<Vec<u8>, (net::SocketAddr, Client)>;
+type ClientMap = HashMap<Vec<u8>, Client>;
 
 fn main() {
     let mut buf = [0; 65535];
@@ -152,14 +152,20 @@ fn main() {
     let conn_id_seed =
         ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
 
+    // Key used to mint and validate the address validation tokens sent in
+    // Retry packets. It's shared with the connections, so that they validate
+    // tokens sent in NEW_TOKEN frames with the same key.
+    let token_key = quiche::TokenKey::generate();
+
+    config.set_token_key(token_key.clone());
+
     let mut clients = ClientMap::new();
 
     loop {
         // Find the shorter timeout from all the active connections.
         //
         // TODO: use event loop that properly supports timers
-        let timeout =
-            clients.values().filter_map(|(_, c)| c.conn.timeout()).min();
+        let timeout = clients.values().filter_map(|c| c.conn.timeout()).min();
 
         poll.poll(&mut events, timeout).unwrap();
 
@@ -172,7 +178,7 @@ fn main() {
             if events.is_empty() {
                 trace!("timed out");
 
-                clients.values_mut().for_each(|(_, c)| c.conn.on_timeout());
+                clients.values_mut().for_each(|c| c.conn.on_timeout());
 
                 break 'read;
             }
@@ -216,7 +222,7 @@ fn main() {
 
             // Lookup a connection based on the packet's connection ID. If there
             // is no connection matching, create a new one.
-            let (_, client) = if !clients.contains_key(&hdr.dcid) &&
+            let client = if !clients.contains_key(&hdr.dcid) &&
                 !clients.contains_key(conn_id)
             {
                 if hdr.ty != quiche::Type::Initial {
@@ -249,15 +255,18 @@ fn main() {
 
                 let mut odcid = None;
 
-                if !args.get_bool("--no-retry") {
-                    // Token is always present in Initial packets.
-                    let token = hdr.token.as_ref().unwrap();
+                // Token is always present in Initial packets.
+                let token = hdr.token.as_ref().unwrap();
 
+                // Tokens sent in NEW_TOKEN frames are validated by the
+                // connection itself, and don't require a retry.
+                if !args.get_bool("--no-retry") && !quiche::is_new_token(token) {
                     // Do stateless retry if the client didn't send a token.
                     if token.is_empty() {
                         warn!("Doing stateless retry");
 
-                        let new_token = mint_token(&hdr, &src);
+                        let new_token =
+                            token_key.mint_retry_token(&src, &hdr.dcid);
 
                         let len = quiche::retry(
                             &hdr.scid,
@@ -281,7 +290,7 @@ fn main() {
                         continue;
                     }
 
-                    odcid = validate_token(&src, token);
+                    odcid = token_key.validate_retry_token(&src, token);
 
                     // The token was not valid, meaning the retry failed, so
                     // drop the packet.
@@ -306,14 +315,16 @@ fn main() {
                     hex_dump(&scid)
                 );
 
-                let conn = quiche::accept(&scid, odcid, &mut config).unwrap();
+                let conn =
+                    quiche::accept(&scid, odcid.as_deref(), src, &mut config)
+                        .unwrap();
 
                 let client = Client {
                     conn,
                     http3_conn: None,
                 };
 
-                clients.insert(scid.to_vec(), (src, client));
+                clients.insert(scid.to_vec(), client);
 
                 clients.get_mut(&scid[..]).unwrap()
             } else {
@@ -324,8 +335,13 @@ fn main() {
                 }
             };
 
+            let recv_info = quiche::RecvInfo {
+                from: src,
+                ecn: quiche::Ecn::NotEct,
+            };
+
             // Process potentially coalesced packets.
-            let read = match client.conn.recv(pkt_buf) {
+            let read = match client.conn.recv(pkt_buf, recv_info) {
                 Ok(v) => v,
 
                 Err(quiche::Error::Done) => {
@@ -472,9 +488,9 @@ fn main() {
         // Generate outgoing QUIC packets for all active connections and send
         // them on the UDP socket, until quiche reports that there are no more
         // packets to be sent.
-        for (peer, client) in clients.values_mut() {
+        for client in clients.values_mut() {
             loop {
-                let write = match client.conn.send(&mut out) {
+                let (write, send_info) = match client.conn.send(&mut out) {
                     Ok(v) => v,
 
                     Err(quiche::Error::Done) => {
@@ -491,7 +507,7 @@ fn main() {
                 };
 
                 // TODO: coalesce packets.
-                if let Err(e) = socket.send_to(&out[..write], &peer) {
+                if let Err(e) = socket.send_to(&out[..write], &send_info.to) {
                     if e.kind() == std::io::ErrorKind::WouldBlock {
                         trace!("send() would block");
                         break;
@@ -505,7 +521,7 @@ fn main() {
         }
 
         // Garbage collect closed connections.
-        clients.retain(|_, (_, ref mut c)| {
+        clients.retain(|_, ref mut c| {
             trace!("Collecting garbage");
 
             if c.conn.is_closed() {
@@ -521,64 +537,6 @@ fn main() {
     }
 }
 
-/// Generate a stateless retry token.
-///
-/// The token includes the static string `"quiche"` followed by the IP address
-/// of the client and by the original destination connection ID generated by the
-/// client.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn mint_token(hdr: &quiche::Header, src: &net::SocketAddr) -> Vec<u8> {
-    let mut token = Vec::new();
-
-    token.extend_from_slice(b"quiche");
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    token.extend_from_slice(&addr);
-    token.extend_from_slice(&hdr.dcid);
-
-    token
-}
-
-/// Validates a stateless retry token.
-///
-/// This checks that the ticket includes the `"quiche"` static string, and that
-/// the client IP address matches the address stored in the ticket.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn validate_token<'a>(
-    src: &net::SocketAddr, token: &'a [u8],
-) -> Option<&'a [u8]> {
-    if token.len() < 6 {
-        return None;
-    }
-
-    if &token[..6] != b"quiche" {
-        return None;
-    }
-
-    let token = &token[6..];
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    if token.len() < addr.len() || &token[..addr.len()] != addr.as_slice() {
-        return None;
-    }
-
-    let token = &token[addr.len()..];
-
-    Some(&token[..])
-}
-
 fn hex_dump(buf: &[u8]) -> String {
     let vec: Vec<String> = buf.iter().map(|b| format!("{:02x}", b)).collect();
 
@@ -180,6 +180,24 @@ impl Event {
         Event::connection_state_updated(None, new)
     }
 
+    /// Returns:
+    /// * `EventCategory`=`Connectivity`
+    /// * `EventType`=`ConnectivityEventType::MtuUpdated`
+    /// * `EventData`=`MtuUpdated`.
+    pub fn mtu_updated(old: Option<u64>, new: u64, done: Option<bool>) -> Self {
+        Event {
+            category: EventCategory::Connectivity,
+            ty: EventType::ConnectivityEventType(
+                ConnectivityEventType::MtuUpdated,
+            ),
+            data: EventData::MtuUpdated { old, new, done },
+        }
+    }
+
+    pub fn mtu_updated_min(new: u64) -> Self {
+        Event::mtu_updated(None, new, None)
+    }
+
     // Transport events.
 
     /// Returns:
@@ -924,7 +942,8 @@ impl Event {
                 EventData::ConnectionStarted { .. } |
                 EventData::ConnectionIdUpdated { .. } |
                 EventData::SpinBitUpdated { .. } |
-                EventData::ConnectionStateUpdated { .. } => true,
+                EventData::ConnectionStateUpdated { .. } |
+                EventData::MtuUpdated { .. } => true,
 
                 _ => false,
             },
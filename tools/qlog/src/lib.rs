@@ -975,6 +975,7 @@ pub enum ConnectivityEventType {
     ConnectionIdUpdated,
     SpinBitUpdated,
     ConnectionStateUpdated,
+    MtuUpdated,
 }
 
 #[derive(Serialize, Clone)]
@@ -1251,6 +1252,12 @@ pub enum EventData {
         new: ConnectionState,
     },
 
+    MtuUpdated {
+        old: Option<u64>,
+        new: u64,
+        done: Option<bool>,
+    },
+
     // ================================================================== //
     // SECURITY
     KeyUpdated {
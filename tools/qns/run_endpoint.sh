@@ -32,12 +32,8 @@ check_testcase () {
         RUST_LOG="info"
         ;;
     resumption | zerortt )
-        if [ "$ROLE" == "client" ]; then
-            # We don't support session resumption on the client-side yet.
-            echo "unsupported"
-            exit 127
-        elif [ "$ROLE" == "server" ]; then
-            echo "supported"
+        echo "supported"
+        if [ "$ROLE" == "server" ]; then
             QUICHE_SERVER_OPT="$QUICHE_SERVER_OPT --early-data"
         fi
         ;;
@@ -65,6 +61,22 @@ run_quiche_client_tests () {
                 $CLIENT_PARAMS $req >> $LOG 2>&1
         done
 
+    elif [ "$TESTCASE" == "resumption" ] || [ "$TESTCASE" == "zerortt" ]; then
+
+        # The first request is used to get a session ticket, the remaining
+        # ones are sent on the resumed connection.
+        SESSION_FILE=/tmp/session.bin
+        FIRST_REQUEST=${REQUESTS%% *}
+        OTHER_REQUESTS=${REQUESTS#* }
+
+        $QUICHE_DIR/$QUICHE_CLIENT $QUICHE_CLIENT_OPT \
+            --session-file $SESSION_FILE \
+            $CLIENT_PARAMS $FIRST_REQUEST >> $LOG 2>&1
+
+        $QUICHE_DIR/$QUICHE_CLIENT $QUICHE_CLIENT_OPT \
+            --session-file $SESSION_FILE \
+            $CLIENT_PARAMS $OTHER_REQUESTS >> $LOG 2>&1
+
     else
 
         $QUICHE_DIR/$QUICHE_CLIENT $QUICHE_CLIENT_OPT \